@@ -0,0 +1,14 @@
+CREATE TABLE email_events(
+    id uuid NOT NULL,
+    PRIMARY KEY (id),
+    provider TEXT NOT NULL,
+    message_id TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    recipient TEXT NOT NULL,
+    description TEXT NULL,
+    occurred_at timestamptz NOT NULL,
+    received_at timestamptz NOT NULL,
+    payload TEXT NOT NULL,
+    -- Providers retry the webhooks, the same event is stored only once
+    UNIQUE (provider, message_id, kind, recipient)
+);
@@ -0,0 +1 @@
+ALTER TABLE subscriptions ADD COLUMN soft_bounce_count INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,9 @@
+CREATE TABLE suppressions(
+    -- Either `address` or `domain`, the value is normalised
+    scope TEXT NOT NULL,
+    value TEXT NOT NULL,
+    reason TEXT NOT NULL,
+    created_at timestamptz NOT NULL,
+    expires_at timestamptz NULL,
+    PRIMARY KEY (scope, value)
+);
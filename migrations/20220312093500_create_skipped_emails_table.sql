@@ -0,0 +1,8 @@
+CREATE TABLE skipped_emails(
+    id uuid NOT NULL,
+    PRIMARY KEY (id),
+    recipient TEXT NOT NULL,
+    subject TEXT NOT NULL,
+    reason TEXT NOT NULL,
+    skipped_at timestamptz NOT NULL
+);
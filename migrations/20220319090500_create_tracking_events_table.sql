@@ -0,0 +1,12 @@
+CREATE TABLE tracking_events(
+    id uuid NOT NULL,
+    PRIMARY KEY (id),
+    issue_id uuid NOT NULL REFERENCES newsletter_issues (id),
+    subscriber_id uuid NOT NULL REFERENCES subscriptions (id),
+    -- Either `open` or `click`
+    kind TEXT NOT NULL,
+    -- The destination of the clicked link
+    url TEXT NULL,
+    occurred_at timestamptz NOT NULL
+);
+CREATE INDEX tracking_events_issue_id_idx ON tracking_events (issue_id);
@@ -0,0 +1,14 @@
+CREATE TABLE issue_deliveries(
+    issue_id uuid NOT NULL REFERENCES newsletter_issues (id),
+    subscriber_id uuid NOT NULL REFERENCES subscriptions (id),
+    -- Either `queued`, `sent`, `skipped` or `failed`, the outcome of the last attempt
+    status TEXT NOT NULL,
+    provider_message_id TEXT NULL,
+    -- Either `transient` or `permanent`, for the failed deliveries
+    error_class TEXT NULL,
+    -- The error of a failed delivery or the reason of a skipped one
+    detail TEXT NULL,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    updated_at timestamptz NOT NULL,
+    PRIMARY KEY (issue_id, subscriber_id)
+);
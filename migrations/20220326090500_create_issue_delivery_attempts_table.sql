@@ -0,0 +1,12 @@
+CREATE TABLE issue_delivery_attempts(
+    id uuid NOT NULL,
+    PRIMARY KEY (id),
+    issue_id uuid NOT NULL,
+    subscriber_id uuid NOT NULL,
+    status TEXT NOT NULL,
+    provider_message_id TEXT NULL,
+    error_class TEXT NULL,
+    detail TEXT NULL,
+    attempted_at timestamptz NOT NULL,
+    FOREIGN KEY (issue_id, subscriber_id) REFERENCES issue_deliveries (issue_id, subscriber_id)
+);
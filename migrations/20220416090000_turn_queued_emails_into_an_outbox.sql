@@ -0,0 +1,6 @@
+-- Every confirmation email goes through the outbox, not only the ones that failed
+ALTER TABLE queued_emails RENAME TO outbox;
+ALTER TABLE outbox RENAME COLUMN queued_at TO created_at;
+ALTER TABLE outbox RENAME COLUMN failed_at TO dead_lettered_at;
+ALTER TABLE outbox ALTER COLUMN last_error DROP NOT NULL;
+ALTER INDEX queued_emails_next_attempt_at_idx RENAME TO outbox_next_attempt_at_idx;
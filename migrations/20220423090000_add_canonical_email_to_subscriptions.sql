@@ -0,0 +1,65 @@
+-- The form of the address used to detect duplicates, see `EmailAddress::canonical`
+ALTER TABLE subscriptions ADD COLUMN canonical_email TEXT NULL;
+
+-- The subscribers sharing their canonical address with an older subscriber, they are left
+-- without a canonical address until the duplicates are resolved
+CREATE TABLE canonical_email_collisions(
+    subscriber_id uuid NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+    PRIMARY KEY (subscriber_id),
+    canonical_email TEXT NOT NULL,
+    -- The oldest subscriber with the same canonical address, it keeps the address
+    kept_subscriber_id uuid NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE
+);
+
+CREATE TEMPORARY TABLE canonical_emails AS
+    WITH parts AS (
+        SELECT id, subscribed_at,
+            lower(substring(trim(email) FROM '^(.*)@')) AS local,
+            lower(substring(trim(email) FROM '@([^@]*)$')) AS domain
+        FROM subscriptions
+    ), untagged AS (
+        SELECT id, subscribed_at, domain,
+            CASE
+                WHEN domain IN ('fastmail.com', 'gmail.com', 'googlemail.com', 'hotmail.com',
+                    'icloud.com', 'live.com', 'me.com', 'outlook.com', 'proton.me',
+                    'protonmail.com')
+                    THEN split_part(local, '+', 1)
+                ELSE local
+            END AS local
+        FROM parts
+    ), canonical AS (
+        SELECT id, subscribed_at,
+            CASE
+                WHEN domain IN ('gmail.com', 'googlemail.com')
+                    THEN replace(local, '.', '') || '@gmail.com'
+                ELSE local || '@' || domain
+            END AS canonical_email
+        FROM untagged
+    )
+    SELECT id, canonical_email,
+        first_value(id) OVER (
+            PARTITION BY canonical_email ORDER BY subscribed_at, id
+        ) AS kept_subscriber_id
+    FROM canonical;
+
+UPDATE subscriptions SET canonical_email = canonical_emails.canonical_email
+    FROM canonical_emails
+    WHERE subscriptions.id = canonical_emails.id
+        AND canonical_emails.id = canonical_emails.kept_subscriber_id;
+INSERT INTO canonical_email_collisions (subscriber_id, canonical_email, kept_subscriber_id)
+    SELECT id, canonical_email, kept_subscriber_id FROM canonical_emails
+        WHERE id <> kept_subscriber_id;
+DROP TABLE canonical_emails;
+
+CREATE UNIQUE INDEX subscriptions_canonical_email_idx ON subscriptions (canonical_email);
+
+DO $$
+DECLARE
+    collisions bigint;
+BEGIN
+    SELECT COUNT(*) INTO collisions FROM canonical_email_collisions;
+    IF collisions > 0 THEN
+        RAISE WARNING '% subscriber(s) share their canonical address with an older subscriber, '
+            'see the table canonical_email_collisions', collisions;
+    END IF;
+END $$;
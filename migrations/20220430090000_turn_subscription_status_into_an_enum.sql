@@ -0,0 +1,4 @@
+-- The allowed transitions between the statuses are in `SubscriptionStatus::can_transition_to`
+CREATE TYPE subscription_status AS ENUM ('pending_confirmation', 'confirmed', 'suppressed');
+ALTER TABLE subscriptions
+    ALTER COLUMN status TYPE subscription_status USING status::subscription_status;
@@ -0,0 +1,13 @@
+-- The subscribers can leave from the preference centre
+ALTER TYPE subscription_status ADD VALUE 'unsubscribed';
+
+-- See `DeliveryFrequency`
+ALTER TABLE subscriptions ADD COLUMN frequency TEXT NOT NULL DEFAULT 'immediate'
+    CHECK (frequency IN ('immediate', 'digest'));
+
+-- The topics chosen by the subscribers among `subscriptions.topics` of the configuration
+CREATE TABLE subscriber_topics(
+    subscriber_id uuid NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+    topic TEXT NOT NULL,
+    PRIMARY KEY (subscriber_id, topic)
+);
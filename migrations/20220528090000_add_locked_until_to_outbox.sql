@@ -0,0 +1,3 @@
+-- The message being sent is leased by the relay until then, no transaction is held while sending.
+-- It is sent again if the relay stops and the lease expires
+ALTER TABLE outbox ADD COLUMN locked_until timestamptz NULL;
@@ -0,0 +1,2 @@
+-- A delivery that failed with a transient error is queued again, it is not claimed before then
+ALTER TABLE issue_deliveries ADD COLUMN next_attempt_at timestamptz NULL;
@@ -0,0 +1,8 @@
+-- The uuids are 16-byte BLOBs and the timestamps are UTC TEXT, see `repository::sqlite`
+CREATE TABLE subscriptions(
+    id BLOB NOT NULL,
+    email TEXT NOT NULL UNIQUE,
+    name TEXT NOT NULL,
+    subscribed_at TEXT NOT NULL,
+    PRIMARY KEY (id)
+);
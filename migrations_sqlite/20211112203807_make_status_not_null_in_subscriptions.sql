@@ -0,0 +1,16 @@
+-- SQLite cannot add a constraint to a column, the table is rebuilt. Nothing references it yet
+UPDATE subscriptions
+    SET status = 'confirmed'
+    WHERE status IS NULL;
+CREATE TABLE subscriptions_new(
+    id BLOB NOT NULL,
+    email TEXT NOT NULL UNIQUE,
+    name TEXT NOT NULL,
+    subscribed_at TEXT NOT NULL,
+    status TEXT NOT NULL,
+    PRIMARY KEY (id)
+);
+INSERT INTO subscriptions_new (id, email, name, subscribed_at, status)
+    SELECT id, email, name, subscribed_at, status FROM subscriptions;
+DROP TABLE subscriptions;
+ALTER TABLE subscriptions_new RENAME TO subscriptions;
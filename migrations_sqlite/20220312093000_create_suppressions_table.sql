@@ -0,0 +1,9 @@
+CREATE TABLE suppressions(
+    -- Either `address` or `domain`, the value is normalised
+    scope TEXT NOT NULL,
+    value TEXT NOT NULL,
+    reason TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    expires_at TEXT NULL,
+    PRIMARY KEY (scope, value)
+);
@@ -0,0 +1,8 @@
+CREATE TABLE skipped_emails(
+    id BLOB NOT NULL,
+    recipient TEXT NOT NULL,
+    subject TEXT NOT NULL,
+    reason TEXT NOT NULL,
+    skipped_at TEXT NOT NULL,
+    PRIMARY KEY (id)
+);
@@ -0,0 +1,10 @@
+CREATE TABLE newsletter_issues(
+    id BLOB NOT NULL,
+    title TEXT NOT NULL,
+    text_content TEXT NOT NULL,
+    html_content TEXT NOT NULL,
+    track_opens BOOLEAN NOT NULL,
+    track_clicks BOOLEAN NOT NULL,
+    published_at TEXT NOT NULL,
+    PRIMARY KEY (id)
+);
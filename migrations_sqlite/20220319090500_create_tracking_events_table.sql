@@ -0,0 +1,12 @@
+CREATE TABLE tracking_events(
+    id BLOB NOT NULL,
+    issue_id BLOB NOT NULL REFERENCES newsletter_issues (id),
+    subscriber_id BLOB NOT NULL REFERENCES subscriptions (id),
+    -- Either `open` or `click`
+    kind TEXT NOT NULL,
+    -- The destination of the clicked link
+    url TEXT NULL,
+    occurred_at TEXT NOT NULL,
+    PRIMARY KEY (id)
+);
+CREATE INDEX tracking_events_issue_id_idx ON tracking_events (issue_id);
@@ -0,0 +1,12 @@
+CREATE TABLE issue_delivery_attempts(
+    id BLOB NOT NULL,
+    issue_id BLOB NOT NULL,
+    subscriber_id BLOB NOT NULL,
+    status TEXT NOT NULL,
+    provider_message_id TEXT NULL,
+    error_class TEXT NULL,
+    detail TEXT NULL,
+    attempted_at TEXT NOT NULL,
+    FOREIGN KEY (issue_id, subscriber_id) REFERENCES issue_deliveries (issue_id, subscriber_id),
+    PRIMARY KEY (id)
+);
@@ -0,0 +1,17 @@
+CREATE TABLE queued_emails(
+    id BLOB NOT NULL,
+    recipient TEXT NOT NULL,
+    subject TEXT NOT NULL,
+    html_content TEXT NOT NULL,
+    text_content TEXT NOT NULL,
+    attempts INTEGER NOT NULL,
+    -- The error of the last attempt
+    last_error TEXT NOT NULL,
+    queued_at TEXT NOT NULL,
+    next_attempt_at TEXT NOT NULL,
+    -- Set when the email is given up on, after a permanent error or too many attempts
+    failed_at TEXT NULL,
+    PRIMARY KEY (id)
+);
+CREATE INDEX queued_emails_next_attempt_at_idx ON queued_emails (next_attempt_at)
+    WHERE failed_at IS NULL;
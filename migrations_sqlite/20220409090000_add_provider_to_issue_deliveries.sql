@@ -0,0 +1,3 @@
+-- The name of the provider that accepted the email, when it was sent
+ALTER TABLE issue_deliveries ADD COLUMN provider TEXT NULL;
+ALTER TABLE issue_delivery_attempts ADD COLUMN provider TEXT NULL;
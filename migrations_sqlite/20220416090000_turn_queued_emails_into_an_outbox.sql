@@ -0,0 +1,27 @@
+-- Every confirmation email goes through the outbox, not only the ones that failed. SQLite cannot
+-- drop a NOT NULL constraint nor rename an index, the table is rebuilt
+CREATE TABLE outbox(
+    id BLOB NOT NULL,
+    recipient TEXT NOT NULL,
+    subject TEXT NOT NULL,
+    html_content TEXT NOT NULL,
+    text_content TEXT NOT NULL,
+    attempts INTEGER NOT NULL,
+    -- The error of the last attempt
+    last_error TEXT NULL,
+    created_at TEXT NOT NULL,
+    next_attempt_at TEXT NOT NULL,
+    -- Set when the email is given up on, after a permanent error or too many attempts
+    dead_lettered_at TEXT NULL,
+    -- SQLite has no row locks, the message is leased by the relay sending it until then
+    locked_until TEXT NULL,
+    PRIMARY KEY (id)
+);
+INSERT INTO outbox (id, recipient, subject, html_content, text_content, attempts, last_error,
+        created_at, next_attempt_at, dead_lettered_at)
+    SELECT id, recipient, subject, html_content, text_content, attempts, last_error, queued_at,
+            next_attempt_at, failed_at
+        FROM queued_emails;
+DROP TABLE queued_emails;
+CREATE INDEX outbox_next_attempt_at_idx ON outbox (next_attempt_at)
+    WHERE dead_lettered_at IS NULL;
@@ -0,0 +1,15 @@
+-- The form of the address used to detect duplicates, see `EmailAddress::canonical`. The
+-- subscribers of a SQLite database are all added after `EmailAddress::canonical`, there is
+-- nothing to backfill
+ALTER TABLE subscriptions ADD COLUMN canonical_email TEXT NULL;
+
+-- Kept to mirror the Postgres schema, it stays empty
+CREATE TABLE canonical_email_collisions(
+    subscriber_id BLOB NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+    canonical_email TEXT NOT NULL,
+    -- The oldest subscriber with the same canonical address, it keeps the address
+    kept_subscriber_id BLOB NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+    PRIMARY KEY (subscriber_id)
+);
+
+CREATE UNIQUE INDEX subscriptions_canonical_email_idx ON subscriptions (canonical_email);
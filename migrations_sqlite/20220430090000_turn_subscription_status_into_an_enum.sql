@@ -0,0 +1,13 @@
+-- SQLite has no enums and cannot add a CHECK to an existing column, the values of the
+-- `subscription_status` enum of Postgres are checked by triggers instead. The allowed
+-- transitions between the statuses are in `SubscriptionStatus::can_transition_to`
+CREATE TRIGGER subscriptions_status_insert BEFORE INSERT ON subscriptions
+    WHEN NEW.status NOT IN ('pending_confirmation', 'confirmed', 'suppressed')
+BEGIN
+    SELECT RAISE(ABORT, 'invalid input value for enum subscription_status');
+END;
+CREATE TRIGGER subscriptions_status_update BEFORE UPDATE OF status ON subscriptions
+    WHEN NEW.status NOT IN ('pending_confirmation', 'confirmed', 'suppressed')
+BEGIN
+    SELECT RAISE(ABORT, 'invalid input value for enum subscription_status');
+END;
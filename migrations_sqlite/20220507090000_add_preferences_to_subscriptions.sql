@@ -0,0 +1,25 @@
+-- The subscribers can leave from the preference centre, the triggers checking the statuses
+-- accept `unsubscribed`
+DROP TRIGGER subscriptions_status_insert;
+DROP TRIGGER subscriptions_status_update;
+CREATE TRIGGER subscriptions_status_insert BEFORE INSERT ON subscriptions
+    WHEN NEW.status NOT IN ('pending_confirmation', 'confirmed', 'suppressed', 'unsubscribed')
+BEGIN
+    SELECT RAISE(ABORT, 'invalid input value for enum subscription_status');
+END;
+CREATE TRIGGER subscriptions_status_update BEFORE UPDATE OF status ON subscriptions
+    WHEN NEW.status NOT IN ('pending_confirmation', 'confirmed', 'suppressed', 'unsubscribed')
+BEGIN
+    SELECT RAISE(ABORT, 'invalid input value for enum subscription_status');
+END;
+
+-- See `DeliveryFrequency`
+ALTER TABLE subscriptions ADD COLUMN frequency TEXT NOT NULL DEFAULT 'immediate'
+    CHECK (frequency IN ('immediate', 'digest'));
+
+-- The topics chosen by the subscribers among `subscriptions.topics` of the configuration
+CREATE TABLE subscriber_topics(
+    subscriber_id BLOB NOT NULL REFERENCES subscriptions (id) ON DELETE CASCADE,
+    topic TEXT NOT NULL,
+    PRIMARY KEY (subscriber_id, topic)
+);
@@ -0,0 +1,10 @@
+-- The messages of the outbox once sent, with the provider that accepted them
+CREATE TABLE sent_emails(
+    id BLOB NOT NULL,
+    recipient TEXT NOT NULL,
+    subject TEXT NOT NULL,
+    provider TEXT NOT NULL,
+    provider_message_id TEXT NULL,
+    sent_at TEXT NOT NULL,
+    PRIMARY KEY (id)
+);
@@ -0,0 +1,8 @@
+-- The topic of the issue among `subscriptions.topics` of the configuration, it is sent to every
+-- subscriber if NULL
+ALTER TABLE newsletter_issues ADD COLUMN topic TEXT NULL;
+
+-- The delivery waits for the next digest of the subscriber, see `DeliveryFrequency`
+ALTER TABLE issue_deliveries ADD COLUMN digest BOOLEAN NOT NULL DEFAULT false;
+-- A digest being sent is leased until then, it is sent again if the lease expires
+ALTER TABLE issue_deliveries ADD COLUMN locked_until TEXT NULL;
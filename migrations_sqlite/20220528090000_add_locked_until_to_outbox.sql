@@ -0,0 +1,3 @@
+-- The outbox of SQLite has had `locked_until` since it was created, see
+-- 20220416090000_turn_queued_emails_into_an_outbox.sql. Nothing to do
+SELECT 1;
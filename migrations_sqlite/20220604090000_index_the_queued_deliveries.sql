@@ -0,0 +1,3 @@
+-- The delivery worker polls the queued deliveries, the oldest first
+CREATE INDEX issue_deliveries_queued_idx ON issue_deliveries (updated_at)
+    WHERE status = 'queued';
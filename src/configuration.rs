@@ -1,128 +0,0 @@
-use std::time::Duration;
-
-use serde::Deserialize;
-use serde_aux::field_attributes::deserialize_number_from_string;
-use sqlx::{
-    postgres::{PgConnectOptions, PgSslMode},
-    ConnectOptions,
-};
-
-use crate::domain::{self, EmailAddress};
-
-#[derive(Deserialize)]
-pub struct Settings {
-    pub database: DatabaseSettings,
-    pub application: ApplicationSettings,
-    pub email_client: EmailClientSettings,
-}
-
-#[derive(Deserialize)]
-pub struct DatabaseSettings {
-    pub host: String,
-    #[serde(deserialize_with = "deserialize_number_from_string")]
-    pub port: u16,
-    pub username: String,
-    pub password: String,
-    pub name: String,
-    #[serde(default)]
-    pub require_ssl: bool,
-}
-
-#[derive(Deserialize)]
-pub struct ApplicationSettings {
-    pub host: String,
-    #[serde(deserialize_with = "deserialize_number_from_string")]
-    pub port: u16,
-    pub base_url: String,
-}
-
-#[derive(Deserialize)]
-pub struct EmailClientSettings {
-    pub base_url: String,
-    pub sender_email: String,
-    pub authorization_token: String,
-    pub timeout_milliseconds: u64,
-}
-
-impl DatabaseSettings {
-    pub fn without_db(&self) -> PgConnectOptions {
-        let ssl_mode = if self.require_ssl {
-            PgSslMode::Require
-        } else {
-            PgSslMode::Prefer
-        };
-
-        PgConnectOptions::new()
-            .host(&self.host)
-            .port(self.port)
-            .ssl_mode(ssl_mode)
-            .username(&self.username)
-            .password(&self.password)
-    }
-
-    pub fn with_db(&self) -> PgConnectOptions {
-        let mut options = self.without_db().database(&self.name);
-        options.log_statements(log::LevelFilter::Trace);
-        options
-    }
-}
-
-impl ApplicationSettings {
-    pub fn address(&self) -> String {
-        format!("{}:{}", self.host, self.port)
-    }
-}
-
-impl EmailClientSettings {
-    pub fn sender(&self) -> Result<EmailAddress, domain::email_address::ParseEmailAddressError> {
-        self.sender_email.parse()
-    }
-
-    pub fn timeout(&self) -> Duration {
-        Duration::from_millis(self.timeout_milliseconds)
-    }
-}
-
-pub fn get_configuration() -> Result<Settings, config::ConfigError> {
-    let base_path = std::env::current_dir().expect("Failed to determine the current directory");
-    let configuration_directory = base_path.join("configuration");
-    let environment: Environment = std::env::var("APP_ENVIRONMENT")
-        .unwrap_or_else(|_| "local".into())
-        .try_into()
-        .expect("Failed to parse APP_ENVIRONMENT");
-
-    config::Config::builder()
-        .add_source(config::File::from(configuration_directory.join("base")).required(true))
-        .add_source(
-            config::File::from(configuration_directory.join(environment.as_str())).required(true),
-        )
-        .add_source(config::Environment::with_prefix("app").separator("__"))
-        .build()?
-        .try_deserialize()
-}
-
-enum Environment {
-    Local,
-    Production,
-}
-
-impl Environment {
-    fn as_str(&self) -> &'static str {
-        match self {
-            Environment::Local => "local",
-            Environment::Production => "production",
-        }
-    }
-}
-
-impl TryFrom<String> for Environment {
-    type Error = String;
-
-    fn try_from(value: String) -> Result<Self, Self::Error> {
-        match value.to_lowercase().as_str() {
-            "local" => Ok(Environment::Local),
-            "production" => Ok(Environment::Production),
-            other => Err(format!("{} is not a valid environment", other)),
-        }
-    }
-}
@@ -0,0 +1,121 @@
+use std::collections::BTreeMap;
+
+use config::{Value, ValueKind};
+
+use super::{
+    get_environment, secret_item_keys, sources, ConfigurationError, SecretProvider,
+    SECRET_ITEM_KEYS, SECRET_KEYS,
+};
+
+const REDACTED: &str = "[REDACTED]";
+
+/// A configuration value after all the sources have been merged.
+#[derive(Debug)]
+pub struct ResolvedValue {
+    pub key: String,
+    pub value: String,
+    /// The source with the highest priority that defines the value.
+    pub source: String,
+}
+
+/// Resolve the configuration keeping track of the source of each value, secrets are redacted.
+pub fn describe_configuration(
+    provider: &dyn SecretProvider,
+) -> Result<Vec<ResolvedValue>, ConfigurationError> {
+    let environment = get_environment()?;
+
+    let mut resolved = BTreeMap::new();
+    for (name, source) in sources(environment) {
+        for (key, value) in source.collect()? {
+            flatten(key, value, &name, &mut resolved);
+        }
+    }
+    for key in SECRET_KEYS {
+        if let Some(provided) = provider.get(key)? {
+            resolved.insert(key.to_string(), (String::new(), provided.origin));
+        }
+    }
+    let providers = resolved
+        .keys()
+        .filter_map(|key| {
+            key.strip_prefix("email_client.fallback_providers.")?
+                .split('.')
+                .next()?
+                .parse::<usize>()
+                .ok()
+        })
+        .max()
+        .map_or(0, |index| index + 1);
+    for (_, _, key) in secret_item_keys(providers) {
+        if let Some(provided) = provider.get(&key)? {
+            resolved.insert(key, (String::new(), provided.origin));
+        }
+    }
+
+    Ok(resolved
+        .into_iter()
+        .map(|(key, (value, source))| {
+            let value = if is_secret(&key) {
+                REDACTED.to_string()
+            } else {
+                value
+            };
+            ResolvedValue { key, value, source }
+        })
+        .collect())
+}
+
+fn flatten(
+    key: String,
+    value: Value,
+    source: &str,
+    resolved: &mut BTreeMap<String, (String, String)>,
+) {
+    match value.kind {
+        ValueKind::Table(table) => {
+            for (child, value) in table {
+                flatten(format!("{}.{}", key, child), value, source, resolved);
+            }
+        }
+        ValueKind::Array(items) if !items.is_empty() => {
+            for (index, value) in items.into_iter().enumerate() {
+                flatten(format!("{}.{}", key, index), value, source, resolved);
+            }
+        }
+        kind => {
+            resolved.insert(key, (kind.to_string(), source.to_string()));
+        }
+    }
+}
+
+/// Whether `key` holds a secret, the items of the lists are matched with `SECRET_ITEM_KEYS`.
+fn is_secret(key: &str) -> bool {
+    SECRET_KEYS.contains(&key)
+        || SECRET_ITEM_KEYS.iter().any(|pattern| {
+            let (mut pattern, mut key) = (pattern.split('.'), key.split('.'));
+            loop {
+                match (pattern.next(), key.next()) {
+                    (None, None) => return true,
+                    (Some("*"), Some(segment)) if segment.parse::<usize>().is_ok() => {}
+                    (Some(expected), Some(segment)) if expected == segment => {}
+                    _ => return false,
+                }
+            }
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::is_secret;
+
+    #[test]
+    fn the_secrets_of_the_list_items_are_detected() {
+        assert!(is_secret("database.password"));
+        assert!(is_secret("email_client.fallback_providers.0.password"));
+        assert!(is_secret(
+            "email_client.fallback_providers.12.authorization_token"
+        ));
+        assert!(!is_secret("email_client.fallback_providers.0.username"));
+        assert!(!is_secret("email_client.fallback_providers.password"));
+    }
+}
@@ -0,0 +1,911 @@
+mod describe;
+mod reload;
+mod secret_provider;
+mod validation;
+
+use std::{path::PathBuf, sync::Arc, time::Duration};
+
+use serde::Deserialize;
+use serde_aux::field_attributes::deserialize_number_from_string;
+use sqlx::{
+    postgres::{PgConnectOptions, PgSslMode},
+    ConnectOptions,
+};
+
+use crate::{
+    domain::{self, EmailAddress, Script},
+    domain_check::{DomainChecker, DomainResolver},
+    email_client::{
+        CircuitBreakerPolicy, EmailClient, EmailClientError, EmailClientPolicy, Postmark,
+        SmtpRelay, SmtpTls, Transport,
+    },
+    human_verification::{CaptchaVerifier, HoneypotVerifier, HumanVerifier},
+    secret::Secret,
+    subscription_policy::{self, PolicyRules},
+    tracking::TrackingKey,
+};
+
+pub use self::{
+    describe::{describe_configuration, ResolvedValue},
+    reload::{ReloadError, Reloader},
+    secret_provider::{EnvFileSecretProvider, ProvidedSecret, SecretProvider, SecretProviderError},
+    validation::{ValidationError, ValidationErrors},
+};
+
+/// Configuration keys holding secrets, they are never printed in clear.
+pub const SECRET_KEYS: &[&str] = &[
+    "application.admin_token",
+    "database.password",
+    "email_client.authorization_token",
+    "subscriptions.human_verification.secret",
+    "telemetry.redaction.hash_key",
+    "tracking.signing_key",
+    "webhooks.postmark.password",
+];
+
+/// Configuration keys holding secrets in the items of a list, `*` stands for the index. They are
+/// all in the items of `email_client.fallback_providers`.
+pub const SECRET_ITEM_KEYS: &[&str] = &[
+    "email_client.fallback_providers.*.authorization_token",
+    "email_client.fallback_providers.*.password",
+];
+
+#[derive(Clone, Deserialize)]
+pub struct Settings {
+    pub database: DatabaseSettings,
+    pub application: ApplicationSettings,
+    pub email_client: EmailClientSettings,
+    #[serde(default)]
+    pub telemetry: TelemetrySettings,
+    pub webhooks: WebhookSettings,
+    pub tracking: TrackingSettings,
+    #[serde(default)]
+    pub subscriptions: SubscriptionSettings,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct DatabaseSettings {
+    pub host: String,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub port: u16,
+    pub username: String,
+    pub password: Secret,
+    pub name: String,
+    #[serde(default)]
+    pub require_ssl: bool,
+    /// Where everything is stored, the settings above are only read by `Postgres`.
+    #[serde(default)]
+    pub backend: DatabaseBackend,
+}
+
+/// The storage of the application, either the Postgres database above or a SQLite file.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum DatabaseBackend {
+    #[default]
+    Postgres,
+    /// A SQLite file, created if it does not exist. It needs the `sqlite` feature.
+    Sqlite { path: String },
+}
+
+#[derive(Clone, Deserialize)]
+pub struct ApplicationSettings {
+    pub host: String,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub port: u16,
+    pub base_url: String,
+    /// Bearer token granting access to the administration endpoints.
+    pub admin_token: Secret,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct EmailClientSettings {
+    pub base_url: String,
+    pub sender_email: String,
+    pub authorization_token: Secret,
+    pub timeout_milliseconds: u64,
+    #[serde(default)]
+    pub max_retries: u32,
+    #[serde(default)]
+    pub retry_backoff_milliseconds: u64,
+    /// Maximum number of emails sent per second, allowed by the plan of the provider.
+    #[serde(default)]
+    pub max_messages_per_second: Option<u32>,
+    #[serde(default)]
+    pub circuit_breaker: CircuitBreakerSettings,
+    /// Providers used in order when the Postmark account above fails.
+    #[serde(default)]
+    pub fallback_providers: Vec<ProviderSettings>,
+}
+
+#[derive(Clone, Deserialize, PartialEq)]
+pub struct ProviderSettings {
+    /// Name of the provider, it is recorded with every email it sends.
+    pub name: String,
+    /// Maximum number of emails sent per second, allowed by the plan of the provider.
+    #[serde(default)]
+    pub max_messages_per_second: Option<u32>,
+    #[serde(flatten)]
+    pub transport: TransportSettings,
+}
+
+#[derive(Clone, Deserialize, PartialEq)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum TransportSettings {
+    Postmark {
+        base_url: String,
+        authorization_token: Secret,
+    },
+    Smtp {
+        host: String,
+        #[serde(deserialize_with = "deserialize_number_from_string")]
+        port: u16,
+        #[serde(default)]
+        tls: SmtpTls,
+        /// Leave the username and the password unset if the relay does not require
+        /// authentication.
+        #[serde(default)]
+        username: Option<String>,
+        #[serde(default)]
+        password: Option<Secret>,
+    },
+}
+
+/// When to stop calling the email provider, the requests fail fast while the circuit is open.
+#[derive(Clone, Deserialize)]
+pub struct CircuitBreakerSettings {
+    /// Share of failed requests, between 0 and 1, that opens the circuit.
+    #[serde(default = "default_failure_rate_threshold")]
+    pub failure_rate_threshold: f64,
+    /// Number of recent requests the failure rate is computed on.
+    #[serde(default = "default_window_size")]
+    pub window_size: u32,
+    #[serde(default = "default_open_duration_milliseconds")]
+    pub open_duration_milliseconds: u64,
+    /// Number of successful probes required to close the circuit.
+    #[serde(default = "default_half_open_probes")]
+    pub half_open_probes: u32,
+}
+
+/// Which addresses may subscribe.
+#[derive(Clone, Deserialize)]
+pub struct SubscriptionSettings {
+    /// Reject the addresses of the disposable email providers.
+    #[serde(default = "default_true")]
+    pub reject_disposable_domains: bool,
+    /// File replacing the bundled list of disposable domains, one domain per line. It is read
+    /// again whenever it changes.
+    #[serde(default)]
+    pub disposable_domains_file: Option<PathBuf>,
+    /// Reject the addresses of a role rather than a person, like `noreply@` or `postmaster@`.
+    #[serde(default = "default_true")]
+    pub reject_role_addresses: bool,
+    /// Domains accepted even if they are in the list of disposable domains.
+    #[serde(default)]
+    pub allowed_domains: Vec<String>,
+    /// Domains always rejected, their subdomains included.
+    #[serde(default)]
+    pub denied_domains: Vec<String>,
+    #[serde(default)]
+    pub domain_check: DomainCheckSettings,
+    /// Minimum number of characters of the names.
+    #[serde(default = "default_min_name_length")]
+    pub min_name_length: usize,
+    /// Scripts the names must be written in, like `latin` or `cyrillic`, any script is accepted
+    /// if empty.
+    #[serde(default)]
+    pub allowed_name_scripts: Vec<Script>,
+    /// Hours after which a confirmation link expires, the links never expire if unset.
+    #[serde(default)]
+    pub confirmation_link_ttl_hours: Option<u64>,
+    #[serde(default)]
+    pub confirmation_pages: ConfirmationPagesSettings,
+    /// Topics the subscribers can choose in the preference centre.
+    #[serde(default)]
+    pub topics: Vec<Topic>,
+    /// Hours between two digests, sent to the subscribers who chose them.
+    #[serde(default = "default_digest_interval_hours")]
+    pub digest_interval_hours: u64,
+    #[serde(default)]
+    pub hosted_pages: HostedPagesSettings,
+    #[serde(default)]
+    pub human_verification: HumanVerificationSettings,
+}
+
+/// A topic of the newsletter, its `id` is stored with the choices of the subscribers.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+pub struct Topic {
+    pub id: String,
+    pub name: String,
+}
+
+/// The theme of the subscription form and of the preference centre.
+#[derive(Clone, Default, Deserialize, PartialEq)]
+pub struct HostedPagesSettings {
+    /// HTML file wrapping the pages, `{{title}}` and `{{content}}` are replaced by the title and
+    /// the content of the page. It is read at startup.
+    #[serde(default)]
+    pub template: Option<PathBuf>,
+    /// Stylesheet linked by the built-in layout.
+    #[serde(default)]
+    pub stylesheet_url: Option<String>,
+}
+
+/// How `POST /subscriptions` tells the humans from the scripts.
+#[derive(Clone, Default, Deserialize, PartialEq)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum HumanVerificationSettings {
+    /// Every submission is accepted.
+    #[default]
+    Disabled,
+    /// The `website` field must be left empty and the form token, issued by
+    /// `GET /subscriptions/form_token`, must be between `min_seconds` and `max_age_seconds` old.
+    Honeypot {
+        #[serde(default = "default_honeypot_min_seconds")]
+        min_seconds: u64,
+        #[serde(default = "default_honeypot_max_age_seconds")]
+        max_age_seconds: u64,
+    },
+    /// The response of a CAPTCHA widget is checked with the `verify_url` of its provider, like
+    /// hCaptcha or Turnstile.
+    Captcha {
+        verify_url: String,
+        #[serde(default)]
+        secret: Secret,
+        #[serde(default = "default_captcha_timeout_milliseconds")]
+        timeout_milliseconds: u64,
+    },
+}
+
+/// The pages shown to the browsers following a confirmation link, the JSON clients always get
+/// JSON.
+#[derive(Clone, Default, Deserialize, PartialEq)]
+pub struct ConfirmationPagesSettings {
+    #[serde(default)]
+    pub confirmed: PageSettings,
+    #[serde(default)]
+    pub already_confirmed: PageSettings,
+    #[serde(default)]
+    pub expired: PageSettings,
+    /// The token is malformed, unknown or belongs to a suppressed or unsubscribed subscriber.
+    #[serde(default)]
+    pub invalid: PageSettings,
+}
+
+/// A built-in page unless one of the fields is set.
+#[derive(Clone, Default, Deserialize, PartialEq)]
+pub struct PageSettings {
+    /// HTML file served instead of the built-in page, it is read at startup.
+    #[serde(default)]
+    pub template: Option<PathBuf>,
+    /// External page the browsers are redirected to with a 303.
+    #[serde(default)]
+    pub redirect_url: Option<String>,
+}
+
+/// Reject the addresses whose domain has no mail server, the resolver is only queried if
+/// `enabled` is set.
+#[derive(Clone, Deserialize, PartialEq)]
+pub struct DomainCheckSettings {
+    #[serde(default)]
+    pub enabled: bool,
+    /// The address is accepted if the resolver does not answer in time.
+    #[serde(default = "default_domain_check_timeout_milliseconds")]
+    pub timeout_milliseconds: u64,
+    #[serde(default = "default_domain_check_cache_ttl_seconds")]
+    pub cache_ttl_seconds: u64,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct WebhookSettings {
+    /// Number of soft bounces after which a subscriber is suppressed.
+    pub soft_bounce_threshold: u32,
+    pub postmark: BasicCredentials,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct TrackingSettings {
+    /// Key of the HMAC signing the open and click tracking links.
+    pub signing_key: Secret,
+}
+
+/// Credentials of the HTTP basic authentication.
+#[derive(Clone, Deserialize)]
+pub struct BasicCredentials {
+    pub username: String,
+    pub password: Secret,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct TelemetrySettings {
+    /// Directives of the log filter, `RUST_LOG` takes precedence at startup.
+    #[serde(default = "default_log_filter")]
+    pub log_filter: String,
+    #[serde(default)]
+    pub format: LogFormat,
+    #[serde(default)]
+    pub redaction: RedactionSettings,
+}
+
+/// Output format of the logs.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// One JSON object per line, for log aggregators.
+    #[default]
+    Bunyan,
+    /// Multi-line human readable output with colours.
+    Pretty,
+    /// Single-line human readable output.
+    Compact,
+    /// `key=value` pairs, one record per line.
+    Logfmt,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct RedactionSettings {
+    #[serde(default)]
+    pub mode: RedactionMode,
+    /// Names of the fields holding personal data, they are redacted in every log format.
+    #[serde(default = "default_pii_fields")]
+    pub fields: Vec<String>,
+    /// Key of the HMAC used by the `hash` mode.
+    #[serde(default)]
+    pub hash_key: Option<Secret>,
+}
+
+/// How personal data is written in the logs.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum RedactionMode {
+    /// Values are written in clear.
+    None,
+    /// Values are replaced by a fixed placeholder.
+    #[default]
+    Mask,
+    /// Values are replaced by a keyed hash, the same value always gets the same hash so that
+    /// records can still be correlated.
+    Hash,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigurationError {
+    #[error("{0} is not a valid environment, use either `local` or `production`")]
+    InvalidEnvironment(String),
+    #[error(transparent)]
+    Config(#[from] config::ConfigError),
+    #[error(transparent)]
+    Secret(#[from] SecretProviderError),
+}
+
+impl DatabaseSettings {
+    pub fn without_db(&self) -> PgConnectOptions {
+        let ssl_mode = if self.require_ssl {
+            PgSslMode::Require
+        } else {
+            PgSslMode::Prefer
+        };
+
+        PgConnectOptions::new()
+            .host(&self.host)
+            .port(self.port)
+            .ssl_mode(ssl_mode)
+            .username(&self.username)
+            .password(self.password.expose())
+    }
+
+    pub fn with_db(&self) -> PgConnectOptions {
+        let mut options = self.without_db().database(&self.name);
+        options.log_statements(log::LevelFilter::Trace);
+        options
+    }
+}
+
+impl ApplicationSettings {
+    pub fn address(&self) -> String {
+        format!("{}:{}", self.host, self.port)
+    }
+}
+
+impl EmailClientSettings {
+    pub fn sender(&self) -> Result<EmailAddress, domain::email_address::ParseEmailAddressError> {
+        self.sender_email.parse()
+    }
+
+    pub fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_milliseconds)
+    }
+
+    pub fn policy(&self) -> EmailClientPolicy {
+        EmailClientPolicy {
+            timeout: self.timeout(),
+            max_retries: self.max_retries,
+            retry_backoff: Duration::from_millis(self.retry_backoff_milliseconds),
+            max_messages_per_second: self.max_messages_per_second,
+            fallback_max_messages_per_second: self
+                .fallback_providers
+                .iter()
+                .map(|provider| (provider.name.clone(), provider.max_messages_per_second))
+                .collect(),
+            circuit_breaker: self.circuit_breaker.policy(),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum EmailClientSettingsError {
+    #[error("invalid sender email address")]
+    Sender(#[from] domain::email_address::ParseEmailAddressError),
+    #[error("invalid settings for the email provider `{0}`")]
+    Provider(String, #[source] EmailClientError),
+}
+
+impl EmailClientSettings {
+    /// Build the client sending through all the configured providers.
+    pub fn client(&self) -> Result<EmailClient, EmailClientSettingsError> {
+        let client = EmailClient::new(
+            &self.base_url,
+            self.sender()?,
+            self.authorization_token.clone(),
+            self.policy(),
+        );
+        self.fallback_providers
+            .iter()
+            .try_fold(client, |client, provider| {
+                let transport = provider.transport.transport().map_err(|error| {
+                    EmailClientSettingsError::Provider(provider.name.clone(), error)
+                })?;
+                Ok(client.with_fallback(
+                    &provider.name,
+                    transport,
+                    provider.max_messages_per_second,
+                ))
+            })
+    }
+}
+
+impl TransportSettings {
+    /// The transport to the provider, the SMTP relays get their pool of connections.
+    pub fn transport(&self) -> Result<Transport, EmailClientError> {
+        Ok(match self {
+            TransportSettings::Postmark {
+                base_url,
+                authorization_token,
+            } => Transport::Postmark(Postmark::new(base_url, authorization_token.clone())),
+            TransportSettings::Smtp {
+                host,
+                port,
+                tls,
+                username,
+                password,
+            } => Transport::Smtp(SmtpRelay::new(
+                host,
+                *port,
+                *tls,
+                username.clone().zip(password.clone()),
+            )?),
+        })
+    }
+}
+
+impl SubscriptionSettings {
+    /// The rules of the subscription policy, the list of disposable domains is read from
+    /// `disposable_domains_file` if set.
+    pub fn rules(&self) -> Result<PolicyRules, std::io::Error> {
+        let disposable_domains = match (
+            &self.disposable_domains_file,
+            self.reject_disposable_domains,
+        ) {
+            (_, false) => Default::default(),
+            (Some(path), true) => {
+                subscription_policy::parse_domain_list(&std::fs::read_to_string(path)?)
+            }
+            (None, true) => subscription_policy::bundled_disposable_domains(),
+        };
+        let domains = |list: &[String]| {
+            list.iter()
+                .map(|domain| subscription_policy::normalise_domain(domain))
+                .collect()
+        };
+        Ok(PolicyRules {
+            disposable_domains,
+            reject_role_addresses: self.reject_role_addresses,
+            allowed_domains: domains(&self.allowed_domains),
+            denied_domains: domains(&self.denied_domains),
+            min_name_length: self.min_name_length,
+            allowed_name_scripts: self.allowed_name_scripts.iter().copied().collect(),
+        })
+    }
+
+    pub fn confirmation_link_ttl(&self) -> Option<chrono::Duration> {
+        self.confirmation_link_ttl_hours
+            .map(|hours| chrono::Duration::hours(hours as i64))
+    }
+
+    pub fn digest_interval(&self) -> Duration {
+        Duration::from_secs(self.digest_interval_hours * 3600)
+    }
+}
+
+impl Settings {
+    /// Replace the secret of `key`, one of `SECRET_KEYS`. The CAPTCHA secret is ignored when the
+    /// CAPTCHA is not used.
+    fn set_secret(&mut self, key: &str, secret: Secret) {
+        match key {
+            "application.admin_token" => self.application.admin_token = secret,
+            "database.password" => self.database.password = secret,
+            "email_client.authorization_token" => self.email_client.authorization_token = secret,
+            "subscriptions.human_verification.secret" => {
+                if let HumanVerificationSettings::Captcha {
+                    secret: current, ..
+                } = &mut self.subscriptions.human_verification
+                {
+                    *current = secret;
+                }
+            }
+            "telemetry.redaction.hash_key" => self.telemetry.redaction.hash_key = Some(secret),
+            "tracking.signing_key" => self.tracking.signing_key = secret,
+            "webhooks.postmark.password" => self.webhooks.postmark.password = secret,
+            _ => unreachable!("`{}` is not a secret", key),
+        }
+    }
+
+    /// Replace the secret of `key`, one of `SECRET_ITEM_KEYS`, for the fallback provider at
+    /// `index`. The secret of another transport is ignored.
+    fn set_item_secret(&mut self, key: &str, index: usize, secret: Secret) {
+        let transport = &mut self.email_client.fallback_providers[index].transport;
+        match (key, transport) {
+            (
+                "email_client.fallback_providers.*.authorization_token",
+                TransportSettings::Postmark {
+                    authorization_token,
+                    ..
+                },
+            ) => *authorization_token = secret,
+            (
+                "email_client.fallback_providers.*.password",
+                TransportSettings::Smtp { password, .. },
+            ) => *password = Some(secret),
+            (
+                "email_client.fallback_providers.*.authorization_token"
+                | "email_client.fallback_providers.*.password",
+                _,
+            ) => {}
+            _ => unreachable!("`{}` is not a secret of the list items", key),
+        }
+    }
+
+    /// Move the secrets found by `provider` into the settings, they take precedence over the
+    /// configured ones.
+    fn set_secrets(&mut self, provider: &dyn SecretProvider) -> Result<(), ConfigurationError> {
+        for key in SECRET_KEYS {
+            if let Some(provided) = provider.get(key)? {
+                self.set_secret(key, provided.secret);
+            }
+        }
+        for (index, pattern, key) in secret_item_keys(self.email_client.fallback_providers.len()) {
+            if let Some(provided) = provider.get(&key)? {
+                self.set_item_secret(pattern, index, provided.secret);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The keys of `SECRET_ITEM_KEYS` for the first `providers` fallback providers, with the index
+/// of the provider and the pattern of the key.
+fn secret_item_keys(providers: usize) -> impl Iterator<Item = (usize, &'static str, String)> {
+    (0..providers).flat_map(|index| {
+        SECRET_ITEM_KEYS
+            .iter()
+            .map(move |pattern| (index, *pattern, pattern.replace('*', &index.to_string())))
+    })
+}
+
+impl DomainCheckSettings {
+    /// A checker querying `resolver`, or `None` if the check is disabled.
+    pub fn checker(&self, resolver: Arc<dyn DomainResolver>) -> Option<DomainChecker> {
+        self.enabled.then(|| {
+            DomainChecker::new(
+                resolver,
+                Duration::from_millis(self.timeout_milliseconds),
+                Duration::from_secs(self.cache_ttl_seconds),
+            )
+        })
+    }
+}
+
+impl HumanVerificationSettings {
+    /// The verifier of the subscriptions, or `None` if the verification is disabled. The form
+    /// tokens are signed with `key`.
+    pub fn verifier(&self, key: &TrackingKey) -> Option<Arc<dyn HumanVerifier>> {
+        match self {
+            HumanVerificationSettings::Disabled => None,
+            HumanVerificationSettings::Honeypot {
+                min_seconds,
+                max_age_seconds,
+            } => Some(Arc::new(HoneypotVerifier::new(
+                key.clone(),
+                Duration::from_secs(*min_seconds),
+                Duration::from_secs(*max_age_seconds),
+            ))),
+            HumanVerificationSettings::Captcha {
+                verify_url,
+                secret,
+                timeout_milliseconds,
+            } => Some(Arc::new(CaptchaVerifier::new(
+                verify_url,
+                secret.clone(),
+                Duration::from_millis(*timeout_milliseconds),
+            ))),
+        }
+    }
+}
+
+impl CircuitBreakerSettings {
+    pub fn policy(&self) -> CircuitBreakerPolicy {
+        CircuitBreakerPolicy {
+            failure_rate_threshold: self.failure_rate_threshold,
+            window_size: self.window_size,
+            open_duration: Duration::from_millis(self.open_duration_milliseconds),
+            half_open_probes: self.half_open_probes,
+        }
+    }
+}
+
+impl Default for CircuitBreakerSettings {
+    fn default() -> Self {
+        Self {
+            failure_rate_threshold: default_failure_rate_threshold(),
+            window_size: default_window_size(),
+            open_duration_milliseconds: default_open_duration_milliseconds(),
+            half_open_probes: default_half_open_probes(),
+        }
+    }
+}
+
+impl Default for SubscriptionSettings {
+    fn default() -> Self {
+        Self {
+            reject_disposable_domains: true,
+            disposable_domains_file: None,
+            reject_role_addresses: true,
+            allowed_domains: Vec::new(),
+            denied_domains: Vec::new(),
+            domain_check: DomainCheckSettings::default(),
+            min_name_length: default_min_name_length(),
+            allowed_name_scripts: Vec::new(),
+            confirmation_link_ttl_hours: None,
+            confirmation_pages: ConfirmationPagesSettings::default(),
+            topics: Vec::new(),
+            digest_interval_hours: default_digest_interval_hours(),
+            hosted_pages: HostedPagesSettings::default(),
+            human_verification: HumanVerificationSettings::default(),
+        }
+    }
+}
+
+impl Default for DomainCheckSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            timeout_milliseconds: default_domain_check_timeout_milliseconds(),
+            cache_ttl_seconds: default_domain_check_cache_ttl_seconds(),
+        }
+    }
+}
+
+impl Default for TelemetrySettings {
+    fn default() -> Self {
+        Self {
+            log_filter: default_log_filter(),
+            format: LogFormat::default(),
+            redaction: RedactionSettings::default(),
+        }
+    }
+}
+
+impl Default for RedactionSettings {
+    fn default() -> Self {
+        Self {
+            mode: RedactionMode::default(),
+            fields: default_pii_fields(),
+            hash_key: None,
+        }
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_min_name_length() -> usize {
+    1
+}
+
+fn default_digest_interval_hours() -> u64 {
+    24
+}
+
+fn default_domain_check_timeout_milliseconds() -> u64 {
+    2000
+}
+
+fn default_domain_check_cache_ttl_seconds() -> u64 {
+    3600
+}
+
+fn default_honeypot_min_seconds() -> u64 {
+    3
+}
+
+fn default_honeypot_max_age_seconds() -> u64 {
+    3600
+}
+
+fn default_captcha_timeout_milliseconds() -> u64 {
+    5000
+}
+
+fn default_failure_rate_threshold() -> f64 {
+    0.5
+}
+
+fn default_window_size() -> u32 {
+    10
+}
+
+fn default_open_duration_milliseconds() -> u64 {
+    30000
+}
+
+fn default_half_open_probes() -> u32 {
+    3
+}
+
+fn default_log_filter() -> String {
+    "info".into()
+}
+
+fn default_pii_fields() -> Vec<String> {
+    [
+        "email",
+        "subscriber_email",
+        "name",
+        "subscriber_name",
+        "token",
+        "subscription_token",
+    ]
+    .into_iter()
+    .map(String::from)
+    .collect()
+}
+
+pub fn get_configuration() -> Result<Settings, ConfigurationError> {
+    get_configuration_with_secrets(&EnvFileSecretProvider::new())
+}
+
+/// Read the configuration, the secrets found by `provider` take precedence over all the other
+/// sources.
+pub fn get_configuration_with_secrets(
+    provider: &dyn SecretProvider,
+) -> Result<Settings, ConfigurationError> {
+    let environment = get_environment()?;
+
+    let sources: Vec<_> = sources(environment)
+        .into_iter()
+        .map(|(_, source)| source)
+        .collect();
+    let mut settings: Settings = config::Config::builder()
+        .add_source(sources)
+        .build()?
+        .try_deserialize()?;
+    // The secrets are moved into the settings rather than merged with the other sources, they
+    // are never copied out of a `Secret`
+    settings.set_secrets(provider)?;
+    Ok(settings)
+}
+
+/// Read the running environment from `APP_ENVIRONMENT`, it defaults to `local`.
+pub fn get_environment() -> Result<Environment, ConfigurationError> {
+    std::env::var("APP_ENVIRONMENT")
+        .unwrap_or_else(|_| "local".into())
+        .try_into()
+        .map_err(ConfigurationError::InvalidEnvironment)
+}
+
+/// The sources of the configuration, sorted by increasing priority.
+fn sources(environment: Environment) -> Vec<(String, Box<dyn config::Source + Send + Sync>)> {
+    let configuration_directory = configuration_directory();
+    let file = |name: &str| -> (String, Box<dyn config::Source + Send + Sync>) {
+        (
+            format!("configuration/{}.yaml", name),
+            Box::new(config::File::from(configuration_directory.join(name)).required(true)),
+        )
+    };
+
+    vec![
+        file("base"),
+        file(environment.as_str()),
+        (
+            "environment".into(),
+            Box::new(config::Environment::with_prefix("app").separator("__")),
+        ),
+    ]
+}
+
+pub(crate) fn configuration_directory() -> PathBuf {
+    let base_path = std::env::current_dir().expect("Failed to determine the current directory");
+    base_path.join("configuration")
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Environment {
+    Local,
+    Production,
+}
+
+impl Environment {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Environment::Local => "local",
+            Environment::Production => "production",
+        }
+    }
+}
+
+impl TryFrom<String> for Environment {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.to_lowercase().as_str() {
+            "local" => Ok(Environment::Local),
+            "production" => Ok(Environment::Production),
+            other => Err(other.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) fn test_settings() -> Settings {
+    Settings {
+        database: DatabaseSettings {
+            host: "127.0.0.1".into(),
+            port: 5432,
+            username: "postgres".into(),
+            password: Secret::new("password".into()),
+            name: "newsletter".into(),
+            require_ssl: false,
+            backend: DatabaseBackend::Postgres,
+        },
+        application: ApplicationSettings {
+            host: "127.0.0.1".into(),
+            port: 8000,
+            base_url: "http://127.0.0.1".into(),
+            admin_token: Secret::new("my-admin-token".into()),
+        },
+        email_client: EmailClientSettings {
+            base_url: "http://localhost".into(),
+            sender_email: "test@gmail.com".into(),
+            authorization_token: Secret::new("my-secret-token".into()),
+            timeout_milliseconds: 10000,
+            max_retries: 0,
+            retry_backoff_milliseconds: 0,
+            max_messages_per_second: None,
+            circuit_breaker: CircuitBreakerSettings::default(),
+            fallback_providers: Vec::new(),
+        },
+        telemetry: TelemetrySettings::default(),
+        webhooks: WebhookSettings {
+            soft_bounce_threshold: 3,
+            postmark: BasicCredentials {
+                username: "postmark".into(),
+                password: Secret::new("my-webhook-password".into()),
+            },
+        },
+        tracking: TrackingSettings {
+            signing_key: Secret::new("my-tracking-key".into()),
+        },
+        subscriptions: SubscriptionSettings::default(),
+    }
+}
@@ -0,0 +1,594 @@
+use std::{path::Path, time::Duration};
+
+use notify::{RecursiveMode, Watcher};
+use tokio::sync::mpsc;
+
+use super::{
+    configuration_directory, get_configuration, ConfigurationError, Environment, ProviderSettings,
+    Settings, ValidationErrors,
+};
+use crate::{
+    email_client::EmailClient, routes::webhooks::email::SoftBounceThreshold, secret::Secret,
+    subscription_policy::SubscriptionPolicy, telemetry::LogFilterHandle,
+};
+
+/// Changes on the file system are collected for this time before reloading.
+const DEBOUNCE: Duration = Duration::from_millis(250);
+
+#[derive(Debug, thiserror::Error)]
+pub enum ReloadError {
+    #[error(transparent)]
+    Configuration(#[from] ConfigurationError),
+    #[error(transparent)]
+    Validation(#[from] ValidationErrors),
+    #[error("failed to read the list of disposable domains")]
+    DisposableDomains(#[source] std::io::Error),
+}
+
+/// Apply the settings that are safe to change while the application is running.
+///
+/// Only the log filter, the delivery policy of the email client, including the rate of every
+/// provider, the subscription policy and the soft bounce threshold are reloaded. Any change to the
+/// other settings is ignored with a warning since it requires a restart.
+pub struct Reloader {
+    current: Settings,
+    environment: Environment,
+    log_filter: Option<LogFilterHandle>,
+    email_client: EmailClient,
+    subscription_policy: SubscriptionPolicy,
+    soft_bounce_threshold: SoftBounceThreshold,
+}
+
+impl Reloader {
+    /// Create a new `Reloader`, the log filter is not reloaded if `log_filter` is `None`.
+    pub fn new(
+        current: Settings,
+        environment: Environment,
+        log_filter: Option<LogFilterHandle>,
+        email_client: EmailClient,
+        subscription_policy: SubscriptionPolicy,
+        soft_bounce_threshold: SoftBounceThreshold,
+    ) -> Self {
+        Self {
+            current,
+            environment,
+            log_filter,
+            email_client,
+            subscription_policy,
+            soft_bounce_threshold,
+        }
+    }
+
+    /// Read the configuration again and apply it.
+    #[tracing::instrument(name = "Reload the configuration", skip(self))]
+    pub fn reload(&mut self) -> Result<(), ReloadError> {
+        let settings = get_configuration()?;
+        self.apply(settings)
+    }
+
+    /// Validate `settings` and apply all the safe changes at once, nothing is applied on error.
+    pub fn apply(&mut self, mut settings: Settings) -> Result<(), ReloadError> {
+        settings.validate(self.environment)?;
+        let subscription_rules = settings
+            .subscriptions
+            .rules()
+            .map_err(ReloadError::DisposableDomains)?;
+
+        for key in requiring_restart(&self.current, &settings) {
+            tracing::warn!(
+                key,
+                "The setting cannot be changed without a restart, ignored"
+            );
+        }
+
+        // The providers are built at startup, the policy keeps the current ones until a restart
+        if !same_providers(
+            &self.current.email_client.fallback_providers,
+            &settings.email_client.fallback_providers,
+        ) {
+            settings.email_client.fallback_providers =
+                self.current.email_client.fallback_providers.clone();
+        }
+        let policy = settings.email_client.policy();
+        if policy != self.email_client.policy() {
+            tracing::info!(?policy, "Email client policy updated");
+            self.email_client.set_policy(policy);
+        }
+        self.current.email_client.timeout_milliseconds = settings.email_client.timeout_milliseconds;
+        self.current.email_client.max_retries = settings.email_client.max_retries;
+        self.current.email_client.retry_backoff_milliseconds =
+            settings.email_client.retry_backoff_milliseconds;
+        self.current.email_client.max_messages_per_second =
+            settings.email_client.max_messages_per_second;
+        self.current.email_client.circuit_breaker = settings.email_client.circuit_breaker;
+        self.current.email_client.fallback_providers = settings.email_client.fallback_providers;
+
+        if subscription_rules != self.subscription_policy.rules() {
+            tracing::info!(
+                disposable_domains = subscription_rules.disposable_domains.len(),
+                allowed_domains = subscription_rules.allowed_domains.len(),
+                denied_domains = subscription_rules.denied_domains.len(),
+                reject_role_addresses = subscription_rules.reject_role_addresses,
+                min_name_length = subscription_rules.min_name_length,
+                allowed_name_scripts = ?subscription_rules.allowed_name_scripts,
+                "Subscription policy updated"
+            );
+            self.subscription_policy.set_rules(subscription_rules);
+        }
+        let subscriptions = &self.current.subscriptions;
+        let domain_check = subscriptions.domain_check.clone();
+        let confirmation_link_ttl_hours = subscriptions.confirmation_link_ttl_hours;
+        let confirmation_pages = subscriptions.confirmation_pages.clone();
+        let topics = subscriptions.topics.clone();
+        let digest_interval_hours = subscriptions.digest_interval_hours;
+        let hosted_pages = subscriptions.hosted_pages.clone();
+        let human_verification = subscriptions.human_verification.clone();
+        self.current.subscriptions = settings.subscriptions;
+        self.current.subscriptions.domain_check = domain_check;
+        self.current.subscriptions.confirmation_link_ttl_hours = confirmation_link_ttl_hours;
+        self.current.subscriptions.confirmation_pages = confirmation_pages;
+        self.current.subscriptions.topics = topics;
+        self.current.subscriptions.digest_interval_hours = digest_interval_hours;
+        self.current.subscriptions.hosted_pages = hosted_pages;
+        self.current.subscriptions.human_verification = human_verification;
+
+        let soft_bounce_threshold = settings.webhooks.soft_bounce_threshold;
+        if soft_bounce_threshold != self.soft_bounce_threshold.get() {
+            tracing::info!(soft_bounce_threshold, "Soft bounce threshold updated");
+            self.soft_bounce_threshold.set(soft_bounce_threshold);
+        }
+        self.current.webhooks.soft_bounce_threshold = soft_bounce_threshold;
+
+        if let Some(log_filter) = &self.log_filter {
+            if settings.telemetry.log_filter != self.current.telemetry.log_filter {
+                match log_filter.set(&settings.telemetry.log_filter) {
+                    Ok(_) => tracing::info!(
+                        filter = %settings.telemetry.log_filter,
+                        "Log filter updated"
+                    ),
+                    Err(error) => tracing::error!(
+                        error.cause_chain = ?error,
+                        "Failed to update the log filter"
+                    ),
+                }
+            }
+        }
+        self.current.telemetry.log_filter = settings.telemetry.log_filter;
+
+        Ok(())
+    }
+
+    /// Reload the configuration when a file in `configuration/` or the list of disposable domains
+    /// changes, or a `SIGHUP` is received.
+    pub async fn watch(mut self) {
+        let (sender, mut receiver) = mpsc::channel(1);
+
+        let file_sender = sender.clone();
+        let watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+            if event.is_ok_and(|event| !event.kind.is_access()) {
+                let _ = file_sender.try_send(());
+            }
+        })
+        .and_then(|mut watcher| {
+            watcher.watch(&configuration_directory(), RecursiveMode::NonRecursive)?;
+            // The directory is watched since editors often replace the file instead of writing it
+            let list_directory = self
+                .current
+                .subscriptions
+                .disposable_domains_file
+                .as_deref()
+                .and_then(Path::parent)
+                .map(|directory| {
+                    directory
+                        .canonicalize()
+                        .unwrap_or_else(|_| directory.into())
+                });
+            if let Some(directory) = list_directory {
+                if directory != configuration_directory() {
+                    watcher.watch(&directory, RecursiveMode::NonRecursive)?;
+                }
+            }
+            Ok(watcher)
+        });
+        let _watcher = match watcher {
+            Ok(watcher) => Some(watcher),
+            Err(error) => {
+                tracing::error!(
+                    error.cause_chain = ?error,
+                    "Failed to watch the configuration directory"
+                );
+                None
+            }
+        };
+
+        #[cfg(unix)]
+        {
+            use tokio::signal::unix::{signal, SignalKind};
+
+            match signal(SignalKind::hangup()) {
+                Ok(mut hangup) => {
+                    tokio::spawn(async move {
+                        while hangup.recv().await.is_some() {
+                            let _ = sender.send(()).await;
+                        }
+                    });
+                }
+                Err(error) => {
+                    tracing::error!(error.cause_chain = ?error, "Failed to listen for SIGHUP");
+                }
+            }
+        }
+
+        while receiver.recv().await.is_some() {
+            tokio::time::sleep(DEBOUNCE).await;
+            while receiver.try_recv().is_ok() {}
+
+            if let Err(error) = self.reload() {
+                tracing::error!(
+                    error.cause_chain = ?error,
+                    error.message = %error,
+                    "Configuration reload rejected"
+                );
+            }
+        }
+    }
+}
+
+/// The keys of the settings changed between `current` and `new` that require a restart.
+fn requiring_restart(current: &Settings, new: &Settings) -> Vec<&'static str> {
+    let changes = [
+        (
+            "application.host",
+            current.application.host != new.application.host,
+        ),
+        (
+            "application.port",
+            current.application.port != new.application.port,
+        ),
+        (
+            "application.base_url",
+            current.application.base_url != new.application.base_url,
+        ),
+        (
+            "application.admin_token",
+            current.application.admin_token.expose() != new.application.admin_token.expose(),
+        ),
+        ("database.host", current.database.host != new.database.host),
+        ("database.port", current.database.port != new.database.port),
+        (
+            "database.username",
+            current.database.username != new.database.username,
+        ),
+        (
+            "database.password",
+            current.database.password.expose() != new.database.password.expose(),
+        ),
+        ("database.name", current.database.name != new.database.name),
+        (
+            "database.require_ssl",
+            current.database.require_ssl != new.database.require_ssl,
+        ),
+        (
+            "database.backend",
+            current.database.backend != new.database.backend,
+        ),
+        (
+            "email_client.base_url",
+            current.email_client.base_url != new.email_client.base_url,
+        ),
+        (
+            "email_client.sender_email",
+            current.email_client.sender_email != new.email_client.sender_email,
+        ),
+        (
+            "email_client.authorization_token",
+            current.email_client.authorization_token.expose()
+                != new.email_client.authorization_token.expose(),
+        ),
+        (
+            "email_client.fallback_providers",
+            !same_providers(
+                &current.email_client.fallback_providers,
+                &new.email_client.fallback_providers,
+            ),
+        ),
+        (
+            "subscriptions.domain_check",
+            current.subscriptions.domain_check != new.subscriptions.domain_check,
+        ),
+        (
+            "subscriptions.confirmation_link_ttl_hours",
+            current.subscriptions.confirmation_link_ttl_hours
+                != new.subscriptions.confirmation_link_ttl_hours,
+        ),
+        (
+            "subscriptions.confirmation_pages",
+            current.subscriptions.confirmation_pages != new.subscriptions.confirmation_pages,
+        ),
+        (
+            "subscriptions.topics",
+            current.subscriptions.topics != new.subscriptions.topics,
+        ),
+        (
+            "subscriptions.digest_interval_hours",
+            current.subscriptions.digest_interval_hours != new.subscriptions.digest_interval_hours,
+        ),
+        (
+            "subscriptions.hosted_pages",
+            current.subscriptions.hosted_pages != new.subscriptions.hosted_pages,
+        ),
+        (
+            "subscriptions.human_verification",
+            current.subscriptions.human_verification != new.subscriptions.human_verification,
+        ),
+        (
+            "webhooks.postmark.username",
+            current.webhooks.postmark.username != new.webhooks.postmark.username,
+        ),
+        (
+            "webhooks.postmark.password",
+            current.webhooks.postmark.password.expose() != new.webhooks.postmark.password.expose(),
+        ),
+        (
+            "tracking.signing_key",
+            current.tracking.signing_key.expose() != new.tracking.signing_key.expose(),
+        ),
+        (
+            "telemetry.format",
+            current.telemetry.format != new.telemetry.format,
+        ),
+        (
+            "telemetry.redaction",
+            current.telemetry.redaction.mode != new.telemetry.redaction.mode
+                || current.telemetry.redaction.fields != new.telemetry.redaction.fields
+                || current
+                    .telemetry
+                    .redaction
+                    .hash_key
+                    .as_ref()
+                    .map(Secret::expose)
+                    != new
+                        .telemetry
+                        .redaction
+                        .hash_key
+                        .as_ref()
+                        .map(Secret::expose),
+        ),
+    ];
+
+    changes
+        .into_iter()
+        .filter_map(|(key, changed)| changed.then_some(key))
+        .collect()
+}
+
+/// Whether the fallback providers are the same but for their rate, which is reloaded.
+fn same_providers(current: &[ProviderSettings], new: &[ProviderSettings]) -> bool {
+    current.len() == new.len()
+        && current
+            .iter()
+            .zip(new)
+            .all(|(current, new)| current.name == new.name && current.transport == new.transport)
+}
+
+#[cfg(test)]
+mod tests {
+    use claim::{assert_err, assert_ok};
+
+    use super::{requiring_restart, Reloader};
+    use crate::{
+        configuration::{
+            test_settings, Environment, LogFormat, ProviderSettings, Settings, TransportSettings,
+        },
+        email_client::PRIMARY_PROVIDER,
+        routes::webhooks::email::SoftBounceThreshold,
+        secret::Secret,
+        subscription_policy::{Rejection, SubscriptionPolicy},
+    };
+
+    fn reloader() -> Reloader {
+        reloader_with(test_settings())
+    }
+
+    fn reloader_with(settings: Settings) -> Reloader {
+        let email_client = settings.email_client.client().unwrap();
+        let subscription_policy = SubscriptionPolicy::new(settings.subscriptions.rules().unwrap());
+        let soft_bounce_threshold =
+            SoftBounceThreshold::new(settings.webhooks.soft_bounce_threshold);
+        Reloader::new(
+            settings,
+            Environment::Local,
+            None,
+            email_client,
+            subscription_policy,
+            soft_bounce_threshold,
+        )
+    }
+
+    #[test]
+    fn safe_settings_are_applied() {
+        let mut reloader = reloader();
+        let mut settings = test_settings();
+        settings.email_client.timeout_milliseconds = 500;
+        settings.email_client.max_retries = 3;
+        settings.email_client.max_messages_per_second = Some(10);
+        settings.email_client.circuit_breaker.half_open_probes = 5;
+        settings.webhooks.soft_bounce_threshold = 7;
+
+        assert_ok!(reloader.apply(settings));
+
+        let policy = reloader.email_client.policy();
+        assert_eq!(policy.timeout.as_millis(), 500);
+        assert_eq!(policy.max_retries, 3);
+        assert_eq!(
+            reloader
+                .email_client
+                .rate_limiter(PRIMARY_PROVIDER)
+                .unwrap()
+                .max_per_second(),
+            Some(10)
+        );
+        assert_eq!(
+            reloader
+                .email_client
+                .circuit_breaker(PRIMARY_PROVIDER)
+                .unwrap()
+                .policy()
+                .half_open_probes,
+            5
+        );
+        assert_eq!(reloader.soft_bounce_threshold.get(), 7);
+    }
+
+    /// The test settings with a fallback provider named `backup`.
+    fn settings_with_a_fallback(max_messages_per_second: Option<u32>) -> Settings {
+        let mut settings = test_settings();
+        settings.email_client.fallback_providers = vec![ProviderSettings {
+            name: "backup".into(),
+            max_messages_per_second,
+            transport: TransportSettings::Postmark {
+                base_url: "http://127.0.0.1".into(),
+                authorization_token: Secret::new("backup-token".into()),
+            },
+        }];
+        settings
+    }
+
+    #[test]
+    fn the_rate_of_every_provider_is_applied() {
+        let mut reloader = reloader_with(settings_with_a_fallback(Some(5)));
+        let mut settings = settings_with_a_fallback(Some(20));
+        settings.email_client.max_messages_per_second = Some(10);
+
+        assert_eq!(
+            requiring_restart(&reloader.current, &settings),
+            Vec::<&str>::new()
+        );
+        assert_ok!(reloader.apply(settings));
+
+        for (provider, max_per_second) in [(PRIMARY_PROVIDER, Some(10)), ("backup", Some(20))] {
+            assert_eq!(
+                reloader
+                    .email_client
+                    .rate_limiter(provider)
+                    .unwrap()
+                    .max_per_second(),
+                max_per_second
+            );
+        }
+        assert_eq!(
+            reloader.current.email_client.fallback_providers[0].max_messages_per_second,
+            Some(20)
+        );
+    }
+
+    #[test]
+    fn another_fallback_provider_requires_a_restart() {
+        let current = settings_with_a_fallback(Some(5));
+        let mut new = settings_with_a_fallback(Some(5));
+        new.email_client.fallback_providers[0].name = "other".into();
+
+        assert_eq!(
+            requiring_restart(&current, &new),
+            vec!["email_client.fallback_providers"]
+        );
+    }
+
+    #[test]
+    fn the_current_providers_are_kept_until_a_restart() {
+        let mut reloader = reloader_with(settings_with_a_fallback(Some(5)));
+        let mut settings = settings_with_a_fallback(Some(20));
+        settings.email_client.fallback_providers[0].name = "other".into();
+
+        assert_ok!(reloader.apply(settings));
+
+        let policy = reloader.email_client.policy();
+        assert_eq!(
+            policy
+                .fallback_max_messages_per_second
+                .into_iter()
+                .collect::<Vec<_>>(),
+            vec![("backup".to_string(), Some(5))]
+        );
+        assert_eq!(
+            reloader
+                .email_client
+                .rate_limiter("backup")
+                .unwrap()
+                .max_per_second(),
+            Some(5)
+        );
+        assert_eq!(
+            reloader.current.email_client.fallback_providers[0].name,
+            "backup"
+        );
+    }
+
+    #[test]
+    fn the_list_of_disposable_domains_is_read_again() {
+        let mut reloader = reloader();
+        let email = "ursula@throwaway.example".parse().unwrap();
+        assert_ok!(reloader.subscription_policy.check(&email));
+
+        let path = std::env::temp_dir().join(format!("{}.txt", uuid::Uuid::new_v4()));
+        std::fs::write(&path, "throwaway.example\n").unwrap();
+        let mut settings = test_settings();
+        settings.subscriptions.disposable_domains_file = Some(path.clone());
+        let applied = reloader.apply(settings);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_ok!(applied);
+        assert_eq!(
+            reloader.subscription_policy.check(&email),
+            Err(Rejection::DisposableDomain)
+        );
+    }
+
+    #[test]
+    fn invalid_settings_are_not_applied() {
+        let mut reloader = reloader();
+        let mut settings = test_settings();
+        settings.email_client.max_retries = 3;
+        settings.email_client.timeout_milliseconds = 0;
+
+        assert_err!(reloader.apply(settings));
+        assert_eq!(reloader.email_client.policy().max_retries, 0);
+    }
+
+    #[test]
+    fn invalid_log_filters_are_not_applied() {
+        let mut reloader = reloader();
+        let mut settings = test_settings();
+        settings.email_client.max_retries = 3;
+        settings.telemetry.log_filter = "zero2prod=[".into();
+
+        assert_err!(reloader.apply(settings));
+        assert_eq!(reloader.email_client.policy().max_retries, 0);
+    }
+
+    #[test]
+    fn structural_changes_are_detected() {
+        let current = test_settings();
+        let mut new = test_settings();
+        new.application.port = 9000;
+        new.database.host = "db.example.com".into();
+        new.email_client.timeout_milliseconds = 500;
+        new.webhooks.soft_bounce_threshold = 7;
+        new.telemetry.format = LogFormat::Logfmt;
+
+        assert_eq!(
+            requiring_restart(&current, &new),
+            vec!["application.port", "database.host", "telemetry.format"]
+        );
+    }
+
+    #[test]
+    fn structural_changes_are_not_applied() {
+        let mut reloader = reloader();
+        let mut settings = test_settings();
+        settings.application.port = 9000;
+
+        assert_ok!(reloader.apply(settings));
+        assert_eq!(reloader.current.application.port, 8000);
+    }
+}
@@ -0,0 +1,252 @@
+use std::path::PathBuf;
+
+use crate::secret::Secret;
+
+/// A secret found by a `SecretProvider`.
+#[derive(Debug)]
+pub struct ProvidedSecret {
+    pub secret: Secret,
+    /// A description of where the secret was found, it is shown by `config print`.
+    pub origin: String,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SecretProviderError {
+    #[error("failed to read secret `{key}` from {}", path.display())]
+    Io {
+        key: String,
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+/// A backend where secrets are stored (environment, mounted files, vaults...).
+pub trait SecretProvider: Send + Sync {
+    /// Look up the secret associated to a configuration key (e.g. `database.password`),
+    /// it returns `None` if the provider does not know the secret.
+    fn get(&self, key: &str) -> Result<Option<ProvidedSecret>, SecretProviderError>;
+}
+
+/// Read secrets from environment variables or from the files they point to.
+///
+/// The variables are named like the configuration overrides: the prefix, `__`, then the key in
+/// upper case with `__` between the sections. The key `database.password` is read from the file
+/// `APP__DATABASE__PASSWORD_FILE` points to (as mounted by Docker or Kubernetes), or from
+/// `APP__DATABASE__PASSWORD`. The older names with a single `_` after the prefix, like
+/// `APP_DATABASE__PASSWORD`, are still read when the new ones are not set.
+pub struct EnvFileSecretProvider {
+    prefix: String,
+}
+
+impl EnvFileSecretProvider {
+    pub fn new() -> Self {
+        Self::with_prefix("APP")
+    }
+
+    pub fn with_prefix(prefix: &str) -> Self {
+        Self {
+            prefix: prefix.to_uppercase(),
+        }
+    }
+
+    /// The variables holding the secret of `key`, by decreasing priority.
+    fn variables(&self, key: &str) -> [String; 2] {
+        let key = key.to_uppercase().replace('.', "__");
+        [
+            format!("{}__{}", self.prefix, key),
+            format!("{}_{}", self.prefix, key),
+        ]
+    }
+}
+
+impl Default for EnvFileSecretProvider {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SecretProvider for EnvFileSecretProvider {
+    fn get(&self, key: &str) -> Result<Option<ProvidedSecret>, SecretProviderError> {
+        for variable in self.variables(key) {
+            let file_variable = format!("{}_FILE", variable);
+            if let Ok(path) = std::env::var(&file_variable) {
+                let path = PathBuf::from(path);
+                // The content goes straight into a `Secret`, so that it is zeroed when dropped
+                let mut secret = Secret::new(std::fs::read_to_string(&path).map_err(|source| {
+                    SecretProviderError::Io {
+                        key: key.to_string(),
+                        path: path.clone(),
+                        source,
+                    }
+                })?);
+                secret.trim_end_newlines();
+                return Ok(Some(ProvidedSecret {
+                    secret,
+                    origin: format!("file {} (from {})", path.display(), file_variable),
+                }));
+            }
+
+            if let Ok(secret) = std::env::var(&variable) {
+                return Ok(Some(ProvidedSecret {
+                    secret: Secret::new(secret),
+                    origin: format!("environment {}", variable),
+                }));
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claim::{assert_err, assert_none};
+    use uuid::Uuid;
+
+    use super::{EnvFileSecretProvider, ProvidedSecret, SecretProvider, SecretProviderError};
+    use crate::{
+        configuration::{
+            get_configuration_with_secrets, test_settings, ProviderSettings, TransportSettings,
+        },
+        email_client::SmtpTls,
+        secret::Secret,
+    };
+
+    /// The secret of every key is the key followed by `!`.
+    struct StubProvider;
+
+    impl SecretProvider for StubProvider {
+        fn get(&self, key: &str) -> Result<Option<ProvidedSecret>, SecretProviderError> {
+            Ok(Some(ProvidedSecret {
+                secret: Secret::new(format!("{}!", key)),
+                origin: "stub".into(),
+            }))
+        }
+    }
+
+    fn unique_prefix() -> String {
+        format!("TEST_{}", Uuid::new_v4().to_simple()).to_uppercase()
+    }
+
+    #[test]
+    fn the_provided_secrets_replace_the_configured_ones() {
+        let settings = get_configuration_with_secrets(&StubProvider).unwrap();
+
+        assert_eq!(settings.database.password.expose(), "database.password!");
+        assert_eq!(
+            settings.tracking.signing_key.expose(),
+            "tracking.signing_key!"
+        );
+        assert_eq!(
+            settings.telemetry.redaction.hash_key.unwrap().expose(),
+            "telemetry.redaction.hash_key!"
+        );
+    }
+
+    #[test]
+    fn the_secrets_of_every_fallback_provider_are_provided() {
+        let mut settings = test_settings();
+        settings.email_client.fallback_providers = vec![
+            ProviderSettings {
+                name: "backup".into(),
+                max_messages_per_second: None,
+                transport: TransportSettings::Postmark {
+                    base_url: "http://127.0.0.1".into(),
+                    authorization_token: Secret::new("backup-token".into()),
+                },
+            },
+            ProviderSettings {
+                name: "relay".into(),
+                max_messages_per_second: None,
+                transport: TransportSettings::Smtp {
+                    host: "127.0.0.1".into(),
+                    port: 25,
+                    tls: SmtpTls::None,
+                    username: Some("newsletter".into()),
+                    password: None,
+                },
+            },
+        ];
+
+        settings.set_secrets(&StubProvider).unwrap();
+
+        let secrets: Vec<_> = settings
+            .email_client
+            .fallback_providers
+            .iter()
+            .map(|provider| match &provider.transport {
+                TransportSettings::Postmark {
+                    authorization_token,
+                    ..
+                } => authorization_token.expose().to_owned(),
+                TransportSettings::Smtp { password, .. } => {
+                    password.as_ref().unwrap().expose().to_owned()
+                }
+            })
+            .collect();
+        assert_eq!(
+            secrets,
+            vec![
+                "email_client.fallback_providers.0.authorization_token!",
+                "email_client.fallback_providers.1.password!",
+            ]
+        );
+    }
+
+    #[test]
+    fn unknown_secrets_are_not_provided() {
+        let provider = EnvFileSecretProvider::with_prefix(&unique_prefix());
+        assert_none!(provider.get("database.password").unwrap());
+    }
+
+    #[test]
+    fn secrets_are_read_from_the_environment() {
+        let prefix = unique_prefix();
+        std::env::set_var(format!("{}__DATABASE__PASSWORD", prefix), "env-password");
+
+        let provider = EnvFileSecretProvider::with_prefix(&prefix);
+        let provided = provider.get("database.password").unwrap().unwrap();
+
+        assert_eq!(provided.secret.expose(), "env-password");
+    }
+
+    #[test]
+    fn the_older_variable_names_are_read_after_the_new_ones() {
+        let prefix = unique_prefix();
+        std::env::set_var(format!("{}_DATABASE__PASSWORD", prefix), "old-password");
+
+        let provider = EnvFileSecretProvider::with_prefix(&prefix);
+        let provided = provider.get("database.password").unwrap().unwrap();
+        assert_eq!(provided.secret.expose(), "old-password");
+
+        std::env::set_var(format!("{}__DATABASE__PASSWORD", prefix), "new-password");
+        let provided = provider.get("database.password").unwrap().unwrap();
+        assert_eq!(provided.secret.expose(), "new-password");
+    }
+
+    #[test]
+    fn secrets_are_read_from_files_before_the_environment() {
+        let prefix = unique_prefix();
+        let path = std::env::temp_dir().join(&prefix);
+        std::fs::write(&path, "file-password\n").unwrap();
+        std::env::set_var(format!("{}__DATABASE__PASSWORD", prefix), "env-password");
+        std::env::set_var(format!("{}__DATABASE__PASSWORD_FILE", prefix), &path);
+
+        let provider = EnvFileSecretProvider::with_prefix(&prefix);
+        let provided = provider.get("database.password").unwrap().unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(provided.secret.expose(), "file-password");
+    }
+
+    #[test]
+    fn missing_secret_files_are_reported() {
+        let prefix = unique_prefix();
+        let path = std::env::temp_dir().join(&prefix);
+        std::env::set_var(format!("{}__DATABASE__PASSWORD_FILE", prefix), &path);
+
+        let provider = EnvFileSecretProvider::with_prefix(&prefix);
+        assert_err!(provider.get("database.password"));
+    }
+}
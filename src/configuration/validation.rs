@@ -0,0 +1,662 @@
+use std::{collections::HashSet, fmt};
+
+use reqwest::Url;
+use tracing_subscriber::EnvFilter;
+
+use super::{
+    DatabaseBackend, Environment, HumanVerificationSettings, RedactionMode, Settings,
+    TransportSettings,
+};
+use crate::{
+    domain::{subscriber_name, EmailAddress},
+    email_client::PRIMARY_PROVIDER,
+    subscription_policy,
+};
+
+/// Secrets shipped in `configuration/base.yaml`, they must be overridden in production.
+const DEFAULT_SECRETS: &[(&str, &str)] = &[
+    ("application.admin_token", "my-admin-token"),
+    ("database.password", "password"),
+    ("email_client.authorization_token", "my-secret-token"),
+    ("tracking.signing_key", "my-tracking-key"),
+    ("webhooks.postmark.password", "my-webhook-password"),
+];
+
+const MINIMUM_TIMEOUT_MILLISECONDS: u64 = 1;
+const MAXIMUM_TIMEOUT_MILLISECONDS: u64 = 60_000;
+const MAXIMUM_RETRIES: u32 = 10;
+const MAXIMUM_CONFIRMATION_LINK_TTL_HOURS: u64 = 24 * 366;
+const MAXIMUM_DIGEST_INTERVAL_HOURS: u64 = 24 * 31;
+
+/// A single invalid configuration value.
+#[derive(Debug)]
+pub struct ValidationError {
+    pub key: &'static str,
+    pub message: String,
+}
+
+/// All the invalid values found in the configuration.
+#[derive(Debug, thiserror::Error)]
+pub struct ValidationErrors(Vec<ValidationError>);
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.key, self.message)
+    }
+}
+
+impl fmt::Display for ValidationErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "the configuration contains {} error(s)", self.0.len())?;
+        for error in &self.0 {
+            writeln!(f, "  - {}", error)?;
+        }
+        Ok(())
+    }
+}
+
+impl ValidationErrors {
+    pub fn iter(&self) -> impl Iterator<Item = &ValidationError> {
+        self.0.iter()
+    }
+}
+
+impl Settings {
+    /// Check the whole configuration, all the errors are reported at once.
+    pub fn validate(&self, environment: Environment) -> Result<(), ValidationErrors> {
+        let mut errors = Vec::new();
+        let mut check = |valid: bool, key: &'static str, message: &dyn fmt::Display| {
+            if !valid {
+                errors.push(ValidationError {
+                    key,
+                    message: message.to_string(),
+                });
+            }
+        };
+
+        check(
+            self.application.port != 0,
+            "application.port",
+            &"the port cannot be zero",
+        );
+        if let Err(e) = parse_http_url(&self.application.base_url) {
+            check(false, "application.base_url", &e);
+        }
+
+        check(
+            self.database.port != 0,
+            "database.port",
+            &"the port cannot be zero",
+        );
+        if let DatabaseBackend::Sqlite { path } = &self.database.backend {
+            check(
+                cfg!(feature = "sqlite"),
+                "database.backend",
+                &"this build does not include SQLite, enable the `sqlite` feature",
+            );
+            check(
+                !path.trim().is_empty(),
+                "database.backend",
+                &"the SQLite backend needs the path of the database file",
+            );
+        }
+
+        if let Err(e) = parse_http_url(&self.email_client.base_url) {
+            check(false, "email_client.base_url", &e);
+        }
+        if let Err(e) = self.email_client.sender_email.parse::<EmailAddress>() {
+            check(false, "email_client.sender_email", &e);
+        }
+        check(
+            (MINIMUM_TIMEOUT_MILLISECONDS..=MAXIMUM_TIMEOUT_MILLISECONDS)
+                .contains(&self.email_client.timeout_milliseconds),
+            "email_client.timeout_milliseconds",
+            &format!(
+                "the timeout must be between {}ms and {}ms",
+                MINIMUM_TIMEOUT_MILLISECONDS, MAXIMUM_TIMEOUT_MILLISECONDS
+            ),
+        );
+
+        check(
+            self.email_client.max_retries <= MAXIMUM_RETRIES,
+            "email_client.max_retries",
+            &format!("at most {} retries are allowed", MAXIMUM_RETRIES),
+        );
+        check(
+            self.email_client.retry_backoff_milliseconds <= MAXIMUM_TIMEOUT_MILLISECONDS,
+            "email_client.retry_backoff_milliseconds",
+            &format!(
+                "the backoff cannot exceed {}ms",
+                MAXIMUM_TIMEOUT_MILLISECONDS
+            ),
+        );
+        check(
+            self.email_client.max_messages_per_second != Some(0),
+            "email_client.max_messages_per_second",
+            &"the rate cannot be zero, leave it unset to send without limit",
+        );
+        let circuit_breaker = &self.email_client.circuit_breaker;
+        check(
+            circuit_breaker.failure_rate_threshold > 0.0
+                && circuit_breaker.failure_rate_threshold <= 1.0,
+            "email_client.circuit_breaker.failure_rate_threshold",
+            &"the threshold must be greater than 0 and at most 1",
+        );
+        check(
+            circuit_breaker.window_size > 0,
+            "email_client.circuit_breaker.window_size",
+            &"the window cannot be empty",
+        );
+        check(
+            circuit_breaker.half_open_probes > 0,
+            "email_client.circuit_breaker.half_open_probes",
+            &"at least one probe is required to close the circuit",
+        );
+        let mut names = vec![PRIMARY_PROVIDER];
+        for provider in &self.email_client.fallback_providers {
+            if names.contains(&provider.name.as_str()) {
+                check(
+                    false,
+                    "email_client.fallback_providers",
+                    &format!("the name `{}` is used by several providers", provider.name),
+                );
+            }
+            names.push(&provider.name);
+            check(
+                provider.max_messages_per_second != Some(0),
+                "email_client.fallback_providers",
+                &format!(
+                    "the rate of `{}` cannot be zero, leave it unset to send without limit",
+                    provider.name
+                ),
+            );
+            match &provider.transport {
+                TransportSettings::Postmark { base_url, .. } => {
+                    if let Err(e) = parse_http_url(base_url) {
+                        check(false, "email_client.fallback_providers", &e);
+                    }
+                }
+                TransportSettings::Smtp {
+                    host,
+                    port,
+                    username,
+                    password,
+                    ..
+                } => {
+                    check(
+                        !host.is_empty() && *port != 0,
+                        "email_client.fallback_providers",
+                        &format!("the SMTP relay `{}` needs a host and a port", provider.name),
+                    );
+                    check(
+                        username.is_some() == password.is_some(),
+                        "email_client.fallback_providers",
+                        &format!(
+                            "the SMTP relay `{}` needs both a username and a password",
+                            provider.name
+                        ),
+                    );
+                }
+            }
+        }
+
+        if let Err(e) = EnvFilter::try_new(&self.telemetry.log_filter) {
+            check(false, "telemetry.log_filter", &e);
+        }
+        check(
+            self.telemetry.redaction.mode != RedactionMode::Hash
+                || self.telemetry.redaction.hash_key.is_some(),
+            "telemetry.redaction.hash_key",
+            &"a key is required to hash the redacted values",
+        );
+
+        check(
+            self.webhooks.soft_bounce_threshold > 0,
+            "webhooks.soft_bounce_threshold",
+            &"the threshold cannot be zero",
+        );
+
+        if let Some(path) = &self.subscriptions.disposable_domains_file {
+            if let Err(e) = std::fs::read_to_string(path) {
+                check(
+                    false,
+                    "subscriptions.disposable_domains_file",
+                    &format!("`{}` cannot be read ({})", path.display(), e),
+                );
+            }
+        }
+        let denied_domains: HashSet<_> = self
+            .subscriptions
+            .denied_domains
+            .iter()
+            .map(|domain| subscription_policy::normalise_domain(domain))
+            .collect();
+        for domain in &self.subscriptions.allowed_domains {
+            check(
+                !denied_domains.contains(&subscription_policy::normalise_domain(domain)),
+                "subscriptions.allowed_domains",
+                &format!("the domain `{}` is both allowed and denied", domain),
+            );
+        }
+
+        check(
+            self.subscriptions.min_name_length <= subscriber_name::MAXIMUM_LENGTH,
+            "subscriptions.min_name_length",
+            &format!(
+                "a name cannot be longer than {} characters",
+                subscriber_name::MAXIMUM_LENGTH
+            ),
+        );
+        check(
+            self.subscriptions
+                .confirmation_link_ttl_hours
+                .is_none_or(|hours| (1..=MAXIMUM_CONFIRMATION_LINK_TTL_HOURS).contains(&hours)),
+            "subscriptions.confirmation_link_ttl_hours",
+            &format!(
+                "the links must expire after 1 to {} hours, leave it unset to never expire them",
+                MAXIMUM_CONFIRMATION_LINK_TTL_HOURS
+            ),
+        );
+        let pages = &self.subscriptions.confirmation_pages;
+        for (name, page) in [
+            ("confirmed", &pages.confirmed),
+            ("already_confirmed", &pages.already_confirmed),
+            ("expired", &pages.expired),
+            ("invalid", &pages.invalid),
+        ] {
+            match (&page.template, &page.redirect_url) {
+                (Some(_), Some(_)) => check(
+                    false,
+                    "subscriptions.confirmation_pages",
+                    &format!("the `{}` page has both a template and a redirection", name),
+                ),
+                (Some(path), None) => {
+                    if let Err(e) = std::fs::read_to_string(path) {
+                        check(
+                            false,
+                            "subscriptions.confirmation_pages",
+                            &format!("`{}` cannot be read ({})", path.display(), e),
+                        );
+                    }
+                }
+                (None, Some(url)) => {
+                    if let Err(e) = parse_http_url(url) {
+                        check(false, "subscriptions.confirmation_pages", &e);
+                    }
+                }
+                (None, None) => {}
+            }
+        }
+        let mut topics = HashSet::new();
+        for topic in &self.subscriptions.topics {
+            check(
+                !topic.id.is_empty()
+                    && topic
+                        .id
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'),
+                "subscriptions.topics",
+                &format!(
+                    "the id `{}` can only have ASCII letters, digits, `-` and `_`",
+                    topic.id
+                ),
+            );
+            check(
+                topics.insert(topic.id.as_str()),
+                "subscriptions.topics",
+                &format!("the topic `{}` is listed twice", topic.id),
+            );
+        }
+        check(
+            (1..=MAXIMUM_DIGEST_INTERVAL_HOURS).contains(&self.subscriptions.digest_interval_hours),
+            "subscriptions.digest_interval_hours",
+            &format!(
+                "the digests must be sent every 1 to {} hours",
+                MAXIMUM_DIGEST_INTERVAL_HOURS
+            ),
+        );
+        let hosted_pages = &self.subscriptions.hosted_pages;
+        if let Some(path) = &hosted_pages.template {
+            match std::fs::read_to_string(path) {
+                Ok(template) => check(
+                    template.contains("{{content}}"),
+                    "subscriptions.hosted_pages.template",
+                    &format!("`{}` has no `{{{{content}}}}`", path.display()),
+                ),
+                Err(e) => check(
+                    false,
+                    "subscriptions.hosted_pages.template",
+                    &format!("`{}` cannot be read ({})", path.display(), e),
+                ),
+            }
+        }
+        if let Some(url) = &hosted_pages.stylesheet_url {
+            if let Err(e) = parse_http_url(url) {
+                check(false, "subscriptions.hosted_pages.stylesheet_url", &e);
+            }
+        }
+        check(
+            !self.subscriptions.domain_check.enabled
+                || self.subscriptions.domain_check.timeout_milliseconds > 0,
+            "subscriptions.domain_check.timeout_milliseconds",
+            &"the timeout cannot be zero",
+        );
+        match &self.subscriptions.human_verification {
+            HumanVerificationSettings::Disabled => {}
+            HumanVerificationSettings::Honeypot {
+                min_seconds,
+                max_age_seconds,
+            } => check(
+                min_seconds < max_age_seconds,
+                "subscriptions.human_verification.max_age_seconds",
+                &"the forms must be valid for longer than `min_seconds`",
+            ),
+            HumanVerificationSettings::Captcha {
+                verify_url,
+                secret,
+                timeout_milliseconds,
+            } => {
+                if let Err(e) = parse_http_url(verify_url) {
+                    check(false, "subscriptions.human_verification.verify_url", &e);
+                }
+                check(
+                    !secret.expose().is_empty(),
+                    "subscriptions.human_verification.secret",
+                    &"the secret cannot be empty",
+                );
+                check(
+                    (MINIMUM_TIMEOUT_MILLISECONDS..=MAXIMUM_TIMEOUT_MILLISECONDS)
+                        .contains(timeout_milliseconds),
+                    "subscriptions.human_verification.timeout_milliseconds",
+                    &format!(
+                        "the timeout must be between {}ms and {}ms",
+                        MINIMUM_TIMEOUT_MILLISECONDS, MAXIMUM_TIMEOUT_MILLISECONDS
+                    ),
+                );
+            }
+        }
+
+        if environment == Environment::Production {
+            for (key, default) in DEFAULT_SECRETS {
+                check(
+                    self.secret(key) != Some(*default),
+                    key,
+                    &"the secret is left at its default value",
+                );
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(ValidationErrors(errors))
+        }
+    }
+
+    fn secret(&self, key: &str) -> Option<&str> {
+        match key {
+            "application.admin_token" => Some(self.application.admin_token.expose()),
+            "database.password" => Some(self.database.password.expose()),
+            "email_client.authorization_token" => {
+                Some(self.email_client.authorization_token.expose())
+            }
+            "tracking.signing_key" => Some(self.tracking.signing_key.expose()),
+            "webhooks.postmark.password" => Some(self.webhooks.postmark.password.expose()),
+            _ => None,
+        }
+    }
+}
+
+fn parse_http_url(url: &str) -> Result<Url, String> {
+    let url = Url::parse(url).map_err(|e| format!("`{}` is not a valid URL ({})", url, e))?;
+    match url.scheme() {
+        "http" | "https" => Ok(url),
+        scheme => Err(format!("the scheme `{}` is not supported", scheme)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claim::{assert_err, assert_ok};
+
+    use crate::{
+        configuration::{
+            test_settings as settings, DatabaseBackend, Environment, HumanVerificationSettings,
+            ProviderSettings, RedactionMode, Settings, Topic, TransportSettings,
+        },
+        email_client::SmtpTls,
+        secret::Secret,
+    };
+
+    fn invalid_keys(settings: &Settings, environment: Environment) -> Vec<&'static str> {
+        match settings.validate(environment) {
+            Ok(()) => vec![],
+            Err(errors) => errors.iter().map(|e| e.key).collect(),
+        }
+    }
+
+    #[test]
+    fn valid_local_settings_are_accepted() {
+        assert_ok!(settings().validate(Environment::Local));
+    }
+
+    #[test]
+    fn all_the_errors_are_reported_at_once() {
+        let mut settings = settings();
+        settings.application.port = 0;
+        settings.email_client.base_url = "localhost".into();
+        settings.email_client.sender_email = "not-an-email".into();
+        settings.email_client.timeout_milliseconds = 0;
+
+        assert_eq!(
+            invalid_keys(&settings, Environment::Local),
+            vec![
+                "application.port",
+                "email_client.base_url",
+                "email_client.sender_email",
+                "email_client.timeout_milliseconds",
+            ]
+        );
+    }
+
+    #[test]
+    fn urls_without_an_http_scheme_are_rejected() {
+        let mut settings = settings();
+        settings.application.base_url = "ftp://127.0.0.1".into();
+        assert_err!(settings.validate(Environment::Local));
+    }
+
+    #[test]
+    fn a_zero_send_rate_is_rejected() {
+        let mut settings = settings();
+        settings.email_client.max_messages_per_second = Some(0);
+        assert_eq!(
+            invalid_keys(&settings, Environment::Local),
+            vec!["email_client.max_messages_per_second"]
+        );
+    }
+
+    #[test]
+    fn the_sqlite_backend_needs_a_path() {
+        let mut settings = settings();
+        settings.database.backend = DatabaseBackend::Sqlite { path: " ".into() };
+        assert!(invalid_keys(&settings, Environment::Local).contains(&"database.backend"));
+
+        settings.database.backend = DatabaseBackend::Sqlite {
+            path: "newsletter.db".into(),
+        };
+        assert_eq!(
+            invalid_keys(&settings, Environment::Local).is_empty(),
+            cfg!(feature = "sqlite")
+        );
+    }
+
+    #[test]
+    fn an_invalid_circuit_breaker_is_rejected() {
+        let mut settings = settings();
+        settings.email_client.circuit_breaker.failure_rate_threshold = 1.5;
+        settings.email_client.circuit_breaker.half_open_probes = 0;
+        assert_eq!(
+            invalid_keys(&settings, Environment::Local),
+            vec![
+                "email_client.circuit_breaker.failure_rate_threshold",
+                "email_client.circuit_breaker.half_open_probes",
+            ]
+        );
+    }
+
+    #[test]
+    fn fallback_providers_need_a_unique_name() {
+        let mut settings = settings();
+        settings.email_client.fallback_providers = vec![ProviderSettings {
+            name: "postmark".into(),
+            max_messages_per_second: None,
+            transport: TransportSettings::Smtp {
+                host: "smtp.example.com".into(),
+                port: 587,
+                tls: SmtpTls::StartTls,
+                username: None,
+                password: None,
+            },
+        }];
+        assert_eq!(
+            invalid_keys(&settings, Environment::Local),
+            vec!["email_client.fallback_providers"]
+        );
+    }
+
+    #[test]
+    fn an_invalid_subscription_policy_is_rejected() {
+        let mut settings = settings();
+        settings.subscriptions.disposable_domains_file = Some("does-not-exist.txt".into());
+        settings.subscriptions.allowed_domains = vec!["example.com".into()];
+        settings.subscriptions.denied_domains = vec!["EXAMPLE.com".into()];
+        settings.subscriptions.min_name_length = 300;
+        settings.subscriptions.domain_check.enabled = true;
+        settings.subscriptions.domain_check.timeout_milliseconds = 0;
+        assert_eq!(
+            invalid_keys(&settings, Environment::Local),
+            vec![
+                "subscriptions.disposable_domains_file",
+                "subscriptions.allowed_domains",
+                "subscriptions.min_name_length",
+                "subscriptions.domain_check.timeout_milliseconds",
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_confirmation_pages_are_rejected() {
+        let mut settings = settings();
+        settings.subscriptions.confirmation_link_ttl_hours = Some(0);
+        settings
+            .subscriptions
+            .confirmation_pages
+            .confirmed
+            .redirect_url = Some("ftp://example.com/welcome".into());
+        settings.subscriptions.confirmation_pages.expired.template =
+            Some("missing/expired.html".into());
+        let invalid = &mut settings.subscriptions.confirmation_pages.invalid;
+        invalid.template = Some("Cargo.toml".into());
+        invalid.redirect_url = Some("https://example.com/invalid".into());
+        assert_eq!(
+            invalid_keys(&settings, Environment::Local),
+            vec![
+                "subscriptions.confirmation_link_ttl_hours",
+                "subscriptions.confirmation_pages",
+                "subscriptions.confirmation_pages",
+                "subscriptions.confirmation_pages",
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_topics_and_hosted_pages_are_rejected() {
+        let mut settings = settings();
+        settings.subscriptions.topics = ["rust", "rust", "release notes"]
+            .into_iter()
+            .map(|id| Topic {
+                id: id.into(),
+                name: id.into(),
+            })
+            .collect();
+        settings.subscriptions.digest_interval_hours = 0;
+        settings.subscriptions.hosted_pages.template = Some("Cargo.toml".into());
+        settings.subscriptions.hosted_pages.stylesheet_url = Some("not a url".into());
+        assert_eq!(
+            invalid_keys(&settings, Environment::Local),
+            vec![
+                "subscriptions.topics",
+                "subscriptions.topics",
+                "subscriptions.digest_interval_hours",
+                "subscriptions.hosted_pages.template",
+                "subscriptions.hosted_pages.stylesheet_url",
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_human_verifications_are_rejected() {
+        let mut settings = settings();
+        settings.subscriptions.human_verification = HumanVerificationSettings::Honeypot {
+            min_seconds: 60,
+            max_age_seconds: 60,
+        };
+        assert_eq!(
+            invalid_keys(&settings, Environment::Local),
+            vec!["subscriptions.human_verification.max_age_seconds"]
+        );
+
+        settings.subscriptions.human_verification = HumanVerificationSettings::Captcha {
+            verify_url: "hcaptcha.com/siteverify".into(),
+            secret: Secret::new(String::new()),
+            timeout_milliseconds: 0,
+        };
+        assert_eq!(
+            invalid_keys(&settings, Environment::Local),
+            vec![
+                "subscriptions.human_verification.verify_url",
+                "subscriptions.human_verification.secret",
+                "subscriptions.human_verification.timeout_milliseconds",
+            ]
+        );
+    }
+
+    #[test]
+    fn hashing_without_a_key_is_rejected() {
+        let mut settings = settings();
+        settings.telemetry.redaction.mode = RedactionMode::Hash;
+        assert_eq!(
+            invalid_keys(&settings, Environment::Local),
+            vec!["telemetry.redaction.hash_key"]
+        );
+
+        settings.telemetry.redaction.hash_key = Some(Secret::new("a-hash-key".into()));
+        assert_ok!(settings.validate(Environment::Local));
+    }
+
+    #[test]
+    fn default_secrets_are_rejected_in_production() {
+        assert_eq!(
+            invalid_keys(&settings(), Environment::Production),
+            vec![
+                "application.admin_token",
+                "database.password",
+                "email_client.authorization_token",
+                "tracking.signing_key",
+                "webhooks.postmark.password",
+            ]
+        );
+    }
+
+    #[test]
+    fn custom_secrets_are_accepted_in_production() {
+        let mut settings = settings();
+        settings.application.admin_token = Secret::new("a-long-admin-token".into());
+        settings.database.password = Secret::new("a-strong-password".into());
+        settings.email_client.authorization_token = Secret::new("a-real-token".into());
+        settings.webhooks.postmark.password = Secret::new("a-webhook-password".into());
+        settings.tracking.signing_key = Secret::new("a-tracking-key".into());
+        assert_ok!(settings.validate(Environment::Production));
+    }
+}
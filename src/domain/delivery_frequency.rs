@@ -0,0 +1,34 @@
+/// How often a subscriber wants to receive the newsletter, stored as text in the `frequency`
+/// column of `subscriptions`.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum DeliveryFrequency {
+    /// Every issue as soon as it is published.
+    #[default]
+    Immediate,
+    /// The issues gathered in a periodic digest.
+    Digest,
+}
+
+impl DeliveryFrequency {
+    pub const ALL: [DeliveryFrequency; 2] =
+        [DeliveryFrequency::Immediate, DeliveryFrequency::Digest];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeliveryFrequency::Immediate => "immediate",
+            DeliveryFrequency::Digest => "digest",
+        }
+    }
+}
+
+impl std::str::FromStr for DeliveryFrequency {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "immediate" => Ok(DeliveryFrequency::Immediate),
+            "digest" => Ok(DeliveryFrequency::Digest),
+            other => Err(format!("`{}` is not a delivery frequency", other)),
+        }
+    }
+}
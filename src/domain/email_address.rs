@@ -1,5 +1,25 @@
 use serde_with::{DeserializeFromStr, SerializeDisplay};
 
+use crate::telemetry::Redacted;
+
+/// Domains of the providers that deliver `user+tag@domain` to `user@domain`.
+const SUBADDRESSING_DOMAINS: &[&str] = &[
+    "fastmail.com",
+    "gmail.com",
+    "googlemail.com",
+    "hotmail.com",
+    "icloud.com",
+    "live.com",
+    "me.com",
+    "outlook.com",
+    "proton.me",
+    "protonmail.com",
+];
+/// Domains of the providers that ignore the dots in the local part.
+const DOTLESS_DOMAINS: &[&str] = &["gmail.com", "googlemail.com"];
+
+/// An email address, the surrounding whitespace is trimmed and the domain is lowercased and
+/// converted to punycode. The local part is kept as given.
 #[derive(Clone, Debug, SerializeDisplay, DeserializeFromStr)]
 pub struct EmailAddress(String);
 
@@ -13,14 +33,69 @@ impl std::str::FromStr for EmailAddress {
     type Err = ParseEmailAddressError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        if validator::validate_email(s) {
-            Ok(Self(s.to_string()))
+        let (local, domain) = s
+            .trim()
+            .rsplit_once('@')
+            .ok_or(ParseEmailAddressError::Invalid)?;
+        let domain = normalise_domain(domain).ok_or(ParseEmailAddressError::Invalid)?;
+        let address = format!("{}@{}", local, domain);
+        if validator::validate_email(address.as_str()) {
+            Ok(Self(address))
         } else {
             Err(ParseEmailAddressError::Invalid)
         }
     }
 }
 
+/// Lowercase the domain and convert it to punycode, the address literals are kept as given.
+fn normalise_domain(domain: &str) -> Option<String> {
+    if domain.starts_with('[') {
+        return Some(domain.to_owned());
+    }
+    idna::domain_to_ascii(domain).ok()
+}
+
+impl EmailAddress {
+    /// The part before the `@`.
+    pub fn local_part(&self) -> &str {
+        self.split().0
+    }
+
+    /// The part after the `@`, in lowercase and punycode.
+    pub fn domain(&self) -> &str {
+        self.split().1
+    }
+
+    fn split(&self) -> (&str, &str) {
+        self.0
+            .rsplit_once('@')
+            .expect("A valid address contains `@`")
+    }
+
+    /// The form used to detect duplicates, the local part is lowercased and the variations
+    /// ignored by the provider, the dots or the `+tag`, are removed.
+    pub fn canonical(&self) -> String {
+        let mut local = self.local_part().to_lowercase();
+        let mut domain = self.domain();
+        if SUBADDRESSING_DOMAINS.contains(&domain) {
+            if let Some((user, _tag)) = local.split_once('+') {
+                local = user.to_owned();
+            }
+        }
+        if DOTLESS_DOMAINS.contains(&domain) {
+            local.retain(|c| c != '.');
+            // Both domains share the same mailboxes
+            domain = "gmail.com";
+        }
+        format!("{}@{}", local, domain)
+    }
+
+    /// Display the value through the log redaction, to be used in `tracing` fields.
+    pub fn redacted(&self) -> Redacted<'_, Self> {
+        Redacted::new(self)
+    }
+}
+
 impl std::fmt::Display for EmailAddress {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         self.0.fmt(f)
@@ -56,6 +131,37 @@ mod tests {
         let email = "@domain.com";
         assert_err!(email.parse::<EmailAddress>());
     }
+    #[test]
+    fn whitespace_is_trimmed_and_the_domain_lowercased() {
+        let email: EmailAddress = "  Ursula@Example.COM \n".parse().unwrap();
+        assert_eq!(email.as_ref(), "Ursula@example.com");
+    }
+    #[test]
+    fn an_internationalised_domain_is_converted_to_punycode() {
+        let email: EmailAddress = "ursula@Bücher.example".parse().unwrap();
+        assert_eq!(email.as_ref(), "ursula@xn--bcher-kva.example");
+    }
+    #[test]
+    fn the_case_of_the_local_part_is_ignored_by_the_canonical_form() {
+        let email: EmailAddress = "Ursula.Le+Guin@Example.com".parse().unwrap();
+        assert_eq!(email.canonical(), "ursula.le+guin@example.com");
+    }
+    #[test]
+    fn the_gmail_dots_and_tags_are_removed_from_the_canonical_form() {
+        let email: EmailAddress = "Ursula.Le.Guin+news@googlemail.com".parse().unwrap();
+        assert_eq!(email.as_ref(), "Ursula.Le.Guin+news@googlemail.com");
+        assert_eq!(email.canonical(), "ursulaleguin@gmail.com");
+    }
+    #[test]
+    fn the_tag_is_removed_for_the_providers_supporting_subaddressing() {
+        let email: EmailAddress = "ursula+news@outlook.com".parse().unwrap();
+        assert_eq!(email.canonical(), "ursula@outlook.com");
+    }
+    #[test]
+    fn redacted_email_is_not_displayed() {
+        let email: EmailAddress = "ursula@domain.com".parse().unwrap();
+        assert!(!email.redacted().to_string().contains("ursula"));
+    }
 
     #[derive(Debug, Clone)]
     struct ValidEmail(String);
@@ -0,0 +1,38 @@
+use chrono::{DateTime, Utc};
+
+use super::EmailAddress;
+
+/// An event reported by an email provider about a message we sent.
+#[derive(Clone, Debug)]
+pub struct EmailEvent {
+    /// Identifier assigned to the message by the provider.
+    pub message_id: String,
+    pub recipient: EmailAddress,
+    pub kind: EmailEventKind,
+    /// Explanation given by the provider, if any.
+    pub description: Option<String>,
+    pub occurred_at: DateTime<Utc>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum EmailEventKind {
+    /// The message was accepted by the recipient's server.
+    Delivered,
+    /// The address does not exist or cannot receive emails anymore.
+    HardBounce,
+    /// Temporary failure (full mailbox, unreachable server...).
+    SoftBounce,
+    /// The recipient marked the message as spam.
+    Complaint,
+}
+
+impl EmailEventKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EmailEventKind::Delivered => "delivered",
+            EmailEventKind::HardBounce => "hard_bounce",
+            EmailEventKind::SoftBounce => "soft_bounce",
+            EmailEventKind::Complaint => "complaint",
+        }
+    }
+}
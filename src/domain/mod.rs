@@ -1,5 +1,15 @@
+pub mod delivery_frequency;
 pub mod email_address;
+pub mod email_event;
+pub mod new_subscriber;
 pub mod subscriber_name;
+pub mod subscription_status;
+pub mod subscription_token;
 
-pub use self::email_address::EmailAddress;
-pub use subscriber_name::SubscriberName;
+pub use self::delivery_frequency::DeliveryFrequency;
+pub use self::email_address::{EmailAddress, ParseEmailAddressError};
+pub use self::email_event::{EmailEvent, EmailEventKind};
+pub use new_subscriber::NewSubscriber;
+pub use subscriber_name::{Script, SubscriberName};
+pub use subscription_status::SubscriptionStatus;
+pub use subscription_token::SubscriptionToken;
@@ -0,0 +1,8 @@
+use crate::domain::{EmailAddress, SubscriberName};
+
+/// A subscriber as submitted through the subscription form.
+#[derive(Debug)]
+pub struct NewSubscriber {
+    pub email: EmailAddress,
+    pub name: SubscriberName,
+}
@@ -1,6 +1,34 @@
+use std::collections::HashSet;
+
+use serde::Deserialize;
 use serde_with::{DeserializeFromStr, SerializeDisplay};
+use unicode_normalization::UnicodeNormalization;
 use unicode_segmentation::UnicodeSegmentation;
 
+use crate::telemetry::Redacted;
+
+/// Maximum number of graphemes of a name.
+pub const MAXIMUM_LENGTH: usize = 256;
+const FORBIDDEN_CHARACTERS: &[char] = &['/', '(', ')', '"', '<', '>', '\\', '{', '}'];
+/// Invisible characters, they can hide or reorder a part of the name.
+const INVISIBLE_CHARACTERS: &[(char, char)] = &[
+    ('\u{00ad}', '\u{00ad}'),
+    ('\u{034f}', '\u{034f}'),
+    ('\u{061c}', '\u{061c}'),
+    ('\u{115f}', '\u{1160}'),
+    ('\u{180e}', '\u{180e}'),
+    ('\u{200b}', '\u{200f}'),
+    ('\u{202a}', '\u{202e}'),
+    ('\u{2060}', '\u{206f}'),
+    ('\u{3164}', '\u{3164}'),
+    ('\u{fe00}', '\u{fe0f}'),
+    ('\u{feff}', '\u{feff}'),
+    ('\u{ffa0}', '\u{ffa0}'),
+    ('\u{fff9}', '\u{fffb}'),
+];
+
+/// The name of a subscriber in NFC, the surrounding whitespace is trimmed and the inner runs of
+/// whitespace are collapsed to a single space.
 #[derive(Clone, Debug, SerializeDisplay, DeserializeFromStr)]
 pub struct SubscriberName(String);
 
@@ -12,29 +40,126 @@ pub enum ParseSubscriberNameError {
     TooLong,
     #[error("subscriber name contains forbidden characters")]
     ForbiddenCharacters,
+    #[error("subscriber name contains control or invisible characters")]
+    InvisibleCharacters,
+}
+
+/// The writing systems a name can be restricted to. The digits, the punctuation and the
+/// combining marks belong to all of them.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Script {
+    Arabic,
+    Armenian,
+    Bengali,
+    Cyrillic,
+    Devanagari,
+    Georgian,
+    Greek,
+    Han,
+    Hangul,
+    Hebrew,
+    Hiragana,
+    Katakana,
+    Latin,
+    Tamil,
+    Thai,
 }
 
 impl std::str::FromStr for SubscriberName {
     type Err = ParseSubscriberNameError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        const MAXIMUM_LENGTH: usize = 256;
-        const FORBIDDEN_CHARACTERS: &[char] = &['/', '(', ')', '"', '<', '>', '\\', '{', '}'];
+        let normalised: String = s.nfc().collect();
+        let name = normalised.split_whitespace().collect::<Vec<_>>().join(" ");
 
-        if s.trim().is_empty() {
+        if name.is_empty() {
             return Err(ParseSubscriberNameError::EmptyOrWhitespace);
         }
-        if s.graphemes(true).count() > MAXIMUM_LENGTH {
+        if name.graphemes(true).count() > MAXIMUM_LENGTH {
             return Err(ParseSubscriberNameError::TooLong);
         }
-        if s.chars().any(|c| FORBIDDEN_CHARACTERS.contains(&c)) {
+        if name.chars().any(|c| FORBIDDEN_CHARACTERS.contains(&c)) {
             return Err(ParseSubscriberNameError::ForbiddenCharacters);
         }
+        if name.chars().any(|c| c.is_control() || is_invisible(c)) {
+            return Err(ParseSubscriberNameError::InvisibleCharacters);
+        }
 
-        Ok(Self(s.to_string()))
+        Ok(Self(name))
     }
 }
 
+impl SubscriberName {
+    /// Display the value through the log redaction, to be used in `tracing` fields.
+    pub fn redacted(&self) -> Redacted<'_, Self> {
+        Redacted::new(self)
+    }
+
+    /// Number of graphemes of the name.
+    pub fn length(&self) -> usize {
+        self.0.graphemes(true).count()
+    }
+
+    /// Whether all the letters of the name belong to one of `scripts`.
+    pub fn is_written_in(&self, scripts: &HashSet<Script>) -> bool {
+        self.0
+            .chars()
+            .filter(|c| c.is_alphabetic())
+            .all(|c| Script::of(c).is_some_and(|script| scripts.contains(&script)))
+    }
+}
+
+impl Script {
+    /// The script of the letter `c`, `None` if it is not one of the supported scripts.
+    fn of(c: char) -> Option<Self> {
+        let script = match c as u32 {
+            0x41..=0x5a
+            | 0x61..=0x7a
+            | 0xaa
+            | 0xba
+            | 0xc0..=0x2af
+            | 0x1e00..=0x1eff
+            | 0x2c60..=0x2c7f
+            | 0xa720..=0xa7ff
+            | 0xab30..=0xab6f
+            | 0xff21..=0xff3a
+            | 0xff41..=0xff5a => Script::Latin,
+            0x370..=0x3ff | 0x1f00..=0x1fff => Script::Greek,
+            0x400..=0x52f | 0x1c80..=0x1c8f | 0x2de0..=0x2dff | 0xa640..=0xa69f => Script::Cyrillic,
+            0x530..=0x58f => Script::Armenian,
+            0x590..=0x5ff | 0xfb1d..=0xfb4f => Script::Hebrew,
+            0x600..=0x6ff | 0x750..=0x77f | 0x8a0..=0x8ff | 0xfb50..=0xfdff | 0xfe70..=0xfefc => {
+                Script::Arabic
+            }
+            0x900..=0x97f => Script::Devanagari,
+            0x980..=0x9ff => Script::Bengali,
+            0xb80..=0xbff => Script::Tamil,
+            0xe00..=0xe7f => Script::Thai,
+            0x10a0..=0x10ff | 0x1c90..=0x1cbf | 0x2d00..=0x2d2f => Script::Georgian,
+            0x1100..=0x11ff | 0x3130..=0x318f | 0xa960..=0xa97f | 0xac00..=0xd7ff => Script::Hangul,
+            0x3040..=0x309f => Script::Hiragana,
+            0x30a0..=0x30ff | 0x31f0..=0x31ff | 0xff66..=0xff9f => Script::Katakana,
+            0x2e80..=0x2fdf
+            | 0x3005
+            | 0x3007
+            | 0x3021..=0x3029
+            | 0x3400..=0x4dbf
+            | 0x4e00..=0x9fff
+            | 0xf900..=0xfaff
+            | 0x20000..=0x3134f => Script::Han,
+            _ => return None,
+        };
+        Some(script)
+    }
+}
+
+fn is_invisible(c: char) -> bool {
+    INVISIBLE_CHARACTERS
+        .iter()
+        .any(|(first, last)| (*first..=*last).contains(&c))
+}
+
 impl std::fmt::Display for SubscriberName {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         self.0.fmt(f)
@@ -49,8 +174,12 @@ impl AsRef<str> for SubscriberName {
 
 #[cfg(test)]
 mod tests {
-    use super::SubscriberName;
+    use std::collections::HashSet;
+
+    use super::{Script, SubscriberName};
     use claim::{assert_err, assert_ok};
+    use fake::{faker::name::en::Name, Fake};
+    use quickcheck::Arbitrary;
 
     #[test]
     fn a_256_grapheme_long_name_is_valid() {
@@ -87,4 +216,78 @@ mod tests {
         let name = "Ursula Le Guin".to_string();
         assert_ok!(name.parse::<SubscriberName>());
     }
+
+    #[test]
+    fn the_whitespace_is_trimmed_and_collapsed() {
+        let name: SubscriberName = "\t Ursula \n Le\u{00a0}\u{3000}Guin  ".parse().unwrap();
+        assert_eq!(name.as_ref(), "Ursula Le Guin");
+    }
+
+    #[test]
+    fn the_name_is_normalised_to_nfc() {
+        let name: SubscriberName = "Zoe\u{0308}".parse().unwrap();
+        assert_eq!(name.as_ref(), "Zo\u{00eb}");
+        assert_eq!(name.length(), 3);
+    }
+
+    #[test]
+    fn control_zero_width_and_bidi_override_characters_are_rejected() {
+        for name in [
+            "Ursula\u{0007}",
+            "Urs\u{200b}ula",
+            "Ursula\u{200d}",
+            "\u{202e}niuG eL alusrU",
+            "Ursula\u{2066}",
+            "\u{feff}Ursula",
+        ] {
+            assert_err!(name.parse::<SubscriberName>(), "{:?} was accepted", name);
+        }
+    }
+
+    #[test]
+    fn the_scripts_of_the_letters_are_detected() {
+        let latin = HashSet::from([Script::Latin]);
+        let name = |name: &str| name.parse::<SubscriberName>().unwrap();
+
+        assert!(name("Zoë O'Brien-Łukasiewicz 3rd").is_written_in(&latin));
+        // A Cyrillic а among Latin letters
+        assert!(!name("Urs\u{0430}la").is_written_in(&latin));
+        assert!(name("山田 太郎").is_written_in(&HashSet::from([Script::Han])));
+        assert!(!name("ᚢᚱᛋᚢᛚᚨ").is_written_in(&latin));
+    }
+
+    #[derive(Clone, Debug)]
+    struct ValidName(String);
+
+    impl Arbitrary for ValidName {
+        fn arbitrary(_: &mut quickcheck::Gen) -> Self {
+            Self(Name().fake())
+        }
+    }
+
+    #[quickcheck_macros::quickcheck]
+    fn valid_names_are_parsed_successfully(valid_name: ValidName) -> bool {
+        valid_name.0.parse::<SubscriberName>().is_ok()
+    }
+
+    #[quickcheck_macros::quickcheck]
+    fn a_parsed_name_has_no_surrounding_or_repeated_whitespace(name: String) -> bool {
+        match name.parse::<SubscriberName>() {
+            Ok(name) => {
+                let name = name.as_ref();
+                name.trim() == name && !name.contains("  ") && !name.contains(char::is_control)
+            }
+            Err(_) => true,
+        }
+    }
+
+    #[quickcheck_macros::quickcheck]
+    fn parsing_a_parsed_name_gives_the_same_name(name: String) -> bool {
+        match name.parse::<SubscriberName>() {
+            Ok(parsed) => {
+                parsed.as_ref().parse::<SubscriberName>().unwrap().as_ref() == parsed.as_ref()
+            }
+            Err(_) => true,
+        }
+    }
 }
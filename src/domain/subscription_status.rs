@@ -0,0 +1,139 @@
+use serde::Serialize;
+use sqlx::postgres::{PgHasArrayType, PgTypeInfo};
+
+/// Where a subscriber is in the subscription process, stored in the `subscription_status`
+/// Postgres enum.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "subscription_status", rename_all = "snake_case")]
+pub enum SubscriptionStatus {
+    /// The confirmation link has not been visited yet.
+    PendingConfirmation,
+    /// The subscriber receives the newsletter.
+    Confirmed,
+    /// The address bounced or complained, nothing is sent to it anymore. It is final.
+    Suppressed,
+    /// The subscriber left from the preference centre. It is final.
+    Unsubscribed,
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("a subscription cannot go from `{}` to `{}`", .from.as_str(), .to.as_str())]
+pub struct InvalidTransition {
+    pub from: SubscriptionStatus,
+    pub to: SubscriptionStatus,
+}
+
+impl SubscriptionStatus {
+    const ALL: [SubscriptionStatus; 4] = [
+        SubscriptionStatus::PendingConfirmation,
+        SubscriptionStatus::Confirmed,
+        SubscriptionStatus::Suppressed,
+        SubscriptionStatus::Unsubscribed,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SubscriptionStatus::PendingConfirmation => "pending_confirmation",
+            SubscriptionStatus::Confirmed => "confirmed",
+            SubscriptionStatus::Suppressed => "suppressed",
+            SubscriptionStatus::Unsubscribed => "unsubscribed",
+        }
+    }
+
+    /// Whether a subscription can go from this status to `next`, every change of status goes
+    /// through it.
+    pub fn can_transition_to(self, next: SubscriptionStatus) -> bool {
+        use SubscriptionStatus::*;
+
+        matches!(
+            (self, next),
+            (PendingConfirmation, Confirmed)
+                | (PendingConfirmation, Suppressed)
+                | (Confirmed, Suppressed)
+                | (PendingConfirmation, Unsubscribed)
+                | (Confirmed, Unsubscribed)
+        )
+    }
+
+    pub fn transition_to(self, next: SubscriptionStatus) -> Result<Self, InvalidTransition> {
+        if self.can_transition_to(next) {
+            Ok(next)
+        } else {
+            Err(InvalidTransition {
+                from: self,
+                to: next,
+            })
+        }
+    }
+
+    /// The statuses a subscription can reach this status from, to restrict the `UPDATE`s with
+    /// `status = ANY($1)`.
+    pub fn predecessors(self) -> Vec<SubscriptionStatus> {
+        Self::ALL
+            .into_iter()
+            .filter(|status| status.can_transition_to(self))
+            .collect()
+    }
+}
+
+impl PgHasArrayType for SubscriptionStatus {
+    fn array_type_info() -> PgTypeInfo {
+        PgTypeInfo::with_name("_subscription_status")
+    }
+}
+
+impl std::fmt::Display for SubscriptionStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.as_str().fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claim::{assert_err, assert_ok};
+
+    use super::SubscriptionStatus::{self, *};
+
+    #[test]
+    fn a_pending_subscription_can_be_confirmed_or_suppressed() {
+        assert_ok!(PendingConfirmation.transition_to(Confirmed));
+        assert_ok!(PendingConfirmation.transition_to(Suppressed));
+        assert_ok!(Confirmed.transition_to(Suppressed));
+    }
+
+    #[test]
+    fn a_suppressed_subscription_stays_suppressed() {
+        for status in SubscriptionStatus::ALL {
+            assert_err!(Suppressed.transition_to(status));
+        }
+    }
+
+    #[test]
+    fn a_subscriber_can_unsubscribe_once() {
+        assert_ok!(PendingConfirmation.transition_to(Unsubscribed));
+        assert_ok!(Confirmed.transition_to(Unsubscribed));
+        for status in SubscriptionStatus::ALL {
+            assert_err!(Unsubscribed.transition_to(status));
+        }
+        assert_err!(Suppressed.transition_to(Unsubscribed));
+    }
+
+    #[test]
+    fn a_subscription_cannot_go_back_to_pending_or_stay_in_place() {
+        for status in SubscriptionStatus::ALL {
+            assert_err!(status.transition_to(PendingConfirmation));
+            assert_err!(status.transition_to(status));
+        }
+    }
+
+    #[test]
+    fn the_predecessors_follow_the_transitions() {
+        assert_eq!(Confirmed.predecessors(), vec![PendingConfirmation]);
+        assert_eq!(
+            Suppressed.predecessors(),
+            vec![PendingConfirmation, Confirmed]
+        );
+        assert!(PendingConfirmation.predecessors().is_empty());
+    }
+}
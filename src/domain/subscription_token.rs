@@ -0,0 +1,82 @@
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+
+/// Number of characters of a token.
+const LENGTH: usize = 25;
+
+/// The secret of a confirmation link, made of ASCII letters and digits.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SubscriptionToken(String);
+
+#[derive(Debug, thiserror::Error)]
+pub enum ParseSubscriptionTokenError {
+    #[error("a subscription token must be {LENGTH} characters long")]
+    InvalidLength,
+    #[error("a subscription token can only contain ASCII letters and digits")]
+    InvalidCharacters,
+}
+
+impl SubscriptionToken {
+    /// A new random token.
+    pub fn generate() -> Self {
+        let mut rng = thread_rng();
+
+        Self(
+            std::iter::repeat_with(|| rng.sample(Alphanumeric))
+                .map(char::from)
+                .take(LENGTH)
+                .collect(),
+        )
+    }
+}
+
+impl std::str::FromStr for SubscriptionToken {
+    type Err = ParseSubscriptionTokenError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.len() != LENGTH {
+            return Err(ParseSubscriptionTokenError::InvalidLength);
+        }
+        if !s.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return Err(ParseSubscriptionTokenError::InvalidCharacters);
+        }
+        Ok(Self(s.to_owned()))
+    }
+}
+
+impl std::fmt::Display for SubscriptionToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl AsRef<str> for SubscriptionToken {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claim::assert_err;
+
+    use super::SubscriptionToken;
+
+    #[test]
+    fn a_token_of_the_wrong_length_is_rejected() {
+        assert_err!("".parse::<SubscriptionToken>());
+        assert_err!("a".repeat(24).parse::<SubscriptionToken>());
+        assert_err!("a".repeat(26).parse::<SubscriptionToken>());
+    }
+
+    #[test]
+    fn a_token_outside_of_the_alphabet_is_rejected() {
+        assert_err!("aaaaaaaaaaaa'OR'1'='1aaaa".parse::<SubscriptionToken>());
+        assert_err!("ééééééééééééa".parse::<SubscriptionToken>());
+    }
+
+    #[quickcheck_macros::quickcheck]
+    fn generated_tokens_are_parsed_successfully() -> bool {
+        let token = SubscriptionToken::generate();
+        token.as_ref().parse::<SubscriptionToken>().ok() == Some(token)
+    }
+}
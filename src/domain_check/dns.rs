@@ -0,0 +1,181 @@
+use std::io;
+
+use async_trait::async_trait;
+use hickory_resolver::{
+    config::{ResolverConfig, ResolverOpts},
+    error::{ResolveError, ResolveErrorKind},
+    proto::{op::ResponseCode, rr::rdata::MX},
+    TokioAsyncResolver,
+};
+
+use super::{DomainResolver, DomainStatus};
+
+/// Resolve the domains with the name servers, search domains and options of `/etc/resolv.conf`.
+/// The answers truncated over UDP are queried again over TCP, and the records that do not answer
+/// the question are dropped.
+pub struct SystemResolver {
+    resolver: TokioAsyncResolver,
+}
+
+/// The answer to an MX query.
+#[derive(Debug, Eq, PartialEq)]
+enum MxAnswer {
+    /// The domain does not exist.
+    NoDomain,
+    /// The exchanges of the domain, sorted by preference, possibly empty.
+    Exchanges(Vec<String>),
+}
+
+impl SystemResolver {
+    pub fn new(resolver: TokioAsyncResolver) -> Self {
+        Self { resolver }
+    }
+
+    /// Use the configuration of the system.
+    pub fn from_system() -> Self {
+        let resolver = TokioAsyncResolver::tokio_from_system_conf().unwrap_or_else(|error| {
+            tracing::warn!(
+                error.message = %error,
+                "No name server found, the MX records cannot be resolved"
+            );
+            TokioAsyncResolver::tokio(ResolverConfig::new(), ResolverOpts::default())
+        });
+        Self::new(resolver)
+    }
+
+    async fn query_mx(&self, domain: &str) -> io::Result<MxAnswer> {
+        let lookup = self
+            .resolver
+            .mx_lookup(domain)
+            .await
+            .map(|lookup| lookup.iter().cloned().collect());
+        mx_answer(lookup)
+    }
+
+    /// Whether the domain has an address, where the emails go without MX records.
+    async fn has_address(&self, domain: &str) -> io::Result<bool> {
+        match self.resolver.lookup_ip(domain).await {
+            Ok(lookup) => Ok(lookup.iter().next().is_some()),
+            Err(error) if matches!(error.kind(), ResolveErrorKind::NoRecordsFound { .. }) => {
+                Ok(false)
+            }
+            Err(error) => Err(io::Error::other(error)),
+        }
+    }
+}
+
+#[async_trait]
+impl DomainResolver for SystemResolver {
+    async fn resolve(&self, domain: &str) -> io::Result<DomainStatus> {
+        match self.query_mx(domain).await? {
+            MxAnswer::NoDomain => Ok(DomainStatus::NoMail),
+            // A null MX, RFC 7505, states that the domain does not accept emails
+            MxAnswer::Exchanges(exchanges) if exchanges.iter().all(String::is_empty) => {
+                if exchanges.is_empty() && self.has_address(domain).await? {
+                    return Ok(DomainStatus::AcceptsMail);
+                }
+                Ok(DomainStatus::NoMail)
+            }
+            MxAnswer::Exchanges(_) => Ok(DomainStatus::AcceptsMail),
+        }
+    }
+}
+
+fn mx_answer(lookup: Result<Vec<MX>, ResolveError>) -> io::Result<MxAnswer> {
+    let records = match lookup {
+        Ok(records) => records,
+        Err(error) => {
+            return match error.kind() {
+                ResolveErrorKind::NoRecordsFound {
+                    response_code: ResponseCode::NXDomain,
+                    ..
+                } => Ok(MxAnswer::NoDomain),
+                // The domain exists, with other records
+                ResolveErrorKind::NoRecordsFound { .. } => Ok(MxAnswer::Exchanges(Vec::new())),
+                _ => Err(io::Error::other(error)),
+            };
+        }
+    };
+    let mut exchanges: Vec<_> = records
+        .iter()
+        .map(|record| {
+            let exchange = record.exchange();
+            let exchange = if exchange.is_root() {
+                String::new()
+            } else {
+                exchange.to_utf8().trim_end_matches('.').to_owned()
+            };
+            (record.preference(), exchange)
+        })
+        .collect();
+    exchanges.sort();
+    Ok(MxAnswer::Exchanges(
+        exchanges
+            .into_iter()
+            .map(|(_, exchange)| exchange)
+            .collect(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use hickory_resolver::{
+        error::{ResolveError, ResolveErrorKind},
+        proto::{
+            op::{Query, ResponseCode},
+            rr::{rdata::MX, Name, RecordType},
+        },
+    };
+
+    use super::{mx_answer, MxAnswer};
+
+    fn no_records(response_code: ResponseCode) -> ResolveError {
+        ResolveErrorKind::NoRecordsFound {
+            query: Box::new(Query::query(
+                Name::from_ascii("example.com.").unwrap(),
+                RecordType::MX,
+            )),
+            soa: None,
+            negative_ttl: None,
+            response_code,
+            trusted: true,
+        }
+        .into()
+    }
+
+    #[test]
+    fn the_exchanges_are_sorted_by_preference() {
+        let records = vec![
+            MX::new(20, Name::from_ascii("mx2.example.com.").unwrap()),
+            MX::new(10, Name::from_ascii("mx1.example.com.").unwrap()),
+        ];
+
+        assert_eq!(
+            mx_answer(Ok(records)).unwrap(),
+            MxAnswer::Exchanges(vec!["mx1.example.com".into(), "mx2.example.com".into()])
+        );
+    }
+
+    #[test]
+    fn a_null_mx_has_an_empty_exchange() {
+        let records = vec![MX::new(0, Name::root())];
+
+        assert_eq!(
+            mx_answer(Ok(records)).unwrap(),
+            MxAnswer::Exchanges(vec!["".into()])
+        );
+    }
+
+    #[test]
+    fn an_unknown_domain_is_reported() {
+        assert_eq!(
+            mx_answer(Err(no_records(ResponseCode::NXDomain))).unwrap(),
+            MxAnswer::NoDomain
+        );
+        assert_eq!(
+            mx_answer(Err(no_records(ResponseCode::NoError))).unwrap(),
+            MxAnswer::Exchanges(vec![])
+        );
+        assert!(mx_answer(Err(ResolveErrorKind::Timeout.into())).is_err());
+    }
+}
@@ -0,0 +1,365 @@
+mod dns;
+
+use std::{
+    collections::HashMap,
+    io,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+
+pub use self::dns::SystemResolver;
+use crate::{
+    domain::EmailAddress,
+    subscription_policy::{Rejection, SubscriptionPolicy},
+};
+
+/// Domains of the largest email providers, a domain a couple of typos away from one of them is
+/// likely misspelt.
+const POPULAR_DOMAINS: &[&str] = &[
+    "gmail.com",
+    "googlemail.com",
+    "yahoo.com",
+    "yahoo.co.uk",
+    "yahoo.fr",
+    "hotmail.com",
+    "hotmail.co.uk",
+    "hotmail.fr",
+    "outlook.com",
+    "live.com",
+    "msn.com",
+    "icloud.com",
+    "me.com",
+    "aol.com",
+    "proton.me",
+    "protonmail.com",
+    "gmx.com",
+    "gmx.de",
+    "web.de",
+    "orange.fr",
+    "free.fr",
+    "comcast.net",
+    "yandex.ru",
+    "mail.ru",
+    "fastmail.com",
+    "zoho.com",
+];
+
+/// Domains of real providers a couple of typos away from a popular domain, they never get a
+/// suggestion.
+const KNOWN_PROVIDER_DOMAINS: &[&str] = &[
+    "aim.com",
+    "email.com",
+    "gmx.at",
+    "gmx.ch",
+    "gmx.net",
+    "hey.com",
+    "hotmail.de",
+    "hotmail.es",
+    "hotmail.it",
+    "live.de",
+    "live.fr",
+    "mac.com",
+    "mail.com",
+    "mail.de",
+    "qq.com",
+    "web.com",
+    "yahoo.de",
+    "yahoo.es",
+    "yahoo.it",
+    "ymail.com",
+];
+
+/// Largest number of typos corrected by a suggestion.
+const MAX_SUGGESTION_DISTANCE: usize = 2;
+/// Largest number of domains kept in the cache.
+const MAX_CACHED_DOMAINS: usize = 10_000;
+
+/// Whether a domain can receive emails.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DomainStatus {
+    AcceptsMail,
+    NoMail,
+}
+
+/// Look up the mail servers of a domain, the MX records first and then the A and AAAA records.
+#[async_trait]
+pub trait DomainResolver: Send + Sync {
+    async fn resolve(&self, domain: &str) -> Result<DomainStatus, io::Error>;
+}
+
+/// Check that the domain of a new subscriber can receive emails, the answers of the resolver are
+/// cached and shared by all the clones.
+#[derive(Clone)]
+pub struct DomainChecker {
+    resolver: Arc<dyn DomainResolver>,
+    timeout: Duration,
+    cache_ttl: Duration,
+    cache: Arc<Mutex<HashMap<String, (DomainStatus, Instant)>>>,
+}
+
+impl DomainChecker {
+    pub fn new(resolver: Arc<dyn DomainResolver>, timeout: Duration, cache_ttl: Duration) -> Self {
+        Self {
+            resolver,
+            timeout,
+            cache_ttl,
+            cache: Default::default(),
+        }
+    }
+
+    /// Reject the address if its domain does not accept emails, with a suggestion if it is a
+    /// close misspelling of a popular domain and not one of the `allowed_domains` of the policy.
+    /// A domain with mail servers is accepted, the suggestion is only logged. The address is
+    /// accepted if the resolver fails or is too slow, a broken resolver must not stop the
+    /// subscriptions.
+    #[tracing::instrument(name = "Check the domain of an address", skip(self, email, policy))]
+    pub async fn check(
+        &self,
+        email: &EmailAddress,
+        policy: &SubscriptionPolicy,
+    ) -> Result<(), Rejection> {
+        let domain = email.domain();
+        // A domain literal is an address, there is nothing to resolve
+        if domain.starts_with('[') {
+            return Ok(());
+        }
+        let suggestion = if policy.is_allowed(domain) {
+            None
+        } else {
+            suggest(domain)
+        };
+        match self.status(domain).await {
+            Ok(DomainStatus::AcceptsMail) => {
+                if let Some(suggestion) = suggestion {
+                    tracing::info!(
+                        suggestion,
+                        "The domain looks misspelt but accepts emails, the address is accepted"
+                    );
+                }
+                Ok(())
+            }
+            Ok(DomainStatus::NoMail) => match suggestion {
+                Some(suggestion) => Err(Rejection::MisspeltDomain {
+                    suggestion: suggestion.to_owned(),
+                }),
+                None => Err(Rejection::UndeliverableDomain),
+            },
+            Err(error) => {
+                tracing::warn!(
+                    error.message = %error,
+                    domain,
+                    "Failed to resolve the domain, the address is accepted"
+                );
+                Ok(())
+            }
+        }
+    }
+
+    async fn status(&self, domain: &str) -> Result<DomainStatus, io::Error> {
+        if let Some(status) = self.cached(domain) {
+            return Ok(status);
+        }
+        let status = tokio::time::timeout(self.timeout, self.resolver.resolve(domain))
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "the resolver timed out"))??;
+
+        let mut cache = self.cache.lock().unwrap();
+        if cache.len() >= MAX_CACHED_DOMAINS {
+            let now = Instant::now();
+            cache.retain(|_, (_, expires_at)| *expires_at > now);
+            if cache.len() >= MAX_CACHED_DOMAINS {
+                cache.clear();
+            }
+        }
+        cache.insert(domain.to_owned(), (status, Instant::now() + self.cache_ttl));
+        Ok(status)
+    }
+
+    fn cached(&self, domain: &str) -> Option<DomainStatus> {
+        let cache = self.cache.lock().unwrap();
+        cache
+            .get(domain)
+            .filter(|(_, expires_at)| *expires_at > Instant::now())
+            .map(|(status, _)| *status)
+    }
+}
+
+/// The popular domain `domain` is probably a misspelling of.
+pub fn suggest(domain: &str) -> Option<&'static str> {
+    if POPULAR_DOMAINS.contains(&domain) || KNOWN_PROVIDER_DOMAINS.contains(&domain) {
+        return None;
+    }
+    POPULAR_DOMAINS
+        .iter()
+        .map(|candidate| (strsim::damerau_levenshtein(domain, candidate), *candidate))
+        .filter(|(distance, _)| *distance <= MAX_SUGGESTION_DISTANCE)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use async_trait::async_trait;
+    use claim::{assert_none, assert_ok};
+
+    use super::{suggest, DomainChecker, DomainResolver, DomainStatus};
+    use crate::{
+        domain::EmailAddress,
+        subscription_policy::{parse_domain_list, PolicyRules, Rejection, SubscriptionPolicy},
+    };
+
+    /// Only `example.com`, `gmial.com` and the real providers accept emails, `slow.example`
+    /// never answers and `broken.example` fails.
+    #[derive(Default)]
+    struct StubResolver {
+        calls: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl DomainResolver for StubResolver {
+        async fn resolve(&self, domain: &str) -> Result<DomainStatus, io::Error> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            match domain {
+                "example.com" | "gmial.com" | "mail.com" | "gmx.net" | "yahoo.de" => {
+                    Ok(DomainStatus::AcceptsMail)
+                }
+                "slow.example" => futures::future::pending().await,
+                "broken.example" => Err(io::Error::other("SERVFAIL")),
+                _ => Ok(DomainStatus::NoMail),
+            }
+        }
+    }
+
+    fn checker(resolver: Arc<StubResolver>) -> DomainChecker {
+        DomainChecker::new(resolver, Duration::from_millis(50), Duration::from_secs(60))
+    }
+
+    fn email(email: &str) -> EmailAddress {
+        email.parse().unwrap()
+    }
+
+    fn policy() -> SubscriptionPolicy {
+        SubscriptionPolicy::new(PolicyRules {
+            allowed_domains: parse_domain_list("gmial.org"),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn a_close_misspelling_of_a_popular_domain_gets_a_suggestion() {
+        assert_eq!(suggest("gmial.com"), Some("gmail.com"));
+        assert_eq!(suggest("hotmal.com"), Some("hotmail.com"));
+        assert_eq!(suggest("yahoo.con"), Some("yahoo.com"));
+    }
+
+    #[test]
+    fn a_popular_or_distant_domain_gets_no_suggestion() {
+        assert_none!(suggest("gmail.com"));
+        assert_none!(suggest("example.com"));
+    }
+
+    #[test]
+    fn a_real_provider_close_to_a_popular_domain_gets_no_suggestion() {
+        for domain in [
+            "mail.com",
+            "ymail.com",
+            "mac.com",
+            "qq.com",
+            "gmx.net",
+            "yahoo.de",
+        ] {
+            assert_none!(suggest(domain), "{} got a suggestion", domain);
+        }
+    }
+
+    #[tokio::test]
+    async fn a_domain_without_mail_servers_is_rejected() {
+        let checker = checker(Default::default());
+
+        assert_ok!(checker.check(&email("ursula@example.com"), &policy()).await);
+        assert_eq!(
+            checker
+                .check(&email("ursula@nowhere.example"), &policy())
+                .await,
+            Err(Rejection::UndeliverableDomain)
+        );
+    }
+
+    #[tokio::test]
+    async fn a_misspelt_domain_without_mail_servers_is_rejected_with_a_suggestion() {
+        let checker = checker(Default::default());
+
+        assert_eq!(
+            checker.check(&email("ursula@gmal.com"), &policy()).await,
+            Err(Rejection::MisspeltDomain {
+                suggestion: "gmail.com".into()
+            })
+        );
+        // The allowed domains get no suggestion
+        assert_eq!(
+            checker.check(&email("ursula@gmial.org"), &policy()).await,
+            Err(Rejection::UndeliverableDomain)
+        );
+    }
+
+    #[tokio::test]
+    async fn a_domain_close_to_a_popular_domain_with_mail_servers_is_accepted() {
+        let checker = checker(Default::default());
+
+        for address in [
+            "ursula@gmial.com",
+            "ursula@mail.com",
+            "ursula@gmx.net",
+            "ursula@yahoo.de",
+        ] {
+            assert_ok!(checker.check(&email(address), &policy()).await);
+        }
+    }
+
+    #[tokio::test]
+    async fn the_answers_of_the_resolver_are_cached() {
+        let resolver = Arc::new(StubResolver::default());
+        let checker = checker(resolver.clone());
+
+        for _ in 0..3 {
+            assert_ok!(checker.check(&email("ursula@example.com"), &policy()).await);
+        }
+
+        assert_eq!(resolver.calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn the_address_is_accepted_if_the_resolver_fails_or_times_out() {
+        let resolver = Arc::new(StubResolver::default());
+        let checker = checker(resolver.clone());
+
+        assert_ok!(
+            checker
+                .check(&email("ursula@slow.example"), &policy())
+                .await
+        );
+        assert_ok!(
+            checker
+                .check(&email("ursula@broken.example"), &policy())
+                .await
+        );
+        // The failures are not cached
+        assert_ok!(
+            checker
+                .check(&email("ursula@broken.example"), &policy())
+                .await
+        );
+        assert_eq!(resolver.calls.load(Ordering::SeqCst), 3);
+    }
+}
@@ -0,0 +1,354 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use tokio::time::Instant;
+
+/// When the circuit opens and how it closes again.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CircuitBreakerPolicy {
+    /// Share of failed requests, between 0 and 1, that opens the circuit.
+    pub failure_rate_threshold: f64,
+    /// Number of recent requests the failure rate is computed on, the circuit cannot open
+    /// before that many requests were made.
+    pub window_size: u32,
+    /// Time during which the requests fail fast before the provider is probed again.
+    pub open_duration: Duration,
+    /// Number of successful probes required to close the circuit.
+    pub half_open_probes: u32,
+}
+
+/// The states of the circuit breaker.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CircuitState {
+    /// The requests go through.
+    Closed,
+    /// The provider is considered down, the requests fail fast.
+    Open,
+    /// A few probes go through to find out whether the provider is back.
+    HalfOpen,
+}
+
+impl CircuitState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CircuitState::Closed => "closed",
+            CircuitState::Open => "open",
+            CircuitState::HalfOpen => "half_open",
+        }
+    }
+}
+
+/// The request was not sent, the circuit breaker is open.
+#[derive(Debug, thiserror::Error)]
+#[error("the email provider is unavailable, the circuit breaker is open")]
+pub struct CircuitOpen;
+
+/// Stop calling the provider while most of the requests fail, it is shared by all the clones of
+/// the `EmailClient`.
+#[derive(Clone)]
+pub struct CircuitBreaker {
+    state: Arc<Mutex<State>>,
+}
+
+struct State {
+    policy: CircuitBreakerPolicy,
+    circuit: Circuit,
+    /// Outcomes of the last requests while closed, `true` for a failure.
+    outcomes: VecDeque<bool>,
+    /// Incremented on every change of state, the outcomes of older requests are ignored.
+    generation: u64,
+}
+
+enum Circuit {
+    Closed,
+    Open { until: Instant },
+    HalfOpen { in_flight: u32, succeeded: u32 },
+}
+
+impl CircuitBreaker {
+    pub fn new(policy: CircuitBreakerPolicy) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(State {
+                policy,
+                circuit: Circuit::Closed,
+                outcomes: VecDeque::new(),
+                generation: 0,
+            })),
+        }
+    }
+
+    pub fn policy(&self) -> CircuitBreakerPolicy {
+        self.state.lock().unwrap().policy.clone()
+    }
+
+    /// Replace the policy, the current state of the circuit is kept.
+    pub fn set_policy(&self, policy: CircuitBreakerPolicy) {
+        let mut state = self.state.lock().unwrap();
+        state.policy = policy;
+        state.trim();
+    }
+
+    pub fn state(&self) -> CircuitState {
+        let mut state = self.state.lock().unwrap();
+        state.refresh(Instant::now());
+        match state.circuit {
+            Circuit::Closed => CircuitState::Closed,
+            Circuit::Open { .. } => CircuitState::Open,
+            Circuit::HalfOpen { .. } => CircuitState::HalfOpen,
+        }
+    }
+
+    /// Ask to send a request, its outcome must be reported through the returned permit.
+    pub fn try_acquire(&self) -> Result<Permit, CircuitOpen> {
+        let mut state = self.state.lock().unwrap();
+        state.refresh(Instant::now());
+        let half_open_probes = state.policy.half_open_probes;
+        match &mut state.circuit {
+            Circuit::Closed => {}
+            Circuit::Open { .. } => return Err(CircuitOpen),
+            Circuit::HalfOpen {
+                in_flight,
+                succeeded,
+            } => {
+                if *in_flight + *succeeded >= half_open_probes {
+                    return Err(CircuitOpen);
+                }
+                *in_flight += 1;
+            }
+        }
+        Ok(Permit {
+            breaker: self.clone(),
+            generation: state.generation,
+            reported: false,
+        })
+    }
+}
+
+impl State {
+    /// Move from open to half-open once the open duration is over.
+    fn refresh(&mut self, now: Instant) {
+        if let Circuit::Open { until } = self.circuit {
+            if now >= until {
+                self.set(Circuit::HalfOpen {
+                    in_flight: 0,
+                    succeeded: 0,
+                });
+                tracing::info!("Probing the email provider, the circuit breaker is half-open");
+            }
+        }
+    }
+
+    fn trim(&mut self) {
+        while self.outcomes.len() > self.policy.window_size as usize {
+            self.outcomes.pop_front();
+        }
+    }
+
+    fn set(&mut self, circuit: Circuit) {
+        self.circuit = circuit;
+        self.outcomes.clear();
+        self.generation += 1;
+    }
+
+    fn open(&mut self) {
+        self.set(Circuit::Open {
+            until: Instant::now() + self.policy.open_duration,
+        });
+        tracing::warn!(
+            open_duration = ?self.policy.open_duration,
+            "The email provider keeps failing, the circuit breaker is open"
+        );
+    }
+
+    fn record(&mut self, generation: u64, failed: bool) {
+        if generation != self.generation {
+            // The request started before the last change of state, its outcome is outdated
+            return;
+        }
+        match &mut self.circuit {
+            Circuit::Closed => {
+                self.outcomes.push_back(failed);
+                self.trim();
+                let failures = self.outcomes.iter().filter(|&&failed| failed).count();
+                let window_size = self.policy.window_size as usize;
+                if failed
+                    && self.outcomes.len() >= window_size
+                    && failures as f64 >= self.policy.failure_rate_threshold * window_size as f64
+                {
+                    self.open();
+                }
+            }
+            Circuit::HalfOpen {
+                in_flight,
+                succeeded,
+            } => {
+                *in_flight -= 1;
+                if failed {
+                    self.open();
+                } else {
+                    *succeeded += 1;
+                    if *succeeded >= self.policy.half_open_probes {
+                        self.set(Circuit::Closed);
+                        tracing::info!("The email provider is back, the circuit breaker is closed");
+                    }
+                }
+            }
+            Circuit::Open { .. } => {}
+        }
+    }
+}
+
+/// The right to send one request, a probe that is dropped without an outcome frees its slot.
+pub struct Permit {
+    breaker: CircuitBreaker,
+    generation: u64,
+    reported: bool,
+}
+
+impl Permit {
+    pub fn succeeded(mut self) {
+        self.report(false);
+    }
+
+    pub fn failed(mut self) {
+        self.report(true);
+    }
+
+    fn report(&mut self, failed: bool) {
+        self.reported = true;
+        self.breaker
+            .state
+            .lock()
+            .unwrap()
+            .record(self.generation, failed);
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        if self.reported {
+            return;
+        }
+        let mut state = self.breaker.state.lock().unwrap();
+        if state.generation != self.generation {
+            return;
+        }
+        if let Circuit::HalfOpen { in_flight, .. } = &mut state.circuit {
+            *in_flight -= 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use claim::assert_ok;
+
+    use super::{CircuitBreaker, CircuitBreakerPolicy, CircuitState};
+
+    fn breaker() -> CircuitBreaker {
+        CircuitBreaker::new(CircuitBreakerPolicy {
+            failure_rate_threshold: 0.5,
+            window_size: 4,
+            open_duration: Duration::from_millis(50),
+            half_open_probes: 2,
+        })
+    }
+
+    fn open(breaker: &CircuitBreaker) {
+        for _ in 0..4 {
+            breaker.try_acquire().unwrap().failed();
+        }
+        assert_eq!(breaker.state(), CircuitState::Open);
+    }
+
+    #[test]
+    fn the_circuit_opens_when_the_failure_rate_reaches_the_threshold() {
+        let breaker = breaker();
+
+        breaker.try_acquire().unwrap().succeeded();
+        breaker.try_acquire().unwrap().failed();
+        breaker.try_acquire().unwrap().succeeded();
+        assert_eq!(breaker.state(), CircuitState::Closed);
+        breaker.try_acquire().unwrap().failed();
+
+        assert_eq!(breaker.state(), CircuitState::Open);
+        assert!(breaker.try_acquire().is_err());
+    }
+
+    #[test]
+    fn the_circuit_stays_closed_below_the_threshold() {
+        let breaker = breaker();
+
+        for _ in 0..10 {
+            for _ in 0..3 {
+                breaker.try_acquire().unwrap().succeeded();
+            }
+            breaker.try_acquire().unwrap().failed();
+        }
+
+        assert_eq!(breaker.state(), CircuitState::Closed);
+    }
+
+    #[tokio::test]
+    async fn successful_probes_close_the_circuit() {
+        let breaker = breaker();
+        open(&breaker);
+
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+        let first = breaker.try_acquire().unwrap();
+        let second = breaker.try_acquire().unwrap();
+        // Only `half_open_probes` requests go through at once
+        assert!(breaker.try_acquire().is_err());
+        first.succeeded();
+        second.succeeded();
+
+        assert_eq!(breaker.state(), CircuitState::Closed);
+        assert_ok!(breaker.try_acquire());
+    }
+
+    #[tokio::test]
+    async fn a_failed_probe_opens_the_circuit_again() {
+        let breaker = breaker();
+        open(&breaker);
+
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        breaker.try_acquire().unwrap().succeeded();
+        breaker.try_acquire().unwrap().failed();
+
+        assert_eq!(breaker.state(), CircuitState::Open);
+    }
+
+    #[tokio::test]
+    async fn a_dropped_probe_frees_its_slot() {
+        let breaker = breaker();
+        open(&breaker);
+
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        drop(breaker.try_acquire().unwrap());
+        drop(breaker.try_acquire().unwrap());
+
+        assert_ok!(breaker.try_acquire());
+    }
+
+    #[tokio::test]
+    async fn the_outcome_of_a_request_started_before_a_change_of_state_is_ignored() {
+        let breaker = breaker();
+        open(&breaker);
+
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        let late = breaker.try_acquire().unwrap();
+        breaker.try_acquire().unwrap().failed();
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        late.succeeded();
+
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+        assert_ok!(breaker.try_acquire());
+        assert_ok!(breaker.try_acquire());
+    }
+}
@@ -0,0 +1,1076 @@
+mod circuit_breaker;
+mod postmark;
+mod rate_limiter;
+mod smtp;
+
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use crate::{domain::EmailAddress, secret::Secret};
+
+use http::StatusCode;
+
+pub use circuit_breaker::{CircuitBreaker, CircuitBreakerPolicy, CircuitOpen, CircuitState};
+pub use postmark::Postmark;
+pub use rate_limiter::{Priority, RateLimiter};
+pub use smtp::{SmtpRelay, SmtpTls};
+
+/// Name of the provider given to `EmailClient::new`.
+pub const PRIMARY_PROVIDER: &str = "postmark";
+
+/// Send the emails through the first available provider, the next ones are used when it fails.
+#[derive(Clone)]
+pub struct EmailClient {
+    /// The providers in priority order, there is at least one.
+    providers: Vec<Provider>,
+    sender: EmailAddress,
+    policy: Arc<RwLock<EmailClientPolicy>>,
+}
+
+/// How to reach an email provider.
+#[derive(Clone)]
+pub enum Transport {
+    Postmark(Postmark),
+    Smtp(SmtpRelay),
+}
+
+/// A provider has its own circuit breaker and its own rate limiter, a failing or slow provider
+/// does not slow down the others.
+#[derive(Clone)]
+struct Provider {
+    name: String,
+    transport: Transport,
+    circuit_breaker: CircuitBreaker,
+    rate_limiter: RateLimiter,
+}
+
+/// Delivery parameters, they can be changed while the client is running.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EmailClientPolicy {
+    /// Maximum time allowed for a single request.
+    pub timeout: Duration,
+    /// Number of retries after a transient failure.
+    pub max_retries: u32,
+    /// Waiting time before the first retry, it grows linearly with the attempts.
+    pub retry_backoff: Duration,
+    /// Maximum number of emails sent per second through the primary provider, `None` if the rate
+    /// is not limited.
+    pub max_messages_per_second: Option<u32>,
+    /// Maximum number of emails sent per second through each fallback provider, by name.
+    pub fallback_max_messages_per_second: BTreeMap<String, Option<u32>>,
+    /// When to stop calling a failing provider.
+    pub circuit_breaker: CircuitBreakerPolicy,
+}
+
+/// Maximum number of emails sent by a single call to the batch API.
+pub const MAX_BATCH_SIZE: usize = 500;
+
+/// An email to send.
+#[derive(Clone, Copy, Debug)]
+pub struct OutgoingEmail<'a> {
+    pub recipient: &'a EmailAddress,
+    pub subject: &'a str,
+    pub html_content: &'a str,
+    pub text_content: &'a str,
+}
+
+/// An email of a batch refused by the provider, the other emails of the batch are not affected.
+#[derive(Debug, PartialEq, thiserror::Error)]
+#[error("the provider rejected the email: {message} (error code {code})")]
+pub struct RejectedEmail {
+    pub code: i64,
+    pub message: String,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum EmailClientError {
+    #[error("failed to call the email provider")]
+    Request(#[from] reqwest::Error),
+    #[error("failed to call the SMTP relay")]
+    Smtp(#[from] lettre::transport::smtp::Error),
+    #[error("the email cannot be written for the SMTP relay")]
+    InvalidMessage(#[from] lettre::error::Error),
+    #[error("the address cannot be used with the SMTP relay")]
+    InvalidAddress(#[from] lettre::address::AddressError),
+    #[error(transparent)]
+    Rejected(#[from] RejectedEmail),
+    #[error(transparent)]
+    CircuitOpen(#[from] CircuitOpen),
+    #[error("the provider did not answer in time")]
+    Timeout,
+    #[error("a batch holds at most {} emails, this one has {size}", MAX_BATCH_SIZE)]
+    BatchTooLarge { size: usize },
+}
+
+impl EmailClientError {
+    /// Whether sending the email again can succeed without changing the request.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            EmailClientError::Request(error) => {
+                is_outage(self) || error.status() == Some(StatusCode::TOO_MANY_REQUESTS)
+            }
+            EmailClientError::Smtp(error) => error.is_transient() || is_outage(self),
+            EmailClientError::CircuitOpen(_) | EmailClientError::Timeout => true,
+            EmailClientError::BatchTooLarge { .. }
+            | EmailClientError::InvalidMessage(_)
+            | EmailClientError::InvalidAddress(_)
+            | EmailClientError::Rejected(_) => false,
+        }
+    }
+}
+
+/// Whether the failure counts against the provider in the circuit breaker, being asked to slow
+/// down or sending an invalid request does not.
+fn is_outage(error: &EmailClientError) -> bool {
+    match error {
+        EmailClientError::Request(error) => {
+            error.is_timeout()
+                || error.is_connect()
+                || error
+                    .status()
+                    .is_some_and(|status| status.is_server_error())
+        }
+        // 421: the service is not available, the relay is closing the connection
+        EmailClientError::Smtp(error) => {
+            error.status().is_some_and(|code| u16::from(code) == 421)
+                || !(error.is_transient()
+                    || error.is_permanent()
+                    || error.is_response()
+                    || error.is_client())
+        }
+        EmailClientError::Timeout => true,
+        _ => false,
+    }
+}
+
+/// An email accepted by a provider.
+#[derive(Debug, PartialEq)]
+pub struct SentEmail {
+    /// Identifier given by the provider, its webhooks refer to it.
+    pub message_id: Option<String>,
+    /// Name of the provider that accepted the email.
+    pub provider: String,
+}
+
+impl EmailClient {
+    /// Create a client sending through the Postmark account at `base_url`, it is the primary
+    /// provider named `PRIMARY_PROVIDER`.
+    pub fn new(
+        base_url: &str,
+        sender: EmailAddress,
+        authorization_token: Secret,
+        policy: EmailClientPolicy,
+    ) -> Self {
+        let primary = Provider {
+            name: PRIMARY_PROVIDER.into(),
+            transport: Transport::Postmark(Postmark::new(base_url, authorization_token)),
+            circuit_breaker: CircuitBreaker::new(policy.circuit_breaker.clone()),
+            rate_limiter: RateLimiter::new(policy.max_messages_per_second),
+        };
+        let policy = Arc::new(RwLock::new(policy));
+
+        Self {
+            providers: vec![primary],
+            sender,
+            policy,
+        }
+    }
+
+    /// Add a provider used when all the previous ones failed, it sends `max_messages_per_second`
+    /// emails per second at most.
+    pub fn with_fallback(
+        mut self,
+        name: &str,
+        transport: Transport,
+        max_messages_per_second: Option<u32>,
+    ) -> Self {
+        self.providers.push(Provider {
+            name: name.to_owned(),
+            transport,
+            circuit_breaker: CircuitBreaker::new(self.policy().circuit_breaker),
+            rate_limiter: RateLimiter::new(max_messages_per_second),
+        });
+        self.policy
+            .write()
+            .unwrap()
+            .fallback_max_messages_per_second
+            .insert(name.to_owned(), max_messages_per_second);
+        self
+    }
+
+    /// The current delivery policy.
+    pub fn policy(&self) -> EmailClientPolicy {
+        self.policy.read().unwrap().clone()
+    }
+
+    /// Replace the delivery policy, it is shared by all the clones of this client. The fallback
+    /// providers missing from `policy` keep their rate.
+    pub fn set_policy(&self, mut policy: EmailClientPolicy) {
+        for (index, provider) in self.providers.iter().enumerate() {
+            let max_messages_per_second = if index == 0 {
+                policy.max_messages_per_second
+            } else {
+                *policy
+                    .fallback_max_messages_per_second
+                    .entry(provider.name.clone())
+                    .or_insert_with(|| provider.rate_limiter.max_per_second())
+            };
+            provider
+                .rate_limiter
+                .set_max_per_second(max_messages_per_second);
+            provider
+                .circuit_breaker
+                .set_policy(policy.circuit_breaker.clone());
+        }
+        *self.policy.write().unwrap() = policy;
+    }
+
+    /// The names of the providers in priority order, with their circuit breaker and their rate
+    /// limiter.
+    pub fn providers(&self) -> impl Iterator<Item = (&str, &CircuitBreaker, &RateLimiter)> {
+        self.providers.iter().map(|provider| {
+            (
+                provider.name.as_str(),
+                &provider.circuit_breaker,
+                &provider.rate_limiter,
+            )
+        })
+    }
+
+    /// The circuit breaker of the provider named `name`.
+    pub fn circuit_breaker(&self, name: &str) -> Option<CircuitBreaker> {
+        self.providers()
+            .find(|(provider, _, _)| *provider == name)
+            .map(|(_, circuit_breaker, _)| circuit_breaker.clone())
+    }
+
+    /// The rate limiter of the provider named `name`, it is shared by all the senders.
+    pub fn rate_limiter(&self, name: &str) -> Option<RateLimiter> {
+        self.providers()
+            .find(|(provider, _, _)| *provider == name)
+            .map(|(_, _, rate_limiter)| rate_limiter.clone())
+    }
+
+    /// Send a single email, it goes through the transactional lane of the rate limiter.
+    pub async fn send_email(
+        &self,
+        recipient: &EmailAddress,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+    ) -> Result<SentEmail, EmailClientError> {
+        let email = &OutgoingEmail {
+            recipient,
+            subject,
+            html_content,
+            text_content,
+        };
+        let sender = &self.sender;
+
+        for (index, provider) in self.providers.iter().enumerate() {
+            let rate_limiter = &provider.rate_limiter;
+            let outcome = self
+                .with_retries(
+                    provider,
+                    Priority::Transactional,
+                    1,
+                    move |timeout| async move {
+                        match &provider.transport {
+                            Transport::Postmark(postmark) => {
+                                postmark
+                                    .send_email(sender, email, timeout, rate_limiter)
+                                    .await
+                            }
+                            Transport::Smtp(relay) => {
+                                relay.send_email(sender, email, timeout).await
+                            }
+                        }
+                    },
+                )
+                .await;
+            match outcome {
+                Ok(message_id) => {
+                    return Ok(SentEmail {
+                        message_id,
+                        provider: provider.name.clone(),
+                    })
+                }
+                Err(error) if error.is_transient() && index + 1 < self.providers.len() => {
+                    failing_over(provider, &error);
+                }
+                Err(error) => return Err(error),
+            }
+        }
+        unreachable!("An email client has at least one provider")
+    }
+
+    /// Send up to `MAX_BATCH_SIZE` emails, the outcome of each email is returned in the order of
+    /// `emails`. It goes through the bulk lane of the rate limiter.
+    pub async fn send_email_batch(
+        &self,
+        emails: &[OutgoingEmail<'_>],
+    ) -> Result<Vec<Result<SentEmail, EmailClientError>>, EmailClientError> {
+        if emails.len() > MAX_BATCH_SIZE {
+            return Err(EmailClientError::BatchTooLarge { size: emails.len() });
+        }
+        let sender = &self.sender;
+
+        for (index, provider) in self.providers.iter().enumerate() {
+            let rate_limiter = &provider.rate_limiter;
+            let outcome = match &provider.transport {
+                Transport::Postmark(postmark) => {
+                    self.with_retries(provider, Priority::Bulk, emails.len(), move |timeout| {
+                        postmark.send_email_batch(sender, emails, timeout, rate_limiter)
+                    })
+                    .await
+                }
+                Transport::Smtp(relay) => self.send_one_by_one(provider, relay, emails).await,
+            };
+            match outcome {
+                Ok(outcomes) => {
+                    return Ok(outcomes
+                        .into_iter()
+                        .map(|outcome| {
+                            outcome.map(|message_id| SentEmail {
+                                message_id,
+                                provider: provider.name.clone(),
+                            })
+                        })
+                        .collect())
+                }
+                Err(error) if error.is_transient() && index + 1 < self.providers.len() => {
+                    failing_over(provider, &error);
+                }
+                Err(error) => return Err(error),
+            }
+        }
+        unreachable!("An email client has at least one provider")
+    }
+
+    /// Send a batch one email at a time, for the providers without a batch API. The batch fails
+    /// as a whole only if its first email could not be sent.
+    async fn send_one_by_one(
+        &self,
+        provider: &Provider,
+        relay: &SmtpRelay,
+        emails: &[OutgoingEmail<'_>],
+    ) -> Result<Vec<Result<Option<String>, EmailClientError>>, EmailClientError> {
+        let sender = &self.sender;
+        let mut outcomes = Vec::with_capacity(emails.len());
+        for email in emails {
+            let outcome = self
+                .with_retries(provider, Priority::Bulk, 1, move |timeout| {
+                    relay.send_email(sender, email, timeout)
+                })
+                .await;
+            match outcome {
+                Err(error) if outcomes.is_empty() && error.is_transient() => return Err(error),
+                outcome => outcomes.push(outcome),
+            }
+        }
+        Ok(outcomes)
+    }
+
+    /// Call `send` with the timeout of the policy, the transient failures are retried according
+    /// to the policy. It fails fast while the circuit breaker of `provider` is open.
+    async fn with_retries<T, F, Fut>(
+        &self,
+        provider: &Provider,
+        priority: Priority,
+        messages: usize,
+        mut send: F,
+    ) -> Result<T, EmailClientError>
+    where
+        F: FnMut(Duration) -> Fut,
+        Fut: Future<Output = Result<T, EmailClientError>>,
+    {
+        let policy = self.policy();
+        let mut attempt = 0;
+        loop {
+            // The permit is taken once the request can go out, a request waiting for the rate
+            // limiter neither holds a probe of the half-open circuit nor skips a circuit opened
+            // meanwhile
+            provider.rate_limiter.acquire(priority, messages).await;
+            let permit = provider.circuit_breaker.try_acquire()?;
+            let outcome = send(policy.timeout).await;
+            match &outcome {
+                Err(error) if is_outage(error) => permit.failed(),
+                _ => permit.succeeded(),
+            }
+
+            match outcome {
+                Err(error) if attempt < policy.max_retries && error.is_transient() => {
+                    attempt += 1;
+                    tracing::warn!(
+                        error.cause_chain = ?error,
+                        provider = %provider.name,
+                        attempt,
+                        "Transient failure while sending an email, retrying"
+                    );
+                    tokio::time::sleep(policy.retry_backoff * attempt).await;
+                }
+                outcome => return outcome,
+            }
+        }
+    }
+}
+
+fn failing_over(provider: &Provider, error: &EmailClientError) {
+    tracing::warn!(
+        error.cause_chain = ?error,
+        provider = %provider.name,
+        "The email provider failed, trying the next one"
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{
+        CircuitBreakerPolicy, CircuitState, EmailClient, EmailClientError, EmailClientPolicy,
+        OutgoingEmail, Postmark, RejectedEmail, SentEmail, Transport, MAX_BATCH_SIZE,
+        PRIMARY_PROVIDER,
+    };
+
+    use crate::{domain::EmailAddress, secret::Secret};
+
+    use claim::{assert_err, assert_ok};
+    use fake::{
+        faker::{
+            internet::en::SafeEmail,
+            lorem::en::{Paragraph, Sentence},
+        },
+        Fake, Faker,
+    };
+    use wiremock::matchers::body_partial_json;
+    use wiremock::{
+        matchers::{any, header, header_exists, method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    struct SendEmailBodyMatcher;
+
+    impl wiremock::Match for SendEmailBodyMatcher {
+        fn matches(&self, request: &wiremock::Request) -> bool {
+            let result = serde_json::from_slice::<serde_json::Value>(&request.body);
+            if let Ok(body) = result {
+                body.get("From").is_some()
+                    && body.get("To").is_some()
+                    && body.get("Subject").is_some()
+                    && body.get("HtmlBody").is_some()
+                    && body.get("TextBody").is_some()
+            } else {
+                false
+            }
+        }
+    }
+
+    fn subject() -> String {
+        Sentence(1..2).fake()
+    }
+
+    fn content() -> String {
+        Paragraph(1..10).fake()
+    }
+
+    fn email() -> EmailAddress {
+        SafeEmail().fake::<String>().parse().unwrap()
+    }
+
+    fn policy() -> EmailClientPolicy {
+        EmailClientPolicy {
+            timeout: Duration::from_millis(200),
+            max_retries: 0,
+            retry_backoff: Duration::ZERO,
+            max_messages_per_second: None,
+            fallback_max_messages_per_second: Default::default(),
+            circuit_breaker: CircuitBreakerPolicy {
+                failure_rate_threshold: 0.5,
+                window_size: 2,
+                open_duration: Duration::from_secs(60),
+                half_open_probes: 1,
+            },
+        }
+    }
+
+    fn email_client(base_url: &str) -> EmailClient {
+        EmailClient::new(base_url, email(), Secret::new(Faker.fake()), policy())
+    }
+
+    #[tokio::test]
+    async fn send_email_sends_the_expected_request() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(&mock_server.uri());
+
+        Mock::given(header_exists("X-Postmark-Server-Token"))
+            .and(header("Content-Type", "application/json"))
+            .and(path("/email"))
+            .and(method("POST"))
+            .and(SendEmailBodyMatcher)
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let _ = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+    }
+
+    #[tokio::test]
+    async fn send_email_succeeds_if_the_server_returns_200() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(&mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        assert_ok!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_returns_the_message_id_given_by_the_server() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(&mock_server.uri());
+
+        let response = ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "To": "ursula@example.com",
+            "MessageID": "b7bc2f4a-e38e-4336-af7d-e6c392c2f817",
+            "ErrorCode": 0,
+            "Message": "OK"
+        }));
+        Mock::given(any())
+            .respond_with(response)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        assert_eq!(
+            outcome.unwrap(),
+            SentEmail {
+                message_id: Some("b7bc2f4a-e38e-4336-af7d-e6c392c2f817".into()),
+                provider: PRIMARY_PROVIDER.into(),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn send_email_fails_if_the_server_returns_500() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(&mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        assert_err!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_times_out_if_the_server_takes_too_long() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(&mock_server.uri());
+
+        let response = ResponseTemplate::new(200).set_delay(Duration::from_secs(180));
+        Mock::given(any())
+            .respond_with(response)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        assert_err!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_retries_after_a_transient_failure() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(&mock_server.uri());
+        email_client.set_policy(EmailClientPolicy {
+            max_retries: 1,
+            ..policy()
+        });
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        assert_ok!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_does_not_retry_after_a_permanent_failure() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(&mock_server.uri());
+        email_client.set_policy(EmailClientPolicy {
+            max_retries: 3,
+            ..policy()
+        });
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(422))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        assert_err!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_batch_sends_all_the_emails_in_one_request() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(&mock_server.uri());
+        let (first, second) = (email(), email());
+
+        Mock::given(header_exists("X-Postmark-Server-Token"))
+            .and(path("/email/batch"))
+            .and(method("POST"))
+            .and(body_partial_json(serde_json::json!([
+                {"To": first.as_ref()},
+                {"To": second.as_ref()}
+            ])))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+                {"ErrorCode": 0, "Message": "OK", "MessageID": "message-1"},
+                {"ErrorCode": 0, "Message": "OK", "MessageID": "message-2"}
+            ])))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let (subject, content) = (subject(), content());
+        let emails: Vec<_> = [&first, &second]
+            .into_iter()
+            .map(|recipient| OutgoingEmail {
+                recipient,
+                subject: &subject,
+                html_content: &content,
+                text_content: &content,
+            })
+            .collect();
+        let outcome = email_client.send_email_batch(&emails).await;
+
+        assert_eq!(outcome.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn send_email_batch_assumes_the_emails_without_a_result_are_sent() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(&mock_server.uri());
+
+        let response = ResponseTemplate::new(200).set_body_json(serde_json::json!([
+            {"ErrorCode": 0, "Message": "OK", "MessageID": "message-1"}
+        ]));
+        Mock::given(path("/email/batch"))
+            .respond_with(response)
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(path("/email/batch"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("not json"))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let (recipient, subject, content) = (email(), subject(), content());
+        let email = OutgoingEmail {
+            recipient: &recipient,
+            subject: &subject,
+            html_content: &content,
+            text_content: &content,
+        };
+        let partial = email_client
+            .send_email_batch(&[email, email])
+            .await
+            .unwrap();
+        let unreadable = email_client.send_email_batch(&[email]).await.unwrap();
+
+        assert_eq!(
+            partial[0].as_ref().unwrap().message_id.as_deref(),
+            Some("message-1")
+        );
+        for outcome in [&partial[1], &unreadable[0]] {
+            assert_eq!(outcome.as_ref().unwrap().message_id, None);
+        }
+    }
+
+    #[tokio::test]
+    async fn send_email_batch_rejects_a_batch_that_is_too_large() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(&mock_server.uri());
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(0)
+            .mount(&mock_server)
+            .await;
+
+        let (recipient, subject, content) = (email(), subject(), content());
+        let email = OutgoingEmail {
+            recipient: &recipient,
+            subject: &subject,
+            html_content: &content,
+            text_content: &content,
+        };
+        let outcome = email_client
+            .send_email_batch(&vec![email; MAX_BATCH_SIZE + 1])
+            .await;
+
+        match outcome {
+            Err(error @ EmailClientError::BatchTooLarge { size: 501 }) => {
+                assert!(!error.is_transient())
+            }
+            outcome => panic!("Unexpected outcome {:?}", outcome),
+        }
+    }
+
+    #[tokio::test]
+    async fn send_email_batch_maps_the_results_to_their_email() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(&mock_server.uri());
+
+        let response = ResponseTemplate::new(200).set_body_json(serde_json::json!([
+            {"ErrorCode": 0, "Message": "OK", "MessageID": "message-1", "To": "a@example.com"},
+            {"ErrorCode": 406, "Message": "Inactive recipient", "To": "b@example.com"},
+            {"ErrorCode": 0, "Message": "OK", "MessageID": "message-3", "To": "c@example.com"}
+        ]));
+        Mock::given(any())
+            .respond_with(response)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let (recipient, subject, content) = (email(), subject(), content());
+        let email = OutgoingEmail {
+            recipient: &recipient,
+            subject: &subject,
+            html_content: &content,
+            text_content: &content,
+        };
+        let outcome = email_client
+            .send_email_batch(&[email, email, email])
+            .await
+            .unwrap();
+
+        assert_eq!(
+            outcome[0].as_ref().unwrap().message_id.as_deref(),
+            Some("message-1")
+        );
+        match &outcome[1] {
+            Err(EmailClientError::Rejected(rejected)) => assert_eq!(
+                rejected,
+                &RejectedEmail {
+                    code: 406,
+                    message: "Inactive recipient".into()
+                }
+            ),
+            outcome => panic!("Unexpected outcome {:?}", outcome),
+        }
+        assert_eq!(
+            outcome[2].as_ref().unwrap().message_id.as_deref(),
+            Some("message-3")
+        );
+    }
+
+    #[tokio::test]
+    async fn send_email_batch_fails_if_the_server_returns_500() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(&mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let (recipient, subject, content) = (email(), subject(), content());
+        let email = OutgoingEmail {
+            recipient: &recipient,
+            subject: &subject,
+            html_content: &content,
+            text_content: &content,
+        };
+        let outcome = email_client.send_email_batch(&[email]).await;
+
+        assert_err!(outcome);
+    }
+
+    #[tokio::test]
+    async fn send_email_waits_for_the_delay_requested_by_a_429() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(&mock_server.uri());
+        email_client.set_policy(EmailClientPolicy {
+            max_retries: 1,
+            ..policy()
+        });
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(429).insert_header("Retry-After", "1"))
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let start = std::time::Instant::now();
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        assert_ok!(outcome);
+        assert!(start.elapsed() >= Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn send_email_fails_fast_while_the_circuit_is_open() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(&mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(503))
+            .expect(2)
+            .mount(&mock_server)
+            .await;
+
+        for _ in 0..2 {
+            let outcome = email_client
+                .send_email(&email(), &subject(), &content(), &content())
+                .await;
+            assert!(matches!(outcome, Err(EmailClientError::Request(_))));
+        }
+        assert_eq!(
+            email_client
+                .circuit_breaker(PRIMARY_PROVIDER)
+                .unwrap()
+                .state(),
+            CircuitState::Open
+        );
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        assert!(matches!(outcome, Err(EmailClientError::CircuitOpen(_))));
+    }
+
+    #[tokio::test]
+    async fn an_email_waiting_for_the_rate_limiter_is_not_sent_once_the_circuit_opened() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(&mock_server.uri());
+        email_client.set_policy(EmailClientPolicy {
+            max_messages_per_second: Some(10),
+            ..policy()
+        });
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(503))
+            .expect(2)
+            .mount(&mock_server)
+            .await;
+
+        let recipient = email();
+        let (subject, content) = (subject(), content());
+        let send = || email_client.send_email(&recipient, &subject, &content, &content);
+        let (first, second, third) = tokio::join!(send(), send(), send());
+
+        assert!(matches!(first, Err(EmailClientError::Request(_))));
+        assert!(matches!(second, Err(EmailClientError::Request(_))));
+        assert!(matches!(third, Err(EmailClientError::CircuitOpen(_))));
+    }
+
+    #[tokio::test]
+    async fn permanent_failures_do_not_open_the_circuit() {
+        let mock_server = MockServer::start().await;
+        let email_client = email_client(&mock_server.uri());
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(422))
+            .expect(3)
+            .mount(&mock_server)
+            .await;
+
+        for _ in 0..3 {
+            let _ = email_client
+                .send_email(&email(), &subject(), &content(), &content())
+                .await;
+        }
+
+        assert_eq!(
+            email_client
+                .circuit_breaker(PRIMARY_PROVIDER)
+                .unwrap()
+                .state(),
+            CircuitState::Closed
+        );
+    }
+
+    #[tokio::test]
+    async fn send_email_fails_over_to_the_next_provider() {
+        let (primary, backup) = (MockServer::start().await, MockServer::start().await);
+        let email_client = email_client(&primary.uri()).with_fallback(
+            "backup",
+            Transport::Postmark(Postmark::new(&backup.uri(), Secret::new(Faker.fake()))),
+            None,
+        );
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(503))
+            .expect(1)
+            .mount(&primary)
+            .await;
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&backup)
+            .await;
+
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        assert_eq!(outcome.unwrap().provider, "backup");
+    }
+
+    #[tokio::test]
+    async fn a_permanent_failure_does_not_fail_over() {
+        let (primary, backup) = (MockServer::start().await, MockServer::start().await);
+        let email_client = email_client(&primary.uri()).with_fallback(
+            "backup",
+            Transport::Postmark(Postmark::new(&backup.uri(), Secret::new(Faker.fake()))),
+            None,
+        );
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(422))
+            .expect(1)
+            .mount(&primary)
+            .await;
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(0)
+            .mount(&backup)
+            .await;
+
+        let outcome = email_client
+            .send_email(&email(), &subject(), &content(), &content())
+            .await;
+
+        assert_err!(outcome);
+    }
+
+    #[tokio::test]
+    async fn a_provider_is_skipped_while_its_circuit_is_open() {
+        let (primary, backup) = (MockServer::start().await, MockServer::start().await);
+        let email_client = email_client(&primary.uri()).with_fallback(
+            "backup",
+            Transport::Postmark(Postmark::new(&backup.uri(), Secret::new(Faker.fake()))),
+            None,
+        );
+
+        // The circuit of the primary opens after two failures, it is not called anymore
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(503))
+            .expect(2)
+            .mount(&primary)
+            .await;
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(200))
+            .expect(3)
+            .mount(&backup)
+            .await;
+
+        for _ in 0..3 {
+            let outcome = email_client
+                .send_email(&email(), &subject(), &content(), &content())
+                .await;
+            assert_eq!(outcome.unwrap().provider, "backup");
+        }
+        assert_eq!(
+            email_client.circuit_breaker("backup").unwrap().state(),
+            CircuitState::Closed
+        );
+    }
+
+    #[tokio::test]
+    async fn send_email_batch_fails_over_to_the_next_provider() {
+        let (primary, backup) = (MockServer::start().await, MockServer::start().await);
+        let email_client = email_client(&primary.uri()).with_fallback(
+            "backup",
+            Transport::Postmark(Postmark::new(&backup.uri(), Secret::new(Faker.fake()))),
+            None,
+        );
+
+        Mock::given(any())
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount(&primary)
+            .await;
+        Mock::given(path("/email/batch"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+                {"ErrorCode": 0, "Message": "OK", "MessageID": "message-1"},
+                {"ErrorCode": 0, "Message": "OK", "MessageID": "message-2"}
+            ])))
+            .expect(1)
+            .mount(&backup)
+            .await;
+
+        let (recipient, subject, content) = (email(), subject(), content());
+        let email = OutgoingEmail {
+            recipient: &recipient,
+            subject: &subject,
+            html_content: &content,
+            text_content: &content,
+        };
+        let outcome = email_client
+            .send_email_batch(&[email, email])
+            .await
+            .unwrap();
+
+        assert!(outcome
+            .iter()
+            .all(|outcome| outcome.as_ref().unwrap().provider == "backup"));
+    }
+}
@@ -0,0 +1,152 @@
+use std::time::Duration;
+
+use http::{header, HeaderMap, StatusCode};
+use reqwest::{Client, Response};
+use serde::{Deserialize, Serialize};
+
+use super::{EmailClientError, OutgoingEmail, RateLimiter, RejectedEmail};
+use crate::{domain::EmailAddress, secret::Secret};
+
+/// The HTTP API of Postmark.
+#[derive(Clone)]
+pub struct Postmark {
+    http_client: Client,
+    base_url: String,
+    authorization_token: Secret,
+}
+
+impl Postmark {
+    pub fn new(base_url: &str, authorization_token: Secret) -> Self {
+        Self {
+            http_client: Client::new(),
+            base_url: base_url.to_owned(),
+            authorization_token,
+        }
+    }
+
+    /// Send a single email, it returns the identifier given by Postmark.
+    pub(super) async fn send_email(
+        &self,
+        sender: &EmailAddress,
+        email: &OutgoingEmail<'_>,
+        timeout: Duration,
+        rate_limiter: &RateLimiter,
+    ) -> Result<Option<String>, EmailClientError> {
+        let url = format!("{}/email", self.base_url);
+        let response = self
+            .post(&url, &request(sender, email), timeout, rate_limiter)
+            .await?;
+        // The email is sent even if the response cannot be read
+        Ok(response
+            .json::<SendEmailResponse>()
+            .await
+            .ok()
+            .and_then(|response| response.message_id))
+    }
+
+    /// Send `emails` with a single request, the outcome of each email is returned in the order
+    /// of `emails`.
+    pub(super) async fn send_email_batch(
+        &self,
+        sender: &EmailAddress,
+        emails: &[OutgoingEmail<'_>],
+        timeout: Duration,
+        rate_limiter: &RateLimiter,
+    ) -> Result<Vec<Result<Option<String>, EmailClientError>>, EmailClientError> {
+        let url = format!("{}/email/batch", self.base_url);
+        let body: Vec<_> = emails.iter().map(|email| request(sender, email)).collect();
+        let response = self.post(&url, &body, timeout, rate_limiter).await?;
+        // The batch was accepted, like `send_email` the emails without a readable result are
+        // assumed sent rather than sent twice
+        let results = response
+            .json::<Vec<SendEmailResponse>>()
+            .await
+            .unwrap_or_default();
+        if results.len() < emails.len() {
+            tracing::warn!(
+                emails = emails.len(),
+                results = results.len(),
+                "Postmark did not report the outcome of every email of the batch, the others are \
+                assumed sent"
+            );
+        }
+        let mut results = results.into_iter();
+        Ok(emails
+            .iter()
+            .map(|_| match results.next() {
+                Some(result) if result.error_code != 0 => Err(RejectedEmail {
+                    code: result.error_code,
+                    message: result.message.unwrap_or_default(),
+                }
+                .into()),
+                Some(result) => Ok(result.message_id),
+                None => Ok(None),
+            })
+            .collect())
+    }
+
+    async fn post(
+        &self,
+        url: &str,
+        body: &impl Serialize,
+        timeout: Duration,
+        rate_limiter: &RateLimiter,
+    ) -> Result<Response, EmailClientError> {
+        let response = self
+            .http_client
+            .post(url)
+            .header("X-Postmark-Server-Token", self.authorization_token.expose())
+            .timeout(timeout)
+            .json(body)
+            .send()
+            .await?;
+        if response.status() == StatusCode::TOO_MANY_REQUESTS {
+            if let Some(retry_after) = retry_after(response.headers()) {
+                rate_limiter.pause(retry_after);
+            }
+        }
+        Ok(response.error_for_status()?)
+    }
+}
+
+fn request<'a>(sender: &'a EmailAddress, email: &OutgoingEmail<'a>) -> SendEmailRequest<'a> {
+    SendEmailRequest {
+        from: sender.as_ref(),
+        to: email.recipient.as_ref(),
+        subject: email.subject,
+        html_body: email.html_content,
+        text_body: email.text_content,
+    }
+}
+
+/// The delay requested by the `Retry-After` header, only the number of seconds is supported.
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    headers
+        .get(header::RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+        .map(Duration::from_secs)
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct SendEmailRequest<'a> {
+    from: &'a str,
+    to: &'a str,
+    subject: &'a str,
+    html_body: &'a str,
+    text_body: &'a str,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct SendEmailResponse {
+    #[serde(rename = "MessageID")]
+    message_id: Option<String>,
+    #[serde(default)]
+    error_code: i64,
+    message: Option<String>,
+}
@@ -0,0 +1,308 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use tokio::{sync::Notify, time::Instant};
+
+/// Longest pause requested by the provider that is honoured.
+const MAX_PAUSE: Duration = Duration::from_secs(60);
+/// Delay before a bulk sender checks again whether the transactional senders are done.
+const OVERTAKEN_DELAY: Duration = Duration::from_millis(10);
+/// Share of the rate the bulk emails can use, the rest is kept for the transactional emails.
+const BULK_SHARE: f64 = 0.8;
+
+/// The lanes of the rate limiter, the transactional emails overtake the bulk ones.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Priority {
+    /// Emails expected right away by their recipient, like the confirmation emails.
+    Transactional,
+    /// Newsletter issues.
+    Bulk,
+}
+
+impl Priority {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Priority::Transactional => "transactional",
+            Priority::Bulk => "bulk",
+        }
+    }
+
+    fn index(&self) -> usize {
+        match self {
+            Priority::Transactional => 0,
+            Priority::Bulk => 1,
+        }
+    }
+}
+
+/// Limit the number of emails sent per second, it is shared by all the clones of the
+/// `EmailClient`.
+#[derive(Clone)]
+pub struct RateLimiter {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    state: Mutex<State>,
+    notify: Notify,
+}
+
+struct State {
+    /// `None` if the rate is not limited.
+    max_per_second: Option<u32>,
+    /// When the next email can be sent.
+    next_slot: Instant,
+    /// When the next bulk email can be sent.
+    next_bulk_slot: Instant,
+    /// Set when the provider asked to slow down.
+    paused_until: Option<Instant>,
+    /// Number of senders waiting in each lane.
+    waiting: [usize; 2],
+}
+
+impl RateLimiter {
+    pub fn new(max_per_second: Option<u32>) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                state: Mutex::new(State {
+                    max_per_second,
+                    next_slot: Instant::now(),
+                    next_bulk_slot: Instant::now(),
+                    paused_until: None,
+                    waiting: [0; 2],
+                }),
+                notify: Notify::new(),
+            }),
+        }
+    }
+
+    pub fn max_per_second(&self) -> Option<u32> {
+        self.inner.state.lock().unwrap().max_per_second
+    }
+
+    pub fn set_max_per_second(&self, max_per_second: Option<u32>) {
+        self.inner.state.lock().unwrap().max_per_second = max_per_second;
+        self.inner.notify.notify_waiters();
+    }
+
+    /// Number of senders waiting in the lane of `priority`.
+    pub fn queue_depth(&self, priority: Priority) -> usize {
+        self.inner.state.lock().unwrap().waiting[priority.index()]
+    }
+
+    /// Stop sending for `duration`, when the provider answers with `429 Too Many Requests`.
+    pub fn pause(&self, duration: Duration) {
+        let duration = duration.min(MAX_PAUSE);
+        let until = Instant::now() + duration;
+        let mut state = self.inner.state.lock().unwrap();
+        if state
+            .paused_until
+            .is_none_or(|paused_until| paused_until < until)
+        {
+            state.paused_until = Some(until);
+            tracing::warn!(
+                ?duration,
+                "The email provider asked to slow down, sending paused"
+            );
+        }
+    }
+
+    /// Wait until `messages` emails can be sent, the transactional senders go first. The slots
+    /// are taken one email at a time, a transactional sender overtakes a large batch between two
+    /// of its emails.
+    pub async fn acquire(&self, priority: Priority, messages: usize) {
+        let waiting = Waiting::new(self, priority);
+        let mut remaining = messages;
+        while remaining > 0 {
+            let notified = self.inner.notify.notified();
+            let delay = {
+                let mut state = self.inner.state.lock().unwrap();
+                let now = Instant::now();
+                let ready_at = state.ready_at(priority);
+                let overtaken = priority == Priority::Bulk
+                    && state.waiting[Priority::Transactional.index()] > 0;
+                if overtaken {
+                    Some(ready_at.saturating_duration_since(now).max(OVERTAKEN_DELAY))
+                } else if now >= ready_at {
+                    remaining = state.reserve(now, priority, remaining);
+                    None
+                } else {
+                    Some(ready_at - now)
+                }
+            };
+
+            if let Some(delay) = delay {
+                let _ = tokio::time::timeout(delay, notified).await;
+            }
+        }
+        drop(waiting);
+        self.inner.notify.notify_waiters();
+    }
+}
+
+impl State {
+    fn ready_at(&self, priority: Priority) -> Instant {
+        let next_slot = match priority {
+            Priority::Transactional => self.next_slot,
+            Priority::Bulk => self.next_slot.max(self.next_bulk_slot),
+        };
+        match self.paused_until {
+            Some(paused_until) => next_slot.max(paused_until),
+            None => next_slot,
+        }
+    }
+
+    /// Take the slot of the next email, it returns the number of emails still waiting for a
+    /// slot. Every email is sent at once if the rate is not limited.
+    fn reserve(&mut self, now: Instant, priority: Priority, remaining: usize) -> usize {
+        let max_per_second = match self.max_per_second {
+            Some(max_per_second) => f64::from(max_per_second),
+            None => return 0,
+        };
+        self.next_slot = self.next_slot.max(now) + Duration::from_secs_f64(1.0 / max_per_second);
+        if priority == Priority::Bulk {
+            self.next_bulk_slot = self.next_bulk_slot.max(now)
+                + Duration::from_secs_f64(1.0 / (max_per_second * BULK_SHARE));
+        }
+        remaining - 1
+    }
+}
+
+/// Count a sender in its lane until it is dropped, even if it gives up waiting.
+struct Waiting<'a> {
+    limiter: &'a RateLimiter,
+    priority: Priority,
+}
+
+impl<'a> Waiting<'a> {
+    fn new(limiter: &'a RateLimiter, priority: Priority) -> Self {
+        limiter.inner.state.lock().unwrap().waiting[priority.index()] += 1;
+        Self { limiter, priority }
+    }
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.limiter.inner.state.lock().unwrap().waiting[self.priority.index()] -= 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use tokio::time::Instant;
+
+    use super::{Priority, RateLimiter};
+
+    #[tokio::test]
+    async fn emails_are_spaced_according_to_the_rate() {
+        let limiter = RateLimiter::new(Some(20));
+        let start = Instant::now();
+
+        for _ in 0..3 {
+            limiter.acquire(Priority::Bulk, 1).await;
+        }
+
+        // The first email goes right away, the next ones wait at least 50ms each
+        assert!(start.elapsed() >= Duration::from_millis(100));
+    }
+
+    #[tokio::test]
+    async fn bulk_emails_leave_a_share_of_the_rate_to_transactional_emails() {
+        let (bulk, transactional) = (RateLimiter::new(Some(100)), RateLimiter::new(Some(100)));
+
+        let start = Instant::now();
+        transactional.acquire(Priority::Transactional, 9).await;
+        let transactional_elapsed = start.elapsed();
+        let start = Instant::now();
+        bulk.acquire(Priority::Bulk, 9).await;
+        let bulk_elapsed = start.elapsed();
+
+        // 8 intervals of 10ms at the full rate, of 12.5ms at the bulk share
+        assert!(transactional_elapsed >= Duration::from_millis(80));
+        assert!(bulk_elapsed >= Duration::from_millis(100));
+    }
+
+    #[tokio::test]
+    async fn a_transactional_email_is_not_held_up_by_a_large_batch() {
+        let limiter = RateLimiter::new(Some(10));
+        let batch = {
+            let limiter = limiter.clone();
+            tokio::spawn(async move { limiter.acquire(Priority::Bulk, 50).await })
+        };
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let start = Instant::now();
+        limiter.acquire(Priority::Transactional, 1).await;
+
+        assert!(start.elapsed() < Duration::from_millis(500));
+        assert_eq!(limiter.queue_depth(Priority::Bulk), 1);
+        batch.abort();
+    }
+
+    #[tokio::test]
+    async fn a_batch_takes_as_many_slots_as_it_has_emails() {
+        let limiter = RateLimiter::new(Some(100));
+        let start = Instant::now();
+
+        limiter.acquire(Priority::Bulk, 10).await;
+        limiter.acquire(Priority::Bulk, 1).await;
+
+        assert!(start.elapsed() >= Duration::from_millis(100));
+    }
+
+    #[tokio::test]
+    async fn the_rate_is_not_limited_without_a_maximum() {
+        let limiter = RateLimiter::new(None);
+        let start = Instant::now();
+
+        for _ in 0..100 {
+            limiter.acquire(Priority::Bulk, 500).await;
+        }
+
+        assert!(start.elapsed() < Duration::from_millis(100));
+    }
+
+    #[tokio::test]
+    async fn transactional_emails_overtake_bulk_emails() {
+        let limiter = RateLimiter::new(Some(20));
+        let order = Arc::new(Mutex::new(Vec::new()));
+        // Occupy the next slot so that everybody has to wait
+        limiter.acquire(Priority::Bulk, 1).await;
+
+        let mut handles = Vec::new();
+        for priority in [Priority::Bulk, Priority::Bulk, Priority::Transactional] {
+            let (limiter, order) = (limiter.clone(), order.clone());
+            handles.push(tokio::spawn(async move {
+                limiter.acquire(priority, 1).await;
+                order.lock().unwrap().push(priority);
+            }));
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        assert_eq!(limiter.queue_depth(Priority::Bulk), 2);
+        assert_eq!(limiter.queue_depth(Priority::Transactional), 1);
+        for handle in handles {
+            handle.await.unwrap();
+        }
+
+        assert_eq!(order.lock().unwrap()[0], Priority::Transactional);
+        assert_eq!(limiter.queue_depth(Priority::Bulk), 0);
+    }
+
+    #[tokio::test]
+    async fn sending_waits_for_the_end_of_a_pause() {
+        let limiter = RateLimiter::new(None);
+        let start = Instant::now();
+
+        limiter.pause(Duration::from_millis(100));
+        limiter.acquire(Priority::Transactional, 1).await;
+
+        assert!(start.elapsed() >= Duration::from_millis(100));
+    }
+}
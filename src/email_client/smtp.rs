@@ -0,0 +1,234 @@
+use std::time::Duration;
+
+use lettre::{
+    message::MultiPart,
+    transport::smtp::{
+        authentication::Credentials,
+        client::{Tls, TlsParameters},
+    },
+    AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
+};
+use serde::Deserialize;
+use uuid::Uuid;
+
+use super::{EmailClientError, OutgoingEmail};
+use crate::{domain::EmailAddress, secret::Secret};
+
+/// How the connection to an SMTP relay is secured.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpTls {
+    /// Plain text, only for a relay on the local network.
+    None,
+    /// The connection is upgraded with `STARTTLS`, usually on port 587.
+    #[default]
+    StartTls,
+    /// The connection is encrypted from the start, usually on port 465.
+    Tls,
+}
+
+/// An SMTP relay, the connections are pooled and reused by the following emails.
+#[derive(Clone)]
+pub struct SmtpRelay {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+}
+
+impl SmtpRelay {
+    /// `credentials` are the username and the password, `None` if the relay does not require
+    /// authentication.
+    pub fn new(
+        host: &str,
+        port: u16,
+        tls: SmtpTls,
+        credentials: Option<(String, Secret)>,
+    ) -> Result<Self, EmailClientError> {
+        let tls = match tls {
+            SmtpTls::None => Tls::None,
+            SmtpTls::StartTls => Tls::Required(TlsParameters::new(host.to_owned())?),
+            SmtpTls::Tls => Tls::Wrapper(TlsParameters::new(host.to_owned())?),
+        };
+        let mut builder = AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host)
+            .port(port)
+            .tls(tls);
+        if let Some((username, password)) = credentials {
+            builder = builder.credentials(Credentials::new(username, password.expose().to_owned()));
+        }
+        Ok(Self {
+            transport: builder.build(),
+        })
+    }
+
+    /// Send a single email, it returns the `Message-ID` given to the email.
+    pub(super) async fn send_email(
+        &self,
+        sender: &EmailAddress,
+        email: &OutgoingEmail<'_>,
+        timeout: Duration,
+    ) -> Result<Option<String>, EmailClientError> {
+        let domain = sender.as_ref().rsplit('@').next().unwrap_or_default();
+        let message_id = format!("<{}@{}>", Uuid::new_v4(), domain);
+        let message = Message::builder()
+            .message_id(Some(message_id.clone()))
+            .from(sender.as_ref().parse()?)
+            .to(email.recipient.as_ref().parse()?)
+            .subject(email.subject)
+            .multipart(MultiPart::alternative_plain_html(
+                email.text_content.to_owned(),
+                email.html_content.to_owned(),
+            ))?;
+
+        // The timeout of the policy can change while the pooled transport is in use
+        tokio::time::timeout(timeout, self.transport.send(message))
+            .await
+            .map_err(|_| EmailClientError::Timeout)??;
+        Ok(Some(message_id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use claim::assert_ok;
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+        net::TcpListener,
+    };
+
+    use super::{SmtpRelay, SmtpTls};
+    use crate::{
+        domain::EmailAddress,
+        email_client::{
+            CircuitBreakerPolicy, CircuitState, EmailClient, EmailClientPolicy, OutgoingEmail,
+            Transport,
+        },
+        secret::Secret,
+    };
+
+    /// A relay accepting every email, or refusing the connections with `421` if `available` is
+    /// `false`. It returns its port and the number of connections it accepted.
+    async fn fake_relay(available: bool) -> (u16, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let connections = Arc::new(AtomicUsize::new(0));
+        let accepted = connections.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                accepted.fetch_add(1, Ordering::SeqCst);
+                tokio::spawn(async move {
+                    let (reader, mut writer) = stream.into_split();
+                    if !available {
+                        let _ = writer
+                            .write_all(b"421 4.3.2 Service not available\r\n")
+                            .await;
+                        return;
+                    }
+                    let _ = writer.write_all(b"220 relay.example.com\r\n").await;
+                    let mut lines = BufReader::new(reader).lines();
+                    let mut in_data = false;
+                    while let Ok(Some(line)) = lines.next_line().await {
+                        let reply: &[u8] = if in_data {
+                            if line != "." {
+                                continue;
+                            }
+                            in_data = false;
+                            b"250 Queued\r\n"
+                        } else if line.starts_with("DATA") {
+                            in_data = true;
+                            b"354 Go ahead\r\n"
+                        } else if line.starts_with("QUIT") {
+                            b"221 Bye\r\n"
+                        } else {
+                            b"250 OK\r\n"
+                        };
+                        if writer.write_all(reply).await.is_err() {
+                            return;
+                        }
+                    }
+                });
+            }
+        });
+        (port, connections)
+    }
+
+    fn relay(port: u16) -> SmtpRelay {
+        SmtpRelay::new("127.0.0.1", port, SmtpTls::None, None).unwrap()
+    }
+
+    fn address(address: &str) -> EmailAddress {
+        address.parse().unwrap()
+    }
+
+    #[tokio::test]
+    async fn the_connections_to_the_relay_are_reused() {
+        let (port, connections) = fake_relay(true).await;
+        let relay = relay(port);
+        let recipient = address("ursula@example.com");
+        let email = OutgoingEmail {
+            recipient: &recipient,
+            subject: "Welcome",
+            html_content: "<p>Hi</p>",
+            text_content: "Hi",
+        };
+
+        for _ in 0..3 {
+            assert_ok!(
+                relay
+                    .send_email(
+                        &address("newsletter@example.com"),
+                        &email,
+                        Duration::from_secs(1)
+                    )
+                    .await
+            );
+            // The connection goes back to the pool on a task of its own
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+
+        assert_eq!(connections.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn a_relay_refusing_the_connections_counts_as_an_outage() {
+        let (port, _) = fake_relay(false).await;
+        let policy = EmailClientPolicy {
+            timeout: Duration::from_secs(1),
+            max_retries: 0,
+            retry_backoff: Duration::ZERO,
+            max_messages_per_second: None,
+            fallback_max_messages_per_second: Default::default(),
+            circuit_breaker: CircuitBreakerPolicy {
+                failure_rate_threshold: 0.5,
+                window_size: 2,
+                open_duration: Duration::from_secs(60),
+                half_open_probes: 1,
+            },
+        };
+        // The primary provider cannot be reached
+        let email_client = EmailClient::new(
+            "http://127.0.0.1:1",
+            address("newsletter@example.com"),
+            Secret::new("a-token".into()),
+            policy,
+        )
+        .with_fallback("relay", Transport::Smtp(relay(port)), None);
+
+        for _ in 0..2 {
+            let outcome = email_client
+                .send_email(&address("ursula@example.com"), "Welcome", "<p>Hi</p>", "Hi")
+                .await;
+            assert!(outcome.unwrap_err().is_transient());
+        }
+
+        assert_eq!(
+            email_client.circuit_breaker("relay").unwrap().state(),
+            CircuitState::Open
+        );
+    }
+}
@@ -0,0 +1,185 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use reqwest::Client;
+use serde::Deserialize;
+
+use super::{HumanVerifier, Submission, VerificationFailure};
+use crate::secret::Secret;
+
+/// Check the response of a CAPTCHA widget with its provider. hCaptcha and Turnstile share the
+/// same verify call: the `secret` and the `response` are posted as a form and the answer is
+/// `{"success": ..., "error-codes": [...]}`.
+pub struct CaptchaVerifier {
+    http_client: Client,
+    verify_url: String,
+    secret: Secret,
+    timeout: Duration,
+}
+
+impl CaptchaVerifier {
+    pub fn new(verify_url: &str, secret: Secret, timeout: Duration) -> Self {
+        Self {
+            http_client: Client::new(),
+            verify_url: verify_url.to_owned(),
+            secret,
+            timeout,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct VerifyResponse {
+    success: bool,
+    #[serde(default, rename = "error-codes")]
+    error_codes: Vec<String>,
+}
+
+#[async_trait]
+impl HumanVerifier for CaptchaVerifier {
+    fn name(&self) -> &'static str {
+        "captcha"
+    }
+
+    async fn verify(&self, submission: &Submission<'_>) -> Result<(), VerificationFailure> {
+        let response = match submission.captcha_response {
+            Some(response) if !response.is_empty() => response,
+            _ => return Err(VerificationFailure::MissingCaptcha),
+        };
+        let answer = self
+            .http_client
+            .post(&self.verify_url)
+            .form(&[("secret", self.secret.expose()), ("response", response)])
+            .timeout(self.timeout)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(VerificationFailure::ProviderUnavailable)?
+            .json::<VerifyResponse>()
+            .await
+            .map_err(VerificationFailure::ProviderUnavailable)?;
+        if answer.success {
+            Ok(())
+        } else {
+            Err(VerificationFailure::CaptchaRejected(answer.error_codes))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use claim::assert_ok;
+    use wiremock::{
+        matchers::{body_string_contains, method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    use super::CaptchaVerifier;
+    use crate::{
+        human_verification::{HumanVerifier, Submission, VerificationFailure},
+        secret::Secret,
+    };
+
+    fn verifier(server: &MockServer) -> CaptchaVerifier {
+        CaptchaVerifier::new(
+            &format!("{}/siteverify", server.uri()),
+            Secret::new("my-captcha-secret".into()),
+            Duration::from_millis(200),
+        )
+    }
+
+    fn answering(response: &str) -> Submission<'_> {
+        Submission {
+            captcha_response: Some(response),
+            ..Submission::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn a_response_accepted_by_the_provider_is_accepted() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/siteverify"))
+            .and(body_string_contains("secret=my-captcha-secret"))
+            .and(body_string_contains("response=a-good-response"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "success": true,
+                "challenge_ts": "2022-05-08T10:00:00Z",
+                "hostname": "example.com"
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        assert_ok!(
+            verifier(&server)
+                .verify(&answering("a-good-response"))
+                .await
+        );
+    }
+
+    #[tokio::test]
+    async fn a_response_rejected_by_the_provider_is_rejected() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "success": false,
+                "error-codes": ["invalid-input-response"]
+            })))
+            .mount(&server)
+            .await;
+
+        let result = verifier(&server).verify(&answering("a-bad-response")).await;
+
+        match result {
+            Err(VerificationFailure::CaptchaRejected(codes)) => {
+                assert_eq!(codes, vec!["invalid-input-response"])
+            }
+            other => panic!("unexpected outcome: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn a_missing_response_is_rejected_without_calling_the_provider() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(0)
+            .mount(&server)
+            .await;
+
+        for submission in [Submission::default(), answering("")] {
+            assert!(matches!(
+                verifier(&server).verify(&submission).await,
+                Err(VerificationFailure::MissingCaptcha)
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn a_failing_or_slow_provider_is_unavailable() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(body_string_contains("response=slow"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(serde_json::json!({ "success": true }))
+                    .set_delay(Duration::from_secs(1)),
+            )
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(500))
+            .mount(&server)
+            .await;
+
+        for response in ["slow", "broken"] {
+            assert!(matches!(
+                verifier(&server).verify(&answering(response)).await,
+                Err(VerificationFailure::ProviderUnavailable(_))
+            ));
+        }
+    }
+}
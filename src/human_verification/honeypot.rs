@@ -0,0 +1,162 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use chrono::Utc;
+
+use super::{HumanVerifier, Submission, VerificationFailure};
+use crate::tracking::{TrackingKey, TrackingToken};
+
+/// A token telling that a form was served now, signed with `key`.
+pub fn form_token(key: &TrackingKey) -> String {
+    key.encode(&TrackingToken::Form {
+        issued_at: Utc::now().timestamp(),
+    })
+}
+
+/// Catch the scripts without a third party: the honeypot must be left empty and the form must
+/// be submitted after a human could have filled it in, but before its token expires. A token
+/// can be submitted again until it expires.
+pub struct HoneypotVerifier {
+    key: TrackingKey,
+    min_age: Duration,
+    max_age: Duration,
+}
+
+impl HoneypotVerifier {
+    pub fn new(key: TrackingKey, min_age: Duration, max_age: Duration) -> Self {
+        Self {
+            key,
+            min_age,
+            max_age,
+        }
+    }
+
+    /// Check the submission at `now`, a Unix timestamp in seconds.
+    fn check(&self, submission: &Submission<'_>, now: i64) -> Result<(), VerificationFailure> {
+        if submission.honeypot.is_some_and(|value| !value.is_empty()) {
+            return Err(VerificationFailure::HoneypotFilled);
+        }
+        let issued_at = match submission
+            .form_token
+            .and_then(|token| self.key.decode(token))
+        {
+            Some(TrackingToken::Form { issued_at }) => issued_at,
+            _ => return Err(VerificationFailure::InvalidFormToken),
+        };
+        let age = now.saturating_sub(issued_at);
+        if age < self.min_age.as_secs() as i64 {
+            Err(VerificationFailure::TooFast)
+        } else if age > self.max_age.as_secs() as i64 {
+            Err(VerificationFailure::Expired)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[async_trait]
+impl HumanVerifier for HoneypotVerifier {
+    fn name(&self) -> &'static str {
+        "honeypot"
+    }
+
+    async fn verify(&self, submission: &Submission<'_>) -> Result<(), VerificationFailure> {
+        self.check(submission, Utc::now().timestamp())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use claim::assert_ok;
+    use uuid::Uuid;
+
+    use super::HoneypotVerifier;
+    use crate::{
+        human_verification::{Submission, VerificationFailure},
+        secret::Secret,
+        tracking::{TrackingKey, TrackingToken},
+    };
+
+    const NOW: i64 = 1_650_000_000;
+
+    fn key() -> TrackingKey {
+        TrackingKey::new(Secret::new("my-tracking-key".into()))
+    }
+
+    fn verifier() -> HoneypotVerifier {
+        HoneypotVerifier::new(key(), Duration::from_secs(3), Duration::from_secs(3600))
+    }
+
+    fn issued_at(issued_at: i64) -> String {
+        key().encode(&TrackingToken::Form { issued_at })
+    }
+
+    fn check(honeypot: Option<&str>, form_token: Option<&str>) -> Result<(), VerificationFailure> {
+        let submission = Submission {
+            honeypot,
+            form_token,
+            captcha_response: None,
+        };
+        verifier().check(&submission, NOW)
+    }
+
+    #[test]
+    fn a_form_submitted_in_time_with_an_empty_honeypot_is_accepted() {
+        let token = issued_at(NOW - 10);
+
+        assert_ok!(check(None, Some(&token)));
+        assert_ok!(check(Some(""), Some(&token)));
+        assert_ok!(check(None, Some(&issued_at(NOW - 3600))));
+    }
+
+    #[test]
+    fn a_filled_honeypot_is_rejected() {
+        let token = issued_at(NOW - 10);
+
+        assert!(matches!(
+            check(Some("https://spam.example"), Some(&token)),
+            Err(VerificationFailure::HoneypotFilled)
+        ));
+    }
+
+    #[test]
+    fn a_form_submitted_too_quickly_or_too_late_is_rejected() {
+        assert!(matches!(
+            check(None, Some(&issued_at(NOW - 1))),
+            Err(VerificationFailure::TooFast)
+        ));
+        assert!(matches!(
+            check(None, Some(&issued_at(NOW + 60))),
+            Err(VerificationFailure::TooFast)
+        ));
+        assert!(matches!(
+            check(None, Some(&issued_at(NOW - 3601))),
+            Err(VerificationFailure::Expired)
+        ));
+    }
+
+    #[test]
+    fn a_missing_or_forged_form_token_is_rejected() {
+        let other_token = key().encode(&TrackingToken::OptOut {
+            subscriber_id: Uuid::new_v4(),
+        });
+        let forged =
+            TrackingKey::new(Secret::new("another-key".into())).encode(&TrackingToken::Form {
+                issued_at: NOW - 10,
+            });
+
+        for token in [
+            None,
+            Some("not-a-token"),
+            Some(other_token.as_str()),
+            Some(forged.as_str()),
+        ] {
+            assert!(matches!(
+                check(None, token),
+                Err(VerificationFailure::InvalidFormToken)
+            ));
+        }
+    }
+}
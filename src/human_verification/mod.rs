@@ -0,0 +1,186 @@
+mod captcha;
+mod honeypot;
+
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+};
+
+use async_trait::async_trait;
+
+pub use self::{
+    captcha::CaptchaVerifier,
+    honeypot::{form_token, HoneypotVerifier},
+};
+
+/// What a subscription carries to prove it comes from a human, each verifier reads its own
+/// fields.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Submission<'a> {
+    /// A field hidden to the humans, the bots filling every field fill it in.
+    pub honeypot: Option<&'a str>,
+    /// The token issued with the form, it tells when the form was served.
+    pub form_token: Option<&'a str>,
+    /// The response of the CAPTCHA widget.
+    pub captcha_response: Option<&'a str>,
+}
+
+/// Why a submission is not believed to come from a human.
+#[derive(Debug, thiserror::Error)]
+pub enum VerificationFailure {
+    #[error("the honeypot field is filled in")]
+    HoneypotFilled,
+    #[error("the form token is missing or invalid")]
+    InvalidFormToken,
+    #[error("the form was submitted too quickly")]
+    TooFast,
+    #[error("the form token has expired")]
+    Expired,
+    #[error("the CAPTCHA response is missing")]
+    MissingCaptcha,
+    #[error("the CAPTCHA response was rejected ({})", .0.join(", "))]
+    CaptchaRejected(Vec<String>),
+    #[error("the CAPTCHA provider could not be reached")]
+    ProviderUnavailable(#[source] reqwest::Error),
+}
+
+impl VerificationFailure {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            VerificationFailure::HoneypotFilled => "honeypot_filled",
+            VerificationFailure::InvalidFormToken => "invalid_form_token",
+            VerificationFailure::TooFast => "too_fast",
+            VerificationFailure::Expired => "expired",
+            VerificationFailure::MissingCaptcha => "missing_captcha",
+            VerificationFailure::CaptchaRejected(_) => "captcha_rejected",
+            VerificationFailure::ProviderUnavailable(_) => "provider_unavailable",
+        }
+    }
+}
+
+/// Tell the humans from the scripts signing up.
+#[async_trait]
+pub trait HumanVerifier: Send + Sync {
+    /// The name of the verifier in the logs and in the metrics.
+    fn name(&self) -> &'static str;
+
+    async fn verify(&self, submission: &Submission<'_>) -> Result<(), VerificationFailure>;
+}
+
+/// Check the subscriptions with the configured verifier, every submission is accepted without
+/// one. The failures are counted by verifier and reason, the counters are shared by all the
+/// clones.
+#[derive(Clone, Default)]
+pub struct HumanVerification {
+    verifier: Option<Arc<dyn HumanVerifier>>,
+    failures: Arc<Mutex<BTreeMap<(&'static str, &'static str), u64>>>,
+}
+
+impl HumanVerification {
+    pub fn new(verifier: Option<Arc<dyn HumanVerifier>>) -> Self {
+        Self {
+            verifier,
+            failures: Default::default(),
+        }
+    }
+
+    #[tracing::instrument(name = "Verify that a human is subscribing", skip(self, submission))]
+    pub async fn verify(&self, submission: &Submission<'_>) -> Result<(), VerificationFailure> {
+        let verifier = match &self.verifier {
+            Some(verifier) => verifier,
+            None => return Ok(()),
+        };
+        let failure = match verifier.verify(submission).await {
+            Ok(()) => return Ok(()),
+            Err(failure) => failure,
+        };
+        match &failure {
+            VerificationFailure::ProviderUnavailable(error) => tracing::warn!(
+                error.cause_chain = ?error,
+                verifier = verifier.name(),
+                "Failed to reach the CAPTCHA provider, the submission is refused"
+            ),
+            _ => tracing::info!(
+                verifier = verifier.name(),
+                reason = failure.as_str(),
+                "The submission failed the human verification"
+            ),
+        }
+        *self
+            .failures
+            .lock()
+            .unwrap()
+            .entry((verifier.name(), failure.as_str()))
+            .or_default() += 1;
+        Err(failure)
+    }
+
+    /// The number of failed verifications, by verifier and reason.
+    pub fn failures(&self) -> Vec<(&'static str, &'static str, u64)> {
+        self.failures
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|((verifier, reason), count)| (*verifier, *reason, *count))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use async_trait::async_trait;
+    use claim::{assert_err, assert_ok};
+
+    use super::{HumanVerification, HumanVerifier, Submission, VerificationFailure};
+
+    /// Only the submissions without a filled honeypot are accepted.
+    struct StubVerifier;
+
+    #[async_trait]
+    impl HumanVerifier for StubVerifier {
+        fn name(&self) -> &'static str {
+            "stub"
+        }
+
+        async fn verify(&self, submission: &Submission<'_>) -> Result<(), VerificationFailure> {
+            match submission.honeypot {
+                Some(_) => Err(VerificationFailure::HoneypotFilled),
+                None => Ok(()),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn every_submission_is_accepted_without_a_verifier() {
+        let verification = HumanVerification::default();
+
+        let submission = Submission {
+            honeypot: Some("https://spam.example"),
+            ..Submission::default()
+        };
+
+        assert_ok!(verification.verify(&submission).await);
+        assert!(verification.failures().is_empty());
+    }
+
+    #[tokio::test]
+    async fn the_failures_are_counted_by_the_clones() {
+        let verification = HumanVerification::new(Some(Arc::new(StubVerifier)));
+        let clone = verification.clone();
+        let bot = Submission {
+            honeypot: Some("https://spam.example"),
+            ..Submission::default()
+        };
+
+        assert_ok!(verification.verify(&Submission::default()).await);
+        assert_err!(verification.verify(&bot).await);
+        assert_err!(clone.verify(&bot).await);
+
+        assert_eq!(
+            verification.failures(),
+            vec![("stub", "honeypot_filled", 2)]
+        );
+    }
+}
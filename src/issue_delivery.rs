@@ -0,0 +1,686 @@
+use std::{
+    collections::{btree_map::Entry, BTreeMap},
+    sync::Arc,
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use futures::{stream, StreamExt};
+use serde::Serialize;
+use tokio::sync::Notify;
+use uuid::Uuid;
+
+use crate::{
+    domain::{EmailAddress, ParseEmailAddressError},
+    email_client::{OutgoingEmail, MAX_BATCH_SIZE},
+    mailer::{Delivery, Mailer, MailerError},
+    outbox::retry_delay,
+    repository::{DeliveryRepository, IssueRepository, RepositoryError},
+    routes::hosted_pages::escape,
+    tracking::{insert_before_body_end, TrackingKey, TrackingOptions},
+};
+
+/// Number of emails sent per call to the batch API of the provider.
+const BATCH_SIZE: usize = MAX_BATCH_SIZE;
+/// Number of batches of an issue sent at the same time.
+const MAX_CONCURRENT_BATCHES: usize = 4;
+/// How long the deliveries of a digest are leased, they are sent again if it was not recorded by
+/// then.
+const DIGEST_LEASE: Duration = Duration::from_secs(300);
+/// How long the deliveries claimed by the worker are leased, they are sent again if they were not
+/// recorded by then.
+const DELIVERY_LEASE: Duration = Duration::from_secs(300);
+/// Largest number of deliveries claimed by the worker at once.
+const CLAIM_SIZE: i64 = (BATCH_SIZE * MAX_CONCURRENT_BATCHES) as i64;
+/// Time between two checks of the queued deliveries when the worker is not woken up, the ones
+/// queued by another instance, retried or whose lease expired are picked up then.
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+/// Number of attempts after which a delivery failing with transient errors is given up on.
+const MAX_ATTEMPTS: i32 = 15;
+
+/// The outcome of the last attempt to deliver an issue to a subscriber.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DeliveryStatus {
+    Queued,
+    Sent,
+    /// The recipient is suppressed.
+    Skipped,
+    Failed,
+}
+
+/// Why a delivery failed.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ErrorClass {
+    /// Sending again later can succeed.
+    Transient,
+    /// The issue or the address must be fixed before sending again.
+    Permanent,
+}
+
+impl DeliveryStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeliveryStatus::Queued => "queued",
+            DeliveryStatus::Sent => "sent",
+            DeliveryStatus::Skipped => "skipped",
+            DeliveryStatus::Failed => "failed",
+        }
+    }
+}
+
+impl ErrorClass {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorClass::Transient => "transient",
+            ErrorClass::Permanent => "permanent",
+        }
+    }
+}
+
+/// A newsletter issue, as stored when it was published.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct NewsletterIssue {
+    pub id: Uuid,
+    pub title: String,
+    pub html_content: String,
+    pub text_content: String,
+    pub tracking: TrackingOptions,
+    /// The id of its topic, see `subscriptions.topics`. It is sent to every subscriber if `None`.
+    pub topic: Option<String>,
+}
+
+/// A subscriber the issue is queued for.
+pub struct Recipient {
+    pub subscriber_id: Uuid,
+    pub email: String,
+    pub tracking_opt_out: bool,
+    /// Number of attempts made so far, the failed ones before a retry.
+    pub attempts: i32,
+}
+
+/// An issue queued for a subscriber.
+pub struct QueuedDelivery {
+    pub issue_id: Uuid,
+    pub recipient: Recipient,
+}
+
+/// The number of deliveries per outcome.
+#[derive(Debug, Default, Serialize)]
+pub struct DeliveryReport {
+    pub sent: u32,
+    pub skipped: u32,
+    pub failed: u32,
+    /// The deliveries that failed with a transient error and are queued again.
+    pub retried: u32,
+}
+
+/// The progress of the delivery of an issue.
+#[derive(Debug, Serialize)]
+pub struct DeliverySummary {
+    pub issue_id: Uuid,
+    pub total: i64,
+    pub queued: i64,
+    pub sent: i64,
+    pub skipped: i64,
+    pub failed: i64,
+    /// The number of emails sent through each provider.
+    pub sent_by_provider: BTreeMap<String, i64>,
+    pub failures: Vec<DeliveryFailure>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DeliveryFailure {
+    pub subscriber_id: Uuid,
+    pub email: String,
+    pub error_class: Option<String>,
+    pub detail: Option<String>,
+    pub attempts: i32,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// The outcome of an attempt to deliver an issue to a subscriber.
+#[derive(Clone)]
+pub struct Attempt {
+    pub status: DeliveryStatus,
+    /// Identifier given by the provider, when it was sent.
+    pub provider_message_id: Option<String>,
+    /// Name of the provider that accepted the email.
+    pub provider: Option<String>,
+    pub error_class: Option<ErrorClass>,
+    /// The error of a failed delivery or the reason of a skipped one.
+    pub detail: Option<String>,
+    /// When the delivery is attempted again, it stays queued until then.
+    pub retry_at: Option<DateTime<Utc>>,
+}
+
+impl Attempt {
+    fn failed(error_class: ErrorClass, detail: String) -> Self {
+        Self {
+            status: DeliveryStatus::Failed,
+            provider_message_id: None,
+            provider: None,
+            error_class: Some(error_class),
+            detail: Some(detail),
+            retry_at: None,
+        }
+    }
+
+    /// Retry a transient failure of a delivery that failed `attempts` times before, unless it
+    /// ran out of attempts. The other outcomes are final.
+    fn retried(mut self, attempts: i32) -> Self {
+        if self.error_class == Some(ErrorClass::Transient) && attempts + 1 < MAX_ATTEMPTS {
+            self.retry_at = Some(Utc::now() + retry_delay(attempts + 1));
+        }
+        self
+    }
+
+    /// The status of the delivery after this attempt, a retried one is queued again.
+    pub fn delivery_status(&self) -> DeliveryStatus {
+        match self.retry_at {
+            Some(_) => DeliveryStatus::Queued,
+            None => self.status,
+        }
+    }
+}
+
+impl From<Delivery> for Attempt {
+    fn from(delivery: Delivery) -> Self {
+        match delivery {
+            Delivery::Sent {
+                message_id,
+                provider,
+            } => Self {
+                status: DeliveryStatus::Sent,
+                provider_message_id: message_id,
+                provider: Some(provider),
+                error_class: None,
+                detail: None,
+                retry_at: None,
+            },
+            Delivery::Skipped { reason } => Self {
+                status: DeliveryStatus::Skipped,
+                provider_message_id: None,
+                provider: None,
+                error_class: None,
+                detail: Some(reason),
+                retry_at: None,
+            },
+        }
+    }
+}
+
+impl From<MailerError> for Attempt {
+    fn from(error: MailerError) -> Self {
+        let error_class = if error.is_transient() {
+            ErrorClass::Transient
+        } else {
+            ErrorClass::Permanent
+        };
+        Self::failed(error_class, format!("{:#}", anyhow::Error::from(error)))
+    }
+}
+
+impl DeliveryReport {
+    fn count(&mut self, attempt: &Attempt) {
+        match attempt.delivery_status() {
+            DeliveryStatus::Sent => self.sent += 1,
+            DeliveryStatus::Skipped => self.skipped += 1,
+            DeliveryStatus::Failed => self.failed += 1,
+            DeliveryStatus::Queued => self.retried += 1,
+        }
+    }
+
+    fn merge(&mut self, other: DeliveryReport) {
+        self.sent += other.sent;
+        self.skipped += other.skipped;
+        self.failed += other.failed;
+        self.retried += other.retried;
+    }
+}
+
+/// Wakes up the worker sending the queued deliveries, see `run_deliveries`.
+#[derive(Clone, Default)]
+pub struct DeliveryQueue(Arc<Notify>);
+
+impl DeliveryQueue {
+    /// Tell the worker that deliveries were queued.
+    pub fn wake(&self) {
+        self.0.notify_one();
+    }
+}
+
+/// Send the issue to the queued recipients in batches, a failed delivery is recorded and does not
+/// prevent the other ones.
+#[tracing::instrument(
+    name = "Deliver an issue",
+    skip(deliveries, mailer, tracking_key, base_url, issue, recipients),
+    fields(issue_id = %issue.id, recipients = recipients.len())
+)]
+pub async fn deliver(
+    deliveries: &dyn DeliveryRepository,
+    mailer: &Mailer,
+    tracking_key: &TrackingKey,
+    base_url: &str,
+    issue: &NewsletterIssue,
+    recipients: Vec<Recipient>,
+) -> Result<DeliveryReport, RepositoryError> {
+    let mut report = DeliveryReport::default();
+
+    let mut personalised = Vec::with_capacity(recipients.len());
+    for recipient in recipients {
+        let email = match recipient.email.parse::<EmailAddress>() {
+            Ok(email) => email,
+            Err(error) => {
+                report.merge(
+                    record_invalid_address(deliveries, &[issue.id], &recipient, error).await?,
+                );
+                continue;
+            }
+        };
+        let (html, text) = personalise(tracking_key, base_url, issue, &recipient);
+        let preferences_url = tracking_key.preferences_url(base_url, recipient.subscriber_id);
+        personalised.push(PersonalisedEmail {
+            subscriber_id: recipient.subscriber_id,
+            email,
+            subject: issue.title.clone(),
+            html: with_preferences_link(html, &preferences_url),
+            text: format!("{}\n\nManage your subscription: {}", text, preferences_url),
+            issue_ids: vec![issue.id],
+            attempts: recipient.attempts,
+        });
+    }
+
+    report.merge(deliver_batches(deliveries, mailer, personalised).await?);
+    Ok(report)
+}
+
+/// Send their digest to the subscribers who chose one, with all the issues queued for them since
+/// the previous one. The deliveries are leased while they are sent, a digest that was not
+/// recorded is sent again once its lease expires.
+#[tracing::instrument(
+    name = "Deliver the digests",
+    skip(deliveries, issues, mailer, tracking_key, base_url)
+)]
+pub async fn deliver_digests(
+    deliveries: &dyn DeliveryRepository,
+    issues: &dyn IssueRepository,
+    mailer: &Mailer,
+    tracking_key: &TrackingKey,
+    base_url: &str,
+) -> Result<DeliveryReport, RepositoryError> {
+    let lease = chrono::Duration::from_std(DIGEST_LEASE).expect("The lease is in range");
+    let entries = deliveries.claim_digests(Utc::now() + lease).await?;
+
+    let mut loaded = BTreeMap::new();
+    for entry in &entries {
+        if let Entry::Vacant(vacant) = loaded.entry(entry.issue_id) {
+            vacant.insert(issues.get(entry.issue_id).await?);
+        }
+    }
+    let mut queued: BTreeMap<Uuid, (Recipient, Vec<NewsletterIssue>)> = BTreeMap::new();
+    for entry in entries {
+        if let Some(issue) = &loaded[&entry.issue_id] {
+            let attempts = entry.recipient.attempts;
+            let (recipient, issues) = queued
+                .entry(entry.recipient.subscriber_id)
+                .or_insert_with(|| (entry.recipient, Vec::new()));
+            // A digest is retried as often as its most retried issue
+            recipient.attempts = recipient.attempts.max(attempts);
+            issues.push(issue.clone());
+        }
+    }
+
+    let mut report = DeliveryReport::default();
+    let mut personalised = Vec::with_capacity(queued.len());
+    for (recipient, issues) in queued.into_values() {
+        let issue_ids: Vec<_> = issues.iter().map(|issue| issue.id).collect();
+        let email = match recipient.email.parse::<EmailAddress>() {
+            Ok(email) => email,
+            Err(error) => {
+                report.merge(
+                    record_invalid_address(deliveries, &issue_ids, &recipient, error).await?,
+                );
+                continue;
+            }
+        };
+        let preferences_url = tracking_key.preferences_url(base_url, recipient.subscriber_id);
+        let mut html = String::from("<html><body>");
+        let mut text = String::new();
+        for issue in &issues {
+            let (issue_html, issue_text) = personalise(tracking_key, base_url, issue, &recipient);
+            html.push_str(&format!(
+                "<section><h1>{}</h1>{}</section><hr>",
+                escape(&issue.title),
+                body_of(&issue_html)
+            ));
+            text.push_str(&format!("{}\n\n{}\n\n", issue.title, issue_text));
+        }
+        html.push_str("</body></html>");
+        let subject = match issues.as_slice() {
+            [issue] => issue.title.clone(),
+            issues => format!("Your digest of {} issues", issues.len()),
+        };
+        personalised.push(PersonalisedEmail {
+            subscriber_id: recipient.subscriber_id,
+            email,
+            subject,
+            html: with_preferences_link(html, &preferences_url),
+            text: format!("{}Manage your subscription: {}", text, preferences_url),
+            issue_ids,
+            attempts: recipient.attempts,
+        });
+    }
+
+    report.merge(deliver_batches(deliveries, mailer, personalised).await?);
+    Ok(report)
+}
+
+/// Send the deliveries queued for the subscribers receiving every issue, a batch of them at a
+/// time. They are leased while they are sent and sent again once their lease expires if they
+/// were not recorded. A delivery failing with a transient error is queued again, it is retried
+/// after a delay doubling with every attempt. It returns the number of deliveries claimed.
+#[tracing::instrument(
+    name = "Deliver the queued issues",
+    skip(deliveries, issues, mailer, tracking_key, base_url)
+)]
+pub async fn deliver_queued(
+    deliveries: &dyn DeliveryRepository,
+    issues: &dyn IssueRepository,
+    mailer: &Mailer,
+    tracking_key: &TrackingKey,
+    base_url: &str,
+) -> Result<usize, RepositoryError> {
+    let lease = chrono::Duration::from_std(DELIVERY_LEASE).expect("The lease is in range");
+    let claimed = deliveries
+        .claim_queued(Utc::now() + lease, CLAIM_SIZE)
+        .await?;
+    let count = claimed.len();
+
+    let mut queued: BTreeMap<Uuid, Vec<Recipient>> = BTreeMap::new();
+    for delivery in claimed {
+        queued
+            .entry(delivery.issue_id)
+            .or_default()
+            .push(delivery.recipient);
+    }
+    for (issue_id, recipients) in queued {
+        // The deliveries are removed with their issue
+        let issue = match issues.get(issue_id).await? {
+            Some(issue) => issue,
+            None => continue,
+        };
+        let report = deliver(
+            deliveries,
+            mailer,
+            tracking_key,
+            base_url,
+            &issue,
+            recipients,
+        )
+        .await?;
+        tracing::info!(
+            %issue_id,
+            sent = report.sent,
+            skipped = report.skipped,
+            failed = report.failed,
+            retried = report.retried,
+            "Queued deliveries sent"
+        );
+    }
+    Ok(count)
+}
+
+/// Send the queued deliveries as they are queued, until the application stops.
+pub async fn run_deliveries(
+    deliveries: Arc<dyn DeliveryRepository>,
+    issues: Arc<dyn IssueRepository>,
+    mailer: Mailer,
+    tracking_key: TrackingKey,
+    base_url: String,
+    queue: DeliveryQueue,
+) {
+    loop {
+        match deliver_queued(
+            deliveries.as_ref(),
+            issues.as_ref(),
+            &mailer,
+            &tracking_key,
+            &base_url,
+        )
+        .await
+        {
+            Ok(0) => {}
+            Ok(_) => continue,
+            Err(error) => {
+                tracing::error!(error.cause_chain = ?error, "Failed to deliver the queued issues");
+            }
+        }
+        let _ = tokio::time::timeout(POLL_INTERVAL, queue.0.notified()).await;
+    }
+}
+
+/// Send the digests every `interval` until the application stops.
+pub async fn run_digests(
+    deliveries: Arc<dyn DeliveryRepository>,
+    issues: Arc<dyn IssueRepository>,
+    mailer: Mailer,
+    tracking_key: TrackingKey,
+    base_url: String,
+    interval: Duration,
+) {
+    loop {
+        tokio::time::sleep(interval).await;
+        if let Err(error) = deliver_digests(
+            deliveries.as_ref(),
+            issues.as_ref(),
+            &mailer,
+            &tracking_key,
+            &base_url,
+        )
+        .await
+        {
+            tracing::error!(error.cause_chain = ?error, "Failed to deliver the digests");
+        }
+    }
+}
+
+/// The HTML and the text of the issue for the recipient, with its tracking.
+fn personalise(
+    tracking_key: &TrackingKey,
+    base_url: &str,
+    issue: &NewsletterIssue,
+    recipient: &Recipient,
+) -> (String, String) {
+    let tracking = if recipient.tracking_opt_out {
+        TrackingOptions::default()
+    } else {
+        issue.tracking
+    };
+    let html = tracking_key.personalise_html(
+        base_url,
+        &issue.html_content,
+        tracking,
+        issue.id,
+        recipient.subscriber_id,
+    );
+    (html, issue.text_content.clone())
+}
+
+/// The link to the preference centre is never tracked.
+fn with_preferences_link(mut html: String, preferences_url: &str) -> String {
+    insert_before_body_end(
+        &mut html,
+        &format!(
+            r#"<p><a href="{}">Manage your subscription</a></p>"#,
+            preferences_url
+        ),
+    );
+    html
+}
+
+/// The content of the `body` element, or the whole document if it has none.
+fn body_of(html: &str) -> &str {
+    let lowercase = html.to_ascii_lowercase();
+    let start = lowercase
+        .find("<body")
+        .and_then(|body| lowercase[body..].find('>').map(|end| body + end + 1))
+        .unwrap_or(0);
+    let end = lowercase
+        .rfind("</body")
+        .filter(|end| *end >= start)
+        .unwrap_or(html.len());
+    &html[start..end]
+}
+
+async fn record_invalid_address(
+    deliveries: &dyn DeliveryRepository,
+    issue_ids: &[Uuid],
+    recipient: &Recipient,
+    error: ParseEmailAddressError,
+) -> Result<DeliveryReport, RepositoryError> {
+    tracing::warn!(
+        error.cause_chain = ?error,
+        "Skipping a confirmed subscriber. Their stored contact details are invalid"
+    );
+    let attempt = Attempt::failed(ErrorClass::Permanent, error.to_string());
+    let mut report = DeliveryReport::default();
+    for issue_id in issue_ids {
+        deliveries
+            .record_attempts(*issue_id, &[(recipient.subscriber_id, &attempt)])
+            .await?;
+        report.count(&attempt);
+    }
+    Ok(report)
+}
+
+/// The email sent to one recipient, one issue or a digest of several.
+struct PersonalisedEmail {
+    subscriber_id: Uuid,
+    email: EmailAddress,
+    subject: String,
+    html: String,
+    text: String,
+    /// The issues the outcome is recorded for.
+    issue_ids: Vec<Uuid>,
+    /// Number of attempts made so far.
+    attempts: i32,
+}
+
+async fn deliver_batches(
+    deliveries: &dyn DeliveryRepository,
+    mailer: &Mailer,
+    mut personalised: Vec<PersonalisedEmail>,
+) -> Result<DeliveryReport, RepositoryError> {
+    let mut batches = Vec::new();
+    while !personalised.is_empty() {
+        let size = personalised.len().min(BATCH_SIZE);
+        batches.push(personalised.drain(..size).collect::<Vec<_>>());
+    }
+    let mut batches = stream::iter(batches)
+        .map(|batch| async move { deliver_batch(deliveries, mailer, &batch).await })
+        .buffer_unordered(MAX_CONCURRENT_BATCHES);
+    let mut report = DeliveryReport::default();
+    while let Some(batch_report) = batches.next().await {
+        report.merge(batch_report?);
+    }
+    Ok(report)
+}
+
+async fn deliver_batch(
+    deliveries: &dyn DeliveryRepository,
+    mailer: &Mailer,
+    batch: &[PersonalisedEmail],
+) -> Result<DeliveryReport, RepositoryError> {
+    let emails: Vec<_> = batch
+        .iter()
+        .map(|personalised| OutgoingEmail {
+            recipient: &personalised.email,
+            subject: &personalised.subject,
+            html_content: &personalised.html,
+            text_content: &personalised.text,
+        })
+        .collect();
+
+    let attempts: Vec<_> = match mailer.send_batch(&emails).await {
+        Ok(deliveries) => deliveries
+            .into_iter()
+            .zip(batch)
+            .map(|(delivery, personalised)| match delivery {
+                Ok(delivery) => Attempt::from(delivery),
+                Err(error) => {
+                    tracing::warn!(
+                        error.cause_chain = ?error,
+                        subscriber_email = %personalised.email.redacted(),
+                        "Failed to deliver a newsletter issue"
+                    );
+                    Attempt::from(error)
+                }
+            })
+            .collect(),
+        Err(error) => {
+            tracing::warn!(
+                error.cause_chain = ?error,
+                emails = batch.len(),
+                "Failed to deliver a batch of a newsletter issue"
+            );
+            let attempt = Attempt::from(error);
+            batch.iter().map(|_| attempt.clone()).collect()
+        }
+    };
+    let attempts: Vec<_> = attempts
+        .into_iter()
+        .zip(batch)
+        .map(|(attempt, personalised)| attempt.retried(personalised.attempts))
+        .collect();
+
+    // A digest is recorded for each of its issues
+    let mut report = DeliveryReport::default();
+    let mut recorded: BTreeMap<Uuid, Vec<(Uuid, &Attempt)>> = BTreeMap::new();
+    for (personalised, attempt) in batch.iter().zip(&attempts) {
+        for issue_id in &personalised.issue_ids {
+            report.count(attempt);
+            recorded
+                .entry(*issue_id)
+                .or_default()
+                .push((personalised.subscriber_id, attempt));
+        }
+    }
+    for (issue_id, attempts) in recorded {
+        deliveries.record_attempts(issue_id, &attempts).await?;
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{body_of, Attempt, DeliveryStatus, ErrorClass, MAX_ATTEMPTS};
+
+    #[test]
+    fn only_the_transient_failures_are_retried_until_they_run_out_of_attempts() {
+        let transient = || Attempt::failed(ErrorClass::Transient, "503".into());
+
+        assert_eq!(
+            transient().retried(0).delivery_status(),
+            DeliveryStatus::Queued
+        );
+        assert_eq!(
+            transient().retried(MAX_ATTEMPTS - 1).delivery_status(),
+            DeliveryStatus::Failed
+        );
+        assert_eq!(
+            Attempt::failed(ErrorClass::Permanent, "422".into())
+                .retried(0)
+                .delivery_status(),
+            DeliveryStatus::Failed
+        );
+    }
+
+    #[test]
+    fn the_body_of_a_document_is_extracted() {
+        assert_eq!(
+            body_of("<html><BODY class=\"issue\"><p>Hello</p></Body></html>"),
+            "<p>Hello</p>"
+        );
+        assert_eq!(body_of("<p>Hello</p>"), "<p>Hello</p>");
+    }
+}
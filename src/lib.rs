@@ -1,7 +1,17 @@
 pub mod configuration;
 pub mod domain;
+pub mod domain_check;
 pub mod email_client;
+pub mod human_verification;
+pub mod issue_delivery;
+pub mod mailer;
+pub mod outbox;
+pub mod repository;
 pub mod request_id;
 pub mod routes;
+pub mod secret;
 pub mod startup;
+pub mod subscription_policy;
+pub mod suppression;
 pub mod telemetry;
+pub mod tracking;
@@ -0,0 +1,312 @@
+use std::sync::Arc;
+
+use crate::{
+    domain::EmailAddress,
+    email_client::{EmailClient, EmailClientError, OutgoingEmail, MAX_BATCH_SIZE},
+    repository::{RepositoryError, SuppressionRepository},
+    suppression::{self, SkippedEmail},
+};
+
+/// The single entry point to send emails, the recipients in the suppression list are skipped.
+#[derive(Clone)]
+pub struct Mailer {
+    email_client: EmailClient,
+    suppressions: Arc<dyn SuppressionRepository>,
+}
+
+/// What happened to an email accepted by the `Mailer`.
+#[derive(Debug, Eq, PartialEq)]
+pub enum Delivery {
+    Sent {
+        /// Identifier given by the provider.
+        message_id: Option<String>,
+        /// Name of the provider that accepted the email.
+        provider: String,
+    },
+    /// The recipient is suppressed, the email was not sent.
+    Skipped { reason: String },
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum MailerError {
+    #[error("failed to access the suppression list")]
+    Database(#[from] RepositoryError),
+    #[error("failed to send the email")]
+    Send(#[from] EmailClientError),
+    /// The whole batch holding the email failed, the error is shared by its emails.
+    #[error("failed to send the batch of the email")]
+    Batch(#[source] Arc<EmailClientError>),
+}
+
+impl MailerError {
+    /// Whether sending the email again can succeed without fixing anything.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            MailerError::Database(_) => true,
+            MailerError::Send(error) => error.is_transient(),
+            MailerError::Batch(error) => error.is_transient(),
+        }
+    }
+}
+
+impl Mailer {
+    pub fn new(email_client: EmailClient, suppressions: Arc<dyn SuppressionRepository>) -> Self {
+        Self {
+            email_client,
+            suppressions,
+        }
+    }
+
+    #[tracing::instrument(
+        name = "Send an email",
+        skip(self, recipient, html_content, text_content),
+        fields(subscriber_email = %recipient.redacted(), provider = tracing::field::Empty)
+    )]
+    pub async fn send(
+        &self,
+        recipient: &EmailAddress,
+        subject: &str,
+        html_content: &str,
+        text_content: &str,
+    ) -> Result<Delivery, MailerError> {
+        if let Some(suppression) =
+            suppression::find_active(self.suppressions.as_ref(), recipient).await?
+        {
+            self.suppressions
+                .record_skipped(&[SkippedEmail {
+                    recipient,
+                    subject,
+                    reason: &suppression.reason,
+                }])
+                .await?;
+            tracing::info!(
+                reason = %suppression.reason,
+                scope = suppression.scope.as_str(),
+                "The recipient is suppressed, the email is skipped"
+            );
+            return Ok(Delivery::Skipped {
+                reason: suppression.reason,
+            });
+        }
+
+        let sent = self
+            .email_client
+            .send_email(recipient, subject, html_content, text_content)
+            .await?;
+        tracing::Span::current().record("provider", &tracing::field::display(&sent.provider));
+        Ok(Delivery::Sent {
+            message_id: sent.message_id,
+            provider: sent.provider,
+        })
+    }
+
+    /// Send `emails` with the batch API of the providers, the outcome of each email is returned in
+    /// the order of `emails`. A batch that failed as a whole is a failure of each of its emails,
+    /// the other batches are still sent. It fails only if the suppression list cannot be read.
+    #[tracing::instrument(name = "Send a batch of emails", skip(self, emails), fields(emails = emails.len()))]
+    pub async fn send_batch(
+        &self,
+        emails: &[OutgoingEmail<'_>],
+    ) -> Result<Vec<Result<Delivery, MailerError>>, MailerError> {
+        let mut deliveries: Vec<Option<Result<Delivery, MailerError>>> =
+            emails.iter().map(|_| None).collect();
+
+        let recipients: Vec<_> = emails.iter().map(|email| email.recipient).collect();
+        let suppressions =
+            suppression::find_active_for_all(self.suppressions.as_ref(), &recipients).await?;
+        let mut unsuppressed = Vec::with_capacity(emails.len());
+        let mut skipped = Vec::new();
+        for (index, (email, suppression)) in emails.iter().zip(&suppressions).enumerate() {
+            match suppression {
+                Some(suppression) => {
+                    skipped.push(SkippedEmail {
+                        recipient: email.recipient,
+                        subject: email.subject,
+                        reason: &suppression.reason,
+                    });
+                    deliveries[index] = Some(Ok(Delivery::Skipped {
+                        reason: suppression.reason.clone(),
+                    }));
+                }
+                None => unsuppressed.push(index),
+            }
+        }
+        if !skipped.is_empty() {
+            self.suppressions.record_skipped(&skipped).await?;
+            tracing::info!(
+                skipped = skipped.len(),
+                "Some recipients are suppressed, their emails are skipped"
+            );
+        }
+
+        for indices in unsuppressed.chunks(MAX_BATCH_SIZE) {
+            let batch: Vec<_> = indices.iter().map(|&index| emails[index]).collect();
+            let outcomes = match self.email_client.send_email_batch(&batch).await {
+                Ok(outcomes) => outcomes,
+                Err(error) => {
+                    tracing::warn!(
+                        error.cause_chain = ?error,
+                        emails = indices.len(),
+                        "Failed to send a batch of emails"
+                    );
+                    let error = Arc::new(error);
+                    for &index in indices {
+                        deliveries[index] = Some(Err(MailerError::Batch(error.clone())));
+                    }
+                    continue;
+                }
+            };
+            for (&index, outcome) in indices.iter().zip(outcomes) {
+                deliveries[index] = Some(
+                    outcome
+                        .map(|sent| Delivery::Sent {
+                            message_id: sent.message_id,
+                            provider: sent.provider,
+                        })
+                        .map_err(MailerError::from),
+                );
+            }
+        }
+
+        Ok(deliveries
+            .into_iter()
+            .map(|delivery| delivery.expect("Every email has an outcome"))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use async_trait::async_trait;
+    use chrono::{DateTime, Utc};
+    use wiremock::{matchers::path, Mock, MockServer, ResponseTemplate};
+
+    use super::{Delivery, Mailer};
+    use crate::{
+        domain::EmailAddress,
+        email_client::{
+            CircuitBreakerPolicy, EmailClient, EmailClientPolicy, OutgoingEmail, MAX_BATCH_SIZE,
+        },
+        repository::{RepositoryError, SuppressionRepository},
+        secret::Secret,
+        suppression::{SkippedEmail, Suppression, SuppressionScope},
+    };
+
+    /// A suppression list without entries.
+    struct NoSuppressions;
+
+    #[async_trait]
+    impl SuppressionRepository for NoSuppressions {
+        async fn find_active(
+            &self,
+            _addresses: &[String],
+            _domains: &[String],
+        ) -> Result<Vec<Suppression>, RepositoryError> {
+            Ok(Vec::new())
+        }
+
+        async fn add(
+            &self,
+            _scope: SuppressionScope,
+            _value: &str,
+            _reason: &str,
+            _expires_at: Option<DateTime<Utc>>,
+        ) -> Result<Suppression, RepositoryError> {
+            Err(RepositoryError::Database(sqlx::Error::RowNotFound))
+        }
+
+        async fn remove(
+            &self,
+            _scope: SuppressionScope,
+            _value: &str,
+        ) -> Result<bool, RepositoryError> {
+            Ok(false)
+        }
+
+        async fn list(&self) -> Result<Vec<Suppression>, RepositoryError> {
+            Ok(Vec::new())
+        }
+
+        async fn record_skipped(
+            &self,
+            _emails: &[SkippedEmail<'_>],
+        ) -> Result<(), RepositoryError> {
+            Ok(())
+        }
+    }
+
+    fn mailer(base_url: &str) -> Mailer {
+        let policy = EmailClientPolicy {
+            timeout: Duration::from_secs(1),
+            max_retries: 0,
+            retry_backoff: Duration::ZERO,
+            max_messages_per_second: None,
+            fallback_max_messages_per_second: Default::default(),
+            circuit_breaker: CircuitBreakerPolicy {
+                failure_rate_threshold: 0.5,
+                window_size: 10,
+                open_duration: Duration::from_secs(60),
+                half_open_probes: 1,
+            },
+        };
+        let email_client = EmailClient::new(
+            base_url,
+            "newsletter@example.com".parse().unwrap(),
+            Secret::new("token".into()),
+            policy,
+        );
+        Mailer::new(email_client, Arc::new(NoSuppressions))
+    }
+
+    #[tokio::test]
+    async fn the_outcomes_of_the_batches_sent_are_kept_when_a_later_batch_fails() {
+        let mock_server = MockServer::start().await;
+        let recipients: Vec<EmailAddress> = (0..=MAX_BATCH_SIZE)
+            .map(|i| format!("reader-{}@example.com", i).parse().unwrap())
+            .collect();
+        let results: Vec<_> = recipients[..MAX_BATCH_SIZE]
+            .iter()
+            .map(|recipient| {
+                serde_json::json!({
+                    "ErrorCode": 0,
+                    "Message": "OK",
+                    "MessageID": format!("message-{}", recipient.as_ref()),
+                    "To": recipient.as_ref()
+                })
+            })
+            .collect();
+        Mock::given(path("/email/batch"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(results))
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        Mock::given(path("/email/batch"))
+            .respond_with(ResponseTemplate::new(500))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+        let emails: Vec<_> = recipients
+            .iter()
+            .map(|recipient| OutgoingEmail {
+                recipient,
+                subject: "Issue",
+                html_content: "<p>Issue</p>",
+                text_content: "Issue",
+            })
+            .collect();
+
+        let deliveries = mailer(&mock_server.uri())
+            .send_batch(&emails)
+            .await
+            .unwrap();
+
+        assert_eq!(deliveries.len(), MAX_BATCH_SIZE + 1);
+        assert!(deliveries[..MAX_BATCH_SIZE]
+            .iter()
+            .all(|delivery| matches!(delivery, Ok(Delivery::Sent { .. }))));
+        assert!(matches!(&deliveries[MAX_BATCH_SIZE], Err(error) if error.is_transient()));
+    }
+}
@@ -1,9 +1,19 @@
-use clap::Parser;
+use std::sync::Arc;
+
+use clap::{Parser, Subcommand};
 use sqlx::postgres::PgPoolOptions;
+use uuid::Uuid;
+#[cfg(feature = "sqlite")]
+use zero2prod::repository::SqliteRepository;
 use zero2prod::{
-    configuration::get_configuration,
-    startup::Application,
-    telemetry::{get_subscriber, init_subscriber},
+    configuration::{
+        describe_configuration, get_configuration, get_environment, DatabaseBackend,
+        DatabaseSettings, EnvFileSecretProvider, Environment, Reloader, Settings,
+    },
+    outbox::dead_letters::MAX_PAGE_SIZE,
+    repository::{CanonicalBackfill, DeadLetterRepository, PgRepository},
+    startup::{get_connection_pool, Application},
+    telemetry::{get_subscriber, init_subscriber, LogFilterHandle},
 };
 
 #[derive(Parser)]
@@ -21,17 +31,81 @@ struct Migrate {
     timeout: u64,
 }
 
+#[derive(Subcommand)]
+enum Config {
+    /// Validate the configuration, all the errors are reported
+    Check,
+    /// Print the resolved configuration and the source of each value
+    Print,
+}
+
+#[derive(Subcommand)]
+enum Dlq {
+    /// List the dead letters, the most recent first
+    List {
+        /// Largest number of dead letters to list, up to 1000
+        #[clap(long, default_value = "100", parse(try_from_str = parse_limit))]
+        limit: i64,
+        /// List the dead letters after this one, the last of the previous page
+        #[clap(long)]
+        after: Option<Uuid>,
+    },
+    /// Print a dead letter with the content of the email
+    Show { id: Uuid },
+    /// Put dead letters back in the outbox, they are sent again by the running service
+    Replay(Selection),
+    /// Delete dead letters
+    Purge(Selection),
+}
+
+/// The limit of `dlq list`, bounded like the page of `GET /admin/dead_letters`.
+fn parse_limit(value: &str) -> Result<i64, String> {
+    let limit: i64 = value.parse().map_err(|e| format!("{}", e))?;
+    if !(1..=MAX_PAGE_SIZE).contains(&limit) {
+        return Err(format!("the limit must be between 1 and {}", MAX_PAGE_SIZE));
+    }
+    Ok(limit)
+}
+
+#[derive(Parser)]
+struct Selection {
+    /// The dead letter
+    #[clap(required_unless_present = "all")]
+    id: Option<Uuid>,
+
+    /// Select all the dead letters
+    #[clap(long, conflicts_with = "id")]
+    all: bool,
+}
+
 #[derive(Parser)]
 enum Args {
     /// Execute database migration
     Migrate(Migrate),
     /// Run the service
     Serve,
+    /// Inspect the configuration
+    #[clap(subcommand)]
+    Config(Config),
+    /// Inspect and replay the emails given up on
+    #[clap(subcommand)]
+    Dlq(Dlq),
 }
 
 async fn migrate(opt: Migrate) {
     let configuration = get_configuration().expect("Failed to read configuration");
 
+    // The SQLite file is created if needed, there is no server to wait for
+    #[cfg(feature = "sqlite")]
+    if let DatabaseBackend::Sqlite { path } = &configuration.database.backend {
+        SqliteRepository::open(path)
+            .migrate()
+            .await
+            .expect("Failed to migrate the SQLite database");
+        println!("Migration completed with success");
+        std::process::exit(0);
+    }
+
     for retry in 0..=opt.retry {
         if retry > 0 {
             println!("Retry number {} (waiting {}s)", retry, opt.retry_delay);
@@ -48,6 +122,17 @@ async fn migrate(opt: Migrate) {
                     .run(&pool)
                     .await
                     .expect("Failed to migrate the database");
+                let backfill = PgRepository::new(pool)
+                    .backfill_canonical_emails()
+                    .await
+                    .expect("Failed to backfill the canonical addresses");
+                if backfill != CanonicalBackfill::default() {
+                    println!(
+                        "Canonical addresses: {} subscriber(s) updated, {} collision(s) recorded \
+                        in canonical_email_collisions, {} suppression(s) keyed again",
+                        backfill.subscribers, backfill.collisions, backfill.suppressions
+                    );
+                }
 
                 println!("Migration completed with success");
                 std::process::exit(0);
@@ -60,20 +145,190 @@ async fn migrate(opt: Migrate) {
     std::process::exit(1);
 }
 
-async fn run() -> hyper::Result<()> {
-    let configuration = get_configuration().expect("Failed to read configuration");
+fn config(command: Config) {
+    match command {
+        Config::Check => {
+            let (environment, configuration) = read_configuration();
+            match configuration.validate(environment) {
+                Ok(()) => println!("The {} configuration is valid", environment.as_str()),
+                Err(errors) => {
+                    eprint!("{}", errors);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Config::Print => {
+            let values = describe_configuration(&EnvFileSecretProvider::new())
+                .unwrap_or_else(|e| fail("Failed to read the configuration", &e));
+            for value in values {
+                println!("{} = {} ({})", value.key, value.value, value.source);
+            }
+        }
+    }
+}
+
+async fn dlq(command: Dlq) {
+    let (_, configuration) = read_configuration();
+    let dead_letters = dead_letter_repository(&configuration.database);
+    match command {
+        Dlq::List { limit, after } => {
+            let dead_letters = dead_letters
+                .list(limit, after)
+                .await
+                .unwrap_or_else(|e| fail("Failed to list the dead letters", &e));
+            let dead_letters = match dead_letters {
+                Some(dead_letters) => dead_letters,
+                None => {
+                    eprintln!("The dead letter {} does not exist anymore", after.unwrap());
+                    std::process::exit(1);
+                }
+            };
+            for dead_letter in &dead_letters {
+                println!(
+                    "{}  {}  {} attempt(s)  {}  {}",
+                    dead_letter.id,
+                    dead_letter.dead_lettered_at.to_rfc3339(),
+                    dead_letter.attempts,
+                    dead_letter.recipient,
+                    dead_letter.subject
+                );
+                println!("    {}", dead_letter.last_error);
+            }
+            println!("{} dead letter(s)", dead_letters.len());
+        }
+        Dlq::Show { id } => {
+            let details = dead_letters
+                .find(id)
+                .await
+                .unwrap_or_else(|e| fail("Failed to fetch the dead letter", &e));
+            let details = match details {
+                Some(details) => details,
+                None => {
+                    eprintln!("The dead letter {} does not exist", id);
+                    std::process::exit(1);
+                }
+            };
+            let dead_letter = &details.dead_letter;
+            println!("id = {}", dead_letter.id);
+            println!("recipient = {}", dead_letter.recipient);
+            println!("subject = {}", dead_letter.subject);
+            println!("attempts = {}", dead_letter.attempts);
+            println!("last_error = {}", dead_letter.last_error);
+            println!("created_at = {}", dead_letter.created_at.to_rfc3339());
+            println!(
+                "dead_lettered_at = {}",
+                dead_letter.dead_lettered_at.to_rfc3339()
+            );
+            println!("\n{}\n\n{}", details.text_content, details.html_content);
+        }
+        Dlq::Replay(Selection { id: Some(id), .. }) => {
+            let replayed = dead_letters
+                .replay(id)
+                .await
+                .unwrap_or_else(|e| fail("Failed to replay the dead letter", &e));
+            if !replayed {
+                eprintln!("The dead letter {} does not exist", id);
+                std::process::exit(1);
+            }
+            println!("The dead letter {} is replayed", id);
+        }
+        Dlq::Replay(Selection { id: None, .. }) => {
+            let count = dead_letters
+                .replay_all()
+                .await
+                .unwrap_or_else(|e| fail("Failed to replay the dead letters", &e));
+            println!("{} dead letter(s) replayed", count);
+        }
+        Dlq::Purge(Selection { id: Some(id), .. }) => {
+            let purged = dead_letters
+                .purge(id)
+                .await
+                .unwrap_or_else(|e| fail("Failed to purge the dead letter", &e));
+            if !purged {
+                eprintln!("The dead letter {} does not exist", id);
+                std::process::exit(1);
+            }
+            println!("The dead letter {} is purged", id);
+        }
+        Dlq::Purge(Selection { id: None, .. }) => {
+            let count = dead_letters
+                .purge_all()
+                .await
+                .unwrap_or_else(|e| fail("Failed to purge the dead letters", &e));
+            println!("{} dead letter(s) purged", count);
+        }
+    }
+}
+
+/// The dead letters of the outbox of the configured backend.
+fn dead_letter_repository(settings: &DatabaseSettings) -> Arc<dyn DeadLetterRepository> {
+    match &settings.backend {
+        DatabaseBackend::Postgres => Arc::new(PgRepository::new(get_connection_pool(settings))),
+        #[cfg(feature = "sqlite")]
+        DatabaseBackend::Sqlite { path } => Arc::new(SqliteRepository::open(path)),
+        #[cfg(not(feature = "sqlite"))]
+        DatabaseBackend::Sqlite { .. } => {
+            eprintln!("The SQLite backend needs the `sqlite` feature");
+            std::process::exit(1)
+        }
+    }
+}
+
+/// The environment and the configuration, the command fails if they cannot be read.
+fn read_configuration() -> (Environment, Settings) {
+    get_environment()
+        .and_then(|environment| Ok((environment, get_configuration()?)))
+        .unwrap_or_else(|e| fail("Failed to read the configuration", &e))
+}
+
+/// Report the error of the command on stderr and exit with the status 1.
+fn fail(context: &str, error: &dyn std::fmt::Display) -> ! {
+    eprintln!("{}: {}", context, error);
+    std::process::exit(1)
+}
+
+async fn run(log_filter: LogFilterHandle) -> hyper::Result<()> {
+    let (environment, configuration) = read_configuration();
+    if let Err(errors) = configuration.validate(environment) {
+        tracing::error!(%errors, "Invalid configuration");
+        std::process::exit(1);
+    }
+
+    // RUST_LOG has the precedence over the configuration, the filter is left untouched
+    let reloaded_log_filter = if std::env::var("RUST_LOG").is_ok() {
+        None
+    } else {
+        Some(log_filter.clone())
+    };
+
+    let application = Application::build(configuration.clone(), log_filter);
+    let reloader = Reloader::new(
+        configuration,
+        environment,
+        reloaded_log_filter,
+        application.email_client(),
+        application.subscription_policy(),
+        application.soft_bounce_threshold(),
+    );
+    tokio::spawn(reloader.watch());
 
-    Application::build(configuration).run().await
+    application.run().await
 }
 
 #[tokio::main]
 async fn main() -> hyper::Result<()> {
-    let subscriber = get_subscriber("zero2prod", "info", std::io::stdout);
+    // An invalid configuration is reported later, the default telemetry is used meanwhile
+    let telemetry = get_configuration()
+        .map(|configuration| configuration.telemetry)
+        .unwrap_or_default();
+    let (subscriber, log_filter) = get_subscriber("zero2prod", &telemetry, std::io::stdout);
     init_subscriber(subscriber);
 
     match Args::parse() {
         Args::Migrate(opt) => migrate(opt).await,
-        Args::Serve => run().await?,
+        Args::Serve => run(log_filter).await?,
+        Args::Config(command) => config(command),
+        Args::Dlq(command) => dlq(command).await,
     }
     Ok(())
 }
@@ -0,0 +1,28 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use uuid::Uuid;
+
+/// Largest number of dead letters listed at once.
+pub const MAX_PAGE_SIZE: i64 = 1000;
+
+/// A message of the outbox given up on, after a permanent error or too many attempts.
+#[derive(Debug, Serialize)]
+pub struct DeadLetter {
+    pub id: Uuid,
+    pub recipient: String,
+    pub subject: String,
+    pub attempts: i32,
+    /// The error of the last attempt.
+    pub last_error: String,
+    pub created_at: DateTime<Utc>,
+    pub dead_lettered_at: DateTime<Utc>,
+}
+
+/// A dead letter with the content of the email.
+#[derive(Debug, Serialize)]
+pub struct DeadLetterDetails {
+    #[serde(flatten)]
+    pub dead_letter: DeadLetter,
+    pub html_content: String,
+    pub text_content: String,
+}
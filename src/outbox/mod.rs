@@ -0,0 +1,346 @@
+pub mod dead_letters;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+
+use std::time::Duration;
+
+use chrono::Utc;
+use sqlx::{postgres::PgListener, Executor, PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::{
+    domain::EmailAddress,
+    email_client::{EmailClientError, OutgoingEmail},
+    mailer::{Delivery, Mailer, MailerError},
+};
+
+/// Channel notified when a message is written to the outbox.
+const CHANNEL: &str = "outbox";
+/// Time between two checks of the outbox when no notification is received.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// Waiting time before the first retry, it doubles with every attempt.
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+/// Longest waiting time between two attempts.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(600);
+/// Number of attempts after which a message is dead-lettered.
+const MAX_ATTEMPTS: i32 = 15;
+/// How long a message is reserved by the relay sending it, longer than the retries of the email
+/// client.
+const LEASE: Duration = Duration::from_secs(300);
+
+/// Write an email to the outbox, it is sent by the relay once `transaction` is committed.
+#[tracing::instrument(
+    name = "Write an email to the outbox",
+    skip(transaction, email),
+    fields(subscriber_email = %email.recipient.redacted())
+)]
+pub async fn enqueue(
+    transaction: &mut Transaction<'_, Postgres>,
+    email: &OutgoingEmail<'_>,
+) -> Result<Uuid, sqlx::Error> {
+    let id = Uuid::new_v4();
+    let now = Utc::now();
+    sqlx::query!(
+        r#"INSERT INTO outbox
+            (id, recipient, subject, html_content, text_content, attempts, created_at,
+                next_attempt_at)
+            VALUES ($1, $2, $3, $4, $5, 0, $6, $6)"#,
+        id,
+        email.recipient.as_ref(),
+        email.subject,
+        email.html_content,
+        email.text_content,
+        now,
+    )
+    .execute(&mut *transaction)
+    .await?;
+    notify(transaction).await?;
+    Ok(id)
+}
+
+/// Wake up the relay, inside a transaction the notification is delivered on commit.
+pub(crate) async fn notify(
+    executor: impl Executor<'_, Database = Postgres>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!("SELECT pg_notify($1, '')", CHANNEL)
+        .execute(executor)
+        .await?;
+    Ok(())
+}
+
+/// Send the messages of the outbox as they become due, until the application stops.
+pub async fn run_relay(pool: PgPool, mailer: Mailer) {
+    let mut listener = listen(&pool).await;
+    loop {
+        match try_relay_next(&pool, &mailer).await {
+            Ok(true) => continue,
+            Ok(false) => {}
+            Err(error) => {
+                tracing::error!(
+                    error.cause_chain = ?error,
+                    "Failed to relay the messages of the outbox"
+                );
+            }
+        }
+        match &mut listener {
+            Some(notifications) => {
+                if let Ok(Err(error)) =
+                    tokio::time::timeout(POLL_INTERVAL, notifications.recv()).await
+                {
+                    // The listener reconnects on the next call
+                    tracing::warn!(
+                        error.cause_chain = ?error,
+                        "Lost the notifications of the outbox"
+                    );
+                    tokio::time::sleep(POLL_INTERVAL).await;
+                }
+            }
+            None => tokio::time::sleep(POLL_INTERVAL).await,
+        }
+    }
+}
+
+/// Listen to the notifications of the outbox, the relay falls back to polling without them.
+async fn listen(pool: &PgPool) -> Option<PgListener> {
+    let listener = async {
+        let mut listener = PgListener::connect_with(pool).await?;
+        listener.listen(CHANNEL).await?;
+        Ok::<_, sqlx::Error>(listener)
+    };
+    match listener.await {
+        Ok(listener) => Some(listener),
+        Err(error) => {
+            tracing::warn!(
+                error.cause_chain = ?error,
+                "Failed to listen to the notifications of the outbox, it is polled instead"
+            );
+            None
+        }
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct Message {
+    id: Uuid,
+    recipient: String,
+    subject: String,
+    html_content: String,
+    text_content: String,
+    attempts: i32,
+}
+
+/// Send the next due message, it returns `false` if no message is due.
+///
+/// The message is leased before sending: no transaction stays open during the call to the
+/// provider, and the other instances skip the message until its `locked_until`. If the relay stops
+/// while sending, the lease expires and the message is sent again.
+#[tracing::instrument(name = "Relay a message of the outbox", skip(pool, mailer))]
+pub async fn try_relay_next(pool: &PgPool, mailer: &Mailer) -> Result<bool, sqlx::Error> {
+    let now = Utc::now();
+    let lease = chrono::Duration::from_std(LEASE).expect("The lease is in range");
+    let message = sqlx::query_as!(
+        Message,
+        r#"UPDATE outbox SET locked_until = $2
+            WHERE id = (
+                SELECT id FROM outbox
+                WHERE dead_lettered_at IS NULL AND next_attempt_at <= $1
+                    AND (locked_until IS NULL OR locked_until <= $1)
+                ORDER BY next_attempt_at
+                LIMIT 1
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING id, recipient, subject, html_content, text_content, attempts"#,
+        now,
+        now + lease,
+    )
+    .fetch_optional(pool)
+    .await?;
+    let message = match message {
+        Some(message) => message,
+        None => return Ok(false),
+    };
+
+    let outcome = attempt(mailer, &message).await;
+    let mut transaction = pool.begin().await?;
+    match outcome {
+        Outcome::Sent {
+            provider,
+            message_id,
+        } => {
+            sqlx::query!(
+                r#"INSERT INTO sent_emails
+                    (id, recipient, subject, provider, provider_message_id, sent_at)
+                    VALUES ($1, $2, $3, $4, $5, $6)"#,
+                message.id,
+                message.recipient,
+                message.subject,
+                provider,
+                message_id,
+                Utc::now(),
+            )
+            .execute(&mut transaction)
+            .await?;
+            sqlx::query!("DELETE FROM outbox WHERE id = $1", message.id)
+                .execute(&mut transaction)
+                .await?;
+        }
+        Outcome::Skipped => {
+            sqlx::query!("DELETE FROM outbox WHERE id = $1", message.id)
+                .execute(&mut transaction)
+                .await?;
+        }
+        Outcome::Retry {
+            attempts,
+            last_error,
+        } => reschedule(&mut transaction, &message, attempts, &last_error).await?,
+        Outcome::DeadLetter { last_error } => {
+            dead_letter(&mut transaction, &message, &last_error).await?
+        }
+    }
+    transaction.commit().await?;
+    Ok(true)
+}
+
+/// What becomes of a message after an attempt to send it.
+enum Outcome {
+    /// The message was sent by `provider`, it leaves the outbox for `sent_emails`.
+    Sent {
+        provider: String,
+        message_id: Option<String>,
+    },
+    /// The recipient is suppressed, the message leaves the outbox.
+    Skipped,
+    /// The message is sent again after a delay, `attempts` is the new count of attempts.
+    Retry { attempts: i32, last_error: String },
+    /// The message is given up on.
+    DeadLetter { last_error: String },
+}
+
+/// Send `message`, whatever the database holding the outbox.
+async fn attempt(mailer: &Mailer, message: &Message) -> Outcome {
+    let recipient = match message.recipient.parse::<EmailAddress>() {
+        Ok(recipient) => recipient,
+        Err(error) => {
+            return Outcome::DeadLetter {
+                last_error: error.to_string(),
+            }
+        }
+    };
+    let outcome = mailer
+        .send(
+            &recipient,
+            &message.subject,
+            &message.html_content,
+            &message.text_content,
+        )
+        .await;
+
+    match outcome {
+        Ok(Delivery::Sent {
+            message_id,
+            provider,
+        }) => {
+            tracing::info!(
+                attempts = message.attempts + 1,
+                %provider,
+                "Message of the outbox sent"
+            );
+            Outcome::Sent {
+                provider,
+                message_id,
+            }
+        }
+        Ok(Delivery::Skipped { reason }) => {
+            tracing::info!(%reason, "Message of the outbox skipped");
+            Outcome::Skipped
+        }
+        // Nothing was sent, the attempt is not counted
+        Err(MailerError::Send(EmailClientError::CircuitOpen(_))) => Outcome::Retry {
+            attempts: message.attempts,
+            last_error: "circuit breaker open".into(),
+        },
+        Err(error) if error.is_transient() && message.attempts + 1 < MAX_ATTEMPTS => {
+            let last_error = format!("{:#}", anyhow::Error::from(error));
+            tracing::warn!(
+                attempts = message.attempts + 1,
+                %last_error,
+                "Failed to send a message of the outbox, it is retried later"
+            );
+            Outcome::Retry {
+                attempts: message.attempts + 1,
+                last_error,
+            }
+        }
+        Err(error) => Outcome::DeadLetter {
+            last_error: format!("{:#}", anyhow::Error::from(error)),
+        },
+    }
+}
+
+async fn reschedule(
+    transaction: &mut Transaction<'_, Postgres>,
+    message: &Message,
+    attempts: i32,
+    last_error: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"UPDATE outbox
+            SET attempts = $2, last_error = $3, next_attempt_at = $4, locked_until = NULL
+            WHERE id = $1"#,
+        message.id,
+        attempts,
+        last_error,
+        Utc::now() + retry_delay(attempts),
+    )
+    .execute(transaction)
+    .await?;
+    Ok(())
+}
+
+async fn dead_letter(
+    transaction: &mut Transaction<'_, Postgres>,
+    message: &Message,
+    last_error: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!(
+        r#"UPDATE outbox
+            SET attempts = attempts + 1, last_error = $2, dead_lettered_at = $3,
+                locked_until = NULL
+            WHERE id = $1"#,
+        message.id,
+        last_error,
+        Utc::now(),
+    )
+    .execute(transaction)
+    .await?;
+    tracing::error!(
+        message_id = %message.id,
+        attempts = message.attempts + 1,
+        last_error,
+        "Gave up sending a message of the outbox, it is dead-lettered"
+    );
+    Ok(())
+}
+
+/// The waiting time after `attempts` failed attempts.
+pub(crate) fn retry_delay(attempts: i32) -> chrono::Duration {
+    let exponent = attempts.clamp(1, 16) as u32 - 1;
+    let delay = (RETRY_DELAY * 2u32.pow(exponent)).min(MAX_RETRY_DELAY);
+    chrono::Duration::from_std(delay).expect("The retry delay is in range")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{retry_delay, MAX_RETRY_DELAY};
+
+    #[test]
+    fn the_retry_delay_doubles_up_to_a_maximum() {
+        assert_eq!(retry_delay(1).num_seconds(), 1);
+        assert_eq!(retry_delay(2).num_seconds(), 2);
+        assert_eq!(retry_delay(5).num_seconds(), 16);
+        assert_eq!(
+            retry_delay(14).num_seconds(),
+            MAX_RETRY_DELAY.as_secs() as i64
+        );
+    }
+}
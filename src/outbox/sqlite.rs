@@ -0,0 +1,159 @@
+//! The outbox of the SQLite backend, with the same table and the same retries as the outbox in
+//! Postgres.
+//!
+//! The relay leases the message like in Postgres: one `UPDATE ... RETURNING` picks the next due
+//! message that is not leased and sets its `locked_until`. SQLite has no `FOR UPDATE SKIP LOCKED`,
+//! but it runs the writes one at a time so two relays never pick the same message. There is no
+//! `LISTEN`/`NOTIFY` either, the outbox is polled.
+
+use chrono::Utc;
+use sqlx::{Sqlite, SqlitePool, Transaction};
+use uuid::Uuid;
+
+use super::{attempt, retry_delay, Message, Outcome, LEASE, POLL_INTERVAL};
+use crate::{email_client::OutgoingEmail, mailer::Mailer};
+
+/// Write an email to the outbox, it is sent by the relay once `transaction` is committed.
+#[tracing::instrument(
+    name = "Write an email to the SQLite outbox",
+    skip(transaction, email),
+    fields(subscriber_email = %email.recipient.redacted())
+)]
+pub async fn enqueue(
+    transaction: &mut Transaction<'_, Sqlite>,
+    email: &OutgoingEmail<'_>,
+) -> Result<Uuid, sqlx::Error> {
+    let id = Uuid::new_v4();
+    let now = Utc::now();
+    sqlx::query(
+        r#"INSERT INTO outbox
+            (id, recipient, subject, html_content, text_content, attempts, created_at,
+                next_attempt_at)
+            VALUES (?, ?, ?, ?, ?, 0, ?, ?)"#,
+    )
+    .bind(id)
+    .bind(email.recipient.as_ref())
+    .bind(email.subject)
+    .bind(email.html_content)
+    .bind(email.text_content)
+    .bind(now)
+    .bind(now)
+    .execute(&mut *transaction)
+    .await?;
+    Ok(id)
+}
+
+/// Send the messages of the outbox as they become due, until the application stops.
+pub async fn run_relay(pool: SqlitePool, mailer: Mailer) {
+    loop {
+        match try_relay_next(&pool, &mailer).await {
+            Ok(true) => continue,
+            Ok(false) => {}
+            Err(error) => {
+                tracing::error!(
+                    error.cause_chain = ?error,
+                    "Failed to relay the messages of the SQLite outbox"
+                );
+            }
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+/// Send the next due message, it returns `false` if no message is due.
+#[tracing::instrument(name = "Relay a message of the SQLite outbox", skip(pool, mailer))]
+pub async fn try_relay_next(pool: &SqlitePool, mailer: &Mailer) -> Result<bool, sqlx::Error> {
+    let now = Utc::now();
+    let lease = chrono::Duration::from_std(LEASE).expect("The lease is in range");
+    let message = sqlx::query_as::<_, Message>(
+        r#"UPDATE outbox SET locked_until = ?
+            WHERE id = (
+                SELECT id FROM outbox
+                WHERE dead_lettered_at IS NULL AND next_attempt_at <= ?
+                    AND (locked_until IS NULL OR locked_until <= ?)
+                ORDER BY next_attempt_at
+                LIMIT 1
+            )
+            RETURNING id, recipient, subject, html_content, text_content, attempts"#,
+    )
+    .bind(now + lease)
+    .bind(now)
+    .bind(now)
+    // Not `fetch_optional`, the lease would be committed after the message is sent
+    .fetch_all(pool)
+    .await?
+    .pop();
+    let message = match message {
+        Some(message) => message,
+        None => return Ok(false),
+    };
+
+    match attempt(mailer, &message).await {
+        Outcome::Sent {
+            provider,
+            message_id,
+        } => {
+            let mut transaction = pool.begin().await?;
+            sqlx::query(
+                r#"INSERT INTO sent_emails
+                    (id, recipient, subject, provider, provider_message_id, sent_at)
+                    VALUES (?, ?, ?, ?, ?, ?)"#,
+            )
+            .bind(message.id)
+            .bind(&message.recipient)
+            .bind(&message.subject)
+            .bind(&provider)
+            .bind(&message_id)
+            .bind(Utc::now())
+            .execute(&mut transaction)
+            .await?;
+            sqlx::query("DELETE FROM outbox WHERE id = ?")
+                .bind(message.id)
+                .execute(&mut transaction)
+                .await?;
+            transaction.commit().await?;
+        }
+        Outcome::Skipped => {
+            sqlx::query("DELETE FROM outbox WHERE id = ?")
+                .bind(message.id)
+                .execute(pool)
+                .await?;
+        }
+        Outcome::Retry {
+            attempts,
+            last_error,
+        } => {
+            sqlx::query(
+                r#"UPDATE outbox
+                    SET attempts = ?, last_error = ?, next_attempt_at = ?, locked_until = NULL
+                    WHERE id = ?"#,
+            )
+            .bind(attempts)
+            .bind(&last_error)
+            .bind(Utc::now() + retry_delay(attempts))
+            .bind(message.id)
+            .execute(pool)
+            .await?;
+        }
+        Outcome::DeadLetter { last_error } => {
+            sqlx::query(
+                r#"UPDATE outbox
+                    SET attempts = attempts + 1, last_error = ?, dead_lettered_at = ?,
+                        locked_until = NULL
+                    WHERE id = ?"#,
+            )
+            .bind(&last_error)
+            .bind(Utc::now())
+            .bind(message.id)
+            .execute(pool)
+            .await?;
+            tracing::error!(
+                message_id = %message.id,
+                attempts = message.attempts + 1,
+                %last_error,
+                "Gave up sending a message of the SQLite outbox, it is dead-lettered"
+            );
+        }
+    }
+    Ok(true)
+}
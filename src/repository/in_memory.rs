@@ -0,0 +1,185 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use async_trait::async_trait;
+use chrono::Utc;
+use uuid::Uuid;
+
+use super::{
+    IssueRepository, Preferences, RepositoryError, Subscriber, SubscriberRepository,
+    TokenRepository,
+};
+use crate::{
+    domain::{NewSubscriber, SubscriberName, SubscriptionStatus, SubscriptionToken},
+    email_client::OutgoingEmail,
+    issue_delivery::NewsletterIssue,
+};
+
+/// The repositories kept in memory, for the tests that do not need a database. The clones share
+/// the same data.
+#[derive(Clone, Default)]
+pub struct InMemoryRepository {
+    state: Arc<Mutex<State>>,
+}
+
+/// An email written to the outbox of an `InMemoryRepository`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct QueuedEmail {
+    pub recipient: String,
+    pub subject: String,
+    pub html_content: String,
+    pub text_content: String,
+}
+
+#[derive(Default)]
+struct State {
+    subscribers: HashMap<Uuid, Subscriber>,
+    preferences: HashMap<Uuid, Preferences>,
+    /// The canonical addresses of the subscribers, they are unique.
+    canonical_emails: HashMap<String, Uuid>,
+    tokens: HashMap<String, Uuid>,
+    issues: HashMap<Uuid, NewsletterIssue>,
+    outbox: Vec<QueuedEmail>,
+}
+
+impl InMemoryRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The emails queued so far, the oldest first.
+    pub fn queued_emails(&self) -> Vec<QueuedEmail> {
+        self.state.lock().unwrap().outbox.clone()
+    }
+}
+
+#[async_trait]
+impl SubscriberRepository for InMemoryRepository {
+    async fn add_pending(
+        &self,
+        subscriber: &NewSubscriber,
+        token: &SubscriptionToken,
+        confirmation: &OutgoingEmail<'_>,
+    ) -> Result<Uuid, RepositoryError> {
+        let mut state = self.state.lock().unwrap();
+        let canonical_email = subscriber.email.canonical();
+        if state.tokens.contains_key(token.as_ref()) {
+            return Err(RepositoryError::Duplicate);
+        }
+        let id = match state.canonical_emails.get(&canonical_email).copied() {
+            Some(id) => {
+                let existing = state.subscribers.get_mut(&id).unwrap();
+                if existing.status != SubscriptionStatus::PendingConfirmation {
+                    return Err(RepositoryError::Duplicate);
+                }
+                existing.subscribed_at = Utc::now();
+                id
+            }
+            None => {
+                if state
+                    .subscribers
+                    .values()
+                    .any(|s| s.email == subscriber.email.as_ref())
+                {
+                    return Err(RepositoryError::Duplicate);
+                }
+                let id = Uuid::new_v4();
+                state.subscribers.insert(
+                    id,
+                    Subscriber {
+                        id,
+                        email: subscriber.email.as_ref().to_owned(),
+                        name: subscriber.name.as_ref().to_owned(),
+                        status: SubscriptionStatus::PendingConfirmation,
+                        subscribed_at: Utc::now(),
+                    },
+                );
+                state.preferences.insert(id, Preferences::default());
+                state.canonical_emails.insert(canonical_email, id);
+                id
+            }
+        };
+        state.tokens.insert(token.as_ref().to_owned(), id);
+        state.outbox.push(QueuedEmail {
+            recipient: confirmation.recipient.as_ref().to_owned(),
+            subject: confirmation.subject.to_owned(),
+            html_content: confirmation.html_content.to_owned(),
+            text_content: confirmation.text_content.to_owned(),
+        });
+        Ok(id)
+    }
+
+    async fn get(&self, id: Uuid) -> Result<Option<Subscriber>, RepositoryError> {
+        Ok(self.state.lock().unwrap().subscribers.get(&id).cloned())
+    }
+
+    async fn transition(
+        &self,
+        id: Uuid,
+        status: SubscriptionStatus,
+    ) -> Result<bool, RepositoryError> {
+        let mut state = self.state.lock().unwrap();
+        match state.subscribers.get_mut(&id) {
+            Some(subscriber) if subscriber.status.can_transition_to(status) => {
+                subscriber.status = status;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn preferences(&self, id: Uuid) -> Result<Option<Preferences>, RepositoryError> {
+        Ok(self.state.lock().unwrap().preferences.get(&id).cloned())
+    }
+
+    async fn update_preferences(
+        &self,
+        id: Uuid,
+        name: &SubscriberName,
+        preferences: &Preferences,
+    ) -> Result<bool, RepositoryError> {
+        let mut state = self.state.lock().unwrap();
+        match state.subscribers.get_mut(&id) {
+            Some(subscriber) => {
+                subscriber.name = name.as_ref().to_owned();
+                state.preferences.insert(id, preferences.clone());
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+#[async_trait]
+impl TokenRepository for InMemoryRepository {
+    async fn subscriber_id(
+        &self,
+        token: &SubscriptionToken,
+    ) -> Result<Option<Uuid>, RepositoryError> {
+        Ok(self
+            .state
+            .lock()
+            .unwrap()
+            .tokens
+            .get(token.as_ref())
+            .copied())
+    }
+}
+
+#[async_trait]
+impl IssueRepository for InMemoryRepository {
+    async fn add(&self, issue: &NewsletterIssue) -> Result<(), RepositoryError> {
+        let mut state = self.state.lock().unwrap();
+        if state.issues.contains_key(&issue.id) {
+            return Err(RepositoryError::Duplicate);
+        }
+        state.issues.insert(issue.id, issue.clone());
+        Ok(())
+    }
+
+    async fn get(&self, id: Uuid) -> Result<Option<NewsletterIssue>, RepositoryError> {
+        Ok(self.state.lock().unwrap().issues.get(&id).cloned())
+    }
+}
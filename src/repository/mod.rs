@@ -0,0 +1,263 @@
+mod in_memory;
+mod postgres;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+
+use std::collections::BTreeSet;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+#[cfg(feature = "sqlite")]
+pub use self::sqlite::SqliteRepository;
+pub use self::{
+    in_memory::{InMemoryRepository, QueuedEmail},
+    postgres::{CanonicalBackfill, PgRepository},
+};
+use crate::{
+    domain::{
+        DeliveryFrequency, EmailEvent, NewSubscriber, SubscriberName, SubscriptionStatus,
+        SubscriptionToken,
+    },
+    email_client::OutgoingEmail,
+    issue_delivery::{Attempt, DeliverySummary, NewsletterIssue, QueuedDelivery},
+    outbox::dead_letters::{DeadLetter, DeadLetterDetails},
+    suppression::{SkippedEmail, Suppression, SuppressionScope},
+    tracking::{IssueStats, TrackingEvent},
+};
+
+/// A subscriber as stored.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Subscriber {
+    pub id: Uuid,
+    pub email: String,
+    pub name: String,
+    pub status: SubscriptionStatus,
+    /// When the confirmation link was sent.
+    pub subscribed_at: DateTime<Utc>,
+}
+
+/// What a subscriber chose in the preference centre.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Preferences {
+    /// The ids of the topics, see `subscriptions.topics` in the configuration.
+    pub topics: BTreeSet<String>,
+    pub frequency: DeliveryFrequency,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum RepositoryError {
+    /// Another subscriber has the same canonical address.
+    #[error("the email address is already subscribed")]
+    Duplicate,
+    #[error("failed to query the database")]
+    Database(#[source] sqlx::Error),
+}
+
+/// The codes of the violations of a unique constraint, the SQLSTATE of Postgres and the extended
+/// result codes of SQLite for a unique index and a primary key.
+const UNIQUE_VIOLATIONS: &[&str] = &["23505", "2067", "1555"];
+
+impl From<sqlx::Error> for RepositoryError {
+    fn from(error: sqlx::Error) -> Self {
+        let code = error.as_database_error().and_then(|e| e.code());
+        if code.is_some_and(|code| UNIQUE_VIOLATIONS.contains(&code.as_ref())) {
+            RepositoryError::Duplicate
+        } else {
+            RepositoryError::Database(error)
+        }
+    }
+}
+
+#[async_trait]
+pub trait SubscriberRepository: Send + Sync {
+    /// Store a subscriber pending confirmation with its token and queue its confirmation email,
+    /// nothing is stored if one of them fails. A subscriber with the same canonical address still
+    /// pending confirmation gets the new token and email instead, its link may have expired, and
+    /// its confirmation period starts again. It returns `Duplicate` if that subscriber is in
+    /// another status.
+    async fn add_pending(
+        &self,
+        subscriber: &NewSubscriber,
+        token: &SubscriptionToken,
+        confirmation: &OutgoingEmail<'_>,
+    ) -> Result<Uuid, RepositoryError>;
+
+    async fn get(&self, id: Uuid) -> Result<Option<Subscriber>, RepositoryError>;
+
+    /// Move the subscriber to `status`, it returns `false` if the subscriber does not exist or
+    /// the transition is not allowed by `SubscriptionStatus::can_transition_to`.
+    async fn transition(
+        &self,
+        id: Uuid,
+        status: SubscriptionStatus,
+    ) -> Result<bool, RepositoryError>;
+
+    async fn preferences(&self, id: Uuid) -> Result<Option<Preferences>, RepositoryError>;
+
+    /// Replace the name and the preferences of the subscriber, it returns `false` if the
+    /// subscriber does not exist.
+    async fn update_preferences(
+        &self,
+        id: Uuid,
+        name: &SubscriberName,
+        preferences: &Preferences,
+    ) -> Result<bool, RepositoryError>;
+}
+
+#[async_trait]
+pub trait TokenRepository: Send + Sync {
+    /// The subscriber the token was issued to.
+    async fn subscriber_id(
+        &self,
+        token: &SubscriptionToken,
+    ) -> Result<Option<Uuid>, RepositoryError>;
+}
+
+#[async_trait]
+pub trait IssueRepository: Send + Sync {
+    async fn add(&self, issue: &NewsletterIssue) -> Result<(), RepositoryError>;
+
+    /// The issue as it was published, `None` if it does not exist.
+    async fn get(&self, id: Uuid) -> Result<Option<NewsletterIssue>, RepositoryError>;
+}
+
+#[async_trait]
+pub trait DeliveryRepository: Send + Sync {
+    /// Queue the issue for the confirmed subscribers who chose its topic, or no topic at all. It
+    /// returns the number of deliveries queued, the ones waiting for a digest included.
+    async fn queue_confirmed_subscribers(&self, issue_id: Uuid) -> Result<u64, RepositoryError>;
+
+    /// Lease until `locked_until` at most `limit` deliveries queued for a confirmed subscriber
+    /// receiving every issue, including the ones whose lease expired. The retries are left out
+    /// until their `next_attempt_at`.
+    async fn claim_queued(
+        &self,
+        locked_until: DateTime<Utc>,
+        limit: i64,
+    ) -> Result<Vec<QueuedDelivery>, RepositoryError>;
+
+    /// Lease the deliveries waiting for the digest of a confirmed subscriber until
+    /// `locked_until`, including the ones whose lease expired. The retries are left out until
+    /// their `next_attempt_at`. They are in the order the issues were published.
+    async fn claim_digests(
+        &self,
+        locked_until: DateTime<Utc>,
+    ) -> Result<Vec<QueuedDelivery>, RepositoryError>;
+
+    /// Queue the issue again for the subscribers whose delivery failed, permanently or after
+    /// running out of retries, the ones who are not confirmed anymore are left out. They get a
+    /// fresh set of attempts. It returns the number of deliveries queued.
+    async fn queue_failures(&self, issue_id: Uuid) -> Result<u64, RepositoryError>;
+
+    /// Record the outcome of an attempt for each subscriber, all of them or none. A retried
+    /// delivery stays queued until its `Attempt::retry_at`.
+    async fn record_attempts(
+        &self,
+        issue_id: Uuid,
+        attempts: &[(Uuid, &Attempt)],
+    ) -> Result<(), RepositoryError>;
+
+    /// The progress of the delivery of an issue, `None` if it does not exist.
+    async fn summary(&self, issue_id: Uuid) -> Result<Option<DeliverySummary>, RepositoryError>;
+}
+
+#[async_trait]
+pub trait SuppressionRepository: Send + Sync {
+    /// The active entries of any of the `addresses` or of the `domains`, their keys are given by
+    /// `suppression::normalise_address` and `suppression::normalise_domain`.
+    async fn find_active(
+        &self,
+        addresses: &[String],
+        domains: &[String],
+    ) -> Result<Vec<Suppression>, RepositoryError>;
+
+    /// Add an entry, an existing entry for the same value is replaced.
+    async fn add(
+        &self,
+        scope: SuppressionScope,
+        value: &str,
+        reason: &str,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Result<Suppression, RepositoryError>;
+
+    /// Remove an entry, it returns `false` if there was no entry.
+    async fn remove(&self, scope: SuppressionScope, value: &str) -> Result<bool, RepositoryError>;
+
+    /// All the entries, including the expired ones.
+    async fn list(&self) -> Result<Vec<Suppression>, RepositoryError>;
+
+    /// Record the emails that were not sent because their recipient is suppressed.
+    async fn record_skipped(&self, emails: &[SkippedEmail<'_>]) -> Result<(), RepositoryError>;
+}
+
+/// What an email event changed.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum AppliedEvent {
+    /// The event was received before, nothing changed.
+    Duplicate,
+    /// The subscriber the event refers to was updated, `suppressed` if the address was added to
+    /// the suppression list.
+    Applied { suppressed: bool },
+}
+
+#[async_trait]
+pub trait EmailEventRepository: Send + Sync {
+    /// Store the event of `provider` and update the subscriber it refers to, the addresses that
+    /// bounced or complained are suppressed and added to the suppression list. The soft bounces
+    /// suppress the subscriber once they are `soft_bounce_threshold` in a row.
+    async fn apply(
+        &self,
+        provider: &str,
+        event: &EmailEvent,
+        payload: &str,
+        soft_bounce_threshold: u32,
+    ) -> Result<AppliedEvent, RepositoryError>;
+}
+
+#[async_trait]
+pub trait TrackingRepository: Send + Sync {
+    /// Store an event of the subscriber, unless they opted out of the tracking.
+    async fn record_event(
+        &self,
+        issue_id: Uuid,
+        subscriber_id: Uuid,
+        event: &TrackingEvent<'_>,
+    ) -> Result<(), RepositoryError>;
+
+    /// Stop tracking the subscriber, the next issues are sent without tracking.
+    async fn opt_out(&self, subscriber_id: Uuid) -> Result<(), RepositoryError>;
+
+    /// The engagement with an issue, `None` if it does not exist.
+    async fn issue_stats(&self, issue_id: Uuid) -> Result<Option<IssueStats>, RepositoryError>;
+}
+
+#[async_trait]
+pub trait DeadLetterRepository: Send + Sync {
+    /// A page of the dead letters, the most recent first. The page starts after the dead letter
+    /// `after`, it returns `None` if `after` is not a dead letter.
+    async fn list(
+        &self,
+        limit: i64,
+        after: Option<Uuid>,
+    ) -> Result<Option<Vec<DeadLetter>>, RepositoryError>;
+
+    async fn find(&self, id: Uuid) -> Result<Option<DeadLetterDetails>, RepositoryError>;
+
+    /// Number of dead letters.
+    async fn count(&self) -> Result<i64, RepositoryError>;
+
+    /// Put a dead letter back in the outbox, it gets a fresh set of attempts. It returns `false`
+    /// if the dead letter does not exist.
+    async fn replay(&self, id: Uuid) -> Result<bool, RepositoryError>;
+
+    /// Put all the dead letters back in the outbox, it returns how many were replayed.
+    async fn replay_all(&self) -> Result<u64, RepositoryError>;
+
+    /// Delete a dead letter, it returns `false` if the dead letter does not exist.
+    async fn purge(&self, id: Uuid) -> Result<bool, RepositoryError>;
+
+    /// Delete all the dead letters, it returns how many were purged.
+    async fn purge_all(&self) -> Result<u64, RepositoryError>;
+}
@@ -0,0 +1,128 @@
+use uuid::Uuid;
+
+use super::PgRepository;
+use crate::domain::EmailAddress;
+
+/// What `PgRepository::backfill_canonical_emails` changed.
+#[derive(Debug, Default, PartialEq)]
+pub struct CanonicalBackfill {
+    /// The subscribers given another canonical address.
+    pub subscribers: u64,
+    /// The subscribers whose canonical address is held by another subscriber, they are recorded
+    /// in `canonical_email_collisions`.
+    pub collisions: u64,
+    /// The address suppressions keyed again, or removed when the new key already exists.
+    pub suppressions: u64,
+}
+
+impl PgRepository {
+    /// Recompute the canonical addresses with `EmailAddress::canonical`.
+    ///
+    /// The migration computes them in SQL with `lower()`, which does not parse the address: the
+    /// domains are not turned into punycode and the addresses stored before the domains were
+    /// normalised keep their Unicode form. This runs after the migrations and is a no-op once
+    /// every row matches. The suppressions of single addresses are keyed the same way.
+    #[tracing::instrument(name = "Backfill the canonical addresses", skip(self))]
+    pub async fn backfill_canonical_emails(&self) -> Result<CanonicalBackfill, sqlx::Error> {
+        let mut backfill = CanonicalBackfill::default();
+        let mut transaction = self.pool.begin().await?;
+
+        let rows = sqlx::query!(
+            r#"SELECT id, email, canonical_email FROM subscriptions
+                WHERE id NOT IN (SELECT subscriber_id FROM canonical_email_collisions)
+                ORDER BY subscribed_at, id"#
+        )
+        .fetch_all(&mut transaction)
+        .await?;
+        let mut changed = Vec::new();
+        for row in rows {
+            let canonical = match row.email.parse::<EmailAddress>() {
+                Ok(email) => email.canonical(),
+                Err(e) => {
+                    tracing::warn!(subscriber_id = %row.id, error = %e, "Skipping an invalid address");
+                    continue;
+                }
+            };
+            if row.canonical_email.as_deref() != Some(canonical.as_str()) {
+                changed.push((row.id, canonical));
+            }
+        }
+
+        // The old values are released first, two rows may swap their canonical addresses
+        let ids: Vec<Uuid> = changed.iter().map(|(id, _)| *id).collect();
+        sqlx::query!(
+            "UPDATE subscriptions SET canonical_email = NULL WHERE id = ANY($1)",
+            &ids
+        )
+        .execute(&mut transaction)
+        .await?;
+        for (id, canonical) in &changed {
+            let holder = sqlx::query_scalar!(
+                "SELECT id FROM subscriptions WHERE canonical_email = $1",
+                canonical
+            )
+            .fetch_optional(&mut transaction)
+            .await?;
+            match holder {
+                Some(kept_subscriber_id) => {
+                    sqlx::query!(
+                        r#"INSERT INTO canonical_email_collisions
+                            (subscriber_id, canonical_email, kept_subscriber_id)
+                            VALUES ($1, $2, $3)"#,
+                        id,
+                        canonical,
+                        kept_subscriber_id
+                    )
+                    .execute(&mut transaction)
+                    .await?;
+                    backfill.collisions += 1;
+                }
+                None => {
+                    sqlx::query!(
+                        "UPDATE subscriptions SET canonical_email = $2 WHERE id = $1",
+                        id,
+                        canonical
+                    )
+                    .execute(&mut transaction)
+                    .await?;
+                    backfill.subscribers += 1;
+                }
+            }
+        }
+
+        let values = sqlx::query_scalar!("SELECT value FROM suppressions WHERE scope = 'address'")
+            .fetch_all(&mut transaction)
+            .await?;
+        for value in values {
+            let canonical = match value.parse::<EmailAddress>() {
+                Ok(email) => email.canonical(),
+                Err(_) => continue,
+            };
+            if canonical == value {
+                continue;
+            }
+            // The entry already keyed on the canonical address is kept
+            sqlx::query!(
+                r#"UPDATE suppressions SET value = $2
+                    WHERE scope = 'address' AND value = $1
+                        AND NOT EXISTS (
+                            SELECT 1 FROM suppressions WHERE scope = 'address' AND value = $2
+                        )"#,
+                value,
+                canonical
+            )
+            .execute(&mut transaction)
+            .await?;
+            sqlx::query!(
+                "DELETE FROM suppressions WHERE scope = 'address' AND value = $1",
+                value
+            )
+            .execute(&mut transaction)
+            .await?;
+            backfill.suppressions += 1;
+        }
+
+        transaction.commit().await?;
+        Ok(backfill)
+    }
+}
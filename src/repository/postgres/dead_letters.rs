@@ -0,0 +1,150 @@
+use async_trait::async_trait;
+use chrono::Utc;
+use uuid::Uuid;
+
+use super::PgRepository;
+use crate::{
+    outbox::{
+        self,
+        dead_letters::{DeadLetter, DeadLetterDetails},
+    },
+    repository::{DeadLetterRepository, RepositoryError},
+};
+
+#[async_trait]
+impl DeadLetterRepository for PgRepository {
+    #[tracing::instrument(name = "List the dead letters", skip(self))]
+    async fn list(
+        &self,
+        limit: i64,
+        after: Option<Uuid>,
+    ) -> Result<Option<Vec<DeadLetter>>, RepositoryError> {
+        // The dead letters are ordered by `(dead_lettered_at DESC, id)`, the page starts after the
+        // position of `after` in that order
+        let cursor = match after {
+            Some(id) => {
+                let cursor = sqlx::query!(
+                    r#"SELECT dead_lettered_at AS "dead_lettered_at!" FROM outbox
+                        WHERE id = $1 AND dead_lettered_at IS NOT NULL"#,
+                    id
+                )
+                .fetch_optional(&self.pool)
+                .await?;
+                match cursor {
+                    Some(cursor) => Some((cursor.dead_lettered_at, id)),
+                    None => return Ok(None),
+                }
+            }
+            None => None,
+        };
+        let (cursor_at, cursor_id) = cursor.unzip();
+        let dead_letters = sqlx::query_as!(
+            DeadLetter,
+            r#"SELECT id, recipient, subject, attempts, last_error AS "last_error!", created_at,
+                    dead_lettered_at AS "dead_lettered_at!"
+                FROM outbox
+                WHERE dead_lettered_at IS NOT NULL
+                    AND ($2::timestamptz IS NULL
+                        OR dead_lettered_at < $2
+                        OR (dead_lettered_at = $2 AND id > $3))
+                ORDER BY dead_lettered_at DESC, id
+                LIMIT $1"#,
+            limit,
+            cursor_at,
+            cursor_id,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(Some(dead_letters))
+    }
+
+    #[tracing::instrument(name = "Find a dead letter", skip(self))]
+    async fn find(&self, id: Uuid) -> Result<Option<DeadLetterDetails>, RepositoryError> {
+        let row = sqlx::query!(
+            r#"SELECT id, recipient, subject, html_content, text_content, attempts,
+                    last_error AS "last_error!", created_at, dead_lettered_at AS "dead_lettered_at!"
+                FROM outbox
+                WHERE id = $1 AND dead_lettered_at IS NOT NULL"#,
+            id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(row.map(|row| DeadLetterDetails {
+            dead_letter: DeadLetter {
+                id: row.id,
+                recipient: row.recipient,
+                subject: row.subject,
+                attempts: row.attempts,
+                last_error: row.last_error,
+                created_at: row.created_at,
+                dead_lettered_at: row.dead_lettered_at,
+            },
+            html_content: row.html_content,
+            text_content: row.text_content,
+        }))
+    }
+
+    async fn count(&self) -> Result<i64, RepositoryError> {
+        let count = sqlx::query!(
+            r#"SELECT COUNT(*) AS "count!" FROM outbox WHERE dead_lettered_at IS NOT NULL"#
+        )
+        .fetch_one(&self.pool)
+        .await?
+        .count;
+        Ok(count)
+    }
+
+    #[tracing::instrument(name = "Replay a dead letter", skip(self))]
+    async fn replay(&self, id: Uuid) -> Result<bool, RepositoryError> {
+        let mut transaction = self.pool.begin().await?;
+        let replayed = sqlx::query!(
+            r#"UPDATE outbox SET attempts = 0, next_attempt_at = $2, dead_lettered_at = NULL
+                WHERE id = $1 AND dead_lettered_at IS NOT NULL"#,
+            id,
+            Utc::now(),
+        )
+        .execute(&mut transaction)
+        .await?
+        .rows_affected();
+        outbox::notify(&mut transaction).await?;
+        transaction.commit().await?;
+        Ok(replayed > 0)
+    }
+
+    #[tracing::instrument(name = "Replay all the dead letters", skip(self))]
+    async fn replay_all(&self) -> Result<u64, RepositoryError> {
+        let mut transaction = self.pool.begin().await?;
+        let replayed = sqlx::query!(
+            r#"UPDATE outbox SET attempts = 0, next_attempt_at = $1, dead_lettered_at = NULL
+                WHERE dead_lettered_at IS NOT NULL"#,
+            Utc::now(),
+        )
+        .execute(&mut transaction)
+        .await?
+        .rows_affected();
+        outbox::notify(&mut transaction).await?;
+        transaction.commit().await?;
+        Ok(replayed)
+    }
+
+    #[tracing::instrument(name = "Purge a dead letter", skip(self))]
+    async fn purge(&self, id: Uuid) -> Result<bool, RepositoryError> {
+        let purged = sqlx::query!(
+            "DELETE FROM outbox WHERE id = $1 AND dead_lettered_at IS NOT NULL",
+            id
+        )
+        .execute(&self.pool)
+        .await?
+        .rows_affected();
+        Ok(purged > 0)
+    }
+
+    #[tracing::instrument(name = "Purge all the dead letters", skip(self))]
+    async fn purge_all(&self) -> Result<u64, RepositoryError> {
+        let purged = sqlx::query!("DELETE FROM outbox WHERE dead_lettered_at IS NOT NULL")
+            .execute(&self.pool)
+            .await?
+            .rows_affected();
+        Ok(purged)
+    }
+}
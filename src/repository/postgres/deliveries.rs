@@ -0,0 +1,260 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+use super::PgRepository;
+use crate::{
+    domain::SubscriptionStatus,
+    issue_delivery::{
+        Attempt, DeliveryFailure, DeliverySummary, ErrorClass, QueuedDelivery, Recipient,
+    },
+    repository::{DeliveryRepository, RepositoryError},
+};
+
+#[async_trait]
+impl DeliveryRepository for PgRepository {
+    #[tracing::instrument(name = "Queue an issue for the confirmed subscribers", skip(self))]
+    async fn queue_confirmed_subscribers(&self, issue_id: Uuid) -> Result<u64, RepositoryError> {
+        let queued = sqlx::query!(
+            r#"INSERT INTO issue_deliveries (issue_id, subscriber_id, status, digest, updated_at)
+                SELECT $1, s.id, 'queued', s.frequency = 'digest', $2
+                    FROM subscriptions s JOIN newsletter_issues i ON i.id = $1
+                    WHERE s.status = $3 AND (
+                        i.topic IS NULL
+                        OR NOT EXISTS (SELECT 1 FROM subscriber_topics t WHERE t.subscriber_id = s.id)
+                        OR EXISTS (
+                            SELECT 1 FROM subscriber_topics t
+                                WHERE t.subscriber_id = s.id AND t.topic = i.topic
+                        )
+                    )"#,
+            issue_id,
+            Utc::now(),
+            SubscriptionStatus::Confirmed as SubscriptionStatus,
+        )
+        .execute(&self.pool)
+        .await?
+        .rows_affected();
+        Ok(queued)
+    }
+
+    #[tracing::instrument(name = "Claim the queued deliveries", skip(self))]
+    async fn claim_queued(
+        &self,
+        locked_until: DateTime<Utc>,
+        limit: i64,
+    ) -> Result<Vec<QueuedDelivery>, RepositoryError> {
+        let claimed = sqlx::query!(
+            r#"UPDATE issue_deliveries SET locked_until = $1
+                FROM subscriptions
+                WHERE issue_deliveries.subscriber_id = subscriptions.id
+                    AND (issue_deliveries.issue_id, issue_deliveries.subscriber_id) IN (
+                        SELECT d.issue_id, d.subscriber_id
+                            FROM issue_deliveries d JOIN subscriptions s ON s.id = d.subscriber_id
+                            WHERE NOT d.digest AND d.status = 'queued'
+                                AND (d.locked_until IS NULL OR d.locked_until <= $2)
+                                AND (d.next_attempt_at IS NULL OR d.next_attempt_at <= $2)
+                                AND s.status = $3
+                            ORDER BY d.updated_at
+                            LIMIT $4
+                            FOR UPDATE OF d SKIP LOCKED
+                    )
+                RETURNING issue_deliveries.issue_id, subscriptions.id AS subscriber_id,
+                    subscriptions.email, subscriptions.tracking_opt_out, issue_deliveries.attempts"#,
+            locked_until,
+            Utc::now(),
+            SubscriptionStatus::Confirmed as SubscriptionStatus,
+            limit,
+        )
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(|r| QueuedDelivery {
+            issue_id: r.issue_id,
+            recipient: Recipient {
+                subscriber_id: r.subscriber_id,
+                email: r.email,
+                tracking_opt_out: r.tracking_opt_out,
+                attempts: r.attempts,
+            },
+        })
+        .collect();
+        Ok(claimed)
+    }
+
+    #[tracing::instrument(name = "Queue an issue for the failed deliveries", skip(self))]
+    async fn queue_failures(&self, issue_id: Uuid) -> Result<u64, RepositoryError> {
+        let queued = sqlx::query!(
+            r#"UPDATE issue_deliveries
+                SET status = 'queued', digest = false, attempts = 0, next_attempt_at = NULL,
+                    updated_at = $2
+                FROM subscriptions
+                WHERE issue_deliveries.subscriber_id = subscriptions.id
+                    AND issue_deliveries.issue_id = $1
+                    AND issue_deliveries.status = 'failed'
+                    AND subscriptions.status = $3"#,
+            issue_id,
+            Utc::now(),
+            SubscriptionStatus::Confirmed as SubscriptionStatus,
+        )
+        .execute(&self.pool)
+        .await?
+        .rows_affected();
+        Ok(queued)
+    }
+
+    #[tracing::instrument(name = "Claim the deliveries of the digests", skip(self))]
+    async fn claim_digests(
+        &self,
+        locked_until: DateTime<Utc>,
+    ) -> Result<Vec<QueuedDelivery>, RepositoryError> {
+        let entries = sqlx::query!(
+            r#"WITH leased AS (
+                UPDATE issue_deliveries SET locked_until = $1
+                FROM subscriptions
+                WHERE issue_deliveries.subscriber_id = subscriptions.id
+                    AND issue_deliveries.digest
+                    AND issue_deliveries.status = 'queued'
+                    AND (issue_deliveries.locked_until IS NULL
+                        OR issue_deliveries.locked_until <= $2)
+                    AND (issue_deliveries.next_attempt_at IS NULL
+                        OR issue_deliveries.next_attempt_at <= $2)
+                    AND subscriptions.status = $3
+                RETURNING issue_deliveries.issue_id, subscriptions.id AS subscriber_id,
+                    subscriptions.email, subscriptions.tracking_opt_out, issue_deliveries.attempts
+            )
+            SELECT leased.issue_id AS "issue_id!", leased.subscriber_id AS "subscriber_id!",
+                leased.email AS "email!", leased.tracking_opt_out AS "tracking_opt_out!",
+                leased.attempts AS "attempts!"
+                FROM leased JOIN newsletter_issues ON newsletter_issues.id = leased.issue_id
+                ORDER BY newsletter_issues.published_at"#,
+            locked_until,
+            Utc::now(),
+            SubscriptionStatus::Confirmed as SubscriptionStatus,
+        )
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(|r| QueuedDelivery {
+            issue_id: r.issue_id,
+            recipient: Recipient {
+                subscriber_id: r.subscriber_id,
+                email: r.email,
+                tracking_opt_out: r.tracking_opt_out,
+                attempts: r.attempts,
+            },
+        })
+        .collect();
+        Ok(entries)
+    }
+
+    #[tracing::instrument(
+        name = "Record delivery attempts",
+        skip(self, attempts),
+        fields(attempts = attempts.len())
+    )]
+    async fn record_attempts(
+        &self,
+        issue_id: Uuid,
+        attempts: &[(Uuid, &Attempt)],
+    ) -> Result<(), RepositoryError> {
+        let now = Utc::now();
+        let mut transaction = self.pool.begin().await?;
+        for (subscriber_id, attempt) in attempts {
+            let error_class = attempt.error_class.as_ref().map(ErrorClass::as_str);
+            sqlx::query!(
+                r#"UPDATE issue_deliveries
+            SET status = $3, provider_message_id = $4, provider = $5, error_class = $6,
+                detail = $7, attempts = attempts + 1, updated_at = $8, locked_until = NULL,
+                next_attempt_at = $9
+            WHERE issue_id = $1 AND subscriber_id = $2"#,
+                issue_id,
+                subscriber_id,
+                attempt.delivery_status().as_str(),
+                attempt.provider_message_id,
+                attempt.provider,
+                error_class,
+                attempt.detail,
+                now,
+                attempt.retry_at,
+            )
+            .execute(&mut transaction)
+            .await?;
+            sqlx::query!(
+                r#"INSERT INTO issue_delivery_attempts
+            (id, issue_id, subscriber_id, status, provider_message_id, provider, error_class,
+                detail, attempted_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)"#,
+                Uuid::new_v4(),
+                issue_id,
+                subscriber_id,
+                attempt.status.as_str(),
+                attempt.provider_message_id,
+                attempt.provider,
+                error_class,
+                attempt.detail,
+                now,
+            )
+            .execute(&mut transaction)
+            .await?;
+        }
+        transaction.commit().await?;
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Summarise the delivery of an issue", skip(self))]
+    async fn summary(&self, issue_id: Uuid) -> Result<Option<DeliverySummary>, RepositoryError> {
+        let counts = sqlx::query!(
+            r#"SELECT
+            COUNT(d.subscriber_id) AS "total!",
+            COUNT(d.subscriber_id) FILTER (WHERE d.status = 'queued') AS "queued!",
+            COUNT(d.subscriber_id) FILTER (WHERE d.status = 'sent') AS "sent!",
+            COUNT(d.subscriber_id) FILTER (WHERE d.status = 'skipped') AS "skipped!",
+            COUNT(d.subscriber_id) FILTER (WHERE d.status = 'failed') AS "failed!"
+            FROM newsletter_issues i LEFT JOIN issue_deliveries d ON d.issue_id = i.id
+            WHERE i.id = $1
+            GROUP BY i.id"#,
+            issue_id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        let counts = match counts {
+            Some(counts) => counts,
+            None => return Ok(None),
+        };
+
+        let sent_by_provider = sqlx::query!(
+            r#"SELECT provider AS "provider!", COUNT(*) AS "count!"
+            FROM issue_deliveries
+            WHERE issue_id = $1 AND status = 'sent' AND provider IS NOT NULL
+            GROUP BY provider"#,
+            issue_id
+        )
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(|r| (r.provider, r.count))
+        .collect();
+
+        let failures = sqlx::query_as!(
+            DeliveryFailure,
+            r#"SELECT d.subscriber_id, s.email, d.error_class, d.detail, d.attempts, d.updated_at
+            FROM issue_deliveries d JOIN subscriptions s ON s.id = d.subscriber_id
+            WHERE d.issue_id = $1 AND d.status = 'failed'
+            ORDER BY d.updated_at"#,
+            issue_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(Some(DeliverySummary {
+            issue_id,
+            total: counts.total,
+            queued: counts.queued,
+            sent: counts.sent,
+            skipped: counts.skipped,
+            failed: counts.failed,
+            sent_by_provider,
+            failures,
+        }))
+    }
+}
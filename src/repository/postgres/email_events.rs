@@ -0,0 +1,117 @@
+use async_trait::async_trait;
+use chrono::Utc;
+use sqlx::{Postgres, Transaction};
+use uuid::Uuid;
+
+use super::{suppressions, PgRepository};
+use crate::{
+    domain::{EmailEvent, EmailEventKind, SubscriptionStatus},
+    repository::{AppliedEvent, EmailEventRepository, RepositoryError},
+    suppression::{self, SuppressionScope},
+};
+
+#[async_trait]
+impl EmailEventRepository for PgRepository {
+    #[tracing::instrument(
+        name = "Store an email event",
+        skip(self, event, payload, soft_bounce_threshold),
+        fields(kind = event.kind.as_str(), message_id = %event.message_id)
+    )]
+    async fn apply(
+        &self,
+        provider: &str,
+        event: &EmailEvent,
+        payload: &str,
+        soft_bounce_threshold: u32,
+    ) -> Result<AppliedEvent, RepositoryError> {
+        let mut transaction = self.pool.begin().await?;
+        let stored = sqlx::query!(
+            r#"INSERT INTO email_events
+            (id, provider, message_id, kind, recipient, description, occurred_at, received_at, payload)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+            ON CONFLICT DO NOTHING"#,
+            Uuid::new_v4(),
+            provider,
+            event.message_id,
+            event.kind.as_str(),
+            event.recipient.as_ref(),
+            event.description,
+            event.occurred_at,
+            Utc::now(),
+            payload,
+        )
+        .execute(&mut transaction)
+        .await?
+        .rows_affected()
+            == 1;
+        if !stored {
+            return Ok(AppliedEvent::Duplicate);
+        }
+
+        let suppressed = update_subscriber(&mut transaction, event, soft_bounce_threshold).await?;
+        if suppressed {
+            suppressions::upsert(
+                &mut transaction,
+                SuppressionScope::Address,
+                &suppression::normalise_address(&event.recipient),
+                event.kind.as_str(),
+                None,
+            )
+            .await?;
+        }
+        transaction.commit().await?;
+        Ok(AppliedEvent::Applied { suppressed })
+    }
+}
+
+/// Update the subscriber the event refers to, it returns whether the address must be suppressed.
+async fn update_subscriber(
+    transaction: &mut Transaction<'_, Postgres>,
+    event: &EmailEvent,
+    soft_bounce_threshold: u32,
+) -> Result<bool, sqlx::Error> {
+    let suppressed = match event.kind {
+        EmailEventKind::Delivered => {
+            sqlx::query!(
+                "UPDATE subscriptions SET soft_bounce_count = 0 WHERE email = $1",
+                event.recipient.as_ref(),
+            )
+            .execute(&mut *transaction)
+            .await?;
+            false
+        }
+        EmailEventKind::HardBounce | EmailEventKind::Complaint => {
+            sqlx::query!(
+                "UPDATE subscriptions SET status = $2 WHERE email = $1 AND status = ANY($3)",
+                event.recipient.as_ref(),
+                SubscriptionStatus::Suppressed as SubscriptionStatus,
+                &SubscriptionStatus::Suppressed.predecessors() as &[SubscriptionStatus],
+            )
+            .execute(&mut *transaction)
+            .await?;
+            // The address is suppressed even if it is not subscribed anymore
+            true
+        }
+        EmailEventKind::SoftBounce => {
+            let threshold = i32::try_from(soft_bounce_threshold).unwrap_or(i32::MAX);
+            sqlx::query!(
+                r#"UPDATE subscriptions
+                    SET soft_bounce_count = soft_bounce_count + 1,
+                        status = CASE
+                            WHEN soft_bounce_count + 1 >= $2 AND status = ANY($4) THEN $3
+                            ELSE status
+                        END
+                    WHERE email = $1
+                    RETURNING status AS "status: SubscriptionStatus""#,
+                event.recipient.as_ref(),
+                threshold,
+                SubscriptionStatus::Suppressed as SubscriptionStatus,
+                &SubscriptionStatus::Suppressed.predecessors() as &[SubscriptionStatus],
+            )
+            .fetch_optional(&mut *transaction)
+            .await?
+            .is_some_and(|r| r.status == SubscriptionStatus::Suppressed)
+        }
+    };
+    Ok(suppressed)
+}
@@ -0,0 +1,253 @@
+mod canonical;
+mod dead_letters;
+mod deliveries;
+mod email_events;
+mod suppressions;
+mod tracking;
+
+pub use self::canonical::CanonicalBackfill;
+
+use async_trait::async_trait;
+use chrono::Utc;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use super::{
+    IssueRepository, Preferences, RepositoryError, Subscriber, SubscriberRepository,
+    TokenRepository,
+};
+use crate::{
+    domain::{NewSubscriber, SubscriberName, SubscriptionStatus, SubscriptionToken},
+    email_client::OutgoingEmail,
+    issue_delivery::NewsletterIssue,
+    outbox,
+    tracking::TrackingOptions,
+};
+
+/// The repositories stored in Postgres.
+#[derive(Clone)]
+pub struct PgRepository {
+    pool: PgPool,
+}
+
+impl PgRepository {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl SubscriberRepository for PgRepository {
+    #[tracing::instrument(
+        name = "Saving new subscriber details in the database",
+        skip(self, subscriber, token, confirmation)
+    )]
+    async fn add_pending(
+        &self,
+        subscriber: &NewSubscriber,
+        token: &SubscriptionToken,
+        confirmation: &OutgoingEmail<'_>,
+    ) -> Result<Uuid, RepositoryError> {
+        let mut transaction = self.pool.begin().await?;
+        let pending = sqlx::query_scalar!(
+            r#"UPDATE subscriptions SET subscribed_at = $2
+                WHERE canonical_email = $1 AND status = $3
+                RETURNING id"#,
+            subscriber.email.canonical(),
+            Utc::now(),
+            SubscriptionStatus::PendingConfirmation as SubscriptionStatus,
+        )
+        .fetch_optional(&mut transaction)
+        .await?;
+        // The insertion fails with `Duplicate` if the address is in another status
+        let subscriber_id = match pending {
+            Some(subscriber_id) => subscriber_id,
+            None => {
+                let subscriber_id = Uuid::new_v4();
+                sqlx::query!(
+                    r#"INSERT INTO subscriptions
+                        (id, email, canonical_email, name, subscribed_at, status)
+                        VALUES ($1, $2, $3, $4, $5, $6)"#,
+                    subscriber_id,
+                    subscriber.email.as_ref(),
+                    subscriber.email.canonical(),
+                    subscriber.name.as_ref(),
+                    Utc::now(),
+                    SubscriptionStatus::PendingConfirmation as SubscriptionStatus,
+                )
+                .execute(&mut transaction)
+                .await?;
+                subscriber_id
+            }
+        };
+        sqlx::query!(
+            "INSERT INTO subscription_tokens(subscription_token, subscriber_id) VALUES($1, $2)",
+            token.as_ref(),
+            subscriber_id
+        )
+        .execute(&mut transaction)
+        .await?;
+        outbox::enqueue(&mut transaction, confirmation).await?;
+        transaction.commit().await?;
+        Ok(subscriber_id)
+    }
+
+    #[tracing::instrument(name = "Get a subscriber", skip(self))]
+    async fn get(&self, id: Uuid) -> Result<Option<Subscriber>, RepositoryError> {
+        let subscriber = sqlx::query_as!(
+            Subscriber,
+            r#"SELECT id, email, name, status AS "status: SubscriptionStatus", subscribed_at
+                FROM subscriptions WHERE id = $1"#,
+            id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(subscriber)
+    }
+
+    #[tracing::instrument(name = "Change the status of a subscriber", skip(self))]
+    async fn transition(
+        &self,
+        id: Uuid,
+        status: SubscriptionStatus,
+    ) -> Result<bool, RepositoryError> {
+        let updated = sqlx::query!(
+            "UPDATE subscriptions SET status = $2 WHERE id = $1 AND status = ANY($3)",
+            id,
+            status as SubscriptionStatus,
+            &status.predecessors() as &[SubscriptionStatus],
+        )
+        .execute(&self.pool)
+        .await?
+        .rows_affected();
+        Ok(updated > 0)
+    }
+
+    #[tracing::instrument(name = "Get the preferences of a subscriber", skip(self))]
+    async fn preferences(&self, id: Uuid) -> Result<Option<Preferences>, RepositoryError> {
+        let frequency = sqlx::query!("SELECT frequency FROM subscriptions WHERE id = $1", id)
+            .fetch_optional(&self.pool)
+            .await?;
+        let frequency = match frequency {
+            Some(r) => r
+                .frequency
+                .parse()
+                .map_err(|e: String| sqlx::Error::Decode(e.into()))?,
+            None => return Ok(None),
+        };
+        let topics = sqlx::query!(
+            "SELECT topic FROM subscriber_topics WHERE subscriber_id = $1",
+            id
+        )
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(|r| r.topic)
+        .collect();
+        Ok(Some(Preferences { topics, frequency }))
+    }
+
+    #[tracing::instrument(name = "Update the preferences of a subscriber", skip(self, name))]
+    async fn update_preferences(
+        &self,
+        id: Uuid,
+        name: &SubscriberName,
+        preferences: &Preferences,
+    ) -> Result<bool, RepositoryError> {
+        let mut transaction = self.pool.begin().await?;
+        let updated = sqlx::query!(
+            "UPDATE subscriptions SET name = $2, frequency = $3 WHERE id = $1",
+            id,
+            name.as_ref(),
+            preferences.frequency.as_str(),
+        )
+        .execute(&mut transaction)
+        .await?
+        .rows_affected();
+        if updated == 0 {
+            return Ok(false);
+        }
+        sqlx::query!("DELETE FROM subscriber_topics WHERE subscriber_id = $1", id)
+            .execute(&mut transaction)
+            .await?;
+        let topics: Vec<_> = preferences.topics.iter().cloned().collect();
+        sqlx::query!(
+            r#"INSERT INTO subscriber_topics (subscriber_id, topic)
+                SELECT $1, topic FROM UNNEST($2::text[]) AS topic"#,
+            id,
+            &topics,
+        )
+        .execute(&mut transaction)
+        .await?;
+        transaction.commit().await?;
+        Ok(true)
+    }
+}
+
+#[async_trait]
+impl TokenRepository for PgRepository {
+    #[tracing::instrument(name = "Get subscriber_id from token", skip(self, token))]
+    async fn subscriber_id(
+        &self,
+        token: &SubscriptionToken,
+    ) -> Result<Option<Uuid>, RepositoryError> {
+        let result = sqlx::query!(
+            "SELECT subscriber_id FROM subscription_tokens WHERE subscription_token = $1",
+            token.as_ref()
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(result.map(|r| r.subscriber_id))
+    }
+}
+
+#[async_trait]
+impl IssueRepository for PgRepository {
+    #[tracing::instrument(
+        name = "Store a newsletter issue",
+        skip(self, issue),
+        fields(issue_id = %issue.id)
+    )]
+    async fn add(&self, issue: &NewsletterIssue) -> Result<(), RepositoryError> {
+        sqlx::query!(
+            r#"INSERT INTO newsletter_issues
+                (id, title, text_content, html_content, track_opens, track_clicks, topic,
+                    published_at)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8)"#,
+            issue.id,
+            issue.title,
+            issue.text_content,
+            issue.html_content,
+            issue.tracking.opens,
+            issue.tracking.clicks,
+            issue.topic,
+            Utc::now(),
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Get a newsletter issue", skip(self))]
+    async fn get(&self, id: Uuid) -> Result<Option<NewsletterIssue>, RepositoryError> {
+        let issue = sqlx::query!(
+            r#"SELECT title, html_content, text_content, track_opens, track_clicks, topic
+                FROM newsletter_issues WHERE id = $1"#,
+            id
+        )
+        .fetch_optional(&self.pool)
+        .await?
+        .map(|r| NewsletterIssue {
+            id,
+            title: r.title,
+            html_content: r.html_content,
+            text_content: r.text_content,
+            tracking: TrackingOptions {
+                opens: r.track_opens,
+                clicks: r.track_clicks,
+            },
+            topic: r.topic,
+        });
+        Ok(issue)
+    }
+}
@@ -0,0 +1,149 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use sqlx::{Executor, Postgres};
+use uuid::Uuid;
+
+use super::PgRepository;
+use crate::{
+    repository::{RepositoryError, SuppressionRepository},
+    suppression::{SkippedEmail, Suppression, SuppressionScope},
+};
+
+struct SuppressionRow {
+    scope: String,
+    value: String,
+    reason: String,
+    created_at: DateTime<Utc>,
+    expires_at: Option<DateTime<Utc>>,
+}
+
+impl TryFrom<SuppressionRow> for Suppression {
+    type Error = sqlx::Error;
+
+    fn try_from(row: SuppressionRow) -> Result<Self, Self::Error> {
+        Ok(Suppression {
+            scope: row
+                .scope
+                .parse()
+                .map_err(|e: String| sqlx::Error::Decode(e.into()))?,
+            value: row.value,
+            reason: row.reason,
+            created_at: row.created_at,
+            expires_at: row.expires_at,
+        })
+    }
+}
+
+/// Add an entry, an existing entry for the same value is replaced.
+pub(super) async fn upsert(
+    executor: impl Executor<'_, Database = Postgres>,
+    scope: SuppressionScope,
+    value: &str,
+    reason: &str,
+    expires_at: Option<DateTime<Utc>>,
+) -> Result<Suppression, sqlx::Error> {
+    sqlx::query_as!(
+        SuppressionRow,
+        r#"INSERT INTO suppressions (scope, value, reason, created_at, expires_at)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (scope, value) DO UPDATE
+                SET reason = EXCLUDED.reason,
+                    created_at = EXCLUDED.created_at,
+                    expires_at = EXCLUDED.expires_at
+            RETURNING scope, value, reason, created_at, expires_at"#,
+        scope.as_str(),
+        value,
+        reason,
+        Utc::now(),
+        expires_at,
+    )
+    .fetch_one(executor)
+    .await?
+    .try_into()
+}
+
+#[async_trait]
+impl SuppressionRepository for PgRepository {
+    async fn find_active(
+        &self,
+        addresses: &[String],
+        domains: &[String],
+    ) -> Result<Vec<Suppression>, RepositoryError> {
+        let suppressions = sqlx::query_as!(
+            SuppressionRow,
+            r#"SELECT scope, value, reason, created_at, expires_at FROM suppressions
+            WHERE ((scope = 'address' AND value = ANY($1)) OR (scope = 'domain' AND value = ANY($2)))
+                AND (expires_at IS NULL OR expires_at > now())"#,
+            addresses,
+            domains,
+        )
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(Suppression::try_from)
+        .collect::<Result<_, _>>()?;
+        Ok(suppressions)
+    }
+
+    #[tracing::instrument(name = "Add a suppression", skip(self, value, expires_at))]
+    async fn add(
+        &self,
+        scope: SuppressionScope,
+        value: &str,
+        reason: &str,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Result<Suppression, RepositoryError> {
+        Ok(upsert(&self.pool, scope, value, reason, expires_at).await?)
+    }
+
+    #[tracing::instrument(name = "Remove a suppression", skip(self, value))]
+    async fn remove(&self, scope: SuppressionScope, value: &str) -> Result<bool, RepositoryError> {
+        let result = sqlx::query!(
+            "DELETE FROM suppressions WHERE scope = $1 AND value = $2",
+            scope.as_str(),
+            value,
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    #[tracing::instrument(name = "List the suppressions", skip(self))]
+    async fn list(&self) -> Result<Vec<Suppression>, RepositoryError> {
+        let suppressions = sqlx::query_as!(
+            SuppressionRow,
+            r#"SELECT scope, value, reason, created_at, expires_at FROM suppressions
+            ORDER BY created_at"#,
+        )
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(Suppression::try_from)
+        .collect::<Result<_, _>>()?;
+        Ok(suppressions)
+    }
+
+    #[tracing::instrument(
+        name = "Record skipped emails",
+        skip(self, emails),
+        fields(emails = emails.len())
+    )]
+    async fn record_skipped(&self, emails: &[SkippedEmail<'_>]) -> Result<(), RepositoryError> {
+        let mut transaction = self.pool.begin().await?;
+        for email in emails {
+            sqlx::query!(
+                r#"INSERT INTO skipped_emails (id, recipient, subject, reason, skipped_at)
+            VALUES ($1, $2, $3, $4, $5)"#,
+                Uuid::new_v4(),
+                email.recipient.as_ref(),
+                email.subject,
+                email.reason,
+                Utc::now(),
+            )
+            .execute(&mut transaction)
+            .await?;
+        }
+        transaction.commit().await?;
+        Ok(())
+    }
+}
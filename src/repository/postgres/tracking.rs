@@ -0,0 +1,87 @@
+use async_trait::async_trait;
+use chrono::Utc;
+use uuid::Uuid;
+
+use super::PgRepository;
+use crate::{
+    repository::{RepositoryError, TrackingRepository},
+    tracking::{IssueStats, LinkStats, TrackingEvent},
+};
+
+#[async_trait]
+impl TrackingRepository for PgRepository {
+    #[tracing::instrument(name = "Record a tracking event", skip(self))]
+    async fn record_event(
+        &self,
+        issue_id: Uuid,
+        subscriber_id: Uuid,
+        event: &TrackingEvent<'_>,
+    ) -> Result<(), RepositoryError> {
+        sqlx::query!(
+            r#"INSERT INTO tracking_events (id, issue_id, subscriber_id, kind, url, occurred_at)
+            SELECT $1, $2, id, $4, $5, $6 FROM subscriptions
+                WHERE id = $3 AND NOT tracking_opt_out"#,
+            Uuid::new_v4(),
+            issue_id,
+            subscriber_id,
+            event.kind(),
+            event.url(),
+            Utc::now(),
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Opt a subscriber out of the tracking", skip(self))]
+    async fn opt_out(&self, subscriber_id: Uuid) -> Result<(), RepositoryError> {
+        sqlx::query!(
+            "UPDATE subscriptions SET tracking_opt_out = true WHERE id = $1",
+            subscriber_id
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Get the statistics of an issue", skip(self))]
+    async fn issue_stats(&self, issue_id: Uuid) -> Result<Option<IssueStats>, RepositoryError> {
+        let issue = sqlx::query!(
+            r#"SELECT title, track_opens, track_clicks,
+            (SELECT COUNT(DISTINCT subscriber_id) FROM tracking_events
+                WHERE issue_id = $1 AND kind = 'open') AS "unique_opens!",
+            (SELECT COUNT(DISTINCT subscriber_id) FROM tracking_events
+                WHERE issue_id = $1 AND kind = 'click') AS "unique_clicks!"
+            FROM newsletter_issues WHERE id = $1"#,
+            issue_id
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        let issue = match issue {
+            Some(issue) => issue,
+            None => return Ok(None),
+        };
+
+        let links = sqlx::query_as!(
+            LinkStats,
+            r#"SELECT url AS "url!", COUNT(DISTINCT subscriber_id) AS "unique_clicks!"
+            FROM tracking_events
+            WHERE issue_id = $1 AND kind = 'click' AND url IS NOT NULL
+            GROUP BY url
+            ORDER BY 2 DESC, 1"#,
+            issue_id
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(Some(IssueStats {
+            issue_id,
+            title: issue.title,
+            track_opens: issue.track_opens,
+            track_clicks: issue.track_clicks,
+            unique_opens: issue.unique_opens,
+            unique_clicks: issue.unique_clicks,
+            links,
+        }))
+    }
+}
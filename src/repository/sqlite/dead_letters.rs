@@ -0,0 +1,190 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+use super::SqliteRepository;
+use crate::{
+    outbox::dead_letters::{DeadLetter, DeadLetterDetails},
+    repository::{DeadLetterRepository, RepositoryError},
+};
+
+type DeadLetterRow = (
+    Uuid,
+    String,
+    String,
+    i32,
+    String,
+    DateTime<Utc>,
+    DateTime<Utc>,
+);
+
+fn dead_letter(row: DeadLetterRow) -> DeadLetter {
+    let (id, recipient, subject, attempts, last_error, created_at, dead_lettered_at) = row;
+    DeadLetter {
+        id,
+        recipient,
+        subject,
+        attempts,
+        last_error,
+        created_at,
+        dead_lettered_at,
+    }
+}
+
+#[async_trait]
+impl DeadLetterRepository for SqliteRepository {
+    #[tracing::instrument(name = "List the dead letters from SQLite", skip(self))]
+    async fn list(
+        &self,
+        limit: i64,
+        after: Option<Uuid>,
+    ) -> Result<Option<Vec<DeadLetter>>, RepositoryError> {
+        // The dead letters are ordered by `(dead_lettered_at DESC, id)`, the page starts after the
+        // position of `after` in that order
+        let cursor = match after {
+            Some(id) => {
+                let cursor = sqlx::query_as::<_, (DateTime<Utc>,)>(
+                    r#"SELECT dead_lettered_at FROM outbox
+                        WHERE id = ? AND dead_lettered_at IS NOT NULL"#,
+                )
+                .bind(id)
+                .fetch_optional(&self.pool)
+                .await?;
+                match cursor {
+                    Some((dead_lettered_at,)) => Some((dead_lettered_at, id)),
+                    None => return Ok(None),
+                }
+            }
+            None => None,
+        };
+        let (cursor_at, cursor_id) = cursor.unzip();
+        let dead_letters = sqlx::query_as::<_, DeadLetterRow>(
+            r#"SELECT id, recipient, subject, attempts, last_error, created_at, dead_lettered_at
+                FROM outbox
+                WHERE dead_lettered_at IS NOT NULL
+                    AND (?2 IS NULL
+                        OR dead_lettered_at < ?2
+                        OR (dead_lettered_at = ?2 AND id > ?3))
+                ORDER BY dead_lettered_at DESC, id
+                LIMIT ?1"#,
+        )
+        .bind(limit)
+        .bind(cursor_at)
+        .bind(cursor_id)
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(dead_letter)
+        .collect();
+        Ok(Some(dead_letters))
+    }
+
+    #[tracing::instrument(name = "Find a dead letter in SQLite", skip(self))]
+    async fn find(&self, id: Uuid) -> Result<Option<DeadLetterDetails>, RepositoryError> {
+        let row = sqlx::query_as::<
+            _,
+            (
+                Uuid,
+                String,
+                String,
+                i32,
+                String,
+                DateTime<Utc>,
+                DateTime<Utc>,
+                String,
+                String,
+            ),
+        >(
+            r#"SELECT id, recipient, subject, attempts, last_error, created_at, dead_lettered_at,
+                    html_content, text_content
+                FROM outbox
+                WHERE id = ? AND dead_lettered_at IS NOT NULL"#,
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(row.map(
+            |(
+                id,
+                recipient,
+                subject,
+                attempts,
+                last_error,
+                created_at,
+                dead_lettered_at,
+                html_content,
+                text_content,
+            )| {
+                DeadLetterDetails {
+                    dead_letter: dead_letter((
+                        id,
+                        recipient,
+                        subject,
+                        attempts,
+                        last_error,
+                        created_at,
+                        dead_lettered_at,
+                    )),
+                    html_content,
+                    text_content,
+                }
+            },
+        ))
+    }
+
+    async fn count(&self) -> Result<i64, RepositoryError> {
+        let (count,) = sqlx::query_as::<_, (i64,)>(
+            "SELECT COUNT(*) FROM outbox WHERE dead_lettered_at IS NOT NULL",
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(count)
+    }
+
+    #[tracing::instrument(name = "Replay a dead letter in SQLite", skip(self))]
+    async fn replay(&self, id: Uuid) -> Result<bool, RepositoryError> {
+        let replayed = sqlx::query(
+            r#"UPDATE outbox SET attempts = 0, next_attempt_at = ?, dead_lettered_at = NULL
+                WHERE id = ? AND dead_lettered_at IS NOT NULL"#,
+        )
+        .bind(Utc::now())
+        .bind(id)
+        .execute(&self.pool)
+        .await?
+        .rows_affected();
+        Ok(replayed > 0)
+    }
+
+    #[tracing::instrument(name = "Replay all the dead letters in SQLite", skip(self))]
+    async fn replay_all(&self) -> Result<u64, RepositoryError> {
+        let replayed = sqlx::query(
+            r#"UPDATE outbox SET attempts = 0, next_attempt_at = ?, dead_lettered_at = NULL
+                WHERE dead_lettered_at IS NOT NULL"#,
+        )
+        .bind(Utc::now())
+        .execute(&self.pool)
+        .await?
+        .rows_affected();
+        Ok(replayed)
+    }
+
+    #[tracing::instrument(name = "Purge a dead letter from SQLite", skip(self))]
+    async fn purge(&self, id: Uuid) -> Result<bool, RepositoryError> {
+        let purged =
+            sqlx::query("DELETE FROM outbox WHERE id = ? AND dead_lettered_at IS NOT NULL")
+                .bind(id)
+                .execute(&self.pool)
+                .await?
+                .rows_affected();
+        Ok(purged > 0)
+    }
+
+    #[tracing::instrument(name = "Purge all the dead letters from SQLite", skip(self))]
+    async fn purge_all(&self) -> Result<u64, RepositoryError> {
+        let purged = sqlx::query("DELETE FROM outbox WHERE dead_lettered_at IS NOT NULL")
+            .execute(&self.pool)
+            .await?
+            .rows_affected();
+        Ok(purged)
+    }
+}
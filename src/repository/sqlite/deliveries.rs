@@ -0,0 +1,303 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+use super::SqliteRepository;
+use crate::{
+    domain::SubscriptionStatus,
+    issue_delivery::{
+        Attempt, DeliveryFailure, DeliverySummary, ErrorClass, QueuedDelivery, Recipient,
+    },
+    repository::{DeliveryRepository, RepositoryError},
+};
+
+#[async_trait]
+impl DeliveryRepository for SqliteRepository {
+    #[tracing::instrument(
+        name = "Queue an issue for the confirmed subscribers in SQLite",
+        skip(self)
+    )]
+    async fn queue_confirmed_subscribers(&self, issue_id: Uuid) -> Result<u64, RepositoryError> {
+        let queued = sqlx::query(
+            r#"INSERT INTO issue_deliveries (issue_id, subscriber_id, status, digest, updated_at)
+                SELECT ?1, s.id, 'queued', s.frequency = 'digest', ?2
+                    FROM subscriptions s JOIN newsletter_issues i ON i.id = ?1
+                    WHERE s.status = ?3 AND (
+                        i.topic IS NULL
+                        OR NOT EXISTS (SELECT 1 FROM subscriber_topics t WHERE t.subscriber_id = s.id)
+                        OR EXISTS (
+                            SELECT 1 FROM subscriber_topics t
+                                WHERE t.subscriber_id = s.id AND t.topic = i.topic
+                        )
+                    )"#,
+        )
+        .bind(issue_id)
+        .bind(Utc::now())
+        .bind(SubscriptionStatus::Confirmed)
+        .execute(&self.pool)
+        .await?
+        .rows_affected();
+        Ok(queued)
+    }
+
+    #[tracing::instrument(name = "Claim the queued deliveries in SQLite", skip(self))]
+    async fn claim_queued(
+        &self,
+        locked_until: DateTime<Utc>,
+        limit: i64,
+    ) -> Result<Vec<QueuedDelivery>, RepositoryError> {
+        // The rows of the other tables cannot be returned by `UPDATE FROM`, the leased ones are
+        // read back in the same transaction
+        let mut transaction = self.pool.begin().await?;
+        sqlx::query(
+            r#"UPDATE issue_deliveries SET locked_until = ?1
+                WHERE (issue_id, subscriber_id) IN (
+                    SELECT d.issue_id, d.subscriber_id
+                        FROM issue_deliveries d JOIN subscriptions s ON s.id = d.subscriber_id
+                        WHERE NOT d.digest AND d.status = 'queued'
+                            AND (d.locked_until IS NULL OR d.locked_until <= ?2)
+                            AND (d.next_attempt_at IS NULL OR d.next_attempt_at <= ?2)
+                            AND s.status = ?3
+                        ORDER BY d.updated_at
+                        LIMIT ?4
+                )"#,
+        )
+        .bind(locked_until)
+        .bind(Utc::now())
+        .bind(SubscriptionStatus::Confirmed)
+        .bind(limit)
+        .execute(&mut transaction)
+        .await?;
+        let claimed = sqlx::query_as::<_, (Uuid, Uuid, String, bool, i32)>(
+            r#"SELECT d.issue_id, s.id, s.email, s.tracking_opt_out, d.attempts
+                FROM issue_deliveries d JOIN subscriptions s ON s.id = d.subscriber_id
+                WHERE NOT d.digest AND d.status = 'queued' AND d.locked_until = ?"#,
+        )
+        .bind(locked_until)
+        .fetch_all(&mut transaction)
+        .await?
+        .into_iter()
+        .map(
+            |(issue_id, subscriber_id, email, tracking_opt_out, attempts)| QueuedDelivery {
+                issue_id,
+                recipient: Recipient {
+                    subscriber_id,
+                    email,
+                    tracking_opt_out,
+                    attempts,
+                },
+            },
+        )
+        .collect();
+        transaction.commit().await?;
+        Ok(claimed)
+    }
+
+    #[tracing::instrument(
+        name = "Queue an issue for the failed deliveries in SQLite",
+        skip(self)
+    )]
+    async fn queue_failures(&self, issue_id: Uuid) -> Result<u64, RepositoryError> {
+        let queued = sqlx::query(
+            r#"UPDATE issue_deliveries
+                SET status = 'queued', digest = false, attempts = 0, next_attempt_at = NULL,
+                    updated_at = ?
+                FROM subscriptions
+                WHERE issue_deliveries.subscriber_id = subscriptions.id
+                    AND issue_deliveries.issue_id = ?
+                    AND issue_deliveries.status = 'failed'
+                    AND subscriptions.status = ?"#,
+        )
+        .bind(Utc::now())
+        .bind(issue_id)
+        .bind(SubscriptionStatus::Confirmed)
+        .execute(&self.pool)
+        .await?
+        .rows_affected();
+        Ok(queued)
+    }
+
+    #[tracing::instrument(name = "Claim the deliveries of the digests in SQLite", skip(self))]
+    async fn claim_digests(
+        &self,
+        locked_until: DateTime<Utc>,
+    ) -> Result<Vec<QueuedDelivery>, RepositoryError> {
+        // The rows of the other tables cannot be returned by `UPDATE FROM`, the leased ones are
+        // read back in the same transaction
+        let mut transaction = self.pool.begin().await?;
+        sqlx::query(
+            r#"UPDATE issue_deliveries SET locked_until = ?1
+                FROM subscriptions
+                WHERE issue_deliveries.subscriber_id = subscriptions.id
+                    AND issue_deliveries.digest
+                    AND issue_deliveries.status = 'queued'
+                    AND (issue_deliveries.locked_until IS NULL
+                        OR issue_deliveries.locked_until <= ?2)
+                    AND (issue_deliveries.next_attempt_at IS NULL
+                        OR issue_deliveries.next_attempt_at <= ?2)
+                    AND subscriptions.status = ?3"#,
+        )
+        .bind(locked_until)
+        .bind(Utc::now())
+        .bind(SubscriptionStatus::Confirmed)
+        .execute(&mut transaction)
+        .await?;
+        let entries = sqlx::query_as::<_, (Uuid, Uuid, String, bool, i32)>(
+            r#"SELECT d.issue_id, s.id, s.email, s.tracking_opt_out, d.attempts
+                FROM issue_deliveries d
+                    JOIN subscriptions s ON s.id = d.subscriber_id
+                    JOIN newsletter_issues i ON i.id = d.issue_id
+                WHERE d.digest AND d.status = 'queued' AND d.locked_until = ?
+                ORDER BY i.published_at"#,
+        )
+        .bind(locked_until)
+        .fetch_all(&mut transaction)
+        .await?
+        .into_iter()
+        .map(
+            |(issue_id, subscriber_id, email, tracking_opt_out, attempts)| QueuedDelivery {
+                issue_id,
+                recipient: Recipient {
+                    subscriber_id,
+                    email,
+                    tracking_opt_out,
+                    attempts,
+                },
+            },
+        )
+        .collect();
+        transaction.commit().await?;
+        Ok(entries)
+    }
+
+    #[tracing::instrument(
+        name = "Record delivery attempts in SQLite",
+        skip(self, attempts),
+        fields(attempts = attempts.len())
+    )]
+    async fn record_attempts(
+        &self,
+        issue_id: Uuid,
+        attempts: &[(Uuid, &Attempt)],
+    ) -> Result<(), RepositoryError> {
+        let now = Utc::now();
+        let mut transaction = self.pool.begin().await?;
+        for (subscriber_id, attempt) in attempts {
+            let error_class = attempt.error_class.as_ref().map(ErrorClass::as_str);
+            sqlx::query(
+                r#"UPDATE issue_deliveries
+                    SET status = ?, provider_message_id = ?, provider = ?, error_class = ?,
+                        detail = ?, attempts = attempts + 1, updated_at = ?,
+                        locked_until = NULL, next_attempt_at = ?
+                    WHERE issue_id = ? AND subscriber_id = ?"#,
+            )
+            .bind(attempt.delivery_status().as_str())
+            .bind(&attempt.provider_message_id)
+            .bind(&attempt.provider)
+            .bind(error_class)
+            .bind(&attempt.detail)
+            .bind(now)
+            .bind(attempt.retry_at)
+            .bind(issue_id)
+            .bind(subscriber_id)
+            .execute(&mut transaction)
+            .await?;
+            sqlx::query(
+                r#"INSERT INTO issue_delivery_attempts
+                    (id, issue_id, subscriber_id, status, provider_message_id, provider,
+                        error_class, detail, attempted_at)
+                    VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)"#,
+            )
+            .bind(Uuid::new_v4())
+            .bind(issue_id)
+            .bind(subscriber_id)
+            .bind(attempt.status.as_str())
+            .bind(&attempt.provider_message_id)
+            .bind(&attempt.provider)
+            .bind(error_class)
+            .bind(&attempt.detail)
+            .bind(now)
+            .execute(&mut transaction)
+            .await?;
+        }
+        transaction.commit().await?;
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Summarise the delivery of an issue from SQLite", skip(self))]
+    async fn summary(&self, issue_id: Uuid) -> Result<Option<DeliverySummary>, RepositoryError> {
+        let counts = sqlx::query_as::<_, (i64, i64, i64, i64, i64)>(
+            r#"SELECT
+                COUNT(d.subscriber_id),
+                COUNT(d.subscriber_id) FILTER (WHERE d.status = 'queued'),
+                COUNT(d.subscriber_id) FILTER (WHERE d.status = 'sent'),
+                COUNT(d.subscriber_id) FILTER (WHERE d.status = 'skipped'),
+                COUNT(d.subscriber_id) FILTER (WHERE d.status = 'failed')
+                FROM newsletter_issues i LEFT JOIN issue_deliveries d ON d.issue_id = i.id
+                WHERE i.id = ?
+                GROUP BY i.id"#,
+        )
+        .bind(issue_id)
+        .fetch_optional(&self.pool)
+        .await?;
+        let (total, queued, sent, skipped, failed) = match counts {
+            Some(counts) => counts,
+            None => return Ok(None),
+        };
+
+        let sent_by_provider = sqlx::query_as::<_, (String, i64)>(
+            r#"SELECT provider, COUNT(*)
+                FROM issue_deliveries
+                WHERE issue_id = ? AND status = 'sent' AND provider IS NOT NULL
+                GROUP BY provider"#,
+        )
+        .bind(issue_id)
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .collect();
+
+        let failures = sqlx::query_as::<
+            _,
+            (
+                Uuid,
+                String,
+                Option<String>,
+                Option<String>,
+                i32,
+                DateTime<Utc>,
+            ),
+        >(
+            r#"SELECT d.subscriber_id, s.email, d.error_class, d.detail, d.attempts, d.updated_at
+                FROM issue_deliveries d JOIN subscriptions s ON s.id = d.subscriber_id
+                WHERE d.issue_id = ? AND d.status = 'failed'
+                ORDER BY d.updated_at"#,
+        )
+        .bind(issue_id)
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(
+            |(subscriber_id, email, error_class, detail, attempts, updated_at)| DeliveryFailure {
+                subscriber_id,
+                email,
+                error_class,
+                detail,
+                attempts,
+                updated_at,
+            },
+        )
+        .collect();
+
+        Ok(Some(DeliverySummary {
+            issue_id,
+            total,
+            queued,
+            sent,
+            skipped,
+            failed,
+            sent_by_provider,
+            failures,
+        }))
+    }
+}
@@ -0,0 +1,119 @@
+use async_trait::async_trait;
+use chrono::Utc;
+use sqlx::{Sqlite, Transaction};
+use uuid::Uuid;
+
+use super::{json_array, suppressions, SqliteRepository};
+use crate::{
+    domain::{EmailEvent, EmailEventKind, SubscriptionStatus},
+    repository::{AppliedEvent, EmailEventRepository, RepositoryError},
+    suppression::{self, SuppressionScope},
+};
+
+#[async_trait]
+impl EmailEventRepository for SqliteRepository {
+    #[tracing::instrument(
+        name = "Store an email event in SQLite",
+        skip(self, event, payload, soft_bounce_threshold),
+        fields(kind = event.kind.as_str(), message_id = %event.message_id)
+    )]
+    async fn apply(
+        &self,
+        provider: &str,
+        event: &EmailEvent,
+        payload: &str,
+        soft_bounce_threshold: u32,
+    ) -> Result<AppliedEvent, RepositoryError> {
+        let mut transaction = self.pool.begin().await?;
+        let stored = sqlx::query(
+            r#"INSERT INTO email_events
+                (id, provider, message_id, kind, recipient, description, occurred_at,
+                    received_at, payload)
+                VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+                ON CONFLICT DO NOTHING"#,
+        )
+        .bind(Uuid::new_v4())
+        .bind(provider)
+        .bind(&event.message_id)
+        .bind(event.kind.as_str())
+        .bind(event.recipient.as_ref())
+        .bind(&event.description)
+        .bind(event.occurred_at)
+        .bind(Utc::now())
+        .bind(payload)
+        .execute(&mut transaction)
+        .await?
+        .rows_affected()
+            == 1;
+        if !stored {
+            return Ok(AppliedEvent::Duplicate);
+        }
+
+        let suppressed = update_subscriber(&mut transaction, event, soft_bounce_threshold).await?;
+        if suppressed {
+            suppressions::upsert(
+                &mut transaction,
+                SuppressionScope::Address,
+                &suppression::normalise_address(&event.recipient),
+                event.kind.as_str(),
+                None,
+            )
+            .await?;
+        }
+        transaction.commit().await?;
+        Ok(AppliedEvent::Applied { suppressed })
+    }
+}
+
+/// Update the subscriber the event refers to, it returns whether the address must be suppressed.
+async fn update_subscriber(
+    transaction: &mut Transaction<'_, Sqlite>,
+    event: &EmailEvent,
+    soft_bounce_threshold: u32,
+) -> Result<bool, sqlx::Error> {
+    let predecessors = json_array(&SubscriptionStatus::Suppressed.predecessors());
+    let suppressed = match event.kind {
+        EmailEventKind::Delivered => {
+            sqlx::query("UPDATE subscriptions SET soft_bounce_count = 0 WHERE email = ?")
+                .bind(event.recipient.as_ref())
+                .execute(&mut *transaction)
+                .await?;
+            false
+        }
+        EmailEventKind::HardBounce | EmailEventKind::Complaint => {
+            sqlx::query(
+                r#"UPDATE subscriptions SET status = ?
+                    WHERE email = ? AND status IN (SELECT value FROM json_each(?))"#,
+            )
+            .bind(SubscriptionStatus::Suppressed)
+            .bind(event.recipient.as_ref())
+            .bind(predecessors)
+            .execute(&mut *transaction)
+            .await?;
+            // The address is suppressed even if it is not subscribed anymore
+            true
+        }
+        EmailEventKind::SoftBounce => {
+            let threshold = i32::try_from(soft_bounce_threshold).unwrap_or(i32::MAX);
+            sqlx::query_as::<_, (SubscriptionStatus,)>(
+                r#"UPDATE subscriptions
+                    SET soft_bounce_count = soft_bounce_count + 1,
+                        status = CASE
+                            WHEN soft_bounce_count + 1 >= ?
+                                AND status IN (SELECT value FROM json_each(?)) THEN ?
+                            ELSE status
+                        END
+                    WHERE email = ?
+                    RETURNING status"#,
+            )
+            .bind(threshold)
+            .bind(predecessors)
+            .bind(SubscriptionStatus::Suppressed)
+            .bind(event.recipient.as_ref())
+            .fetch_optional(&mut *transaction)
+            .await?
+            .is_some_and(|(status,)| status == SubscriptionStatus::Suppressed)
+        }
+    };
+    Ok(suppressed)
+}
@@ -0,0 +1,326 @@
+//! The repositories stored in a SQLite file, for the installs running on a single node.
+//!
+//! The schema is in `migrations_sqlite/`, one migration for each of `migrations/`. What Postgres
+//! does natively is done as follows:
+//! - the uuids are 16-byte BLOBs and the timestamps are UTC TEXT, which sorts chronologically;
+//! - `subscription_status` is a TEXT column checked by triggers, SQLite has no enums;
+//! - `status = ANY($1)` is a compare-and-set on the current status, see `transition`;
+//! - the other arrays are bound as JSON and read with `json_each`, see `json_array`;
+//! - `FOR UPDATE SKIP LOCKED` is left out of the lease on the message, see `outbox::sqlite`;
+//! - there is no `LISTEN`/`NOTIFY`, the outbox is polled;
+//! - a `RETURNING` write outside a transaction is read with `fetch_all`, `fetch_one` returns at
+//!   the first row and SQLite commits the write only once the statement ran to its end.
+
+mod dead_letters;
+mod deliveries;
+mod email_events;
+mod suppressions;
+mod tracking;
+
+use std::time::Duration;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{
+    migrate::MigrateError,
+    sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePool, SqlitePoolOptions},
+};
+use uuid::Uuid;
+
+use super::{
+    IssueRepository, Preferences, RepositoryError, Subscriber, SubscriberRepository,
+    TokenRepository,
+};
+use crate::{
+    domain::{NewSubscriber, SubscriberName, SubscriptionStatus, SubscriptionToken},
+    email_client::OutgoingEmail,
+    issue_delivery::NewsletterIssue,
+    outbox,
+    tracking::TrackingOptions,
+};
+
+/// How long a write waits for the lock held by another write.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A list bound as a JSON array, it is read with `IN (SELECT value FROM json_each(?))` where
+/// Postgres has `= ANY($1)`.
+fn json_array<T: Serialize>(values: &[T]) -> String {
+    serde_json::to_string(values).expect("A list of strings is valid JSON")
+}
+
+#[derive(Clone)]
+pub struct SqliteRepository {
+    pool: SqlitePool,
+}
+
+impl SqliteRepository {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+
+    /// Open the database file at `path`, it is created if it does not exist. The connections are
+    /// opened when first used.
+    pub fn open(path: &str) -> Self {
+        let options = SqliteConnectOptions::new()
+            .filename(path)
+            .create_if_missing(true)
+            // The readers do not wait for the writer
+            .journal_mode(SqliteJournalMode::Wal)
+            .busy_timeout(BUSY_TIMEOUT)
+            .foreign_keys(true);
+        Self::new(SqlitePoolOptions::new().connect_lazy_with(options))
+    }
+
+    /// Apply the migrations of `migrations_sqlite/` that were not applied yet.
+    pub async fn migrate(&self) -> Result<(), MigrateError> {
+        sqlx::migrate!("./migrations_sqlite").run(&self.pool).await
+    }
+
+    pub fn pool(&self) -> &SqlitePool {
+        &self.pool
+    }
+}
+
+#[async_trait]
+impl SubscriberRepository for SqliteRepository {
+    #[tracing::instrument(
+        name = "Saving new subscriber details in SQLite",
+        skip(self, subscriber, token, confirmation)
+    )]
+    async fn add_pending(
+        &self,
+        subscriber: &NewSubscriber,
+        token: &SubscriptionToken,
+        confirmation: &OutgoingEmail<'_>,
+    ) -> Result<Uuid, RepositoryError> {
+        let mut transaction = self.pool.begin().await?;
+        // The write comes first, a deferred transaction reading first could not take the write
+        // lock while another connection writes
+        let updated = sqlx::query(
+            "UPDATE subscriptions SET subscribed_at = ? WHERE canonical_email = ? AND status = ?",
+        )
+        .bind(Utc::now())
+        .bind(subscriber.email.canonical())
+        .bind(SubscriptionStatus::PendingConfirmation)
+        .execute(&mut transaction)
+        .await?
+        .rows_affected();
+        let pending = if updated > 0 {
+            sqlx::query_as::<_, (Uuid,)>("SELECT id FROM subscriptions WHERE canonical_email = ?")
+                .bind(subscriber.email.canonical())
+                .fetch_optional(&mut transaction)
+                .await?
+                .map(|(id,)| id)
+        } else {
+            None
+        };
+        // The insertion fails with `Duplicate` if the address is in another status
+        let subscriber_id = match pending {
+            Some(subscriber_id) => subscriber_id,
+            None => {
+                let subscriber_id = Uuid::new_v4();
+                sqlx::query(
+                    r#"INSERT INTO subscriptions
+                        (id, email, canonical_email, name, subscribed_at, status)
+                        VALUES (?, ?, ?, ?, ?, ?)"#,
+                )
+                .bind(subscriber_id)
+                .bind(subscriber.email.as_ref())
+                .bind(subscriber.email.canonical())
+                .bind(subscriber.name.as_ref())
+                .bind(Utc::now())
+                .bind(SubscriptionStatus::PendingConfirmation)
+                .execute(&mut transaction)
+                .await?;
+                subscriber_id
+            }
+        };
+        sqlx::query(
+            "INSERT INTO subscription_tokens(subscription_token, subscriber_id) VALUES(?, ?)",
+        )
+        .bind(token.as_ref())
+        .bind(subscriber_id)
+        .execute(&mut transaction)
+        .await?;
+        outbox::sqlite::enqueue(&mut transaction, confirmation).await?;
+        transaction.commit().await?;
+        Ok(subscriber_id)
+    }
+
+    #[tracing::instrument(name = "Get a subscriber from SQLite", skip(self))]
+    async fn get(&self, id: Uuid) -> Result<Option<Subscriber>, RepositoryError> {
+        let subscriber =
+            sqlx::query_as::<_, (Uuid, String, String, SubscriptionStatus, DateTime<Utc>)>(
+                "SELECT id, email, name, status, subscribed_at FROM subscriptions WHERE id = ?",
+            )
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await?
+            .map(|(id, email, name, status, subscribed_at)| Subscriber {
+                id,
+                email,
+                name,
+                status,
+                subscribed_at,
+            });
+        Ok(subscriber)
+    }
+
+    /// The status only changes if nobody changed it since it was read, the transition is
+    /// checked here rather than with `status = ANY($3)`.
+    #[tracing::instrument(name = "Change the status of a subscriber in SQLite", skip(self))]
+    async fn transition(
+        &self,
+        id: Uuid,
+        status: SubscriptionStatus,
+    ) -> Result<bool, RepositoryError> {
+        let current = sqlx::query_as::<_, (SubscriptionStatus,)>(
+            "SELECT status FROM subscriptions WHERE id = ?",
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?;
+        let current = match current {
+            Some((current,)) if current.can_transition_to(status) => current,
+            _ => return Ok(false),
+        };
+        let updated =
+            sqlx::query("UPDATE subscriptions SET status = ? WHERE id = ? AND status = ?")
+                .bind(status)
+                .bind(id)
+                .bind(current)
+                .execute(&self.pool)
+                .await?
+                .rows_affected();
+        Ok(updated > 0)
+    }
+
+    #[tracing::instrument(name = "Get the preferences of a subscriber from SQLite", skip(self))]
+    async fn preferences(&self, id: Uuid) -> Result<Option<Preferences>, RepositoryError> {
+        let frequency =
+            sqlx::query_as::<_, (String,)>("SELECT frequency FROM subscriptions WHERE id = ?")
+                .bind(id)
+                .fetch_optional(&self.pool)
+                .await?;
+        let frequency = match frequency {
+            Some((frequency,)) => frequency
+                .parse()
+                .map_err(|e: String| sqlx::Error::Decode(e.into()))?,
+            None => return Ok(None),
+        };
+        let topics = sqlx::query_as::<_, (String,)>(
+            "SELECT topic FROM subscriber_topics WHERE subscriber_id = ?",
+        )
+        .bind(id)
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(|(topic,)| topic)
+        .collect();
+        Ok(Some(Preferences { topics, frequency }))
+    }
+
+    #[tracing::instrument(
+        name = "Update the preferences of a subscriber in SQLite",
+        skip(self, name)
+    )]
+    async fn update_preferences(
+        &self,
+        id: Uuid,
+        name: &SubscriberName,
+        preferences: &Preferences,
+    ) -> Result<bool, RepositoryError> {
+        let mut transaction = self.pool.begin().await?;
+        let updated = sqlx::query("UPDATE subscriptions SET name = ?, frequency = ? WHERE id = ?")
+            .bind(name.as_ref())
+            .bind(preferences.frequency.as_str())
+            .bind(id)
+            .execute(&mut transaction)
+            .await?
+            .rows_affected();
+        if updated == 0 {
+            return Ok(false);
+        }
+        sqlx::query("DELETE FROM subscriber_topics WHERE subscriber_id = ?")
+            .bind(id)
+            .execute(&mut transaction)
+            .await?;
+        for topic in &preferences.topics {
+            sqlx::query("INSERT INTO subscriber_topics (subscriber_id, topic) VALUES (?, ?)")
+                .bind(id)
+                .bind(topic)
+                .execute(&mut transaction)
+                .await?;
+        }
+        transaction.commit().await?;
+        Ok(true)
+    }
+}
+
+#[async_trait]
+impl TokenRepository for SqliteRepository {
+    #[tracing::instrument(name = "Get subscriber_id from token in SQLite", skip(self, token))]
+    async fn subscriber_id(
+        &self,
+        token: &SubscriptionToken,
+    ) -> Result<Option<Uuid>, RepositoryError> {
+        let result = sqlx::query_as::<_, (Uuid,)>(
+            "SELECT subscriber_id FROM subscription_tokens WHERE subscription_token = ?",
+        )
+        .bind(token.as_ref())
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(result.map(|(subscriber_id,)| subscriber_id))
+    }
+}
+
+#[async_trait]
+impl IssueRepository for SqliteRepository {
+    #[tracing::instrument(
+        name = "Store a newsletter issue in SQLite",
+        skip(self, issue),
+        fields(issue_id = %issue.id)
+    )]
+    async fn add(&self, issue: &NewsletterIssue) -> Result<(), RepositoryError> {
+        sqlx::query(
+            r#"INSERT INTO newsletter_issues
+                (id, title, text_content, html_content, track_opens, track_clicks, topic,
+                    published_at)
+                VALUES (?, ?, ?, ?, ?, ?, ?, ?)"#,
+        )
+        .bind(issue.id)
+        .bind(&issue.title)
+        .bind(&issue.text_content)
+        .bind(&issue.html_content)
+        .bind(issue.tracking.opens)
+        .bind(issue.tracking.clicks)
+        .bind(&issue.topic)
+        .bind(Utc::now())
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Get a newsletter issue from SQLite", skip(self))]
+    async fn get(&self, id: Uuid) -> Result<Option<NewsletterIssue>, RepositoryError> {
+        let issue = sqlx::query_as::<_, (String, String, String, bool, bool, Option<String>)>(
+            r#"SELECT title, html_content, text_content, track_opens, track_clicks, topic
+                FROM newsletter_issues WHERE id = ?"#,
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?
+        .map(
+            |(title, html_content, text_content, opens, clicks, topic)| NewsletterIssue {
+                id,
+                title,
+                html_content,
+                text_content,
+                tracking: TrackingOptions { opens, clicks },
+                topic,
+            },
+        );
+        Ok(issue)
+    }
+}
@@ -0,0 +1,138 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use sqlx::{Executor, Sqlite};
+use uuid::Uuid;
+
+use super::{json_array, SqliteRepository};
+use crate::{
+    repository::{RepositoryError, SuppressionRepository},
+    suppression::{SkippedEmail, Suppression, SuppressionScope},
+};
+
+type SuppressionRow = (String, String, String, DateTime<Utc>, Option<DateTime<Utc>>);
+
+fn suppression(row: SuppressionRow) -> Result<Suppression, sqlx::Error> {
+    let (scope, value, reason, created_at, expires_at) = row;
+    Ok(Suppression {
+        scope: scope
+            .parse()
+            .map_err(|e: String| sqlx::Error::Decode(e.into()))?,
+        value,
+        reason,
+        created_at,
+        expires_at,
+    })
+}
+
+/// Add an entry, an existing entry for the same value is replaced.
+pub(super) async fn upsert(
+    executor: impl Executor<'_, Database = Sqlite>,
+    scope: SuppressionScope,
+    value: &str,
+    reason: &str,
+    expires_at: Option<DateTime<Utc>>,
+) -> Result<Suppression, sqlx::Error> {
+    let row = sqlx::query_as::<_, SuppressionRow>(
+        r#"INSERT INTO suppressions (scope, value, reason, created_at, expires_at)
+            VALUES (?, ?, ?, ?, ?)
+            ON CONFLICT (scope, value) DO UPDATE
+                SET reason = excluded.reason,
+                    created_at = excluded.created_at,
+                    expires_at = excluded.expires_at
+            RETURNING scope, value, reason, created_at, expires_at"#,
+    )
+    .bind(scope.as_str())
+    .bind(value)
+    .bind(reason)
+    .bind(Utc::now())
+    .bind(expires_at)
+    .fetch_all(executor)
+    .await?
+    .pop()
+    .ok_or(sqlx::Error::RowNotFound)?;
+    suppression(row)
+}
+
+#[async_trait]
+impl SuppressionRepository for SqliteRepository {
+    async fn find_active(
+        &self,
+        addresses: &[String],
+        domains: &[String],
+    ) -> Result<Vec<Suppression>, RepositoryError> {
+        let suppressions = sqlx::query_as::<_, SuppressionRow>(
+            r#"SELECT scope, value, reason, created_at, expires_at FROM suppressions
+                WHERE ((scope = 'address' AND value IN (SELECT value FROM json_each(?)))
+                        OR (scope = 'domain' AND value IN (SELECT value FROM json_each(?))))
+                    AND (expires_at IS NULL OR expires_at > ?)"#,
+        )
+        .bind(json_array(addresses))
+        .bind(json_array(domains))
+        .bind(Utc::now())
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(suppression)
+        .collect::<Result<_, _>>()?;
+        Ok(suppressions)
+    }
+
+    #[tracing::instrument(name = "Add a suppression in SQLite", skip(self, value, expires_at))]
+    async fn add(
+        &self,
+        scope: SuppressionScope,
+        value: &str,
+        reason: &str,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Result<Suppression, RepositoryError> {
+        Ok(upsert(&self.pool, scope, value, reason, expires_at).await?)
+    }
+
+    #[tracing::instrument(name = "Remove a suppression from SQLite", skip(self, value))]
+    async fn remove(&self, scope: SuppressionScope, value: &str) -> Result<bool, RepositoryError> {
+        let result = sqlx::query("DELETE FROM suppressions WHERE scope = ? AND value = ?")
+            .bind(scope.as_str())
+            .bind(value)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    #[tracing::instrument(name = "List the suppressions from SQLite", skip(self))]
+    async fn list(&self) -> Result<Vec<Suppression>, RepositoryError> {
+        let suppressions = sqlx::query_as::<_, SuppressionRow>(
+            r#"SELECT scope, value, reason, created_at, expires_at FROM suppressions
+                ORDER BY created_at"#,
+        )
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(suppression)
+        .collect::<Result<_, _>>()?;
+        Ok(suppressions)
+    }
+
+    #[tracing::instrument(
+        name = "Record skipped emails in SQLite",
+        skip(self, emails),
+        fields(emails = emails.len())
+    )]
+    async fn record_skipped(&self, emails: &[SkippedEmail<'_>]) -> Result<(), RepositoryError> {
+        let mut transaction = self.pool.begin().await?;
+        for email in emails {
+            sqlx::query(
+                r#"INSERT INTO skipped_emails (id, recipient, subject, reason, skipped_at)
+                    VALUES (?, ?, ?, ?, ?)"#,
+            )
+            .bind(Uuid::new_v4())
+            .bind(email.recipient.as_ref())
+            .bind(email.subject)
+            .bind(email.reason)
+            .bind(Utc::now())
+            .execute(&mut transaction)
+            .await?;
+        }
+        transaction.commit().await?;
+        Ok(())
+    }
+}
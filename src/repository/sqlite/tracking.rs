@@ -0,0 +1,87 @@
+use async_trait::async_trait;
+use chrono::Utc;
+use uuid::Uuid;
+
+use super::SqliteRepository;
+use crate::{
+    repository::{RepositoryError, TrackingRepository},
+    tracking::{IssueStats, LinkStats, TrackingEvent},
+};
+
+#[async_trait]
+impl TrackingRepository for SqliteRepository {
+    #[tracing::instrument(name = "Record a tracking event in SQLite", skip(self))]
+    async fn record_event(
+        &self,
+        issue_id: Uuid,
+        subscriber_id: Uuid,
+        event: &TrackingEvent<'_>,
+    ) -> Result<(), RepositoryError> {
+        sqlx::query(
+            r#"INSERT INTO tracking_events (id, issue_id, subscriber_id, kind, url, occurred_at)
+                SELECT ?, ?, id, ?, ?, ? FROM subscriptions
+                    WHERE id = ? AND NOT tracking_opt_out"#,
+        )
+        .bind(Uuid::new_v4())
+        .bind(issue_id)
+        .bind(event.kind())
+        .bind(event.url())
+        .bind(Utc::now())
+        .bind(subscriber_id)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Opt a subscriber out of the tracking in SQLite", skip(self))]
+    async fn opt_out(&self, subscriber_id: Uuid) -> Result<(), RepositoryError> {
+        sqlx::query("UPDATE subscriptions SET tracking_opt_out = true WHERE id = ?")
+            .bind(subscriber_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    #[tracing::instrument(name = "Get the statistics of an issue from SQLite", skip(self))]
+    async fn issue_stats(&self, issue_id: Uuid) -> Result<Option<IssueStats>, RepositoryError> {
+        let issue = sqlx::query_as::<_, (String, bool, bool, i64, i64)>(
+            r#"SELECT title, track_opens, track_clicks,
+                (SELECT COUNT(DISTINCT subscriber_id) FROM tracking_events
+                    WHERE issue_id = ?1 AND kind = 'open'),
+                (SELECT COUNT(DISTINCT subscriber_id) FROM tracking_events
+                    WHERE issue_id = ?1 AND kind = 'click')
+                FROM newsletter_issues WHERE id = ?1"#,
+        )
+        .bind(issue_id)
+        .fetch_optional(&self.pool)
+        .await?;
+        let (title, track_opens, track_clicks, unique_opens, unique_clicks) = match issue {
+            Some(issue) => issue,
+            None => return Ok(None),
+        };
+
+        let links = sqlx::query_as::<_, (String, i64)>(
+            r#"SELECT url, COUNT(DISTINCT subscriber_id)
+                FROM tracking_events
+                WHERE issue_id = ? AND kind = 'click' AND url IS NOT NULL
+                GROUP BY url
+                ORDER BY 2 DESC, 1"#,
+        )
+        .bind(issue_id)
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(|(url, unique_clicks)| LinkStats { url, unique_clicks })
+        .collect();
+
+        Ok(Some(IssueStats {
+            issue_id,
+            title,
+            track_opens,
+            track_clicks,
+            unique_opens,
+            unique_clicks,
+            links,
+        }))
+    }
+}
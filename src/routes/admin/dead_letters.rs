@@ -0,0 +1,195 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use axum::{
+    extract::{Extension, Path, Query},
+    response::IntoResponse,
+    Json,
+};
+use http::StatusCode;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::Admin;
+use crate::{
+    outbox::dead_letters::{DeadLetter, DeadLetterDetails, MAX_PAGE_SIZE},
+    repository::DeadLetterRepository,
+    request_id::RequestId,
+};
+
+/// Number of dead letters in a page when no limit is given.
+const DEFAULT_PAGE_SIZE: i64 = 100;
+
+/// A page of the dead letters: at most `limit` of them, starting after the dead letter `after`,
+/// the last one of the previous page.
+#[derive(Debug, Deserialize)]
+pub struct Page {
+    limit: Option<i64>,
+    after: Option<Uuid>,
+}
+
+/// Outcome of a bulk operation on the dead letters.
+#[derive(Debug, Serialize)]
+pub struct BulkReport {
+    count: u64,
+}
+
+#[tracing::instrument(name = "List the dead letters", skip(_admin, dead_letters))]
+pub async fn list(
+    _admin: Admin,
+    Query(page): Query<Page>,
+    Extension(dead_letters): Extension<Arc<dyn DeadLetterRepository>>,
+) -> Result<Json<Vec<DeadLetter>>, Error> {
+    let limit = page.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    if !(1..=MAX_PAGE_SIZE).contains(&limit) {
+        return Err(Error::InvalidLimit);
+    }
+    let dead_letters = dead_letters
+        .list(limit, page.after)
+        .await
+        .context("failed to list the dead letters")?
+        .ok_or(Error::UnknownCursor)?;
+    Ok(Json(dead_letters))
+}
+
+#[tracing::instrument(name = "Show a dead letter", skip(_admin, dead_letters))]
+pub async fn show(
+    _admin: Admin,
+    Path(id): Path<Uuid>,
+    Extension(dead_letters): Extension<Arc<dyn DeadLetterRepository>>,
+) -> Result<Json<DeadLetterDetails>, Error> {
+    let dead_letter = dead_letters
+        .find(id)
+        .await
+        .context("failed to fetch the dead letter")?
+        .ok_or(Error::NotFound)?;
+    Ok(Json(dead_letter))
+}
+
+#[tracing::instrument(name = "Replay a dead letter", skip(_admin, request_id, dead_letters))]
+pub async fn replay(
+    _admin: Admin,
+    request_id: RequestId,
+    Path(id): Path<Uuid>,
+    Extension(dead_letters): Extension<Arc<dyn DeadLetterRepository>>,
+) -> Result<StatusCode, Error> {
+    let replayed = dead_letters
+        .replay(id)
+        .await
+        .context("failed to replay the dead letter")?;
+    if !replayed {
+        return Err(Error::NotFound);
+    }
+
+    tracing::info!(
+        target: "audit",
+        request_id = %request_id,
+        action = "dead_letter.replay",
+        dead_letter_id = %id,
+        "Dead letter replayed"
+    );
+
+    Ok(StatusCode::ACCEPTED)
+}
+
+#[tracing::instrument(
+    name = "Replay all the dead letters",
+    skip(_admin, request_id, dead_letters)
+)]
+pub async fn replay_all(
+    _admin: Admin,
+    request_id: RequestId,
+    Extension(dead_letters): Extension<Arc<dyn DeadLetterRepository>>,
+) -> Result<(StatusCode, Json<BulkReport>), Error> {
+    let count = dead_letters
+        .replay_all()
+        .await
+        .context("failed to replay the dead letters")?;
+
+    tracing::info!(
+        target: "audit",
+        request_id = %request_id,
+        action = "dead_letter.replay_all",
+        count,
+        "Dead letters replayed"
+    );
+
+    Ok((StatusCode::ACCEPTED, Json(BulkReport { count })))
+}
+
+#[tracing::instrument(name = "Purge a dead letter", skip(_admin, request_id, dead_letters))]
+pub async fn purge(
+    _admin: Admin,
+    request_id: RequestId,
+    Path(id): Path<Uuid>,
+    Extension(dead_letters): Extension<Arc<dyn DeadLetterRepository>>,
+) -> Result<StatusCode, Error> {
+    let purged = dead_letters
+        .purge(id)
+        .await
+        .context("failed to purge the dead letter")?;
+    if !purged {
+        return Err(Error::NotFound);
+    }
+
+    tracing::info!(
+        target: "audit",
+        request_id = %request_id,
+        action = "dead_letter.purge",
+        dead_letter_id = %id,
+        "Dead letter purged"
+    );
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[tracing::instrument(
+    name = "Purge all the dead letters",
+    skip(_admin, request_id, dead_letters)
+)]
+pub async fn purge_all(
+    _admin: Admin,
+    request_id: RequestId,
+    Extension(dead_letters): Extension<Arc<dyn DeadLetterRepository>>,
+) -> Result<Json<BulkReport>, Error> {
+    let count = dead_letters
+        .purge_all()
+        .await
+        .context("failed to purge the dead letters")?;
+
+    tracing::info!(
+        target: "audit",
+        request_id = %request_id,
+        action = "dead_letter.purge_all",
+        count,
+        "Dead letters purged"
+    );
+
+    Ok(Json(BulkReport { count }))
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("the dead letter does not exist")]
+    NotFound,
+    #[error("the limit must be between 1 and {}", MAX_PAGE_SIZE)]
+    InvalidLimit,
+    #[error("the dead letter to list after does not exist anymore")]
+    UnknownCursor,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> axum::response::Response {
+        match self {
+            Error::NotFound => (StatusCode::NOT_FOUND, self.to_string()).into_response(),
+            Error::InvalidLimit | Error::UnknownCursor => {
+                (StatusCode::BAD_REQUEST, self.to_string()).into_response()
+            }
+            Error::UnexpectedError(source) => {
+                (StatusCode::INTERNAL_SERVER_ERROR, source.to_string()).into_response()
+            }
+        }
+    }
+}
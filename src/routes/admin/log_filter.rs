@@ -0,0 +1,107 @@
+use std::time::Duration;
+
+use axum::{extract::Extension, response::IntoResponse, Json};
+use http::StatusCode;
+use serde::{Deserialize, Serialize};
+
+use super::Admin;
+use crate::{
+    request_id::RequestId,
+    telemetry::{LogFilterError, LogFilterHandle},
+};
+
+#[tracing::instrument(name = "Get the log filter", skip(_admin, log_filter))]
+pub async fn get(
+    _admin: Admin,
+    Extension(log_filter): Extension<LogFilterHandle>,
+) -> Result<Json<LogFilter>, Error> {
+    let filter = log_filter.current()?;
+    Ok(Json(LogFilter { filter }))
+}
+
+#[tracing::instrument(name = "Set the log filter", skip(_admin, request_id, log_filter))]
+pub async fn put(
+    _admin: Admin,
+    request_id: RequestId,
+    Json(body): Json<BodyData>,
+    Extension(log_filter): Extension<LogFilterHandle>,
+) -> Result<Json<LogFilterChange>, Error> {
+    let previous = log_filter.current()?;
+    let generation = log_filter.set(&body.filter)?;
+
+    tracing::info!(
+        target: "audit",
+        request_id = %request_id,
+        action = "log_filter.set",
+        previous = %previous,
+        filter = %body.filter,
+        ttl_seconds = ?body.ttl_seconds,
+        "Log filter changed"
+    );
+
+    if let Some(ttl_seconds) = body.ttl_seconds {
+        let log_filter = log_filter.clone();
+        let previous = previous.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_secs(ttl_seconds)).await;
+            match log_filter.set_if_unchanged(&previous, generation) {
+                Ok(true) => tracing::info!(
+                    target: "audit",
+                    request_id = %request_id,
+                    action = "log_filter.restore",
+                    filter = %previous,
+                    "Log filter restored after its TTL expired"
+                ),
+                Ok(false) => {}
+                Err(error) => tracing::error!(
+                    error.cause_chain = ?error,
+                    "Failed to restore the previous log filter"
+                ),
+            }
+        });
+    }
+
+    Ok(Json(LogFilterChange {
+        filter: body.filter,
+        previous,
+        ttl_seconds: body.ttl_seconds,
+    }))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BodyData {
+    filter: String,
+    /// The previous filter is restored after this number of seconds.
+    ttl_seconds: Option<u64>,
+}
+
+#[derive(Serialize)]
+pub struct LogFilter {
+    filter: String,
+}
+
+#[derive(Serialize)]
+pub struct LogFilterChange {
+    filter: String,
+    previous: String,
+    ttl_seconds: Option<u64>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    LogFilter(#[from] LogFilterError),
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> axum::response::Response {
+        match self {
+            Error::LogFilter(source @ LogFilterError::InvalidDirectives(_)) => {
+                (StatusCode::BAD_REQUEST, source.to_string()).into_response()
+            }
+            Error::LogFilter(source) => {
+                (StatusCode::INTERNAL_SERVER_ERROR, source.to_string()).into_response()
+            }
+        }
+    }
+}
@@ -0,0 +1,116 @@
+use std::{fmt::Write, sync::Arc};
+
+use axum::{extract::Extension, response::Headers};
+use http::header;
+
+use super::Admin;
+use crate::{
+    email_client::{CircuitState, EmailClient, Priority},
+    human_verification::HumanVerification,
+    repository::DeadLetterRepository,
+};
+
+/// The metrics of the application in the Prometheus text format. The metrics read from the
+/// database are left out when it fails, the others are still exported.
+pub async fn handler(
+    _admin: Admin,
+    Extension(email_client): Extension<EmailClient>,
+    Extension(dead_letters): Extension<Arc<dyn DeadLetterRepository>>,
+    Extension(human_verification): Extension<HumanVerification>,
+) -> (Headers<[(header::HeaderName, &'static str); 1]>, String) {
+    let dead_letters = match dead_letters.count().await {
+        Ok(count) => Some(count),
+        Err(error) => {
+            tracing::error!(
+                error.cause_chain = ?error,
+                "Failed to count the dead letters, they are left out of the metrics"
+            );
+            None
+        }
+    };
+
+    let mut body = String::new();
+    writeln!(
+        body,
+        "# HELP email_rate_limiter_queue_depth Senders waiting for the rate limiter of each email provider."
+    )
+    .unwrap();
+    writeln!(body, "# TYPE email_rate_limiter_queue_depth gauge").unwrap();
+    for (provider, _, rate_limiter) in email_client.providers() {
+        for priority in [Priority::Transactional, Priority::Bulk] {
+            writeln!(
+                body,
+                "email_rate_limiter_queue_depth{{provider=\"{}\",lane=\"{}\"}} {}",
+                provider,
+                priority.as_str(),
+                rate_limiter.queue_depth(priority)
+            )
+            .unwrap();
+        }
+    }
+    writeln!(
+        body,
+        "# HELP email_circuit_breaker_state Current state of the circuit breaker of each email provider."
+    )
+    .unwrap();
+    writeln!(body, "# TYPE email_circuit_breaker_state gauge").unwrap();
+    for (provider, circuit_breaker, _) in email_client.providers() {
+        let current = circuit_breaker.state();
+        for state in [
+            CircuitState::Closed,
+            CircuitState::Open,
+            CircuitState::HalfOpen,
+        ] {
+            writeln!(
+                body,
+                "email_circuit_breaker_state{{provider=\"{}\",state=\"{}\"}} {}",
+                provider,
+                state.as_str(),
+                u8::from(state == current)
+            )
+            .unwrap();
+        }
+    }
+
+    writeln!(
+        body,
+        "# HELP outbox_dead_letters Messages of the outbox given up on."
+    )
+    .unwrap();
+    writeln!(body, "# TYPE outbox_dead_letters gauge").unwrap();
+    if let Some(dead_letters) = dead_letters {
+        writeln!(body, "outbox_dead_letters {}", dead_letters).unwrap();
+    }
+    writeln!(
+        body,
+        "# HELP metrics_collection_failed Metrics that could not be collected for this scrape."
+    )
+    .unwrap();
+    writeln!(body, "# TYPE metrics_collection_failed gauge").unwrap();
+    writeln!(
+        body,
+        "metrics_collection_failed{{metric=\"outbox_dead_letters\"}} {}",
+        u8::from(dead_letters.is_none())
+    )
+    .unwrap();
+
+    writeln!(
+        body,
+        "# HELP human_verification_failures_total Subscriptions refused by the human verification."
+    )
+    .unwrap();
+    writeln!(body, "# TYPE human_verification_failures_total counter").unwrap();
+    for (verifier, reason, count) in human_verification.failures() {
+        writeln!(
+            body,
+            "human_verification_failures_total{{verifier=\"{}\",reason=\"{}\"}} {}",
+            verifier, reason, count
+        )
+        .unwrap();
+    }
+
+    (
+        Headers([(header::CONTENT_TYPE, "text/plain; version=0.0.4")]),
+        body,
+    )
+}
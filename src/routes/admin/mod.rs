@@ -0,0 +1,64 @@
+pub mod dead_letters;
+pub mod log_filter;
+pub mod metrics;
+pub mod newsletters;
+pub mod suppressions;
+
+use async_trait::async_trait;
+use axum::{
+    extract::{Extension, FromRequest, RequestParts},
+    response::{IntoResponse, Response},
+};
+use http::{header, HeaderValue, StatusCode};
+
+use crate::secret::Secret;
+
+/// The token granting access to the administration endpoints.
+#[derive(Clone)]
+pub struct AdminToken(pub Secret);
+
+/// Extractor checking that the request carries the administration token as a bearer token.
+pub struct Admin;
+
+/// Rejection type for `Admin` if the request is not authenticated.
+#[derive(Debug, thiserror::Error)]
+#[error("missing or invalid administration token")]
+pub struct Unauthorized;
+
+impl IntoResponse for Unauthorized {
+    fn into_response(self) -> Response {
+        let mut response = StatusCode::UNAUTHORIZED.into_response();
+        response.headers_mut().insert(
+            header::WWW_AUTHENTICATE,
+            HeaderValue::from_static(r#"Bearer realm="admin""#),
+        );
+        response
+    }
+}
+
+#[async_trait]
+impl<B> FromRequest<B> for Admin
+where
+    B: Send,
+{
+    type Rejection = Unauthorized;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let Extension(AdminToken(expected)) = Extension::<AdminToken>::from_request(req)
+            .await
+            .map_err(|_| Unauthorized)?;
+
+        let provided = req
+            .headers()
+            .and_then(|headers| headers.get(header::AUTHORIZATION))
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or(Unauthorized)?;
+
+        if expected.matches(provided) {
+            Ok(Admin)
+        } else {
+            Err(Unauthorized)
+        }
+    }
+}
@@ -0,0 +1,148 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use axum::{
+    extract::{Extension, Path},
+    response::IntoResponse,
+    Json,
+};
+use http::StatusCode;
+use serde::Serialize;
+use uuid::Uuid;
+
+use super::Admin;
+use crate::{
+    issue_delivery::{self, DeliveryQueue, DeliveryReport, DeliverySummary},
+    mailer::Mailer,
+    repository::{DeliveryRepository, IssueRepository, TrackingRepository},
+    request_id::RequestId,
+    startup::ApplicationBaseUrl,
+    tracking::{IssueStats, TrackingKey},
+};
+
+#[tracing::instrument(name = "Get the statistics of an issue", skip(_admin, tracking))]
+pub async fn stats(
+    _admin: Admin,
+    Path(issue_id): Path<Uuid>,
+    Extension(tracking): Extension<Arc<dyn TrackingRepository>>,
+) -> Result<Json<IssueStats>, Error> {
+    let stats = tracking
+        .issue_stats(issue_id)
+        .await
+        .context("failed to fetch the statistics of the newsletter issue")?
+        .ok_or(Error::NotFound)?;
+    Ok(Json(stats))
+}
+
+#[tracing::instrument(name = "Get the deliveries of an issue", skip(_admin, deliveries))]
+pub async fn deliveries(
+    _admin: Admin,
+    Path(issue_id): Path<Uuid>,
+    Extension(deliveries): Extension<Arc<dyn DeliveryRepository>>,
+) -> Result<Json<DeliverySummary>, Error> {
+    let summary = deliveries
+        .summary(issue_id)
+        .await
+        .context("failed to summarise the deliveries of the issue")?
+        .ok_or(Error::NotFound)?;
+    Ok(Json(summary))
+}
+
+/// The deliveries queued again by `resend`.
+#[derive(Debug, Serialize)]
+pub struct Resent {
+    queued: u64,
+}
+
+/// Queue the issue again for the subscribers whose delivery failed, permanently or after running
+/// out of retries, it is sent in the background.
+#[tracing::instrument(
+    name = "Resend an issue",
+    skip(_admin, request_id, issues, deliveries, queue)
+)]
+pub async fn resend(
+    _admin: Admin,
+    request_id: RequestId,
+    Path(issue_id): Path<Uuid>,
+    Extension(issues): Extension<Arc<dyn IssueRepository>>,
+    Extension(deliveries): Extension<Arc<dyn DeliveryRepository>>,
+    Extension(queue): Extension<DeliveryQueue>,
+) -> Result<(StatusCode, Json<Resent>), Error> {
+    issues
+        .get(issue_id)
+        .await
+        .context("failed to fetch the newsletter issue")?
+        .ok_or(Error::NotFound)?;
+
+    let queued = deliveries
+        .queue_failures(issue_id)
+        .await
+        .context("failed to queue the failed deliveries")?;
+    queue.wake();
+
+    tracing::info!(
+        target: "audit",
+        request_id = %request_id,
+        action = "newsletter.resend",
+        %issue_id,
+        queued,
+        "Issue queued again for the failed deliveries"
+    );
+
+    Ok((StatusCode::ACCEPTED, Json(Resent { queued })))
+}
+
+/// Send their digest to the subscribers who chose one, without waiting for the next interval.
+#[tracing::instrument(
+    name = "Send the digests",
+    skip(_admin, request_id, issues, deliveries, mailer, tracking_key, base_url)
+)]
+pub async fn send_digests(
+    _admin: Admin,
+    request_id: RequestId,
+    Extension(issues): Extension<Arc<dyn IssueRepository>>,
+    Extension(deliveries): Extension<Arc<dyn DeliveryRepository>>,
+    Extension(mailer): Extension<Mailer>,
+    Extension(tracking_key): Extension<TrackingKey>,
+    Extension(base_url): Extension<ApplicationBaseUrl>,
+) -> Result<Json<DeliveryReport>, Error> {
+    let report = issue_delivery::deliver_digests(
+        deliveries.as_ref(),
+        issues.as_ref(),
+        &mailer,
+        &tracking_key,
+        base_url.as_str(),
+    )
+    .await
+    .context("failed to deliver the digests")?;
+
+    tracing::info!(
+        target: "audit",
+        request_id = %request_id,
+        action = "newsletter.digests",
+        sent = report.sent,
+        failed = report.failed,
+        "Digests sent"
+    );
+
+    Ok(Json(report))
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("the newsletter issue does not exist")]
+    NotFound,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> axum::response::Response {
+        match self {
+            Error::NotFound => (StatusCode::NOT_FOUND, self.to_string()).into_response(),
+            Error::UnexpectedError(source) => {
+                (StatusCode::INTERNAL_SERVER_ERROR, source.to_string()).into_response()
+            }
+        }
+    }
+}
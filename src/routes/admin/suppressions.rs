@@ -0,0 +1,147 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use axum::{
+    extract::{Extension, Path},
+    response::IntoResponse,
+    Json,
+};
+use chrono::{DateTime, Utc};
+use http::StatusCode;
+use serde::Deserialize;
+
+use super::Admin;
+use crate::{
+    domain::EmailAddress,
+    repository::SuppressionRepository,
+    request_id::RequestId,
+    suppression::{self, Suppression, SuppressionScope},
+    telemetry::Redacted,
+};
+
+#[tracing::instrument(name = "List the suppression list", skip(_admin, suppressions))]
+pub async fn list(
+    _admin: Admin,
+    Extension(suppressions): Extension<Arc<dyn SuppressionRepository>>,
+) -> Result<Json<Vec<Suppression>>, Error> {
+    let suppressions = suppressions
+        .list()
+        .await
+        .context("failed to list the suppressions")?;
+    Ok(Json(suppressions))
+}
+
+#[tracing::instrument(
+    name = "Add to the suppression list",
+    skip(_admin, request_id, body, suppressions)
+)]
+pub async fn add(
+    _admin: Admin,
+    request_id: RequestId,
+    Json(body): Json<BodyData>,
+    Extension(suppressions): Extension<Arc<dyn SuppressionRepository>>,
+) -> Result<(StatusCode, Json<Suppression>), Error> {
+    let value = normalise(body.scope, &body.value)?;
+    let suppression = suppressions
+        .add(body.scope, &value, &body.reason, body.expires_at)
+        .await
+        .context("failed to add the suppression")?;
+
+    tracing::info!(
+        target: "audit",
+        request_id = %request_id,
+        action = "suppression.add",
+        scope = body.scope.as_str(),
+        value = %Redacted::new(&value),
+        reason = %body.reason,
+        expires_at = ?body.expires_at,
+        "Suppression added"
+    );
+
+    Ok((StatusCode::CREATED, Json(suppression)))
+}
+
+#[tracing::instrument(
+    name = "Remove from the suppression list",
+    skip(_admin, request_id, value, suppressions)
+)]
+pub async fn remove(
+    _admin: Admin,
+    request_id: RequestId,
+    Path((scope, value)): Path<(String, String)>,
+    Extension(suppressions): Extension<Arc<dyn SuppressionRepository>>,
+) -> Result<StatusCode, Error> {
+    let scope: SuppressionScope = scope.parse().map_err(Error::InvalidValue)?;
+    let value = normalise(scope, &value)?;
+    let removed = suppressions
+        .remove(scope, &value)
+        .await
+        .context("failed to remove the suppression")?;
+    if !removed {
+        return Err(Error::NotFound);
+    }
+
+    tracing::info!(
+        target: "audit",
+        request_id = %request_id,
+        action = "suppression.remove",
+        scope = scope.as_str(),
+        value = %Redacted::new(&value),
+        "Suppression removed"
+    );
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BodyData {
+    scope: SuppressionScope,
+    /// The address or the domain.
+    value: String,
+    reason: String,
+    expires_at: Option<DateTime<Utc>>,
+}
+
+/// Check `value` and convert it to the key used by the suppression list.
+fn normalise(scope: SuppressionScope, value: &str) -> Result<String, Error> {
+    match scope {
+        SuppressionScope::Address => value
+            .trim()
+            .parse::<EmailAddress>()
+            .map(|email| suppression::normalise_address(&email))
+            .map_err(|e| Error::InvalidValue(e.to_string())),
+        SuppressionScope::Domain => {
+            let domain = suppression::normalise_domain(value);
+            if domain.is_empty() || domain.contains(|c: char| c == '@' || c.is_whitespace()) {
+                Err(Error::InvalidValue(format!(
+                    "`{}` is not a valid domain",
+                    value
+                )))
+            } else {
+                Ok(domain)
+            }
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("{0}")]
+    InvalidValue(String),
+    #[error("the suppression does not exist")]
+    NotFound,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> axum::response::Response {
+        match self {
+            Error::InvalidValue(_) => (StatusCode::BAD_REQUEST, self.to_string()).into_response(),
+            Error::NotFound => (StatusCode::NOT_FOUND, self.to_string()).into_response(),
+            Error::UnexpectedError(source) => {
+                (StatusCode::INTERNAL_SERVER_ERROR, source.to_string()).into_response()
+            }
+        }
+    }
+}
@@ -0,0 +1,100 @@
+use std::io;
+
+use axum::response::Html;
+
+use crate::configuration::HostedPagesSettings;
+
+/// The layout of the pages served to the subscribers, the subscription form and the preference
+/// centre. It is either built in or read from `subscriptions.hosted_pages.template`.
+#[derive(Clone)]
+pub struct HostedPages {
+    template: String,
+}
+
+impl HostedPages {
+    pub fn load(settings: &HostedPagesSettings) -> Result<Self, io::Error> {
+        let template = match &settings.template {
+            Some(path) => std::fs::read_to_string(path)?,
+            None => built_in_template(settings.stylesheet_url.as_deref()),
+        };
+        Ok(Self { template })
+    }
+
+    /// The page with its `title` and its `content`, the title is escaped and the content is
+    /// inserted as is.
+    pub fn render(&self, title: &str, content: &str) -> Html<String> {
+        Html(
+            self.template
+                .replace("{{title}}", &escape(title))
+                .replace("{{content}}", content),
+        )
+    }
+}
+
+fn built_in_template(stylesheet_url: Option<&str>) -> String {
+    let stylesheet = stylesheet_url
+        .map(|url| format!(r#"<link rel="stylesheet" href="{}">"#, escape(url)))
+        .unwrap_or_default();
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta charset="utf-8">
+    <meta name="viewport" content="width=device-width, initial-scale=1">
+    <title>{{{{title}}}}</title>
+    {}
+</head>
+<body>
+    <h1>{{{{title}}}}</h1>
+    {{{{content}}}}
+</body>
+</html>
+"#,
+        stylesheet
+    )
+}
+
+/// Escape the text inserted in HTML content or in a quoted attribute.
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{escape, HostedPages};
+    use crate::configuration::HostedPagesSettings;
+
+    #[test]
+    fn the_title_is_escaped_but_not_the_content() {
+        let pages = HostedPages::load(&HostedPagesSettings {
+            template: None,
+            stylesheet_url: Some("https://example.com/theme.css?a=1&b=2".into()),
+        })
+        .unwrap();
+
+        let html = pages.render("<Preferences>", "<p>Hi</p>").0;
+
+        assert!(html.contains("<title>&lt;Preferences&gt;</title>"));
+        assert!(html.contains("<p>Hi</p>"));
+        assert!(html.contains(r#"href="https://example.com/theme.css?a=1&amp;b=2""#));
+    }
+
+    #[test]
+    fn quotes_are_escaped() {
+        assert_eq!(
+            escape(r#"" onmouseover='x'"#),
+            "&quot; onmouseover=&#39;x&#39;"
+        );
+    }
+}
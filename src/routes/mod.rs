@@ -1,3 +1,9 @@
+pub mod admin;
 pub mod health_check;
+pub mod hosted_pages;
 pub mod newsletters;
+pub mod preferences;
+pub mod subscribe;
 pub mod subscriptions;
+pub mod tracking;
+pub mod webhooks;
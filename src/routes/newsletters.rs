@@ -1,53 +1,75 @@
+use std::sync::Arc;
+
 use anyhow::Context;
 use axum::{extract::Extension, response::IntoResponse, Json};
 use http::StatusCode;
-use serde::Deserialize;
-use sqlx::PgPool;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
 
-use crate::{domain::EmailAddress, email_client::EmailClient};
+use crate::{
+    issue_delivery::{DeliveryQueue, NewsletterIssue},
+    repository::{DeliveryRepository, IssueRepository},
+    routes::preferences::NewsletterTopics,
+    tracking::TrackingOptions,
+};
 
-// Dummy implementation
+/// Store the issue and queue it for the confirmed subscribers, it is sent in the background. The
+/// progress is reported by `GET /admin/newsletters/:issue_id/deliveries`.
+#[tracing::instrument(
+    name = "Publish a newsletter issue",
+    skip(body, issues, deliveries, queue, topics),
+    fields(title = %body.title)
+)]
 pub async fn handler(
     Json(body): Json<BodyData>,
-    Extension(pool): Extension<PgPool>,
-    Extension(email_client): Extension<EmailClient>,
-) -> Result<(), Error> {
-    let subscribers = get_confirmed_subscribers(&pool)
-        .await
-        .map_err(Error::UnexpectedError)?;
-
-    for subscriber in subscribers {
-        match subscriber {
-            Ok(subscriber) => {
-                email_client
-                    .send_email(
-                        &subscriber.email,
-                        &body.title,
-                        &body.content.html,
-                        &body.content.text,
-                    )
-                    .await
-                    .with_context(|| {
-                        format!("Failed to send newsletter issue to {}", subscriber.email)
-                    })
-                    .map_err(Error::UnexpectedError)?;
-            }
-            Err(error) => {
-                tracing::warn!(
-                error.cause_chain = ?error,
-                "Skipping a confirmed subscriber. Their stored contact details are invalid"
-                );
-            }
+    Extension(issues): Extension<Arc<dyn IssueRepository>>,
+    Extension(deliveries): Extension<Arc<dyn DeliveryRepository>>,
+    Extension(queue): Extension<DeliveryQueue>,
+    Extension(topics): Extension<NewsletterTopics>,
+) -> Result<(StatusCode, Json<Published>), Error> {
+    if let Some(topic) = &body.topic {
+        if !topics.0.iter().any(|t| t.id == *topic) {
+            return Err(Error::UnknownTopic(topic.clone()));
         }
     }
+    let issue = NewsletterIssue {
+        id: Uuid::new_v4(),
+        title: body.title,
+        html_content: body.content.html,
+        text_content: body.content.text,
+        tracking: body.tracking,
+        topic: body.topic,
+    };
+    issues
+        .add(&issue)
+        .await
+        .context("failed to store the newsletter issue")?;
+
+    let queued = deliveries
+        .queue_confirmed_subscribers(issue.id)
+        .await
+        .context("failed to queue the deliveries of the newsletter issue")?;
+    queue.wake();
 
-    Ok(())
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(Published {
+            issue_id: issue.id,
+            queued,
+        }),
+    ))
 }
 
 #[derive(Debug, Deserialize)]
 pub struct BodyData {
     title: String,
     content: Content,
+    /// Open and click tracking, both are disabled by default.
+    #[serde(default)]
+    tracking: TrackingOptions,
+    /// The id of a topic of `subscriptions.topics`, the issue is sent to everyone if it is unset.
+    #[serde(default)]
+    topic: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -56,8 +78,17 @@ pub struct Content {
     text: String,
 }
 
+#[derive(Debug, Serialize)]
+pub struct Published {
+    issue_id: Uuid,
+    /// The number of deliveries queued, the ones waiting for a digest included.
+    queued: u64,
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
+    #[error("`{0}` is not a topic of the newsletter")]
+    UnknownTopic(String),
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
 }
@@ -65,32 +96,12 @@ pub enum Error {
 impl IntoResponse for Error {
     fn into_response(self) -> axum::response::Response {
         match self {
+            Error::UnknownTopic(_) => {
+                (StatusCode::UNPROCESSABLE_ENTITY, self.to_string()).into_response()
+            }
             Error::UnexpectedError(source) => {
                 (StatusCode::INTERNAL_SERVER_ERROR, source.to_string()).into_response()
             }
         }
     }
 }
-
-struct ConfirmedSubscriber {
-    email: EmailAddress,
-}
-
-#[tracing::instrument(name = "Get confirmed subscribers", skip(pool))]
-async fn get_confirmed_subscribers(
-    pool: &PgPool,
-) -> Result<Vec<Result<ConfirmedSubscriber, anyhow::Error>>, anyhow::Error> {
-    let confirmed_subscribers =
-        sqlx::query!(r#"SELECT email FROM subscriptions WHERE status = 'confirmed'"#)
-            .fetch_all(pool)
-            .await?
-            .into_iter()
-            .map(|r| {
-                r.email
-                    .parse::<EmailAddress>()
-                    .map(|email| ConfirmedSubscriber { email })
-                    .map_err(|e| anyhow::anyhow!(e))
-            })
-            .collect();
-    Ok(confirmed_subscribers)
-}
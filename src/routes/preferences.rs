@@ -0,0 +1,271 @@
+use std::{collections::BTreeSet, sync::Arc};
+
+use anyhow::Context;
+use axum::{
+    extract::{Extension, Form, Path},
+    response::{IntoResponse, Response},
+};
+use http::StatusCode;
+use uuid::Uuid;
+
+use super::hosted_pages::{escape, HostedPages};
+use crate::{
+    configuration::Topic,
+    domain::{DeliveryFrequency, SubscriberName, SubscriptionStatus},
+    repository::{Preferences, Subscriber, SubscriberRepository},
+    subscription_policy::SubscriptionPolicy,
+    tracking::{TrackingKey, TrackingToken},
+};
+
+const TITLE: &str = "Your subscription";
+
+/// The topics the subscribers can choose, see `subscriptions.topics`.
+#[derive(Clone, Debug, Default)]
+pub struct NewsletterTopics(pub Vec<Topic>);
+
+/// Serve the preference centre of the subscriber the token was issued to.
+#[tracing::instrument(
+    name = "Serve the preference centre",
+    skip(token, key, subscribers, topics, pages)
+)]
+pub async fn show(
+    Path(token): Path<String>,
+    Extension(key): Extension<TrackingKey>,
+    Extension(subscribers): Extension<Arc<dyn SubscriberRepository>>,
+    Extension(topics): Extension<NewsletterTopics>,
+    Extension(pages): Extension<HostedPages>,
+) -> Result<Response, Error> {
+    let subscriber = match subscriber(&key, &token, &*subscribers).await? {
+        Some(subscriber) => subscriber,
+        None => return Ok(invalid_link(&pages)),
+    };
+    if is_closed(subscriber.status) {
+        return Ok(closed(&pages, StatusCode::OK));
+    }
+    let preferences = subscribers
+        .preferences(subscriber.id)
+        .await
+        .context("failed to retrieve the preferences of the subscriber")?
+        .unwrap_or_default();
+
+    let content = preferences_content(&topics, &subscriber.name, &preferences, None);
+    Ok(pages.render(TITLE, &content).into_response())
+}
+
+/// Save the preferences of the subscriber, or unsubscribe them if they pressed the
+/// `unsubscribe` button. The name is checked by the subscription policy, like at signup.
+#[tracing::instrument(
+    name = "Update the preferences of a subscriber",
+    skip(token, fields, key, subscribers, policy, topics, pages)
+)]
+pub async fn update(
+    Path(token): Path<String>,
+    Form(fields): Form<Vec<(String, String)>>,
+    Extension(key): Extension<TrackingKey>,
+    Extension(subscribers): Extension<Arc<dyn SubscriberRepository>>,
+    Extension(policy): Extension<SubscriptionPolicy>,
+    Extension(topics): Extension<NewsletterTopics>,
+    Extension(pages): Extension<HostedPages>,
+) -> Result<Response, Error> {
+    let subscriber = match subscriber(&key, &token, &*subscribers).await? {
+        Some(subscriber) => subscriber,
+        None => return Ok(invalid_link(&pages)),
+    };
+    if is_closed(subscriber.status) {
+        return Ok(closed(&pages, StatusCode::CONFLICT));
+    }
+
+    let field = |name: &str| {
+        fields
+            .iter()
+            .find(|(key, _)| key == name)
+            .map_or("", |(_, value)| value.as_str())
+    };
+    if field("action") == "unsubscribe" {
+        subscribers
+            .transition(subscriber.id, SubscriptionStatus::Unsubscribed)
+            .await
+            .context("failed to unsubscribe the subscriber")?;
+        tracing::info!(subscriber_id = %subscriber.id, "Subscriber unsubscribed");
+        return Ok(closed(&pages, StatusCode::OK));
+    }
+
+    // The topics that are not offered anymore are dropped
+    let preferences = Preferences {
+        topics: fields
+            .iter()
+            .filter(|(key, _)| key == "topic")
+            .filter(|(_, id)| topics.0.iter().any(|topic| topic.id == *id))
+            .map(|(_, id)| id.clone())
+            .collect::<BTreeSet<_>>(),
+        frequency: field("frequency").parse().unwrap_or_default(),
+    };
+    let name = field("name")
+        .parse::<SubscriberName>()
+        .map_err(|e| e.to_string())
+        .and_then(|name| {
+            policy.check_name(&name).map_err(|rejection| {
+                tracing::info!(
+                    reason = rejection.as_str(),
+                    "The name is rejected by the subscription policy"
+                );
+                rejection.to_string()
+            })?;
+            Ok(name)
+        });
+    let name = match name {
+        Ok(name) => name,
+        Err(e) => {
+            let content = preferences_content(&topics, field("name"), &preferences, Some(&e));
+            return Ok((
+                StatusCode::UNPROCESSABLE_ENTITY,
+                pages.render(TITLE, &content),
+            )
+                .into_response());
+        }
+    };
+    subscribers
+        .update_preferences(subscriber.id, &name, &preferences)
+        .await
+        .context("failed to update the preferences of the subscriber")?;
+
+    let content = format!(
+        "<p class=\"notice\">Your preferences are saved.</p>\n{}",
+        preferences_content(&topics, name.as_ref(), &preferences, None)
+    );
+    Ok(pages.render(TITLE, &content).into_response())
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        match self {
+            Error::UnexpectedError(source) => {
+                (StatusCode::INTERNAL_SERVER_ERROR, source.to_string()).into_response()
+            }
+        }
+    }
+}
+
+/// The subscriber the token was issued to, `None` if the token is invalid or if the subscriber
+/// does not exist anymore.
+async fn subscriber(
+    key: &TrackingKey,
+    token: &str,
+    subscribers: &dyn SubscriberRepository,
+) -> Result<Option<Subscriber>, Error> {
+    let subscriber_id: Uuid = match key.decode(token) {
+        Some(TrackingToken::Preferences { subscriber_id }) => subscriber_id,
+        _ => return Ok(None),
+    };
+    let subscriber = subscribers
+        .get(subscriber_id)
+        .await
+        .context("failed to retrieve the subscriber")?;
+    Ok(subscriber)
+}
+
+/// Whether nothing is sent to the subscriber anymore.
+fn is_closed(status: SubscriptionStatus) -> bool {
+    matches!(
+        status,
+        SubscriptionStatus::Suppressed | SubscriptionStatus::Unsubscribed
+    )
+}
+
+fn invalid_link(pages: &HostedPages) -> Response {
+    let content = "<p>This link is not valid.</p>";
+    (StatusCode::NOT_FOUND, pages.render(TITLE, content)).into_response()
+}
+
+fn closed(pages: &HostedPages, status: StatusCode) -> Response {
+    let content = "<p>You are unsubscribed, you will not receive the newsletter anymore.</p>";
+    (status, pages.render(TITLE, content)).into_response()
+}
+
+fn preferences_content(
+    topics: &NewsletterTopics,
+    name: &str,
+    preferences: &Preferences,
+    error: Option<&str>,
+) -> String {
+    let error = error
+        .map(|error| format!("<p class=\"error\">{}</p>\n", escape(error)))
+        .unwrap_or_default();
+    let topics = if topics.0.is_empty() {
+        String::new()
+    } else {
+        let checkboxes: String = topics
+            .0
+            .iter()
+            .map(|topic| {
+                let checked = if preferences.topics.contains(&topic.id) {
+                    " checked"
+                } else {
+                    ""
+                };
+                format!(
+                    r#"
+        <label><input type="checkbox" name="topic" value="{}"{}> {}</label>"#,
+                    escape(&topic.id),
+                    checked,
+                    escape(&topic.name)
+                )
+            })
+            .collect();
+        format!(
+            r#"
+    <fieldset>
+        <legend>Topics</legend>
+        <p>Leave them all unchecked to receive every issue.</p>{}
+    </fieldset>"#,
+            checkboxes
+        )
+    };
+    let frequencies: String = DeliveryFrequency::ALL
+        .iter()
+        .map(|frequency| {
+            let checked = if *frequency == preferences.frequency {
+                " checked"
+            } else {
+                ""
+            };
+            let label = match frequency {
+                DeliveryFrequency::Immediate => "Every issue when it is published",
+                DeliveryFrequency::Digest => "A digest of the issues",
+            };
+            format!(
+                r#"
+        <label><input type="radio" name="frequency" value="{}"{}> {}</label>"#,
+                frequency.as_str(),
+                checked,
+                label
+            )
+        })
+        .collect();
+
+    format!(
+        r#"{error}<form method="post">
+    <p>
+        <label for="name">Name</label>
+        <input id="name" name="name" value="{name}" required>
+    </p>{topics}
+    <fieldset>
+        <legend>Frequency</legend>{frequencies}
+    </fieldset>
+    <p><button type="submit" name="action" value="save">Save</button></p>
+</form>
+<form method="post">
+    <p><button type="submit" name="action" value="unsubscribe">Unsubscribe</button></p>
+</form>"#,
+        error = error,
+        name = escape(name),
+        topics = topics,
+        frequencies = frequencies,
+    )
+}
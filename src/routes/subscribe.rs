@@ -0,0 +1,236 @@
+//! The subscription form served to the browsers, it posts to itself rather than to
+//! `/subscriptions` so that the sites without a backend can link to it.
+//!
+//! The form is protected by a double-submit cookie: the CSRF token is set in a `SameSite=Strict`
+//! cookie and in a hidden field, and both must match. The bots filling every field are caught by
+//! a honeypot field hidden to the humans, they get the usual answer but nothing is stored.
+
+use std::sync::Arc;
+
+use axum::{
+    extract::{Extension, Form},
+    response::{IntoResponse, Response},
+};
+use http::{header, HeaderMap, HeaderValue, StatusCode};
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use serde::Deserialize;
+
+use super::{
+    hosted_pages::{escape, HostedPages},
+    subscriptions::{self, add_subscriber},
+};
+use crate::{
+    domain::{EmailAddress, NewSubscriber, SubscriberName},
+    domain_check::DomainChecker,
+    repository::SubscriberRepository,
+    secret::Secret,
+    startup::ApplicationBaseUrl,
+    subscription_policy::SubscriptionPolicy,
+};
+
+const CSRF_COOKIE: &str = "subscribe_csrf";
+/// Number of characters of a CSRF token.
+const CSRF_TOKEN_LENGTH: usize = 32;
+
+/// Serve the subscription form, the CSRF cookie is kept if the browser already has one.
+#[tracing::instrument(name = "Serve the subscription form", skip(pages, base_url, headers))]
+pub async fn form(
+    Extension(pages): Extension<HostedPages>,
+    Extension(base_url): Extension<ApplicationBaseUrl>,
+    headers: HeaderMap,
+) -> Response {
+    let csrf_token = csrf_cookie(&headers).unwrap_or_else(generate_csrf_token);
+    let mut response = pages
+        .render("Subscribe", &form_content(&csrf_token, "", "", None))
+        .into_response();
+    let secure = if base_url.as_str().starts_with("https://") {
+        "; Secure"
+    } else {
+        ""
+    };
+    let cookie = format!(
+        "{}={}; Path=/subscribe; HttpOnly; SameSite=Strict{}",
+        CSRF_COOKIE, csrf_token, secure
+    );
+    response.headers_mut().insert(
+        header::SET_COOKIE,
+        HeaderValue::from_str(&cookie).expect("The cookie is a valid header"),
+    );
+    response
+}
+
+#[tracing::instrument(
+    name = "Adding a new subscriber from the subscription form",
+    skip(data, subscribers, policy, domain_checker, base_url, pages, headers)
+)]
+pub async fn submit(
+    Form(data): Form<FormData>,
+    Extension(subscribers): Extension<Arc<dyn SubscriberRepository>>,
+    Extension(policy): Extension<SubscriptionPolicy>,
+    Extension(domain_checker): Extension<Option<DomainChecker>>,
+    Extension(base_url): Extension<ApplicationBaseUrl>,
+    Extension(pages): Extension<HostedPages>,
+    headers: HeaderMap,
+) -> Result<Response, subscriptions::Error> {
+    let csrf_token = match csrf_cookie(&headers) {
+        Some(cookie) if Secret::new(cookie.clone()).matches(&data.csrf_token) => cookie,
+        _ => {
+            tracing::info!("The subscription form has no valid CSRF token");
+            let content =
+                r#"<p>This form has expired. Please <a href="subscribe">try again</a>.</p>"#;
+            return Ok((StatusCode::FORBIDDEN, pages.render("Subscribe", content)).into_response());
+        }
+    };
+    if !data.website.is_empty() {
+        tracing::info!("The honeypot of the subscription form was filled in");
+        return Ok(check_your_inbox(&pages));
+    }
+
+    let subscriber = data
+        .email
+        .parse::<EmailAddress>()
+        .map_err(|e| e.to_string())
+        .and_then(|email| {
+            let name = data
+                .name
+                .parse::<SubscriberName>()
+                .map_err(|e| e.to_string())?;
+            Ok(NewSubscriber { email, name })
+        });
+    let result = match subscriber {
+        Ok(subscriber) => {
+            add_subscriber(
+                subscriber,
+                &*subscribers,
+                &policy,
+                domain_checker.as_ref(),
+                &base_url,
+            )
+            .await
+        }
+        Err(message) => {
+            let content = form_content(&csrf_token, &data.name, &data.email, Some(&message));
+            return Ok((
+                StatusCode::UNPROCESSABLE_ENTITY,
+                pages.render("Subscribe", &content),
+            )
+                .into_response());
+        }
+    };
+    match result {
+        Ok(()) => Ok(check_your_inbox(&pages)),
+        Err(e @ subscriptions::Error::Rejected(_))
+        | Err(e @ subscriptions::Error::AlreadySubscribed) => {
+            let status = match e {
+                subscriptions::Error::AlreadySubscribed => StatusCode::CONFLICT,
+                _ => StatusCode::UNPROCESSABLE_ENTITY,
+            };
+            let message = e.to_string();
+            let content = form_content(&csrf_token, &data.name, &data.email, Some(&message));
+            Ok((status, pages.render("Subscribe", &content)).into_response())
+        }
+        Err(e) => Err(e),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FormData {
+    #[serde(default)]
+    email: String,
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    csrf_token: String,
+    /// The honeypot, it is hidden to the humans.
+    #[serde(default)]
+    website: String,
+}
+
+fn check_your_inbox(pages: &HostedPages) -> Response {
+    let content = "<p>Thank you! Please follow the link we sent you by email to confirm your subscription.</p>";
+    pages.render("Check your inbox", content).into_response()
+}
+
+fn form_content(csrf_token: &str, name: &str, email: &str, error: Option<&str>) -> String {
+    let error = error
+        .map(|error| format!(r#"<p class="error">{}</p>"#, escape(error)))
+        .unwrap_or_default();
+    format!(
+        r#"{error}
+<form method="post">
+    <input type="hidden" name="csrf_token" value="{csrf_token}">
+    <p>
+        <label for="name">Name</label>
+        <input id="name" name="name" value="{name}" required>
+    </p>
+    <p>
+        <label for="email">Email</label>
+        <input id="email" name="email" type="email" value="{email}" required>
+    </p>
+    <p style="position: absolute; left: -10000px;" aria-hidden="true">
+        <label for="website">Leave this field empty</label>
+        <input id="website" name="website" tabindex="-1" autocomplete="off">
+    </p>
+    <p><button type="submit">Subscribe</button></p>
+</form>"#,
+        error = error,
+        csrf_token = escape(csrf_token),
+        name = escape(name),
+        email = escape(email),
+    )
+}
+
+fn generate_csrf_token() -> String {
+    let mut rng = thread_rng();
+    std::iter::repeat_with(|| rng.sample(Alphanumeric))
+        .map(char::from)
+        .take(CSRF_TOKEN_LENGTH)
+        .collect()
+}
+
+/// The CSRF token of the `Cookie` headers, the malformed ones are ignored.
+fn csrf_cookie(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .find(|(name, _)| *name == CSRF_COOKIE)
+        .map(|(_, value)| value.to_string())
+        .filter(|value| {
+            value.len() == CSRF_TOKEN_LENGTH && value.chars().all(|c| c.is_ascii_alphanumeric())
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use http::{header, HeaderMap, HeaderValue};
+
+    use super::{csrf_cookie, generate_csrf_token};
+
+    #[test]
+    fn the_csrf_token_is_read_among_the_cookies() {
+        let token = generate_csrf_token();
+        let mut headers = HeaderMap::new();
+        headers.append(header::COOKIE, HeaderValue::from_static("theme=dark"));
+        headers.append(
+            header::COOKIE,
+            HeaderValue::from_str(&format!("a=b; subscribe_csrf={}", token)).unwrap(),
+        );
+
+        assert_eq!(csrf_cookie(&headers), Some(token));
+    }
+
+    #[test]
+    fn a_malformed_csrf_token_is_ignored() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::COOKIE,
+            HeaderValue::from_static("subscribe_csrf=\"><script>"),
+        );
+
+        assert_eq!(csrf_cookie(&headers), None);
+        assert_eq!(csrf_cookie(&HeaderMap::new()), None);
+    }
+}
@@ -1,29 +1,109 @@
+use std::sync::Arc;
+
 use anyhow::Context;
 use axum::{
     extract::{Extension, Query},
-    response::IntoResponse,
+    response::{IntoResponse, Response},
 };
-use http::StatusCode;
+use chrono::{Duration, Utc};
+use http::{HeaderMap, StatusCode};
 use serde::Deserialize;
-use sqlx::PgPool;
-use uuid::Uuid;
 
+use super::confirmation_pages::{ConfirmationPages, PageKind};
+use crate::{
+    domain::{SubscriptionStatus, SubscriptionToken},
+    repository::{SubscriberRepository, TokenRepository},
+};
+
+/// How long a confirmation link stays valid, `None` if it does not expire.
+#[derive(Clone, Copy, Debug)]
+pub struct ConfirmationLinkTtl(pub Option<Duration>);
+
+#[tracing::instrument(
+    name = "Confirm a pending subscriber",
+    skip(parameters, tokens, subscribers, pages, ttl, headers)
+)]
 pub async fn handler(
     Query(parameters): Query<Parameters>,
-    Extension(pool): Extension<PgPool>,
-) -> Result<(), Error> {
-    let subscriber_id = get_subscriber_id_from_token(&pool, &parameters.token)
-        .await
-        .context("failed to retrieve the subscriber id associated with the provided token")
-        .map_err(Error::UnexpectedError)?
-        .ok_or(Error::UnknownToken)?;
+    Extension(tokens): Extension<Arc<dyn TokenRepository>>,
+    Extension(subscribers): Extension<Arc<dyn SubscriberRepository>>,
+    Extension(pages): Extension<ConfirmationPages>,
+    Extension(ttl): Extension<ConfirmationLinkTtl>,
+    headers: HeaderMap,
+) -> Result<Response, Error> {
+    let outcome = confirm(&parameters.token, &*tokens, &*subscribers, ttl).await?;
+    let (kind, status) = match outcome {
+        Outcome::Confirmed => (PageKind::Confirmed, StatusCode::OK),
+        Outcome::AlreadyConfirmed => (PageKind::AlreadyConfirmed, StatusCode::OK),
+        Outcome::Expired => (PageKind::Expired, StatusCode::GONE),
+        Outcome::MalformedToken => (PageKind::Invalid, StatusCode::BAD_REQUEST),
+        Outcome::UnknownToken => (PageKind::Invalid, StatusCode::UNAUTHORIZED),
+        // A suppressed or unsubscribed subscriber stays so even if the link is visited
+        Outcome::Closed => (PageKind::Invalid, StatusCode::GONE),
+    };
+    Ok(pages.respond(kind, status, &headers))
+}
 
-    confirm_subscriber(&pool, subscriber_id)
+/// What following a confirmation link did.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Outcome {
+    Confirmed,
+    AlreadyConfirmed,
+    Expired,
+    MalformedToken,
+    UnknownToken,
+    /// The subscriber was suppressed or unsubscribed.
+    Closed,
+}
+
+async fn confirm(
+    token: &str,
+    tokens: &dyn TokenRepository,
+    subscribers: &dyn SubscriberRepository,
+    ConfirmationLinkTtl(ttl): ConfirmationLinkTtl,
+) -> Result<Outcome, Error> {
+    let token: SubscriptionToken = match token.parse() {
+        Ok(token) => token,
+        Err(_) => return Ok(Outcome::MalformedToken),
+    };
+    let subscriber_id = match tokens
+        .subscriber_id(&token)
+        .await
+        .context("failed to retrieve the subscriber id associated with the provided token")?
+    {
+        Some(subscriber_id) => subscriber_id,
+        None => return Ok(Outcome::UnknownToken),
+    };
+    let subscriber = match subscribers
+        .get(subscriber_id)
         .await
-        .context("failed to update the subscriber status to `confirmed`")
-        .map_err(Error::UnexpectedError)?;
+        .context("failed to retrieve the subscriber associated with the provided token")?
+    {
+        Some(subscriber) => subscriber,
+        None => return Ok(Outcome::UnknownToken),
+    };
 
-    Ok(())
+    match subscriber.status {
+        SubscriptionStatus::PendingConfirmation => {}
+        SubscriptionStatus::Confirmed => return Ok(Outcome::AlreadyConfirmed),
+        SubscriptionStatus::Suppressed | SubscriptionStatus::Unsubscribed => {
+            return Ok(Outcome::Closed)
+        }
+    }
+    if ttl.is_some_and(|ttl| subscriber.subscribed_at + ttl < Utc::now()) {
+        return Ok(Outcome::Expired);
+    }
+
+    let confirmed = subscribers
+        .transition(subscriber_id, SubscriptionStatus::Confirmed)
+        .await
+        .context("failed to update the subscriber status to `confirmed`")?;
+    // The subscriber may have been confirmed, suppressed or unsubscribed meanwhile
+    if confirmed {
+        Ok(Outcome::Confirmed)
+    } else {
+        Ok(Outcome::Closed)
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -35,42 +115,134 @@ pub struct Parameters {
 pub enum Error {
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
-    #[error("there is no subscriber associated with the provided token")]
-    UnknownToken,
 }
 
 impl IntoResponse for Error {
-    fn into_response(self) -> axum::response::Response {
+    fn into_response(self) -> Response {
         match self {
             Error::UnexpectedError(source) => {
                 (StatusCode::INTERNAL_SERVER_ERROR, source.to_string()).into_response()
             }
-            Error::UnknownToken => StatusCode::UNAUTHORIZED.into_response(),
         }
     }
 }
 
-#[tracing::instrument(name = "Get subscriber_id from token", skip(pool, token))]
-async fn get_subscriber_id_from_token(
-    pool: &PgPool,
-    token: &str,
-) -> Result<Option<Uuid>, sqlx::Error> {
-    let result = sqlx::query!(
-        "SELECT subscriber_id FROM subscription_tokens WHERE subscription_token = $1",
-        token
-    )
-    .fetch_optional(pool)
-    .await?;
-    Ok(result.map(|r| r.subscriber_id))
-}
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use uuid::Uuid;
+
+    use super::{confirm, ConfirmationLinkTtl, Outcome};
+    use crate::{
+        domain::{NewSubscriber, SubscriptionStatus, SubscriptionToken},
+        email_client::OutgoingEmail,
+        repository::{InMemoryRepository, SubscriberRepository},
+    };
+
+    async fn add_pending(repository: &InMemoryRepository, token: &SubscriptionToken) -> Uuid {
+        let subscriber = NewSubscriber {
+            email: "ursula@example.com".parse().unwrap(),
+            name: "le guin".parse().unwrap(),
+        };
+        let confirmation = OutgoingEmail {
+            recipient: &subscriber.email,
+            subject: "Welcome!",
+            html_content: "",
+            text_content: "",
+        };
+        repository
+            .add_pending(&subscriber, token, &confirmation)
+            .await
+            .unwrap()
+    }
 
-#[tracing::instrument(name = "Mark subscriber as confirmed", skip(pool, subscriber_id))]
-async fn confirm_subscriber(pool: &PgPool, subscriber_id: Uuid) -> Result<(), sqlx::Error> {
-    sqlx::query!(
-        "UPDATE subscriptions SET status = 'confirmed' WHERE id = $1",
-        subscriber_id
-    )
-    .execute(pool)
-    .await?;
-    Ok(())
+    async fn follow(
+        repository: &InMemoryRepository,
+        token: &str,
+        ttl: Option<Duration>,
+    ) -> Outcome {
+        confirm(token, repository, repository, ConfirmationLinkTtl(ttl))
+            .await
+            .unwrap()
+    }
+
+    async fn status(repository: &InMemoryRepository, id: Uuid) -> SubscriptionStatus {
+        SubscriberRepository::get(repository, id)
+            .await
+            .unwrap()
+            .unwrap()
+            .status
+    }
+
+    #[tokio::test]
+    async fn a_valid_token_confirms_its_subscriber() {
+        let repository = InMemoryRepository::new();
+        let token = SubscriptionToken::generate();
+        let id = add_pending(&repository, &token).await;
+
+        let outcome = follow(&repository, token.as_ref(), Some(Duration::hours(1))).await;
+
+        assert_eq!(outcome, Outcome::Confirmed);
+        assert_eq!(status(&repository, id).await, SubscriptionStatus::Confirmed);
+    }
+
+    #[tokio::test]
+    async fn a_link_followed_twice_is_already_confirmed() {
+        let repository = InMemoryRepository::new();
+        let token = SubscriptionToken::generate();
+        add_pending(&repository, &token).await;
+
+        follow(&repository, token.as_ref(), None).await;
+        let outcome = follow(&repository, token.as_ref(), None).await;
+
+        assert_eq!(outcome, Outcome::AlreadyConfirmed);
+    }
+
+    #[tokio::test]
+    async fn an_expired_link_does_not_confirm() {
+        let repository = InMemoryRepository::new();
+        let token = SubscriptionToken::generate();
+        let id = add_pending(&repository, &token).await;
+
+        let outcome = follow(&repository, token.as_ref(), Some(Duration::hours(-1))).await;
+
+        assert_eq!(outcome, Outcome::Expired);
+        assert_eq!(
+            status(&repository, id).await,
+            SubscriptionStatus::PendingConfirmation
+        );
+    }
+
+    #[tokio::test]
+    async fn a_suppressed_subscriber_stays_suppressed() {
+        let repository = InMemoryRepository::new();
+        let token = SubscriptionToken::generate();
+        let id = add_pending(&repository, &token).await;
+        repository
+            .transition(id, SubscriptionStatus::Suppressed)
+            .await
+            .unwrap();
+
+        let outcome = follow(&repository, token.as_ref(), None).await;
+
+        assert_eq!(outcome, Outcome::Closed);
+        assert_eq!(
+            status(&repository, id).await,
+            SubscriptionStatus::Suppressed
+        );
+    }
+
+    #[tokio::test]
+    async fn a_malformed_or_unknown_token_is_rejected() {
+        let repository = InMemoryRepository::new();
+
+        assert_eq!(
+            follow(&repository, "not-a-token", None).await,
+            Outcome::MalformedToken
+        );
+        assert_eq!(
+            follow(&repository, SubscriptionToken::generate().as_ref(), None).await,
+            Outcome::UnknownToken
+        );
+    }
 }
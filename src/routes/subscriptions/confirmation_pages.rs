@@ -0,0 +1,227 @@
+use std::io;
+
+use axum::{
+    response::{Html, IntoResponse, Redirect, Response},
+    Json,
+};
+use http::{header, HeaderMap, HeaderValue, StatusCode, Uri};
+use serde::Serialize;
+
+use crate::configuration::{ConfirmationPagesSettings, PageSettings};
+
+/// The pages a confirmation link can lead to.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PageKind {
+    Confirmed,
+    AlreadyConfirmed,
+    Expired,
+    Invalid,
+}
+
+impl PageKind {
+    fn title(self) -> &'static str {
+        match self {
+            PageKind::Confirmed => "Subscription confirmed",
+            PageKind::AlreadyConfirmed => "Subscription already confirmed",
+            PageKind::Expired => "Confirmation link expired",
+            PageKind::Invalid => "Invalid confirmation link",
+        }
+    }
+
+    pub fn message(self) -> &'static str {
+        match self {
+            PageKind::Confirmed => "Thank you, your subscription to the newsletter is confirmed.",
+            PageKind::AlreadyConfirmed => {
+                "Your subscription to the newsletter was already confirmed."
+            }
+            PageKind::Expired => {
+                "This confirmation link has expired, please subscribe again to get a new one."
+            }
+            PageKind::Invalid => "This confirmation link is not valid.",
+        }
+    }
+
+    /// The link of the built-in page, relative to `/subscriptions/confirm`.
+    fn link(self) -> Option<(&'static str, &'static str)> {
+        match self {
+            PageKind::Expired => Some(("../subscribe", "Subscribe again")),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone)]
+enum Page {
+    Html(String),
+    Redirect(Uri),
+}
+
+/// The pages shown after following a confirmation link, the browsers get the configured page and
+/// the clients preferring JSON get `{"status": ..., "message": ...}`.
+#[derive(Clone)]
+pub struct ConfirmationPages {
+    confirmed: Page,
+    already_confirmed: Page,
+    expired: Page,
+    invalid: Page,
+}
+
+#[derive(Serialize)]
+struct Body {
+    status: PageKind,
+    message: &'static str,
+}
+
+impl ConfirmationPages {
+    /// Read the templates of the pages, the other pages are built in.
+    pub fn load(settings: &ConfirmationPagesSettings) -> Result<Self, io::Error> {
+        Ok(Self {
+            confirmed: load_page(&settings.confirmed, PageKind::Confirmed)?,
+            already_confirmed: load_page(&settings.already_confirmed, PageKind::AlreadyConfirmed)?,
+            expired: load_page(&settings.expired, PageKind::Expired)?,
+            invalid: load_page(&settings.invalid, PageKind::Invalid)?,
+        })
+    }
+
+    /// The page of `kind` in the format preferred by the client, a redirection is always a 303.
+    pub fn respond(&self, kind: PageKind, status: StatusCode, headers: &HeaderMap) -> Response {
+        let page = match kind {
+            PageKind::Confirmed => &self.confirmed,
+            PageKind::AlreadyConfirmed => &self.already_confirmed,
+            PageKind::Expired => &self.expired,
+            PageKind::Invalid => &self.invalid,
+        };
+        let mut response = if prefers_json(headers) {
+            let body = Body {
+                status: kind,
+                message: kind.message(),
+            };
+            (status, Json(body)).into_response()
+        } else {
+            match page {
+                Page::Html(html) => (status, Html(html.clone())).into_response(),
+                Page::Redirect(uri) => Redirect::to(uri.clone()).into_response(),
+            }
+        };
+        response
+            .headers_mut()
+            .insert(header::VARY, HeaderValue::from_static("accept"));
+        response
+    }
+}
+
+fn load_page(settings: &PageSettings, kind: PageKind) -> Result<Page, io::Error> {
+    if let Some(url) = &settings.redirect_url {
+        let uri = url
+            .parse()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        return Ok(Page::Redirect(uri));
+    }
+    let html = match &settings.template {
+        Some(path) => std::fs::read_to_string(path)?,
+        None => built_in_page(kind),
+    };
+    Ok(Page::Html(html))
+}
+
+fn built_in_page(kind: PageKind) -> String {
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+    <meta charset="utf-8">
+    <title>{title}</title>
+</head>
+<body>
+    <h1>{title}</h1>
+    <p>{message}</p>{link}
+</body>
+</html>
+"#,
+        title = kind.title(),
+        message = kind.message(),
+        link = kind
+            .link()
+            .map(|(href, text)| format!("\n    <p><a href=\"{}\">{}</a></p>", href, text))
+            .unwrap_or_default()
+    )
+}
+
+/// Whether the `Accept` header ranks JSON above HTML, HTML is served when they are equal or
+/// without header.
+fn prefers_json(headers: &HeaderMap) -> bool {
+    match headers
+        .get(header::ACCEPT)
+        .and_then(|value| value.to_str().ok())
+    {
+        Some(accept) => quality(accept, "application/json") > quality(accept, "text/html"),
+        None => false,
+    }
+}
+
+/// The quality of `media_type` in an `Accept` header, given by its most specific range.
+fn quality(accept: &str, media_type: &str) -> f32 {
+    let wildcard = format!("{}/*", media_type.split('/').next().unwrap_or_default());
+    let mut best: Option<(u8, f32)> = None;
+    for range in accept.split(',') {
+        let mut parameters = range.split(';');
+        let range = parameters
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+        let specificity = if range == media_type {
+            2
+        } else if range == wildcard {
+            1
+        } else if range == "*/*" {
+            0
+        } else {
+            continue;
+        };
+        let q = parameters
+            .filter_map(|parameter| parameter.trim().strip_prefix("q="))
+            .find_map(|q| q.parse().ok())
+            .unwrap_or(1.0);
+        if best.is_none_or(|(best, _)| specificity > best) {
+            best = Some((specificity, q));
+        }
+    }
+    best.map_or(0.0, |(_, q)| q)
+}
+
+#[cfg(test)]
+mod tests {
+    use http::{header, HeaderMap, HeaderValue};
+
+    use super::prefers_json;
+
+    fn accepting(accept: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ACCEPT, HeaderValue::from_static(accept));
+        headers
+    }
+
+    #[test]
+    fn browsers_get_html() {
+        assert!(!prefers_json(&HeaderMap::new()));
+        assert!(!prefers_json(&accepting("*/*")));
+        assert!(!prefers_json(&accepting(
+            "text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8"
+        )));
+        assert!(!prefers_json(&accepting(
+            "application/json;q=0.5, text/html"
+        )));
+    }
+
+    #[test]
+    fn api_clients_get_json() {
+        assert!(prefers_json(&accepting("application/json")));
+        assert!(prefers_json(&accepting(
+            "application/json, text/html;q=0.9"
+        )));
+        assert!(prefers_json(&accepting("application/*, text/*;q=0.1")));
+        assert!(prefers_json(&accepting("text/html;q=0.5, */*")));
+    }
+}
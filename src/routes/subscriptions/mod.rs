@@ -1,68 +1,159 @@
 pub mod confirm;
+pub mod confirmation_pages;
+
+use std::sync::Arc;
 
-use anyhow::Context;
 use axum::{
     extract::{Extension, Form},
-    response::IntoResponse,
+    response::{Headers, IntoResponse},
+    Json,
 };
-use chrono::Utc;
-use http::StatusCode;
-use rand::{distributions::Alphanumeric, thread_rng, Rng};
-use serde::Deserialize;
-use sqlx::{PgPool, Postgres, Transaction};
-use uuid::Uuid;
+use http::{header, StatusCode};
+use serde::{Deserialize, Serialize};
 
 use crate::{
-    domain::{EmailAddress, SubscriberName},
-    email_client::EmailClient,
+    domain::{EmailAddress, NewSubscriber, SubscriberName, SubscriptionToken},
+    domain_check::DomainChecker,
+    email_client::OutgoingEmail,
+    human_verification::{self, HumanVerification, Submission, VerificationFailure},
+    repository::{RepositoryError, SubscriberRepository},
     startup::ApplicationBaseUrl,
+    subscription_policy::{Rejection, SubscriptionPolicy},
+    tracking::TrackingKey,
 };
 
+#[tracing::instrument(
+    name = "Adding a new subscriber",
+    skip(data, subscribers, policy, domain_checker, verification, base_url),
+    fields(
+        subscriber_email = %data.email.redacted(),
+        subscriber_name = %data.name.redacted()
+    )
+)]
 pub async fn handler(
     Form(data): Form<FormData>,
-    Extension(pool): Extension<PgPool>,
-    Extension(email_client): Extension<EmailClient>,
+    Extension(subscribers): Extension<Arc<dyn SubscriberRepository>>,
+    Extension(policy): Extension<SubscriptionPolicy>,
+    Extension(domain_checker): Extension<Option<DomainChecker>>,
+    Extension(verification): Extension<HumanVerification>,
     Extension(base_url): Extension<ApplicationBaseUrl>,
 ) -> Result<(), Error> {
-    let mut transaction = pool
-        .begin()
-        .await
-        .context("failed to acquire a Postgres connection from the pool")
-        .map_err(Error::from)?;
-    let subscriber_id = insert_subscriber(&mut transaction, &data)
-        .await
-        .context("failed to insert new subscriber in the database")
-        .map_err(Error::from)?;
-    let subscription_token = generate_subscription_token();
-    store_token(&mut transaction, subscriber_id, &subscription_token)
-        .await
-        .context("failed to store the confirmation token for a new subscriber")
-        .map_err(Error::from)?;
-    transaction
-        .commit()
-        .await
-        .context("failed to commit SQL transaction to store a new subscriber")
-        .map_err(Error::from)?;
-    send_confirmation_email(
-        &email_client,
-        &data.email,
-        base_url.as_str(),
-        &subscription_token,
+    verification
+        .verify(&Submission {
+            honeypot: data.website.as_deref(),
+            form_token: data.form_token.as_deref(),
+            captcha_response: data.captcha_response.as_deref(),
+        })
+        .await?;
+    let subscriber = NewSubscriber {
+        email: data.email,
+        name: data.name,
+    };
+    add_subscriber(
+        subscriber,
+        &*subscribers,
+        &policy,
+        domain_checker.as_ref(),
+        &base_url,
     )
     .await
-    .context("failed to send a confirmation email")
-    .map_err(Error::from)?;
-    Ok(())
+}
+
+/// Check the new subscriber against the subscription policy and the domain of its address, then
+/// store it with its confirmation email.
+pub async fn add_subscriber(
+    subscriber: NewSubscriber,
+    subscribers: &dyn SubscriberRepository,
+    policy: &SubscriptionPolicy,
+    domain_checker: Option<&DomainChecker>,
+    base_url: &ApplicationBaseUrl,
+) -> Result<(), Error> {
+    if let Err(rejection) = policy
+        .check(&subscriber.email)
+        .and_then(|_| policy.check_name(&subscriber.name))
+    {
+        tracing::info!(
+            reason = rejection.as_str(),
+            "The address is rejected by the subscription policy"
+        );
+        return Err(rejection.into());
+    }
+    if let Some(domain_checker) = domain_checker {
+        if let Err(rejection) = domain_checker.check(&subscriber.email, policy).await {
+            tracing::info!(
+                reason = rejection.as_str(),
+                "The domain of the address cannot receive emails"
+            );
+            return Err(rejection.into());
+        }
+    }
+    let subscription_token = SubscriptionToken::generate();
+    let (html_content, text_content) =
+        confirmation_email_content(base_url.as_str(), &subscription_token);
+    let confirmation = OutgoingEmail {
+        recipient: &subscriber.email,
+        subject: "Welcome!",
+        html_content: &html_content,
+        text_content: &text_content,
+    };
+    match subscribers
+        .add_pending(&subscriber, &subscription_token, &confirmation)
+        .await
+    {
+        Ok(_) => Ok(()),
+        Err(RepositoryError::Duplicate) => {
+            tracing::info!("The canonical address is already subscribed and not pending");
+            Err(Error::AlreadySubscribed)
+        }
+        Err(e) => Err(anyhow::Error::new(e)
+            .context("failed to store the new subscriber and queue its confirmation email")
+            .into()),
+    }
+}
+
+/// Issue the token the forms posting to `/subscriptions` send back in `form_token`, it tells
+/// when the form was served.
+pub async fn form_token(
+    Extension(key): Extension<TrackingKey>,
+) -> (
+    Headers<[(header::HeaderName, &'static str); 1]>,
+    Json<FormToken>,
+) {
+    (
+        Headers([(header::CACHE_CONTROL, "no-store")]),
+        Json(FormToken {
+            form_token: human_verification::form_token(&key),
+        }),
+    )
+}
+
+#[derive(Serialize)]
+pub struct FormToken {
+    form_token: String,
 }
 
 #[derive(Debug, Deserialize)]
 pub struct FormData {
     email: EmailAddress,
     name: SubscriberName,
+    /// The honeypot, it is hidden to the humans.
+    website: Option<String>,
+    form_token: Option<String>,
+    #[serde(alias = "h-captcha-response", alias = "cf-turnstile-response")]
+    captcha_response: Option<String>,
 }
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
+    #[error(transparent)]
+    Rejected(#[from] Rejection),
+    /// Another form of the address is subscribed and confirmed, suppressed or unsubscribed, see
+    /// `EmailAddress::canonical`.
+    #[error("this email address is already subscribed, please subscribe with another address")]
+    AlreadySubscribed,
+    /// The reason is logged but not given to the client.
+    #[error("the submission could not be verified as coming from a human")]
+    NotVerified(#[from] VerificationFailure),
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
 }
@@ -70,6 +161,14 @@ pub enum Error {
 impl IntoResponse for Error {
     fn into_response(self) -> axum::response::Response {
         match self {
+            Error::Rejected(_) => {
+                (StatusCode::UNPROCESSABLE_ENTITY, self.to_string()).into_response()
+            }
+            Error::AlreadySubscribed => (StatusCode::CONFLICT, self.to_string()).into_response(),
+            Error::NotVerified(VerificationFailure::ProviderUnavailable(_)) => {
+                (StatusCode::SERVICE_UNAVAILABLE, self.to_string()).into_response()
+            }
+            Error::NotVerified(_) => (StatusCode::FORBIDDEN, self.to_string()).into_response(),
             Error::UnexpectedError(source) => {
                 (StatusCode::INTERNAL_SERVER_ERROR, source.to_string()).into_response()
             }
@@ -77,58 +176,8 @@ impl IntoResponse for Error {
     }
 }
 
-#[tracing::instrument(
-    name = "Saving new subscriber details in the database",
-    skip(transaction, new_subscriber)
-)]
-async fn insert_subscriber(
-    transaction: &mut Transaction<'_, Postgres>,
-    new_subscriber: &FormData,
-) -> Result<Uuid, sqlx::Error> {
-    let subscriber_id = Uuid::new_v4();
-
-    sqlx::query!(
-        r#"INSERT INTO subscriptions (id, email, name, subscribed_at, status)
-            VALUES ($1, $2, $3, $4, 'pending_confirmation')"#,
-        subscriber_id,
-        new_subscriber.email.as_ref(),
-        new_subscriber.name.as_ref(),
-        Utc::now(),
-    )
-    .execute(transaction)
-    .await?;
-    Ok(subscriber_id)
-}
-
-#[tracing::instrument(
-    name = "Store subscription token in the database",
-    skip(transaction, subscription_token)
-)]
-async fn store_token(
-    transaction: &mut Transaction<'_, Postgres>,
-    subscriber_id: Uuid,
-    subscription_token: &str,
-) -> Result<(), sqlx::Error> {
-    sqlx::query!(
-        "INSERT INTO subscription_tokens(subscription_token, subscriber_id) VALUES($1, $2)",
-        subscription_token,
-        subscriber_id
-    )
-    .execute(transaction)
-    .await?;
-    Ok(())
-}
-
-#[tracing::instrument(
-    name = "Send a confirmation email to a new subscriber",
-    skip(email_client, address, base_url, token)
-)]
-async fn send_confirmation_email(
-    email_client: &EmailClient,
-    address: &EmailAddress,
-    base_url: &str,
-    token: &str,
-) -> Result<(), reqwest::Error> {
+/// The HTML and text content of the confirmation email.
+fn confirmation_email_content(base_url: &str, token: &SubscriptionToken) -> (String, String) {
     let confirmation_link = format!("{}/subscriptions/confirm?token={}", base_url, token);
 
     let html_body = format!(
@@ -140,17 +189,117 @@ async fn send_confirmation_email(
         "Welcome to our newsletter!\nVisit {} to confirm your subscription.",
         confirmation_link
     );
+    (html_body, text_body)
+}
 
-    email_client
-        .send_email(address, "Welcome!", &html_body, &text_body)
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use axum::extract::{Extension, Form};
+    use claim::assert_ok;
+    use uuid::Uuid;
+
+    use super::{handler, Error, FormData};
+    use crate::{
+        domain::SubscriptionStatus,
+        human_verification::HumanVerification,
+        repository::{InMemoryRepository, SubscriberRepository, TokenRepository},
+        startup::ApplicationBaseUrl,
+        subscription_policy::{PolicyRules, SubscriptionPolicy},
+    };
+
+    async fn subscribe(repository: &InMemoryRepository, email: &str) -> Result<(), Error> {
+        let data = FormData {
+            email: email.parse().unwrap(),
+            name: "le guin".parse().unwrap(),
+            website: None,
+            form_token: None,
+            captcha_response: None,
+        };
+        let policy = SubscriptionPolicy::new(PolicyRules {
+            reject_role_addresses: true,
+            ..PolicyRules::default()
+        });
+        handler(
+            Form(data),
+            Extension(Arc::new(repository.clone())),
+            Extension(policy),
+            Extension(None),
+            Extension(HumanVerification::default()),
+            Extension(ApplicationBaseUrl("https://example.com".into())),
+        )
         .await
-}
+    }
+
+    /// The subscriber of the last confirmation email.
+    async fn subscriber_id(repository: &InMemoryRepository) -> Uuid {
+        let token = repository
+            .queued_emails()
+            .last()
+            .unwrap()
+            .text_content
+            .split("?token=")
+            .nth(1)
+            .and_then(|rest| rest.split_whitespace().next())
+            .unwrap()
+            .parse()
+            .unwrap();
+        repository.subscriber_id(&token).await.unwrap().unwrap()
+    }
 
-fn generate_subscription_token() -> String {
-    let mut rng = thread_rng();
+    #[tokio::test]
+    async fn a_new_subscriber_is_stored_with_a_confirmation_email() {
+        let repository = InMemoryRepository::new();
 
-    std::iter::repeat_with(|| rng.sample(Alphanumeric))
-        .map(char::from)
-        .take(25)
-        .collect()
+        assert_ok!(subscribe(&repository, "ursula@example.com").await);
+
+        let emails = repository.queued_emails();
+        assert_eq!(emails.len(), 1);
+        assert_eq!(emails[0].recipient, "ursula@example.com");
+        let subscriber_id = subscriber_id(&repository).await;
+        let subscriber = SubscriberRepository::get(&repository, subscriber_id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(subscriber.status, SubscriptionStatus::PendingConfirmation);
+    }
+
+    #[tokio::test]
+    async fn a_rejected_address_is_not_stored() {
+        let repository = InMemoryRepository::new();
+
+        let result = subscribe(&repository, "noreply@example.com").await;
+
+        assert!(matches!(result, Err(Error::Rejected(_))));
+        assert!(repository.queued_emails().is_empty());
+    }
+
+    #[tokio::test]
+    async fn a_pending_address_subscribing_again_gets_another_confirmation_email() {
+        let repository = InMemoryRepository::new();
+
+        assert_ok!(subscribe(&repository, "ursula@example.com").await);
+        let id = subscriber_id(&repository).await;
+        assert_ok!(subscribe(&repository, "Ursula@example.com").await);
+
+        assert_eq!(repository.queued_emails().len(), 2);
+        assert_eq!(subscriber_id(&repository).await, id);
+    }
+
+    #[tokio::test]
+    async fn a_confirmed_address_cannot_subscribe_twice() {
+        let repository = InMemoryRepository::new();
+        assert_ok!(subscribe(&repository, "ursula@example.com").await);
+        let id = subscriber_id(&repository).await;
+        repository
+            .transition(id, SubscriptionStatus::Confirmed)
+            .await
+            .unwrap();
+
+        let result = subscribe(&repository, "Ursula@example.com").await;
+
+        assert!(matches!(result, Err(Error::AlreadySubscribed)));
+        assert_eq!(repository.queued_emails().len(), 1);
+    }
 }
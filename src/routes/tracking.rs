@@ -0,0 +1,166 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use axum::{
+    extract::{Extension, Path},
+    response::{Headers, Html, IntoResponse},
+};
+use http::{header, HeaderValue, StatusCode};
+use reqwest::Url;
+use uuid::Uuid;
+
+use super::hosted_pages::HostedPages;
+use crate::{
+    repository::TrackingRepository,
+    tracking::{TrackingEvent, TrackingKey, TrackingToken, PIXEL},
+};
+
+/// Record that the issue was opened and serve the tracking pixel.
+#[tracing::instrument(name = "Track an open", skip(token, key, tracking))]
+pub async fn open(
+    Path(token): Path<String>,
+    Extension(key): Extension<TrackingKey>,
+    Extension(tracking): Extension<Arc<dyn TrackingRepository>>,
+) -> Result<impl IntoResponse, Error> {
+    let (issue_id, subscriber_id) = match key.decode(&token) {
+        Some(TrackingToken::Open {
+            issue_id,
+            subscriber_id,
+        }) => (issue_id, subscriber_id),
+        _ => return Err(Error::InvalidToken),
+    };
+
+    record_event(
+        tracking.as_ref(),
+        issue_id,
+        subscriber_id,
+        TrackingEvent::Open,
+    )
+    .await;
+
+    Ok((
+        Headers([
+            (header::CONTENT_TYPE, "image/gif"),
+            (header::CACHE_CONTROL, "no-store, max-age=0"),
+        ]),
+        PIXEL,
+    ))
+}
+
+/// Record that a link was clicked and redirect to its destination.
+#[tracing::instrument(name = "Track a click", skip(token, key, tracking))]
+pub async fn click(
+    Path(token): Path<String>,
+    Extension(key): Extension<TrackingKey>,
+    Extension(tracking): Extension<Arc<dyn TrackingRepository>>,
+) -> Result<impl IntoResponse, Error> {
+    let (issue_id, subscriber_id, url) = match key.decode(&token) {
+        Some(TrackingToken::Click {
+            issue_id,
+            subscriber_id,
+            url,
+        }) => (issue_id, subscriber_id, url),
+        _ => return Err(Error::InvalidToken),
+    };
+    let destination = Url::parse(&url)
+        .ok()
+        .and_then(|url| HeaderValue::from_str(url.as_str()).ok())
+        .ok_or(Error::InvalidToken)?;
+
+    record_event(
+        tracking.as_ref(),
+        issue_id,
+        subscriber_id,
+        TrackingEvent::Click { url: &url },
+    )
+    .await;
+
+    Ok((
+        StatusCode::FOUND,
+        Headers([(header::LOCATION, destination)]),
+    ))
+}
+
+/// Ask the subscriber to confirm that they want to stop being tracked, following the link does
+/// not change anything since the link checkers of the mail providers follow it too.
+#[tracing::instrument(name = "Show the tracking opt-out", skip(token, key, pages))]
+pub async fn show_opt_out(
+    Path(token): Path<String>,
+    Extension(key): Extension<TrackingKey>,
+    Extension(pages): Extension<HostedPages>,
+) -> Result<Html<String>, Error> {
+    opted_out_subscriber(&key, &token)?;
+
+    Ok(pages.render(
+        "Stop tracking",
+        r#"<form method="post">
+    <p>The newsletters will not tell us anymore when you read them or follow their links.</p>
+    <button type="submit">Stop tracking the emails I read</button>
+</form>"#,
+    ))
+}
+
+/// Stop tracking the subscriber, the next issues are sent without tracking. It also answers the
+/// one-click requests, whatever their body.
+#[tracing::instrument(name = "Opt out of the tracking", skip(token, key, tracking, pages))]
+pub async fn opt_out(
+    Path(token): Path<String>,
+    Extension(key): Extension<TrackingKey>,
+    Extension(tracking): Extension<Arc<dyn TrackingRepository>>,
+    Extension(pages): Extension<HostedPages>,
+) -> Result<Html<String>, Error> {
+    let subscriber_id = opted_out_subscriber(&key, &token)?;
+
+    tracking
+        .opt_out(subscriber_id)
+        .await
+        .context("failed to opt the subscriber out of the tracking")?;
+    tracing::info!(%subscriber_id, "Subscriber opted out of the tracking");
+
+    Ok(pages.render("Stop tracking", "<p>You will not be tracked anymore.</p>"))
+}
+
+fn opted_out_subscriber(key: &TrackingKey, token: &str) -> Result<Uuid, Error> {
+    match key.decode(token) {
+        Some(TrackingToken::OptOut { subscriber_id }) => Ok(subscriber_id),
+        _ => Err(Error::InvalidToken),
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("the tracking token is invalid")]
+    InvalidToken,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> axum::response::Response {
+        match self {
+            Error::InvalidToken => (StatusCode::NOT_FOUND, self.to_string()).into_response(),
+            Error::UnexpectedError(source) => {
+                (StatusCode::INTERNAL_SERVER_ERROR, source.to_string()).into_response()
+            }
+        }
+    }
+}
+
+/// Store a tracking event, unless the subscriber opted out. A failure is only logged, the reader
+/// still gets the pixel or the redirect.
+async fn record_event(
+    tracking: &dyn TrackingRepository,
+    issue_id: Uuid,
+    subscriber_id: Uuid,
+    event: TrackingEvent<'_>,
+) {
+    let result = tracking.record_event(issue_id, subscriber_id, &event).await;
+
+    if let Err(error) = result {
+        tracing::error!(
+            error.cause_chain = ?error,
+            kind = event.kind(),
+            "Failed to record a tracking event"
+        );
+    }
+}
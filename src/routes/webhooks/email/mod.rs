@@ -0,0 +1,167 @@
+pub mod postmark;
+
+use anyhow::Context;
+use axum::{
+    body::Bytes,
+    extract::{Extension, Path},
+    response::{IntoResponse, Response},
+};
+use std::sync::{
+    atomic::{AtomicU32, Ordering},
+    Arc,
+};
+
+use http::{header, HeaderMap, HeaderValue, StatusCode};
+
+use crate::{
+    configuration::{BasicCredentials, WebhookSettings},
+    domain::EmailEvent,
+    repository::{AppliedEvent, EmailEventRepository},
+};
+
+/// The email providers sending webhooks.
+#[derive(Clone, Copy, Debug)]
+pub enum Provider {
+    Postmark,
+}
+
+impl Provider {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Provider::Postmark => "postmark",
+        }
+    }
+
+    fn credentials<'a>(&self, settings: &'a WebhookSettings) -> &'a BasicCredentials {
+        match self {
+            Provider::Postmark => &settings.postmark,
+        }
+    }
+
+    fn parse(&self, payload: &[u8]) -> Result<Option<EmailEvent>, serde_json::Error> {
+        match self {
+            Provider::Postmark => postmark::parse(payload),
+        }
+    }
+}
+
+impl std::str::FromStr for Provider {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "postmark" => Ok(Provider::Postmark),
+            _ => Err(()),
+        }
+    }
+}
+
+/// The number of soft bounces in a row after which a subscriber is suppressed, it can be changed
+/// while running and the change is seen by all the clones.
+#[derive(Clone)]
+pub struct SoftBounceThreshold(Arc<AtomicU32>);
+
+impl SoftBounceThreshold {
+    pub fn new(threshold: u32) -> Self {
+        Self(Arc::new(AtomicU32::new(threshold)))
+    }
+
+    pub fn get(&self) -> u32 {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    pub fn set(&self, threshold: u32) {
+        self.0.store(threshold, Ordering::Relaxed);
+    }
+}
+
+#[tracing::instrument(
+    name = "Receive an email webhook",
+    skip(headers, payload, email_events, settings, soft_bounce_threshold)
+)]
+pub async fn handler(
+    Path(provider): Path<String>,
+    headers: HeaderMap,
+    payload: Bytes,
+    Extension(email_events): Extension<Arc<dyn EmailEventRepository>>,
+    Extension(settings): Extension<WebhookSettings>,
+    Extension(soft_bounce_threshold): Extension<SoftBounceThreshold>,
+) -> Result<(), Error> {
+    let provider: Provider = provider
+        .parse()
+        .map_err(|_| Error::UnknownProvider(provider))?;
+    authenticate(&headers, provider.credentials(&settings))?;
+
+    let event = match provider.parse(&payload).map_err(Error::InvalidPayload)? {
+        Some(event) => event,
+        None => return Ok(()),
+    };
+
+    let applied = email_events
+        .apply(
+            provider.as_str(),
+            &event,
+            &String::from_utf8_lossy(&payload),
+            soft_bounce_threshold.get(),
+        )
+        .await
+        .context("failed to store the email event")?;
+    if applied == (AppliedEvent::Applied { suppressed: true }) {
+        tracing::info!(
+            kind = event.kind.as_str(),
+            subscriber_email = %event.recipient.redacted(),
+            "Subscriber suppressed"
+        );
+    }
+    Ok(())
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("unknown email provider `{0}`")]
+    UnknownProvider(String),
+    #[error("missing or invalid credentials")]
+    Unauthorized,
+    #[error("invalid payload: {0}")]
+    InvalidPayload(#[source] serde_json::Error),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl IntoResponse for Error {
+    fn into_response(self) -> Response {
+        match self {
+            Error::UnknownProvider(_) => (StatusCode::NOT_FOUND, self.to_string()).into_response(),
+            Error::Unauthorized => {
+                let mut response = StatusCode::UNAUTHORIZED.into_response();
+                response.headers_mut().insert(
+                    header::WWW_AUTHENTICATE,
+                    HeaderValue::from_static(r#"Basic realm="webhooks""#),
+                );
+                response
+            }
+            Error::InvalidPayload(_) => (StatusCode::BAD_REQUEST, self.to_string()).into_response(),
+            Error::UnexpectedError(source) => {
+                (StatusCode::INTERNAL_SERVER_ERROR, source.to_string()).into_response()
+            }
+        }
+    }
+}
+
+/// Check the basic authentication credentials of the request.
+fn authenticate(headers: &HeaderMap, expected: &BasicCredentials) -> Result<(), Error> {
+    let credentials = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Basic "))
+        .and_then(|value| base64::decode(value).ok())
+        .and_then(|value| String::from_utf8(value).ok())
+        .ok_or(Error::Unauthorized)?;
+    let (username, password) = credentials.split_once(':').ok_or(Error::Unauthorized)?;
+
+    if username == expected.username && expected.password.matches(password) {
+        Ok(())
+    } else {
+        Err(Error::Unauthorized)
+    }
+}
@@ -0,0 +1,126 @@
+//! Webhooks of [Postmark](https://postmarkapp.com/developer/webhooks/webhooks-overview).
+
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+
+use crate::domain::{EmailAddress, EmailEvent, EmailEventKind};
+
+#[derive(Deserialize)]
+#[serde(tag = "RecordType")]
+enum Record {
+    Bounce(Bounce),
+    SpamComplaint(Bounce),
+    Delivery(Delivery),
+    /// Opens, clicks and subscription changes are not handled.
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Bounce {
+    #[serde(rename = "Type")]
+    kind: String,
+    #[serde(rename = "MessageID")]
+    message_id: String,
+    email: EmailAddress,
+    description: Option<String>,
+    bounced_at: DateTime<Utc>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Delivery {
+    #[serde(rename = "MessageID")]
+    message_id: String,
+    recipient: EmailAddress,
+    details: Option<String>,
+    delivered_at: DateTime<Utc>,
+}
+
+/// Parse the payload of a webhook, it returns `None` for the events that are not relevant.
+pub fn parse(payload: &[u8]) -> Result<Option<EmailEvent>, serde_json::Error> {
+    let event = match serde_json::from_slice(payload)? {
+        Record::Bounce(bounce) | Record::SpamComplaint(bounce) => {
+            bounce_kind(&bounce.kind).map(|kind| EmailEvent {
+                message_id: bounce.message_id,
+                recipient: bounce.email,
+                kind,
+                description: bounce.description.filter(|d| !d.is_empty()),
+                occurred_at: bounce.bounced_at,
+            })
+        }
+        Record::Delivery(delivery) => Some(EmailEvent {
+            message_id: delivery.message_id,
+            recipient: delivery.recipient,
+            kind: EmailEventKind::Delivered,
+            description: delivery.details.filter(|d| !d.is_empty()),
+            occurred_at: delivery.delivered_at,
+        }),
+        Record::Other => None,
+    };
+    Ok(event)
+}
+
+/// Classify the bounce types, auto-responders and the other notifications do not tell anything
+/// about the address and they are ignored.
+fn bounce_kind(kind: &str) -> Option<EmailEventKind> {
+    match kind {
+        "HardBounce" | "BadEmailAddress" | "ManuallyDeactivated" => {
+            Some(EmailEventKind::HardBounce)
+        }
+        "SoftBounce" | "Transient" | "DnsError" => Some(EmailEventKind::SoftBounce),
+        "SpamComplaint" | "SpamNotification" => Some(EmailEventKind::Complaint),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use claim::{assert_err, assert_none, assert_some};
+
+    use super::parse;
+    use crate::domain::EmailEventKind;
+
+    fn kind(payload: serde_json::Value) -> Option<EmailEventKind> {
+        parse(payload.to_string().as_bytes())
+            .unwrap()
+            .map(|event| event.kind)
+    }
+
+    fn bounce(kind: &str) -> serde_json::Value {
+        serde_json::json!({
+            "RecordType": "Bounce",
+            "Type": kind,
+            "MessageID": "883953f4-6105-42a2-a16a-77a8eac79483",
+            "Email": "john@example.com",
+            "Description": "",
+            "BouncedAt": "2019-11-05T16:33:54.9070259Z",
+        })
+    }
+
+    #[test]
+    fn bounces_are_classified() {
+        assert_eq!(kind(bounce("HardBounce")), Some(EmailEventKind::HardBounce));
+        assert_eq!(kind(bounce("SoftBounce")), Some(EmailEventKind::SoftBounce));
+        assert_eq!(kind(bounce("Transient")), Some(EmailEventKind::SoftBounce));
+        assert_none!(kind(bounce("AutoResponder")));
+    }
+
+    #[test]
+    fn empty_descriptions_are_dropped() {
+        let event = assert_some!(parse(bounce("HardBounce").to_string().as_bytes()).unwrap());
+        assert_none!(event.description);
+    }
+
+    #[test]
+    fn other_record_types_are_ignored() {
+        assert_none!(kind(serde_json::json!({"RecordType": "Open"})));
+    }
+
+    #[test]
+    fn malformed_payloads_are_rejected() {
+        assert_err!(parse(b"{\"RecordType\": \"Bounce\"}"));
+        assert_err!(parse(b"not json"));
+    }
+}
@@ -0,0 +1,94 @@
+use std::fmt;
+
+use serde::Deserialize;
+use zeroize::Zeroize;
+
+const REDACTED: &str = "[REDACTED]";
+
+/// A sensitive value, it is never printed and its memory is zeroed when dropped.
+#[derive(Clone, Default, Deserialize)]
+#[serde(transparent)]
+pub struct Secret(String);
+
+impl Secret {
+    /// Create a new `Secret`.
+    pub fn new(value: String) -> Self {
+        Self(value)
+    }
+
+    /// Access the value of the secret, take care to not leak it.
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+
+    /// Remove the line breaks ending the secret, like the one closing a secret file. The
+    /// characters removed are not secret.
+    pub fn trim_end_newlines(&mut self) {
+        let length = self.0.trim_end_matches(&['\r', '\n'][..]).len();
+        self.0.truncate(length);
+    }
+
+    /// Compare the secret with a value provided by a client, the time taken does not depend on
+    /// the position of the first difference.
+    pub fn matches(&self, provided: &str) -> bool {
+        let (a, b) = (self.0.as_bytes(), provided.as_bytes());
+        a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+    }
+}
+
+/// Secrets are compared in constant time.
+impl PartialEq for Secret {
+    fn eq(&self, other: &Self) -> bool {
+        self.matches(other.expose())
+    }
+}
+
+impl From<String> for Secret {
+    fn from(value: String) -> Self {
+        Self::new(value)
+    }
+}
+
+impl Drop for Secret {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+impl fmt::Display for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Secret;
+
+    #[test]
+    fn debug_and_display_do_not_leak_the_secret() {
+        let secret = Secret::new("my-secret-token".into());
+        assert!(!format!("{:?}", secret).contains("my-secret-token"));
+        assert!(!format!("{}", secret).contains("my-secret-token"));
+    }
+
+    #[test]
+    fn the_secret_can_be_exposed() {
+        let secret = Secret::new("my-secret-token".into());
+        assert_eq!(secret.expose(), "my-secret-token");
+    }
+
+    #[test]
+    fn only_the_same_value_matches() {
+        let secret = Secret::new("my-secret-token".into());
+        assert!(secret.matches("my-secret-token"));
+        assert!(!secret.matches("my-secret-tokem"));
+        assert!(!secret.matches("my-secret"));
+    }
+}
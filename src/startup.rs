@@ -1,13 +1,36 @@
+#[cfg(feature = "sqlite")]
+use crate::repository::SqliteRepository;
 use crate::{
-    configuration::{DatabaseSettings, Settings},
+    configuration::{DatabaseBackend, DatabaseSettings, Settings},
+    domain_check::{DomainResolver, SystemResolver},
     email_client::EmailClient,
+    human_verification::HumanVerification,
+    issue_delivery::{self, DeliveryQueue},
+    mailer::Mailer,
+    outbox,
+    repository::{
+        DeadLetterRepository, DeliveryRepository, EmailEventRepository, IssueRepository,
+        PgRepository, SubscriberRepository, SuppressionRepository, TokenRepository,
+        TrackingRepository,
+    },
     request_id::{AddRequestIdLayer, MakeSpanWithRequestId, UseRequestId},
-    routes,
+    routes::{
+        self,
+        admin::AdminToken,
+        hosted_pages::HostedPages,
+        preferences::NewsletterTopics,
+        subscriptions::{confirm::ConfirmationLinkTtl, confirmation_pages::ConfirmationPages},
+        webhooks::email::SoftBounceThreshold,
+    },
+    subscription_policy::SubscriptionPolicy,
+    telemetry::LogFilterHandle,
+    tracking::TrackingKey,
 };
 
-use std::{net::TcpListener, time::Duration};
+use std::{net::TcpListener, sync::Arc, time::Duration};
 
 use axum::{routing, AddExtensionLayer, Router};
+use futures::{future::BoxFuture, FutureExt};
 use sqlx::postgres::{PgPool, PgPoolOptions};
 use tower::ServiceBuilder;
 use tower_http::{trace::TraceLayer, ServiceBuilderExt};
@@ -16,6 +39,22 @@ use tracing::Level;
 pub struct Application {
     app: Router,
     listener: TcpListener,
+    email_client: EmailClient,
+    subscription_policy: SubscriptionPolicy,
+    soft_bounce_threshold: SoftBounceThreshold,
+    storage: Storage,
+    mailer: Mailer,
+    /// Sends the queued deliveries in the background, see `issue_delivery::run_deliveries`.
+    deliveries: BoxFuture<'static, ()>,
+    /// Sends the digests in the background, see `issue_delivery::run_digests`.
+    digests: BoxFuture<'static, ()>,
+}
+
+/// Where everything is stored, see `DatabaseBackend`.
+enum Storage {
+    Postgres(PgPool),
+    #[cfg(feature = "sqlite")]
+    Sqlite(SqliteRepository),
 }
 
 #[derive(Clone)]
@@ -28,20 +67,93 @@ impl ApplicationBaseUrl {
 }
 
 impl Application {
-    pub fn build(settings: Settings) -> Self {
-        let db_pool = get_connection_pool(&settings.database);
+    pub fn build(settings: Settings, log_filter: LogFilterHandle) -> Self {
+        Self::build_with_resolver(
+            settings,
+            log_filter,
+            Arc::new(SystemResolver::from_system()),
+        )
+    }
+
+    /// Build the application with the resolver used to check the domains of the new subscribers.
+    pub fn build_with_resolver(
+        settings: Settings,
+        log_filter: LogFilterHandle,
+        resolver: Arc<dyn DomainResolver>,
+    ) -> Self {
+        let email_client = settings
+            .email_client
+            .client()
+            .expect("Invalid settings of the email client");
 
-        let email_client = EmailClient::new(
-            &settings.email_client.base_url,
+        let subscription_policy = SubscriptionPolicy::new(
             settings
-                .email_client
-                .sender()
-                .expect("Invalid sender email address"),
-            &settings.email_client.authorization_token,
-            settings.email_client.timeout(),
+                .subscriptions
+                .rules()
+                .expect("Failed to read the list of disposable domains"),
         );
+        let domain_checker = settings.subscriptions.domain_check.checker(resolver);
+        let confirmation_pages =
+            ConfirmationPages::load(&settings.subscriptions.confirmation_pages)
+                .expect("Failed to read the templates of the confirmation pages");
+        let confirmation_link_ttl =
+            ConfirmationLinkTtl(settings.subscriptions.confirmation_link_ttl());
+        let hosted_pages = HostedPages::load(&settings.subscriptions.hosted_pages)
+            .expect("Failed to read the template of the hosted pages");
+        let topics = NewsletterTopics(settings.subscriptions.topics.clone());
+        let (storage, repositories) = match &settings.database.backend {
+            DatabaseBackend::Postgres => {
+                let db_pool = get_connection_pool(&settings.database);
+                (
+                    Storage::Postgres(db_pool.clone()),
+                    Repositories::new(PgRepository::new(db_pool)),
+                )
+            }
+            #[cfg(feature = "sqlite")]
+            DatabaseBackend::Sqlite { path } => {
+                let repository = SqliteRepository::open(path);
+                (
+                    Storage::Sqlite(repository.clone()),
+                    Repositories::new(repository),
+                )
+            }
+            #[cfg(not(feature = "sqlite"))]
+            DatabaseBackend::Sqlite { .. } => {
+                panic!("The SQLite backend needs the `sqlite` feature")
+            }
+        };
+        let mailer = Mailer::new(email_client.clone(), repositories.suppressions.clone());
 
         let application_base_url = ApplicationBaseUrl(settings.application.base_url.clone());
+        let admin_token = AdminToken(settings.application.admin_token.clone());
+        let webhooks = settings.webhooks.clone();
+        let soft_bounce_threshold = SoftBounceThreshold::new(webhooks.soft_bounce_threshold);
+        let tracking_key = TrackingKey::new(settings.tracking.signing_key.clone());
+        let delivery_queue = DeliveryQueue::default();
+        let deliveries = issue_delivery::run_deliveries(
+            repositories.deliveries.clone(),
+            repositories.issues.clone(),
+            mailer.clone(),
+            tracking_key.clone(),
+            application_base_url.0.clone(),
+            delivery_queue.clone(),
+        )
+        .boxed();
+        let digests = issue_delivery::run_digests(
+            repositories.deliveries.clone(),
+            repositories.issues.clone(),
+            mailer.clone(),
+            tracking_key.clone(),
+            application_base_url.0.clone(),
+            settings.subscriptions.digest_interval(),
+        )
+        .boxed();
+        let human_verification = HumanVerification::new(
+            settings
+                .subscriptions
+                .human_verification
+                .verifier(&tracking_key),
+        );
 
         let middleware = ServiceBuilder::new()
             .layer(AddRequestIdLayer)
@@ -52,9 +164,30 @@ impl Application {
             )
             .set_x_request_id(UseRequestId)
             .propagate_x_request_id()
-            .layer(AddExtensionLayer::new(db_pool))
-            .layer(AddExtensionLayer::new(email_client))
+            .layer(AddExtensionLayer::new(repositories.subscribers))
+            .layer(AddExtensionLayer::new(repositories.tokens))
+            .layer(AddExtensionLayer::new(repositories.issues))
+            .layer(AddExtensionLayer::new(repositories.deliveries))
+            .layer(AddExtensionLayer::new(delivery_queue))
+            .layer(AddExtensionLayer::new(repositories.suppressions))
+            .layer(AddExtensionLayer::new(repositories.email_events))
+            .layer(AddExtensionLayer::new(repositories.tracking))
+            .layer(AddExtensionLayer::new(repositories.dead_letters))
+            .layer(AddExtensionLayer::new(mailer.clone()))
+            .layer(AddExtensionLayer::new(email_client.clone()))
+            .layer(AddExtensionLayer::new(subscription_policy.clone()))
+            .layer(AddExtensionLayer::new(domain_checker))
+            .layer(AddExtensionLayer::new(human_verification))
+            .layer(AddExtensionLayer::new(confirmation_pages))
+            .layer(AddExtensionLayer::new(confirmation_link_ttl))
+            .layer(AddExtensionLayer::new(hosted_pages))
+            .layer(AddExtensionLayer::new(topics))
             .layer(AddExtensionLayer::new(application_base_url))
+            .layer(AddExtensionLayer::new(admin_token))
+            .layer(AddExtensionLayer::new(log_filter))
+            .layer(AddExtensionLayer::new(webhooks))
+            .layer(AddExtensionLayer::new(soft_bounce_threshold.clone()))
+            .layer(AddExtensionLayer::new(tracking_key))
             .into_inner();
 
         let app = Router::new()
@@ -63,19 +196,114 @@ impl Application {
                 "/subscriptions",
                 routing::post(routes::subscriptions::handler),
             )
+            .route(
+                "/subscriptions/form_token",
+                routing::get(routes::subscriptions::form_token),
+            )
             .route(
                 "/subscriptions/confirm",
                 routing::get(routes::subscriptions::confirm::handler),
             )
+            .route(
+                "/subscribe",
+                routing::get(routes::subscribe::form).post(routes::subscribe::submit),
+            )
+            .route(
+                "/preferences/:token",
+                routing::get(routes::preferences::show).post(routes::preferences::update),
+            )
             .route("/newsletters", routing::post(routes::newsletters::handler))
+            .route("/t/o/:token", routing::get(routes::tracking::open))
+            .route("/t/c/:token", routing::get(routes::tracking::click))
+            .route(
+                "/t/opt_out/:token",
+                routing::get(routes::tracking::show_opt_out).post(routes::tracking::opt_out),
+            )
+            .route(
+                "/admin/metrics",
+                routing::get(routes::admin::metrics::handler),
+            )
+            .route(
+                "/admin/log_filter",
+                routing::get(routes::admin::log_filter::get).put(routes::admin::log_filter::put),
+            )
+            .route(
+                "/admin/suppressions",
+                routing::get(routes::admin::suppressions::list)
+                    .post(routes::admin::suppressions::add),
+            )
+            .route(
+                "/admin/suppressions/:scope/:value",
+                routing::delete(routes::admin::suppressions::remove),
+            )
+            .route(
+                "/admin/newsletters/digests",
+                routing::post(routes::admin::newsletters::send_digests),
+            )
+            .route(
+                "/admin/newsletters/:issue_id/stats",
+                routing::get(routes::admin::newsletters::stats),
+            )
+            .route(
+                "/admin/newsletters/:issue_id/deliveries",
+                routing::get(routes::admin::newsletters::deliveries),
+            )
+            .route(
+                "/admin/newsletters/:issue_id/resend",
+                routing::post(routes::admin::newsletters::resend),
+            )
+            .route(
+                "/admin/dead_letters",
+                routing::get(routes::admin::dead_letters::list)
+                    .delete(routes::admin::dead_letters::purge_all),
+            )
+            .route(
+                "/admin/dead_letters/replay",
+                routing::post(routes::admin::dead_letters::replay_all),
+            )
+            .route(
+                "/admin/dead_letters/:id",
+                routing::get(routes::admin::dead_letters::show)
+                    .delete(routes::admin::dead_letters::purge),
+            )
+            .route(
+                "/admin/dead_letters/:id/replay",
+                routing::post(routes::admin::dead_letters::replay),
+            )
+            .route(
+                "/webhooks/email/:provider",
+                routing::post(routes::webhooks::email::handler),
+            )
             .layer(middleware);
 
-        let listener = TcpListener::bind(&settings.application.address()).unwrap();
+        let listener = TcpListener::bind(settings.application.address()).unwrap();
 
-        Application { app, listener }
+        Application {
+            app,
+            listener,
+            email_client,
+            subscription_policy,
+            soft_bounce_threshold,
+            storage,
+            mailer,
+            deliveries,
+            digests,
+        }
     }
 
+    /// Serve the requests, the messages of the outbox are relayed and the issues and the digests
+    /// are sent in the background.
     pub async fn run(self) -> Result<(), hyper::Error> {
+        tokio::spawn(self.deliveries);
+        tokio::spawn(self.digests);
+        match self.storage {
+            Storage::Postgres(db_pool) => tokio::spawn(outbox::run_relay(db_pool, self.mailer)),
+            #[cfg(feature = "sqlite")]
+            Storage::Sqlite(repository) => tokio::spawn(outbox::sqlite::run_relay(
+                repository.pool().clone(),
+                self.mailer,
+            )),
+        };
         hyper::Server::from_tcp(self.listener)?
             .serve(self.app.into_make_service())
             .await
@@ -88,6 +316,60 @@ impl Application {
     pub fn port(&self) -> u16 {
         self.listener.local_addr().unwrap().port()
     }
+
+    /// The email client used by the application, its policy can be changed while running.
+    pub fn email_client(&self) -> EmailClient {
+        self.email_client.clone()
+    }
+
+    /// The policy checking the new subscribers, its rules can be changed while running.
+    pub fn subscription_policy(&self) -> SubscriptionPolicy {
+        self.subscription_policy.clone()
+    }
+
+    /// The number of soft bounces suppressing a subscriber, it can be changed while running.
+    pub fn soft_bounce_threshold(&self) -> SoftBounceThreshold {
+        self.soft_bounce_threshold.clone()
+    }
+}
+
+/// The repositories of the backend, each one is an extension of the routes.
+struct Repositories {
+    subscribers: Arc<dyn SubscriberRepository>,
+    tokens: Arc<dyn TokenRepository>,
+    issues: Arc<dyn IssueRepository>,
+    deliveries: Arc<dyn DeliveryRepository>,
+    suppressions: Arc<dyn SuppressionRepository>,
+    email_events: Arc<dyn EmailEventRepository>,
+    tracking: Arc<dyn TrackingRepository>,
+    dead_letters: Arc<dyn DeadLetterRepository>,
+}
+
+impl Repositories {
+    fn new<R>(repository: R) -> Self
+    where
+        R: SubscriberRepository
+            + TokenRepository
+            + IssueRepository
+            + DeliveryRepository
+            + SuppressionRepository
+            + EmailEventRepository
+            + TrackingRepository
+            + DeadLetterRepository
+            + Clone
+            + 'static,
+    {
+        Self {
+            subscribers: Arc::new(repository.clone()),
+            tokens: Arc::new(repository.clone()),
+            issues: Arc::new(repository.clone()),
+            deliveries: Arc::new(repository.clone()),
+            suppressions: Arc::new(repository.clone()),
+            email_events: Arc::new(repository.clone()),
+            tracking: Arc::new(repository.clone()),
+            dead_letters: Arc::new(repository),
+        }
+    }
 }
 
 pub fn get_connection_pool(settings: &DatabaseSettings) -> PgPool {
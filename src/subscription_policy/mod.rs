@@ -0,0 +1,280 @@
+use std::{
+    collections::HashSet,
+    sync::{Arc, RwLock},
+};
+
+use crate::domain::{EmailAddress, Script, SubscriberName};
+
+/// Disposable domains shipped with the application.
+const BUNDLED_DISPOSABLE_DOMAINS: &str = include_str!("disposable_domains.txt");
+
+/// Local parts of the addresses of a role rather than a person.
+const ROLE_LOCAL_PARTS: &[&str] = &[
+    "abuse",
+    "admin",
+    "administrator",
+    "do-not-reply",
+    "donotreply",
+    "hostmaster",
+    "mailer-daemon",
+    "no-reply",
+    "noreply",
+    "postmaster",
+    "root",
+    "security",
+    "webmaster",
+];
+
+/// Why an address may not subscribe, the message is shown to the visitor.
+#[derive(Clone, Debug, Eq, PartialEq, thiserror::Error)]
+pub enum Rejection {
+    #[error("subscriptions from this email domain are not accepted")]
+    DeniedDomain,
+    #[error("disposable email addresses are not accepted, please use a permanent address")]
+    DisposableDomain,
+    #[error(
+        "role addresses such as noreply@ or postmaster@ are not accepted, please use a personal \
+         address"
+    )]
+    RoleAddress,
+    /// The domain has no mail server.
+    #[error("this email domain cannot receive emails")]
+    UndeliverableDomain,
+    /// The domain is probably a misspelling of the popular domain `suggestion`.
+    #[error("this email domain looks misspelt, did you mean {suggestion}?")]
+    MisspeltDomain { suggestion: String },
+    #[error("the name must be at least {min_length} characters long")]
+    NameTooShort { min_length: usize },
+    #[error("the name must be written in one of the accepted alphabets")]
+    NameScript,
+}
+
+impl Rejection {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Rejection::DeniedDomain => "denied_domain",
+            Rejection::DisposableDomain => "disposable_domain",
+            Rejection::RoleAddress => "role_address",
+            Rejection::UndeliverableDomain => "undeliverable_domain",
+            Rejection::MisspeltDomain { .. } => "misspelt_domain",
+            Rejection::NameTooShort { .. } => "name_too_short",
+            Rejection::NameScript => "name_script",
+        }
+    }
+}
+
+/// The rules deciding which addresses may subscribe, the domains are in lowercase and punycode.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PolicyRules {
+    /// Domains of the disposable email providers, their subdomains are matched too.
+    pub disposable_domains: HashSet<String>,
+    pub reject_role_addresses: bool,
+    /// Domains accepted even if they are in `disposable_domains`.
+    pub allowed_domains: HashSet<String>,
+    /// Domains always rejected, their subdomains are matched too.
+    pub denied_domains: HashSet<String>,
+    /// Minimum number of graphemes of the names.
+    pub min_name_length: usize,
+    /// Scripts the letters of the names must belong to, any script is accepted if empty.
+    pub allowed_name_scripts: HashSet<Script>,
+}
+
+/// Check the addresses of the new subscribers, the rules are shared by all the clones and can be
+/// replaced while running.
+#[derive(Clone)]
+pub struct SubscriptionPolicy {
+    rules: Arc<RwLock<PolicyRules>>,
+}
+
+impl SubscriptionPolicy {
+    pub fn new(rules: PolicyRules) -> Self {
+        Self {
+            rules: Arc::new(RwLock::new(rules)),
+        }
+    }
+
+    pub fn rules(&self) -> PolicyRules {
+        self.rules.read().unwrap().clone()
+    }
+
+    pub fn set_rules(&self, rules: PolicyRules) {
+        *self.rules.write().unwrap() = rules;
+    }
+
+    pub fn check(&self, email: &EmailAddress) -> Result<(), Rejection> {
+        let rules = self.rules.read().unwrap();
+        let domain = email.domain();
+        if matches(&rules.denied_domains, domain) {
+            return Err(Rejection::DeniedDomain);
+        }
+        if rules.reject_role_addresses && is_role(email.local_part()) {
+            return Err(Rejection::RoleAddress);
+        }
+        if !matches(&rules.allowed_domains, domain) && matches(&rules.disposable_domains, domain) {
+            return Err(Rejection::DisposableDomain);
+        }
+        Ok(())
+    }
+
+    /// Whether `domain` or one of its parents is in `allowed_domains`.
+    pub fn is_allowed(&self, domain: &str) -> bool {
+        matches(&self.rules.read().unwrap().allowed_domains, domain)
+    }
+
+    pub fn check_name(&self, name: &SubscriberName) -> Result<(), Rejection> {
+        let rules = self.rules.read().unwrap();
+        if name.length() < rules.min_name_length {
+            return Err(Rejection::NameTooShort {
+                min_length: rules.min_name_length,
+            });
+        }
+        if !rules.allowed_name_scripts.is_empty()
+            && !name.is_written_in(&rules.allowed_name_scripts)
+        {
+            return Err(Rejection::NameScript);
+        }
+        Ok(())
+    }
+}
+
+/// Whether `domain` or one of its parents is in `domains`.
+fn matches(domains: &HashSet<String>, domain: &str) -> bool {
+    let mut domain = domain;
+    loop {
+        if domains.contains(domain) {
+            return true;
+        }
+        match domain.split_once('.') {
+            Some((_, parent)) => domain = parent,
+            None => return false,
+        }
+    }
+}
+
+fn is_role(local_part: &str) -> bool {
+    let local_part = local_part.to_lowercase();
+    let user = local_part.split('+').next().unwrap_or_default();
+    ROLE_LOCAL_PARTS.contains(&user)
+}
+
+/// The disposable domains shipped with the application.
+pub fn bundled_disposable_domains() -> HashSet<String> {
+    parse_domain_list(BUNDLED_DISPOSABLE_DOMAINS)
+}
+
+/// Parse a list with one domain per line, the blank lines and the comments starting with `#`
+/// are ignored.
+pub fn parse_domain_list(list: &str) -> HashSet<String> {
+    list.lines()
+        .map(|line| line.split('#').next().unwrap_or_default().trim())
+        .filter(|line| !line.is_empty())
+        .map(normalise_domain)
+        .collect()
+}
+
+/// Convert a domain to the form of the domain of an `EmailAddress`.
+pub fn normalise_domain(domain: &str) -> String {
+    let domain = domain.trim().trim_end_matches('.');
+    idna::domain_to_ascii(domain).unwrap_or_else(|_| domain.to_lowercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use claim::assert_ok;
+
+    use super::{
+        bundled_disposable_domains, parse_domain_list, PolicyRules, Rejection, SubscriptionPolicy,
+    };
+    use crate::domain::{EmailAddress, Script, SubscriberName};
+
+    fn policy() -> SubscriptionPolicy {
+        SubscriptionPolicy::new(PolicyRules {
+            disposable_domains: bundled_disposable_domains(),
+            reject_role_addresses: true,
+            allowed_domains: parse_domain_list("mailinator.com"),
+            denied_domains: parse_domain_list("spam.example.com\nBÜCHER.example"),
+            min_name_length: 2,
+            allowed_name_scripts: HashSet::from([Script::Latin, Script::Greek]),
+        })
+    }
+
+    fn check(email: &str) -> Result<(), Rejection> {
+        policy().check(&email.parse::<EmailAddress>().unwrap())
+    }
+
+    #[test]
+    fn a_personal_address_is_accepted() {
+        assert_ok!(check("ursula@example.com"));
+    }
+
+    #[test]
+    fn a_disposable_domain_is_rejected_with_its_subdomains() {
+        assert_eq!(
+            check("ursula@yopmail.com"),
+            Err(Rejection::DisposableDomain)
+        );
+        assert_eq!(
+            check("ursula@inbox.YOPMAIL.com"),
+            Err(Rejection::DisposableDomain)
+        );
+    }
+
+    #[test]
+    fn an_allowed_domain_is_accepted_even_if_disposable() {
+        assert_ok!(check("ursula@mailinator.com"));
+    }
+
+    #[test]
+    fn a_denied_domain_is_rejected() {
+        assert_eq!(
+            check("ursula@news.spam.example.com"),
+            Err(Rejection::DeniedDomain)
+        );
+        assert_eq!(check("ursula@bücher.example"), Err(Rejection::DeniedDomain));
+    }
+
+    #[test]
+    fn a_role_address_is_rejected_whatever_its_case_or_tag() {
+        assert_eq!(check("noreply@example.com"), Err(Rejection::RoleAddress));
+        assert_eq!(check("PostMaster@example.com"), Err(Rejection::RoleAddress));
+        assert_eq!(check("abuse+news@example.com"), Err(Rejection::RoleAddress));
+    }
+
+    fn check_name(name: &str) -> Result<(), Rejection> {
+        policy().check_name(&name.parse::<SubscriberName>().unwrap())
+    }
+
+    #[test]
+    fn a_name_in_an_allowed_script_is_accepted() {
+        assert_ok!(check_name("Zoë Παπαδοπούλου"));
+    }
+
+    #[test]
+    fn a_name_shorter_than_the_minimum_is_rejected() {
+        assert_eq!(
+            check_name("Z"),
+            Err(Rejection::NameTooShort { min_length: 2 })
+        );
+    }
+
+    #[test]
+    fn a_name_mixing_in_another_script_is_rejected() {
+        assert_eq!(check_name("Urs\u{0430}la"), Err(Rejection::NameScript));
+    }
+
+    #[test]
+    fn the_checks_can_be_disabled() {
+        let policy = SubscriptionPolicy::new(PolicyRules::default());
+        assert_ok!(policy.check(&"noreply@yopmail.com".parse().unwrap()));
+        assert_ok!(policy.check_name(&"Z\u{0430}".parse().unwrap()));
+    }
+
+    #[test]
+    fn comments_and_blank_lines_are_ignored_in_a_domain_list() {
+        let domains = parse_domain_list("# a comment\n\n  Example.COM.  # trailing\n");
+        assert_eq!(domains.len(), 1);
+        assert!(domains.contains("example.com"));
+    }
+}
@@ -0,0 +1,139 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    domain::EmailAddress,
+    repository::{RepositoryError, SuppressionRepository},
+};
+
+/// What a suppression applies to.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SuppressionScope {
+    /// A single address.
+    Address,
+    /// All the addresses of a domain.
+    Domain,
+}
+
+/// An entry of the suppression list, no email is sent to the matching addresses.
+#[derive(Clone, Debug, Serialize)]
+pub struct Suppression {
+    pub scope: SuppressionScope,
+    pub value: String,
+    pub reason: String,
+    pub created_at: DateTime<Utc>,
+    /// The entry has no effect after this time, it never expires if `None`.
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+impl SuppressionScope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SuppressionScope::Address => "address",
+            SuppressionScope::Domain => "domain",
+        }
+    }
+}
+
+impl std::str::FromStr for SuppressionScope {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "address" => Ok(SuppressionScope::Address),
+            "domain" => Ok(SuppressionScope::Domain),
+            other => Err(format!("`{}` is not a valid suppression scope", other)),
+        }
+    }
+}
+
+/// An email that was not sent because its recipient is suppressed.
+pub struct SkippedEmail<'a> {
+    pub recipient: &'a EmailAddress,
+    pub subject: &'a str,
+    /// The reason of the suppression.
+    pub reason: &'a str,
+}
+
+/// The key of an address in the suppression list, its canonical form so that every form of a
+/// suppressed address is suppressed.
+pub fn normalise_address(email: &EmailAddress) -> String {
+    email.canonical()
+}
+
+/// The key of the domain of an address in the suppression list.
+pub fn normalise_domain(domain: &str) -> String {
+    domain.trim().trim_end_matches('.').to_lowercase()
+}
+
+fn domain_of(email: &EmailAddress) -> String {
+    let domain = email
+        .as_ref()
+        .rsplit_once('@')
+        .map_or("", |(_, domain)| domain);
+    normalise_domain(domain)
+}
+
+/// The active entry matching `email`, the entries of the address take precedence over the
+/// entries of its domain.
+#[tracing::instrument(name = "Check the suppression list", skip(suppressions, email))]
+pub async fn find_active(
+    suppressions: &dyn SuppressionRepository,
+    email: &EmailAddress,
+) -> Result<Option<Suppression>, RepositoryError> {
+    Ok(find_active_for_all(suppressions, &[email]).await?.remove(0))
+}
+
+/// The active entry matching each of `emails`, in the order of `emails`, with a single query.
+#[tracing::instrument(
+    name = "Check the suppression list for a batch",
+    skip(suppressions, emails),
+    fields(emails = emails.len())
+)]
+pub async fn find_active_for_all(
+    suppressions: &dyn SuppressionRepository,
+    emails: &[&EmailAddress],
+) -> Result<Vec<Option<Suppression>>, RepositoryError> {
+    let addresses: Vec<_> = emails
+        .iter()
+        .map(|email| normalise_address(email))
+        .collect();
+    let domains: Vec<_> = emails.iter().map(|email| domain_of(email)).collect();
+    let suppressions = suppressions.find_active(&addresses, &domains).await?;
+
+    let find = |scope: SuppressionScope, value: &str| {
+        suppressions
+            .iter()
+            .find(|suppression| suppression.scope == scope && suppression.value == value)
+    };
+    Ok(addresses
+        .iter()
+        .zip(&domains)
+        .map(|(address, domain)| {
+            find(SuppressionScope::Address, address)
+                .or_else(|| find(SuppressionScope::Domain, domain))
+                .cloned()
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{domain_of, normalise_address, normalise_domain};
+    use crate::domain::EmailAddress;
+
+    #[test]
+    fn addresses_are_normalised() {
+        let email: EmailAddress = "Ursula.Le.Guin@Example.COM".parse().unwrap();
+        assert_eq!(normalise_address(&email), "ursula.le.guin@example.com");
+        assert_eq!(domain_of(&email), "example.com");
+        let email: EmailAddress = "Ursula.Le.Guin+news@GoogleMail.com".parse().unwrap();
+        assert_eq!(normalise_address(&email), "ursulaleguin@gmail.com");
+    }
+
+    #[test]
+    fn domains_are_normalised() {
+        assert_eq!(normalise_domain(" Example.COM. "), "example.com");
+    }
+}
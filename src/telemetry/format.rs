@@ -0,0 +1,396 @@
+use std::fmt;
+
+use tracing::{
+    field::{Field, Visit},
+    Event, Level, Subscriber,
+};
+use tracing_subscriber::{
+    field::RecordFields,
+    fmt::{format::Writer, FmtContext, FormatEvent, FormatFields, FormattedFields},
+    registry::LookupSpan,
+};
+
+use super::Redactor;
+
+/// Format the fields of spans and events, the personal data is redacted.
+pub struct RedactingFields {
+    redactor: Redactor,
+    style: FieldStyle,
+}
+
+#[derive(Clone, Copy)]
+pub enum FieldStyle {
+    /// `name=value` pairs, the message is written first without its name.
+    Plain,
+    /// `name=value` pairs, the message is named `msg`.
+    Logfmt,
+}
+
+impl RedactingFields {
+    pub fn new(redactor: Redactor, style: FieldStyle) -> Self {
+        Self { redactor, style }
+    }
+}
+
+impl<'writer> FormatFields<'writer> for RedactingFields {
+    fn format_fields<R: RecordFields>(
+        &self,
+        mut writer: Writer<'writer>,
+        fields: R,
+    ) -> fmt::Result {
+        let mut collector = FieldCollector::new(&self.redactor);
+        fields.record(&mut collector);
+        collector.write(&mut writer, self.style)
+    }
+}
+
+/// A field value, strings are quoted unless they have been redacted.
+struct Value {
+    text: String,
+    quoted: bool,
+}
+
+struct FieldCollector<'a> {
+    redactor: &'a Redactor,
+    message: Option<Value>,
+    fields: Vec<(&'static str, Value)>,
+}
+
+impl<'a> FieldCollector<'a> {
+    fn new(redactor: &'a Redactor) -> Self {
+        Self {
+            redactor,
+            message: None,
+            fields: Vec::new(),
+        }
+    }
+
+    fn record(&mut self, field: &Field, text: String, quoted: bool) {
+        let value = if self.redactor.is_pii(field.name()) {
+            Value {
+                text: self.redactor.redact(&text),
+                quoted: false,
+            }
+        } else {
+            Value { text, quoted }
+        };
+
+        match field.name() {
+            "message" => self.message = Some(value),
+            // Metadata of the records forwarded by `tracing-log`
+            name if name.starts_with("log.") => {}
+            name => self.fields.push((name, value)),
+        }
+    }
+
+    fn write(&self, writer: &mut Writer<'_>, style: FieldStyle) -> fmt::Result {
+        let mut separator = "";
+        if let Some(message) = &self.message {
+            match style {
+                FieldStyle::Plain => write!(writer, "{}", message.text)?,
+                FieldStyle::Logfmt => write!(writer, "msg={}", logfmt_value(&message.text))?,
+            }
+            separator = " ";
+        }
+        for (name, value) in &self.fields {
+            write!(writer, "{}", separator)?;
+            match style {
+                FieldStyle::Plain if value.quoted => write!(writer, "{}={:?}", name, value.text)?,
+                FieldStyle::Plain => write!(writer, "{}={}", name, value.text)?,
+                FieldStyle::Logfmt => write!(writer, "{}={}", name, logfmt_value(&value.text))?,
+            }
+            separator = " ";
+        }
+        Ok(())
+    }
+}
+
+impl Visit for FieldCollector<'_> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.record(field, value.to_string(), true);
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.record(field, format!("{:?}", value), false);
+    }
+}
+
+/// Quote `value` if it cannot be written verbatim in a logfmt record.
+fn logfmt_value(value: &str) -> String {
+    let needs_quotes = value.is_empty()
+        || value
+            .chars()
+            .any(|c| c.is_whitespace() || c.is_control() || c == '"' || c == '=');
+    if needs_quotes {
+        format!("{:?}", value)
+    } else {
+        value.to_string()
+    }
+}
+
+fn timestamp() -> String {
+    chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true)
+}
+
+/// One record per line made of `key=value` pairs, the fields of the spans follow the fields of
+/// the event.
+pub struct Logfmt;
+
+impl<S, N> FormatEvent<S, N> for Logfmt
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        let metadata = event.metadata();
+        write!(
+            writer,
+            "ts={} level={} target={}",
+            timestamp(),
+            metadata.level().as_str().to_lowercase(),
+            logfmt_value(metadata.target())
+        )?;
+        if let Some(span) = ctx.event_scope().and_then(|mut scope| scope.next()) {
+            write!(writer, " span={}", logfmt_value(span.name()))?;
+        }
+
+        writer.write_char(' ')?;
+        ctx.format_fields(writer.by_ref(), event)?;
+
+        if let Some(scope) = ctx.event_scope() {
+            for span in scope.from_root() {
+                let extensions = span.extensions();
+                if let Some(fields) = extensions.get::<FormattedFields<N>>() {
+                    if !fields.is_empty() {
+                        write!(writer, " {}", fields.fields)?;
+                    }
+                }
+            }
+        }
+        writeln!(writer)
+    }
+}
+
+/// Multi-line human readable records, coloured when the output supports ANSI escapes.
+pub struct Pretty {
+    redactor: Redactor,
+}
+
+impl Pretty {
+    pub fn new(redactor: Redactor) -> Self {
+        Self { redactor }
+    }
+}
+
+impl<S, N> FormatEvent<S, N> for Pretty
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        let ansi = Ansi(writer.has_ansi_escapes());
+        let metadata = event.metadata();
+
+        let mut collector = FieldCollector::new(&self.redactor);
+        event.record(&mut collector);
+
+        write!(
+            writer,
+            "{} {} {}: ",
+            ansi.paint(DIMMED, &timestamp()),
+            ansi.paint(
+                level_colour(metadata.level()),
+                &format!("{:>5}", metadata.level())
+            ),
+            ansi.paint(BOLD, metadata.target()),
+        )?;
+        if let Some(message) = collector.message.take() {
+            write!(writer, "{}", message.text)?;
+        }
+        writeln!(writer)?;
+
+        if !collector.fields.is_empty() {
+            write!(writer, "    ")?;
+            collector.write(&mut writer, FieldStyle::Plain)?;
+            writeln!(writer)?;
+        }
+
+        if let Some(scope) = ctx.event_scope() {
+            for span in scope {
+                write!(
+                    writer,
+                    "    {} {}",
+                    ansi.paint(DIMMED, "in"),
+                    ansi.paint(BOLD, span.name())
+                )?;
+                let extensions = span.extensions();
+                if let Some(fields) = extensions.get::<FormattedFields<N>>() {
+                    if !fields.is_empty() {
+                        write!(writer, " {} {}", ansi.paint(DIMMED, "with"), fields.fields)?;
+                    }
+                }
+                writeln!(writer)?;
+            }
+        }
+        writeln!(writer)
+    }
+}
+
+const BOLD: &str = "1";
+const DIMMED: &str = "2";
+
+fn level_colour(level: &Level) -> &'static str {
+    match *level {
+        Level::ERROR => "31",
+        Level::WARN => "33",
+        Level::INFO => "32",
+        Level::DEBUG => "34",
+        Level::TRACE => "35",
+    }
+}
+
+/// Wrap text in ANSI escapes when they are enabled.
+#[derive(Clone, Copy)]
+struct Ansi(bool);
+
+impl Ansi {
+    fn paint(&self, style: &str, text: &str) -> String {
+        if self.0 {
+            format!("\x1b[{}m{}\x1b[0m", style, text)
+        } else {
+            text.to_string()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use tracing_bunyan_formatter::{BunyanFormattingLayer, JsonStorageLayer};
+    use tracing_subscriber::{fmt::MakeWriter, layer::SubscriberExt, Layer, Registry};
+
+    use super::{FieldStyle, Logfmt, Pretty, RedactingFields};
+    use crate::{
+        configuration::{RedactionMode, RedactionSettings},
+        telemetry::{redaction::RedactingMakeWriter, Redactor},
+    };
+
+    #[derive(Clone, Default)]
+    struct Output(Arc<Mutex<Vec<u8>>>);
+
+    impl Output {
+        fn contents(&self) -> String {
+            String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+        }
+    }
+
+    impl std::io::Write for Output {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl<'a> MakeWriter<'a> for Output {
+        type Writer = Self;
+
+        fn make_writer(&'a self) -> Self::Writer {
+            self.clone()
+        }
+    }
+
+    fn redactor() -> Redactor {
+        Redactor::new(&RedactionSettings {
+            mode: RedactionMode::Mask,
+            ..RedactionSettings::default()
+        })
+    }
+
+    fn log_with(layer: impl tracing_subscriber::Layer<Registry> + Send + Sync) {
+        let subscriber = Registry::default().with(layer);
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!("subscribe", subscriber_email = "ursula@example.com");
+            let _guard = span.enter();
+            tracing::info!(name = "Ursula", attempt = 1, "A new subscriber");
+        });
+    }
+
+    #[test]
+    fn logfmt_records_are_redacted() {
+        let output = Output::default();
+        log_with(
+            tracing_subscriber::fmt::layer()
+                .event_format(Logfmt)
+                .fmt_fields(RedactingFields::new(redactor(), FieldStyle::Logfmt))
+                .with_writer(output.clone()),
+        );
+
+        let record = output.contents();
+        assert!(record.starts_with("ts="));
+        assert!(record.contains(
+            r#"level=info target=zero2prod::telemetry::format::tests span=subscribe msg="A new subscriber" name=[REDACTED] attempt=1 subscriber_email=[REDACTED]"#
+        ));
+        assert!(!record.contains("ursula"));
+    }
+
+    #[test]
+    fn pretty_records_are_redacted() {
+        let output = Output::default();
+        log_with(
+            tracing_subscriber::fmt::layer()
+                .event_format(Pretty::new(redactor()))
+                .fmt_fields(RedactingFields::new(redactor(), FieldStyle::Plain))
+                .with_ansi(false)
+                .with_writer(output.clone()),
+        );
+
+        let record = output.contents();
+        assert!(record.contains("INFO zero2prod::telemetry::format::tests: A new subscriber"));
+        assert!(record.contains("    name=[REDACTED] attempt=1\n"));
+        assert!(record.contains("    in subscribe with subscriber_email=[REDACTED]\n"));
+        assert!(!record.contains("Ursula"));
+    }
+
+    #[test]
+    fn bunyan_records_are_redacted() {
+        let output = Output::default();
+        let subscriber =
+            Registry::default().with(JsonStorageLayer.and_then(BunyanFormattingLayer::new(
+                "zero2prod".into(),
+                RedactingMakeWriter::new(output.clone(), redactor(), "zero2prod"),
+            )));
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!("subscribe", subscriber_email = "ursula@example.com");
+            let _guard = span.enter();
+            tracing::info!(
+                name = "Ursula Le Guin",
+                subscriber_name = "Ursula Le Guin",
+                "A new subscriber"
+            );
+        });
+
+        let contents = output.contents();
+        for line in contents.lines() {
+            let record: serde_json::Value = serde_json::from_str(line).unwrap();
+            assert_eq!(record["name"], "zero2prod");
+        }
+        assert!(contents.contains(r#""subscriber_email":"[REDACTED]""#));
+        assert!(contents.contains(r#""subscriber_name":"[REDACTED]""#));
+        assert!(!contents.contains("Ursula"));
+        assert!(!contents.contains("ursula"));
+    }
+}
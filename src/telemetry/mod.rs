@@ -0,0 +1,136 @@
+mod format;
+mod redaction;
+
+use std::sync::{Arc, Mutex};
+
+use tracing::{subscriber::set_global_default, Subscriber};
+use tracing_bunyan_formatter::{BunyanFormattingLayer, JsonStorageLayer};
+use tracing_log::LogTracer;
+use tracing_subscriber::{
+    fmt::{self, format::FmtSpan, MakeWriter},
+    layer::{Layered, SubscriberExt},
+    reload, EnvFilter, Layer, Registry,
+};
+
+use self::{
+    format::{FieldStyle, Logfmt, Pretty, RedactingFields},
+    redaction::RedactingMakeWriter,
+};
+use crate::configuration::{LogFormat, TelemetrySettings};
+
+pub use self::redaction::{Redacted, Redactor};
+
+type FilteredRegistry = Layered<reload::Layer<EnvFilter, Registry>, Registry>;
+
+/// Handle to change the log filter of a running subscriber.
+#[derive(Clone)]
+pub struct LogFilterHandle {
+    handle: reload::Handle<EnvFilter, Registry>,
+    /// Incremented each time the filter changes.
+    generation: Arc<Mutex<u64>>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum LogFilterError {
+    #[error("invalid log filter directives")]
+    InvalidDirectives(#[from] tracing_subscriber::filter::ParseError),
+    #[error("the subscriber is no longer available")]
+    Reload(#[from] reload::Error),
+}
+
+impl LogFilterHandle {
+    fn new(handle: reload::Handle<EnvFilter, Registry>) -> Self {
+        Self {
+            handle,
+            generation: Arc::new(Mutex::new(0)),
+        }
+    }
+
+    /// Replace the current filter with the given directives (e.g. `info,zero2prod=debug`), it
+    /// returns the generation of the new filter.
+    pub fn set(&self, directives: &str) -> Result<u64, LogFilterError> {
+        let env_filter = EnvFilter::try_new(directives)?;
+        let mut generation = self.generation.lock().unwrap();
+        self.handle.reload(env_filter)?;
+        *generation += 1;
+        Ok(*generation)
+    }
+
+    /// Replace the current filter only if it has not changed since `generation`, it returns
+    /// `false` if the filter was left untouched.
+    pub fn set_if_unchanged(
+        &self,
+        directives: &str,
+        generation: u64,
+    ) -> Result<bool, LogFilterError> {
+        let env_filter = EnvFilter::try_new(directives)?;
+        let mut current = self.generation.lock().unwrap();
+        if *current != generation {
+            return Ok(false);
+        }
+        self.handle.reload(env_filter)?;
+        *current += 1;
+        Ok(true)
+    }
+
+    /// The directives of the current filter.
+    pub fn current(&self) -> Result<String, LogFilterError> {
+        Ok(self
+            .handle
+            .with_current(|env_filter| env_filter.to_string())?)
+    }
+}
+
+/// Build a subscriber writing to `sink` in the configured format, `RUST_LOG` takes precedence
+/// over the configured log filter.
+///
+/// The human readable formats log the closing of the spans, like the Bunyan format does. The
+/// redactor of the PII fields is also installed for [`Redacted`].
+pub fn get_subscriber(
+    name: &str,
+    settings: &TelemetrySettings,
+    sink: impl for<'a> MakeWriter<'a> + 'static + Send + Sync,
+) -> (impl Subscriber + Send + Sync, LogFilterHandle) {
+    let env_filter =
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(&settings.log_filter));
+    let (env_filter, handle) = reload::Layer::new(env_filter);
+
+    let redactor = Redactor::new(&settings.redaction);
+    redactor.install();
+
+    let formatting_layer: Box<dyn Layer<FilteredRegistry> + Send + Sync> = match settings.format {
+        LogFormat::Bunyan => Box::new(JsonStorageLayer.and_then(BunyanFormattingLayer::new(
+            name.into(),
+            RedactingMakeWriter::new(sink, redactor, name),
+        ))),
+        LogFormat::Pretty => Box::new(
+            fmt::layer()
+                .with_span_events(FmtSpan::CLOSE)
+                .event_format(Pretty::new(redactor.clone()))
+                .fmt_fields(RedactingFields::new(redactor, FieldStyle::Plain))
+                .with_writer(sink),
+        ),
+        LogFormat::Compact => Box::new(
+            fmt::layer()
+                .with_span_events(FmtSpan::CLOSE)
+                .compact()
+                .fmt_fields(RedactingFields::new(redactor, FieldStyle::Plain))
+                .with_writer(sink),
+        ),
+        LogFormat::Logfmt => Box::new(
+            fmt::layer()
+                .with_span_events(FmtSpan::CLOSE)
+                .event_format(Logfmt)
+                .fmt_fields(RedactingFields::new(redactor, FieldStyle::Logfmt))
+                .with_writer(sink),
+        ),
+    };
+
+    let subscriber = Registry::default().with(env_filter).with(formatting_layer);
+    (subscriber, LogFilterHandle::new(handle))
+}
+
+pub fn init_subscriber(subscriber: impl Subscriber + Send + Sync) {
+    LogTracer::init().expect("Failed to set logger");
+    set_global_default(subscriber).expect("Failed to set subscriber");
+}
@@ -0,0 +1,300 @@
+use std::{collections::HashSet, fmt, io, sync::Arc};
+
+use hmac::{Hmac, Mac};
+use once_cell::sync::OnceCell;
+use sha2::Sha256;
+use tracing_subscriber::fmt::MakeWriter;
+
+use crate::{
+    configuration::{RedactionMode, RedactionSettings},
+    secret::Secret,
+};
+
+/// Placeholder written in place of the masked values.
+const MASK: &str = "[REDACTED]";
+/// Prefix of the hashed values.
+const HASH_PREFIX: &str = "pii:";
+/// Number of bytes of the HMAC kept in the logs.
+const HASH_LENGTH: usize = 8;
+
+/// Fields written by the Bunyan formatter itself, they never hold personal data. The `name` of
+/// the record is the name of the application, it is told apart from the fields of the spans and
+/// of the events with the same name by its value.
+const BUNYAN_FIELDS: &[&str] = &[
+    "v", "msg", "level", "hostname", "pid", "time", "target", "line", "file",
+];
+
+static REDACTOR: OnceCell<Redactor> = OnceCell::new();
+
+/// Rewrite personal data before it reaches the logs.
+#[derive(Clone)]
+pub struct Redactor {
+    mode: RedactionMode,
+    fields: Arc<HashSet<String>>,
+    hash_key: Option<Secret>,
+}
+
+impl Redactor {
+    pub fn new(settings: &RedactionSettings) -> Self {
+        Self {
+            mode: settings.mode,
+            fields: Arc::new(settings.fields.iter().cloned().collect()),
+            hash_key: settings.hash_key.clone(),
+        }
+    }
+
+    /// Make this redactor the one used by [`Redacted`], only the first call has effect.
+    pub fn install(&self) {
+        let _ = REDACTOR.set(self.clone());
+    }
+
+    /// Whether the field `name` holds personal data that must be redacted.
+    pub fn is_pii(&self, name: &str) -> bool {
+        self.mode != RedactionMode::None && self.fields.contains(name)
+    }
+
+    /// Redact `value`, the values that are already redacted are left untouched.
+    pub fn redact(&self, value: &str) -> String {
+        match self.mode {
+            RedactionMode::None => value.to_string(),
+            _ if is_redacted(value) => value.to_string(),
+            RedactionMode::Mask => MASK.to_string(),
+            RedactionMode::Hash => {
+                let key = self.hash_key.as_ref().map_or("", Secret::expose);
+                let mut mac = Hmac::<Sha256>::new_from_slice(key.as_bytes())
+                    .expect("HMAC can take a key of any size");
+                mac.update(value.as_bytes());
+                let hash = mac.finalize().into_bytes();
+                format!("{}{}", HASH_PREFIX, hex::encode(&hash[..HASH_LENGTH]))
+            }
+        }
+    }
+
+    /// Redact the PII fields of a JSON record written by the application `app_name`, the record
+    /// is returned unchanged if it cannot be parsed.
+    fn redact_json(&self, record: &[u8], app_name: &str) -> Option<Vec<u8>> {
+        let mut record: serde_json::Map<String, serde_json::Value> =
+            serde_json::from_slice(record).ok()?;
+        let mut redacted = false;
+        for (name, value) in record.iter_mut() {
+            let record_field = BUNYAN_FIELDS.contains(&name.as_str())
+                || (name == "name" && value.as_str() == Some(app_name));
+            if self.is_pii(name) && !record_field {
+                let clear = match value.take() {
+                    serde_json::Value::String(value) => value,
+                    value => value.to_string(),
+                };
+                *value = serde_json::Value::String(self.redact(&clear));
+                redacted = true;
+            }
+        }
+        if redacted {
+            serde_json::to_vec(&record).ok()
+        } else {
+            None
+        }
+    }
+}
+
+fn is_redacted(value: &str) -> bool {
+    value == MASK
+        || value.strip_prefix(HASH_PREFIX).is_some_and(|hash| {
+            hash.len() == 2 * HASH_LENGTH && hash.bytes().all(|b| b.is_ascii_hexdigit())
+        })
+}
+
+/// Display a value holding personal data (e.g. `%email.redacted()`) through the installed
+/// redactor, the value is masked if no redactor was installed.
+pub struct Redacted<'a, T>(&'a T);
+
+impl<'a, T: fmt::Display> Redacted<'a, T> {
+    pub fn new(value: &'a T) -> Self {
+        Self(value)
+    }
+}
+
+impl<T: fmt::Display> fmt::Display for Redacted<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match REDACTOR.get() {
+            Some(redactor) => f.write_str(&redactor.redact(&self.0.to_string())),
+            None => f.write_str(MASK),
+        }
+    }
+}
+
+impl<T: fmt::Display> fmt::Debug for Redacted<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+/// Redact the JSON records, one per line, written by the Bunyan formatter.
+pub struct RedactingMakeWriter<M> {
+    make_writer: M,
+    redactor: Redactor,
+    app_name: String,
+}
+
+impl<M> RedactingMakeWriter<M> {
+    /// `app_name` is the name given to the Bunyan formatter.
+    pub fn new(make_writer: M, redactor: Redactor, app_name: &str) -> Self {
+        Self {
+            make_writer,
+            redactor,
+            app_name: app_name.to_owned(),
+        }
+    }
+}
+
+impl<'a, M: MakeWriter<'a>> MakeWriter<'a> for RedactingMakeWriter<M> {
+    type Writer = RedactingWriter<'a, M::Writer>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        RedactingWriter {
+            writer: self.make_writer.make_writer(),
+            redactor: &self.redactor,
+            app_name: &self.app_name,
+            buffer: Vec::new(),
+        }
+    }
+}
+
+pub struct RedactingWriter<'a, W: io::Write> {
+    writer: W,
+    redactor: &'a Redactor,
+    app_name: &'a str,
+    buffer: Vec<u8>,
+}
+
+impl<W: io::Write> RedactingWriter<'_, W> {
+    fn write_line(&mut self, line: &[u8]) -> io::Result<()> {
+        let content = line.strip_suffix(b"\n").unwrap_or(line);
+        match self.redactor.redact_json(content, self.app_name) {
+            Some(mut redacted) => {
+                redacted.extend_from_slice(&line[content.len()..]);
+                self.writer.write_all(&redacted)
+            }
+            None => self.writer.write_all(line),
+        }
+    }
+}
+
+impl<W: io::Write> io::Write for RedactingWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        while let Some(end) = self.buffer.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=end).collect();
+            self.write_line(&line)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+impl<W: io::Write> Drop for RedactingWriter<'_, W> {
+    fn drop(&mut self) {
+        if !self.buffer.is_empty() {
+            let line = std::mem::take(&mut self.buffer);
+            let _ = self.write_line(&line);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::{RedactingMakeWriter, Redactor};
+    use crate::{
+        configuration::{RedactionMode, RedactionSettings},
+        secret::Secret,
+    };
+    use tracing_subscriber::fmt::MakeWriter;
+
+    fn redactor(mode: RedactionMode) -> Redactor {
+        Redactor::new(&RedactionSettings {
+            mode,
+            hash_key: Some(Secret::new("a-hash-key".into())),
+            ..RedactionSettings::default()
+        })
+    }
+
+    #[test]
+    fn only_pii_fields_are_redacted() {
+        let redactor = redactor(RedactionMode::Mask);
+        assert!(redactor.is_pii("subscriber_email"));
+        assert!(!redactor.is_pii("request_id"));
+        assert!(!self::redactor(RedactionMode::None).is_pii("subscriber_email"));
+    }
+
+    #[test]
+    fn masked_values_are_hidden() {
+        let redactor = redactor(RedactionMode::Mask);
+        assert_eq!(redactor.redact("ursula@example.com"), "[REDACTED]");
+    }
+
+    #[test]
+    fn hashed_values_can_be_correlated() {
+        let redactor = redactor(RedactionMode::Hash);
+        let hash = redactor.redact("ursula@example.com");
+
+        assert!(!hash.contains("ursula"));
+        assert_eq!(hash, redactor.redact("ursula@example.com"));
+        assert_ne!(hash, redactor.redact("le_guin@example.com"));
+        assert_eq!(hash, redactor.redact(&hash));
+    }
+
+    #[test]
+    fn hashes_depend_on_the_key() {
+        let other = Redactor::new(&RedactionSettings {
+            mode: RedactionMode::Hash,
+            hash_key: Some(Secret::new("another-hash-key".into())),
+            ..RedactionSettings::default()
+        });
+        assert_ne!(
+            redactor(RedactionMode::Hash).redact("ursula@example.com"),
+            other.redact("ursula@example.com")
+        );
+    }
+
+    #[test]
+    fn json_records_are_redacted() {
+        let make_writer =
+            RedactingMakeWriter::new(Vec::new, redactor(RedactionMode::Mask), "zero2prod");
+        let mut output = Vec::new();
+        {
+            let mut writer = make_writer.make_writer();
+            writer
+                .write_all(br#"{"name":"zero2prod","msg":"Subscribed","email":"ursula@example.com","attempt":1}"#)
+                .unwrap();
+            writer.write_all(b"\n").unwrap();
+            output.append(&mut writer.writer);
+        }
+
+        let record: serde_json::Value = serde_json::from_slice(&output).unwrap();
+        assert_eq!(record["email"], "[REDACTED]");
+        assert_eq!(record["name"], "zero2prod");
+        assert_eq!(record["msg"], "Subscribed");
+        assert_eq!(record["attempt"], 1);
+    }
+
+    #[test]
+    fn only_the_name_of_the_application_is_kept() {
+        let make_writer =
+            RedactingMakeWriter::new(Vec::new, redactor(RedactionMode::Mask), "zero2prod");
+        let mut output = Vec::new();
+        {
+            let mut writer = make_writer.make_writer();
+            writer
+                .write_all(b"{\"name\":\"Ursula\",\"msg\":\"A new subscriber\"}\n")
+                .unwrap();
+            output.append(&mut writer.writer);
+        }
+
+        let record: serde_json::Value = serde_json::from_slice(&output).unwrap();
+        assert_eq!(record["name"], "[REDACTED]");
+    }
+}
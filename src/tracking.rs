@@ -0,0 +1,378 @@
+use hmac::{Hmac, Mac};
+use once_cell::sync::Lazy;
+use regex::{Captures, Regex};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use uuid::Uuid;
+
+use crate::secret::Secret;
+
+/// Number of bytes of the HMAC kept in the tokens.
+const SIGNATURE_LENGTH: usize = 16;
+
+/// A transparent 1x1 GIF, served by the open tracking route.
+pub const PIXEL: &[u8] = &[
+    0x47, 0x49, 0x46, 0x38, 0x39, 0x61, 0x01, 0x00, 0x01, 0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0xff, 0xff, 0xff, 0x21, 0xf9, 0x04, 0x01, 0x00, 0x00, 0x00, 0x00, 0x2c, 0x00, 0x00, 0x00, 0x00,
+    0x01, 0x00, 0x01, 0x00, 0x00, 0x02, 0x02, 0x44, 0x01, 0x00, 0x3b,
+];
+
+/// The `href` attributes of the anchors pointing to an absolute HTTP URL.
+static LINK: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r#"(?i)(<a\s[^>]*?href\s*=\s*)(?:"(https?://[^"]*)"|'(https?://[^']*)')"#)
+        .expect("The link pattern is valid")
+});
+static BODY_END: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?i)</body\s*>").expect("The body pattern is valid"));
+
+/// The tracking enabled for a newsletter issue.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+pub struct TrackingOptions {
+    #[serde(default)]
+    pub opens: bool,
+    #[serde(default)]
+    pub clicks: bool,
+}
+
+impl TrackingOptions {
+    pub fn is_enabled(&self) -> bool {
+        self.opens || self.clicks
+    }
+}
+
+/// What a subscriber did with a tracked issue.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TrackingEvent<'a> {
+    Open,
+    /// A link to `url` was followed.
+    Click {
+        url: &'a str,
+    },
+}
+
+impl TrackingEvent<'_> {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            TrackingEvent::Open => "open",
+            TrackingEvent::Click { .. } => "click",
+        }
+    }
+
+    pub fn url(&self) -> Option<&str> {
+        match self {
+            TrackingEvent::Open => None,
+            TrackingEvent::Click { url } => Some(url),
+        }
+    }
+}
+
+/// The engagement with a newsletter issue, only the subscribers who did not opt out are counted.
+#[derive(Debug, Serialize)]
+pub struct IssueStats {
+    pub issue_id: Uuid,
+    pub title: String,
+    pub track_opens: bool,
+    pub track_clicks: bool,
+    pub unique_opens: i64,
+    pub unique_clicks: i64,
+    pub links: Vec<LinkStats>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LinkStats {
+    pub url: String,
+    pub unique_clicks: i64,
+}
+
+/// What a tracking link refers to, it is carried by a signed token.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum TrackingToken {
+    Open {
+        issue_id: Uuid,
+        subscriber_id: Uuid,
+    },
+    Click {
+        issue_id: Uuid,
+        subscriber_id: Uuid,
+        url: String,
+    },
+    OptOut {
+        subscriber_id: Uuid,
+    },
+    /// The preference centre of the subscriber.
+    Preferences {
+        subscriber_id: Uuid,
+    },
+    /// A subscription form served at `issued_at`, a Unix timestamp in seconds.
+    Form {
+        issued_at: i64,
+    },
+}
+
+impl TrackingToken {
+    fn to_payload(&self) -> String {
+        match self {
+            TrackingToken::Open {
+                issue_id,
+                subscriber_id,
+            } => format!("o:{}:{}", issue_id, subscriber_id),
+            TrackingToken::Click {
+                issue_id,
+                subscriber_id,
+                url,
+            } => format!("c:{}:{}:{}", issue_id, subscriber_id, url),
+            TrackingToken::OptOut { subscriber_id } => format!("x:{}", subscriber_id),
+            TrackingToken::Preferences { subscriber_id } => format!("p:{}", subscriber_id),
+            TrackingToken::Form { issued_at } => format!("f:{}", issued_at),
+        }
+    }
+
+    fn from_payload(payload: &str) -> Option<Self> {
+        let (kind, rest) = payload.split_once(':')?;
+        match kind {
+            "o" => {
+                let (issue_id, subscriber_id) = rest.split_once(':')?;
+                Some(TrackingToken::Open {
+                    issue_id: issue_id.parse().ok()?,
+                    subscriber_id: subscriber_id.parse().ok()?,
+                })
+            }
+            "c" => {
+                let mut parts = rest.splitn(3, ':');
+                let issue_id = parts.next()?.parse().ok()?;
+                let subscriber_id = parts.next()?.parse().ok()?;
+                let url = parts.next()?;
+                match Url::parse(url).ok()?.scheme() {
+                    "http" | "https" => Some(TrackingToken::Click {
+                        issue_id,
+                        subscriber_id,
+                        url: url.to_string(),
+                    }),
+                    _ => None,
+                }
+            }
+            "x" => Some(TrackingToken::OptOut {
+                subscriber_id: rest.parse().ok()?,
+            }),
+            "p" => Some(TrackingToken::Preferences {
+                subscriber_id: rest.parse().ok()?,
+            }),
+            "f" => Some(TrackingToken::Form {
+                issued_at: rest.parse().ok()?,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Sign the tracking tokens, so that the links cannot be forged to record fake events or to
+/// redirect to arbitrary destinations.
+#[derive(Clone)]
+pub struct TrackingKey(Secret);
+
+impl TrackingKey {
+    pub fn new(key: Secret) -> Self {
+        Self(key)
+    }
+
+    pub fn encode(&self, token: &TrackingToken) -> String {
+        let payload = token.to_payload();
+        let signature = self.mac(&payload).finalize().into_bytes();
+        format!(
+            "{}.{}",
+            base64::encode_config(&payload, base64::URL_SAFE_NO_PAD),
+            base64::encode_config(&signature[..SIGNATURE_LENGTH], base64::URL_SAFE_NO_PAD)
+        )
+    }
+
+    /// The token carried by `encoded`, `None` if it is malformed or if its signature is invalid.
+    pub fn decode(&self, encoded: &str) -> Option<TrackingToken> {
+        let (payload, signature) = encoded.split_once('.')?;
+        let payload = base64::decode_config(payload, base64::URL_SAFE_NO_PAD).ok()?;
+        let payload = String::from_utf8(payload).ok()?;
+        let signature = base64::decode_config(signature, base64::URL_SAFE_NO_PAD).ok()?;
+        if signature.len() != SIGNATURE_LENGTH {
+            return None;
+        }
+        self.mac(&payload).verify_truncated_left(&signature).ok()?;
+        TrackingToken::from_payload(&payload)
+    }
+
+    /// The HTML content of an issue sent to `subscriber_id`, with its tracked links, its pixel
+    /// and a link to opt out of the tracking.
+    pub fn personalise_html(
+        &self,
+        base_url: &str,
+        html: &str,
+        options: TrackingOptions,
+        issue_id: Uuid,
+        subscriber_id: Uuid,
+    ) -> String {
+        if !options.is_enabled() {
+            return html.to_string();
+        }
+
+        let mut html = if options.clicks {
+            rewrite_links(html, |url| {
+                let token = self.encode(&TrackingToken::Click {
+                    issue_id,
+                    subscriber_id,
+                    url: url.to_string(),
+                });
+                format!("{}/t/c/{}", base_url, token)
+            })
+        } else {
+            html.to_string()
+        };
+
+        let opt_out = self.encode(&TrackingToken::OptOut { subscriber_id });
+        let mut footer = format!(
+            r#"<p><a href="{}/t/opt_out/{}">Stop tracking the emails I read</a></p>"#,
+            base_url, opt_out
+        );
+        if options.opens {
+            let open = self.encode(&TrackingToken::Open {
+                issue_id,
+                subscriber_id,
+            });
+            footer.push_str(&format!(
+                r#"<img src="{}/t/o/{}" width="1" height="1" alt="" style="display:none">"#,
+                base_url, open
+            ));
+        }
+        insert_before_body_end(&mut html, &footer);
+        html
+    }
+
+    /// The link to the preference centre of `subscriber_id`.
+    pub fn preferences_url(&self, base_url: &str, subscriber_id: Uuid) -> String {
+        let token = self.encode(&TrackingToken::Preferences { subscriber_id });
+        format!("{}/preferences/{}", base_url, token)
+    }
+
+    fn mac(&self, payload: &str) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.0.expose().as_bytes())
+            .expect("HMAC can take a key of any size");
+        mac.update(payload.as_bytes());
+        mac
+    }
+}
+
+/// Replace the destination of the absolute HTTP links of `html` by `rewrite(destination)`.
+fn rewrite_links(html: &str, rewrite: impl Fn(&str) -> String) -> String {
+    LINK.replace_all(html, |captures: &Captures| {
+        let url = captures
+            .get(2)
+            .or_else(|| captures.get(3))
+            .map_or("", |url| url.as_str());
+        format!(
+            r#"{}"{}""#,
+            &captures[1],
+            rewrite(&url.replace("&amp;", "&"))
+        )
+    })
+    .into_owned()
+}
+
+pub(crate) fn insert_before_body_end(html: &mut String, content: &str) {
+    match BODY_END.find_iter(html).last() {
+        Some(body_end) => html.insert_str(body_end.start(), content),
+        None => html.push_str(content),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::{rewrite_links, TrackingKey, TrackingOptions, TrackingToken};
+    use crate::secret::Secret;
+
+    fn key() -> TrackingKey {
+        TrackingKey::new(Secret::new("my-tracking-key".into()))
+    }
+
+    #[test]
+    fn tokens_are_decoded_with_the_same_key() {
+        let token = TrackingToken::Click {
+            issue_id: Uuid::new_v4(),
+            subscriber_id: Uuid::new_v4(),
+            url: "https://example.com/a?b=c:d".into(),
+        };
+
+        let encoded = key().encode(&token);
+
+        assert_eq!(key().decode(&encoded), Some(token));
+        let other_key = TrackingKey::new(Secret::new("another-key".into()));
+        assert_eq!(other_key.decode(&encoded), None);
+    }
+
+    #[test]
+    fn tampered_tokens_are_rejected() {
+        let token = TrackingToken::OptOut {
+            subscriber_id: Uuid::new_v4(),
+        };
+        let encoded = key().encode(&token);
+        let (_, signature) = encoded.split_once('.').unwrap();
+        let forged = format!(
+            "{}.{}",
+            base64::encode_config(
+                "c:00000000-0000-0000-0000-000000000000:00000000-0000-0000-0000-000000000000:https://evil.com",
+                base64::URL_SAFE_NO_PAD
+            ),
+            signature
+        );
+
+        assert_eq!(key().decode(&forged), None);
+        assert_eq!(key().decode("not-a-token"), None);
+        assert_eq!(key().decode(&encoded[..encoded.len() - 1]), None);
+    }
+
+    #[test]
+    fn only_absolute_http_links_are_rewritten() {
+        let html = r#"<a href="https://example.com/?a=1&amp;b=2">x</a> <A class='y' HREF='http://example.com'>y</A> <a href="mailto:a@b.c">z</a> <a href="/relative">r</a>"#;
+
+        let rewritten = rewrite_links(html, |url| format!("tracked({})", url));
+
+        assert_eq!(
+            rewritten,
+            r#"<a href="tracked(https://example.com/?a=1&b=2)">x</a> <A class='y' HREF="tracked(http://example.com)">y</A> <a href="mailto:a@b.c">z</a> <a href="/relative">r</a>"#
+        );
+    }
+
+    #[test]
+    fn the_pixel_is_added_before_the_end_of_the_body() {
+        let options = TrackingOptions {
+            opens: true,
+            clicks: false,
+        };
+
+        let html = key().personalise_html(
+            "http://localhost",
+            "<html><body><p>Hi</p></body></html>",
+            options,
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+        );
+
+        assert!(html.starts_with("<html><body><p>Hi</p><p><a href=\"http://localhost/t/opt_out/"));
+        assert!(html.contains(r#"<img src="http://localhost/t/o/"#));
+        assert!(html.ends_with("</body></html>"));
+    }
+
+    #[test]
+    fn untracked_content_is_left_untouched() {
+        let html = r#"<p><a href="https://example.com">x</a></p>"#;
+
+        let personalised = key().personalise_html(
+            "http://localhost",
+            html,
+            TrackingOptions::default(),
+            Uuid::new_v4(),
+            Uuid::new_v4(),
+        );
+
+        assert_eq!(personalised, html);
+    }
+}
@@ -0,0 +1,210 @@
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{create_dead_letter, spawn_app, TestApp};
+
+async fn dead_letters(app: &TestApp) -> Vec<serde_json::Value> {
+    app.get_dead_letters(&app.admin_token)
+        .await
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn dead_letters_require_the_admin_token() {
+    let app = spawn_app().await;
+
+    let response = app.get_dead_letters("wrong-token").await;
+    assert_eq!(response.status().as_u16(), 401);
+    let response = app.post_dead_letters_replay("wrong-token").await;
+    assert_eq!(response.status().as_u16(), 401);
+    let response = app.delete_dead_letters("wrong-token").await;
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn a_rejected_email_is_listed_with_its_last_error() {
+    let app = spawn_app().await;
+    create_dead_letter(&app, "ursula@example.com").await;
+
+    let dead_letters = dead_letters(&app).await;
+
+    assert_eq!(dead_letters.len(), 1);
+    assert_eq!(dead_letters[0]["recipient"], "ursula@example.com");
+    assert_eq!(dead_letters[0]["subject"], "Welcome!");
+    assert_eq!(dead_letters[0]["attempts"], 1);
+    assert!(dead_letters[0]["last_error"]
+        .as_str()
+        .unwrap()
+        .contains("422"));
+    // The content is only part of the details
+    assert!(dead_letters[0].get("text_content").is_none());
+
+    let id = dead_letters[0]["id"].as_str().unwrap();
+    let response = app.get_dead_letter(&app.admin_token, id).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let details: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(details["recipient"], "ursula@example.com");
+    assert!(details["text_content"]
+        .as_str()
+        .unwrap()
+        .contains("/subscriptions/confirm?token="));
+}
+
+#[tokio::test]
+async fn an_unknown_dead_letter_is_not_found() {
+    let app = spawn_app().await;
+    let id = uuid::Uuid::new_v4().to_string();
+
+    let response = app.get_dead_letter(&app.admin_token, &id).await;
+    assert_eq!(response.status().as_u16(), 404);
+    let response = app.post_dead_letter_replay(&app.admin_token, &id).await;
+    assert_eq!(response.status().as_u16(), 404);
+    let response = app.delete_dead_letter(&app.admin_token, &id).await;
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn a_replayed_dead_letter_is_sent_again() {
+    let app = spawn_app().await;
+    create_dead_letter(&app, "ursula@example.com").await;
+    create_dead_letter(&app, "octavia@example.com").await;
+    let id = dead_letters(&app)
+        .await
+        .into_iter()
+        .find(|dead_letter| dead_letter["recipient"] == "ursula@example.com")
+        .unwrap()["id"]
+        .as_str()
+        .unwrap()
+        .to_owned();
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_dead_letter_replay(&app.admin_token, &id).await;
+
+    assert_eq!(response.status().as_u16(), 202);
+    app.dispatch_pending_emails().await;
+    let dead_letters = dead_letters(&app).await;
+    assert_eq!(dead_letters.len(), 1);
+    assert_eq!(dead_letters[0]["recipient"], "octavia@example.com");
+}
+
+#[tokio::test]
+async fn the_dead_letters_are_listed_by_page() {
+    let app = spawn_app().await;
+    for email in [
+        "ursula@example.com",
+        "octavia@example.com",
+        "nora@example.com",
+    ] {
+        create_dead_letter(&app, email).await;
+    }
+
+    let first_page: Vec<serde_json::Value> = app
+        .get_dead_letters_page(&app.admin_token, &[("limit", "2")])
+        .await
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let last = first_page[1]["id"].as_str().unwrap();
+    let second_page: Vec<serde_json::Value> = app
+        .get_dead_letters_page(&app.admin_token, &[("limit", "2"), ("after", last)])
+        .await
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+
+    let recipients: Vec<_> = first_page
+        .iter()
+        .chain(&second_page)
+        .map(|dead_letter| dead_letter["recipient"].as_str().unwrap())
+        .collect();
+    assert_eq!(first_page.len(), 2);
+    assert_eq!(
+        recipients,
+        [
+            "nora@example.com",
+            "octavia@example.com",
+            "ursula@example.com"
+        ]
+    );
+}
+
+#[tokio::test]
+async fn an_invalid_page_of_dead_letters_is_rejected() {
+    let app = spawn_app().await;
+    let unknown = uuid::Uuid::new_v4().to_string();
+
+    for page in [
+        vec![("limit", "0")],
+        vec![("limit", "1001")],
+        vec![("after", unknown.as_str())],
+    ] {
+        let response = app.get_dead_letters_page(&app.admin_token, &page).await;
+
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "the page {:?} was accepted",
+            page
+        );
+    }
+}
+
+#[tokio::test]
+async fn all_the_dead_letters_can_be_replayed() {
+    let app = spawn_app().await;
+    create_dead_letter(&app, "ursula@example.com").await;
+    create_dead_letter(&app, "octavia@example.com").await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_dead_letters_replay(&app.admin_token).await;
+
+    assert_eq!(response.status().as_u16(), 202);
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(report["count"], 2);
+    app.dispatch_pending_emails().await;
+    assert!(dead_letters(&app).await.is_empty());
+}
+
+#[tokio::test]
+async fn purged_dead_letters_are_deleted() {
+    let app = spawn_app().await;
+    create_dead_letter(&app, "ursula@example.com").await;
+    create_dead_letter(&app, "octavia@example.com").await;
+    create_dead_letter(&app, "ted@example.com").await;
+    let id = dead_letters(&app).await[0]["id"]
+        .as_str()
+        .unwrap()
+        .to_owned();
+
+    let response = app.delete_dead_letter(&app.admin_token, &id).await;
+    assert_eq!(response.status().as_u16(), 204);
+    assert_eq!(dead_letters(&app).await.len(), 2);
+
+    let response = app.delete_dead_letters(&app.admin_token).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(report["count"], 2);
+    assert!(dead_letters(&app).await.is_empty());
+}
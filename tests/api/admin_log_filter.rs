@@ -0,0 +1,79 @@
+use std::time::Duration;
+
+use crate::helpers::{spawn_app, spawn_app_with_own_log_filter};
+
+#[tokio::test]
+async fn requests_without_a_valid_token_are_rejected() {
+    let app = spawn_app().await;
+
+    let response = app.get_log_filter("wrong-token").await;
+    assert_eq!(response.status().as_u16(), 401);
+    assert!(response.headers().contains_key("WWW-Authenticate"));
+
+    let response = reqwest::get(format!("{}/admin/log_filter", app.address))
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 401);
+
+    let response = app
+        .put_log_filter("wrong-token", serde_json::json!({"filter": "trace"}))
+        .await;
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn the_current_log_filter_is_returned() {
+    let app = spawn_app().await;
+
+    let response = app.get_log_filter(&app.admin_token).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert!(body["filter"].is_string());
+}
+
+#[tokio::test]
+async fn invalid_directives_are_rejected_with_a_400() {
+    let app = spawn_app_with_own_log_filter().await;
+
+    let response = app
+        .put_log_filter(
+            &app.admin_token,
+            serde_json::json!({"filter": "zero2prod=not-a-level"}),
+        )
+        .await;
+
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn the_previous_log_filter_is_restored_after_the_ttl() {
+    let app = spawn_app_with_own_log_filter().await;
+    let previous = app.get_log_filter(&app.admin_token).await;
+    let previous: serde_json::Value = previous.json().await.unwrap();
+
+    let response = app
+        .put_log_filter(
+            &app.admin_token,
+            serde_json::json!({"filter": "zero2prod::email_client=trace", "ttl_seconds": 1}),
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let current: serde_json::Value = app
+        .get_log_filter(&app.admin_token)
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(current["filter"], "zero2prod::email_client=trace");
+
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    let restored: serde_json::Value = app
+        .get_log_filter(&app.admin_token)
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(restored["filter"], previous["filter"]);
+}
@@ -0,0 +1,67 @@
+use crate::helpers::{create_dead_letter, spawn_app};
+
+#[tokio::test]
+async fn metrics_require_the_admin_token() {
+    let app = spawn_app().await;
+
+    let response = app.get_metrics("wrong-token").await;
+
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn the_queue_depth_of_the_rate_limiter_is_exported() {
+    let app = spawn_app().await;
+
+    let response = app.get_metrics(&app.admin_token).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let body = response.text().await.unwrap();
+    assert!(body.contains("# TYPE email_rate_limiter_queue_depth gauge"));
+    assert!(body
+        .contains(r#"email_rate_limiter_queue_depth{provider="postmark",lane="transactional"} 0"#));
+    assert!(body.contains(r#"email_rate_limiter_queue_depth{provider="postmark",lane="bulk"} 0"#));
+}
+
+#[tokio::test]
+async fn the_state_of_the_circuit_breaker_is_exported() {
+    let app = spawn_app().await;
+
+    let response = app.get_metrics(&app.admin_token).await;
+
+    let body = response.text().await.unwrap();
+    assert!(body.contains("# TYPE email_circuit_breaker_state gauge"));
+    assert!(body.contains(r#"email_circuit_breaker_state{provider="postmark",state="closed"} 1"#));
+    assert!(body.contains(r#"email_circuit_breaker_state{provider="postmark",state="open"} 0"#));
+}
+
+#[tokio::test]
+async fn the_number_of_dead_letters_is_exported() {
+    let app = spawn_app().await;
+    create_dead_letter(&app, "ursula@example.com").await;
+
+    let response = app.get_metrics(&app.admin_token).await;
+
+    let body = response.text().await.unwrap();
+    assert!(body.contains("# TYPE outbox_dead_letters gauge"));
+    assert!(body.contains("outbox_dead_letters 1"));
+    assert!(body.contains(r#"metrics_collection_failed{metric="outbox_dead_letters"} 0"#));
+}
+
+#[tokio::test]
+async fn the_metrics_are_exported_when_the_dead_letters_cannot_be_counted() {
+    let app = spawn_app().await;
+    // Sabotage the database
+    app.execute("ALTER TABLE outbox RENAME COLUMN dead_lettered_at TO given_up_at;")
+        .await;
+
+    let response = app.get_metrics(&app.admin_token).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let body = response.text().await.unwrap();
+    assert!(body.contains(r#"email_circuit_breaker_state{provider="postmark",state="closed"} 1"#));
+    assert!(!body
+        .lines()
+        .any(|line| line.starts_with("outbox_dead_letters ")));
+    assert!(body.contains(r#"metrics_collection_failed{metric="outbox_dead_letters"} 1"#));
+}
@@ -0,0 +1,257 @@
+use wiremock::{
+    matchers::{any, method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{
+    create_confirmed_subscriber, create_confirmed_subscriber_with_email, spawn_app, BatchResponder,
+    TestApp,
+};
+
+async fn skipped_emails(app: &TestApp) -> Vec<(String, String)> {
+    app.fetch_all("SELECT recipient, reason FROM skipped_emails")
+        .await
+}
+
+#[tokio::test]
+async fn requests_without_a_valid_token_are_rejected() {
+    let app = spawn_app().await;
+
+    let response = app.get_suppressions("wrong-token").await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    let response = app
+        .post_suppressions(
+            "wrong-token",
+            serde_json::json!({"scope": "domain", "value": "example.com", "reason": "manual"}),
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    let response = app
+        .delete_suppression("wrong-token", "domain", "example.com")
+        .await;
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn suppressions_can_be_added_listed_and_removed() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_suppressions(
+            &app.admin_token,
+            serde_json::json!({"scope": "address", "value": " Ursula@Example.com ", "reason": "manual"}),
+        )
+        .await;
+    assert_eq!(response.status().as_u16(), 201);
+
+    let response = app.get_suppressions(&app.admin_token).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let suppressions: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(suppressions.as_array().unwrap().len(), 1);
+    assert_eq!(suppressions[0]["scope"], "address");
+    assert_eq!(suppressions[0]["value"], "ursula@example.com");
+    assert_eq!(suppressions[0]["reason"], "manual");
+
+    let response = app
+        .delete_suppression(&app.admin_token, "address", "ursula@example.com")
+        .await;
+    assert_eq!(response.status().as_u16(), 204);
+
+    let response = app.get_suppressions(&app.admin_token).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let suppressions: serde_json::Value = response.json().await.unwrap();
+    assert!(suppressions.as_array().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn invalid_values_are_rejected_with_a_400() {
+    let app = spawn_app().await;
+
+    let test_cases = vec![
+        (
+            serde_json::json!({"scope": "address", "value": "not-an-email", "reason": "manual"}),
+            "invalid address",
+        ),
+        (
+            serde_json::json!({"scope": "domain", "value": "user@example.com", "reason": "manual"}),
+            "address as a domain",
+        ),
+        (
+            serde_json::json!({"scope": "domain", "value": " ", "reason": "manual"}),
+            "empty domain",
+        ),
+    ];
+
+    for (body, description) in test_cases {
+        let response = app.post_suppressions(&app.admin_token, body).await;
+
+        assert_eq!(
+            response.status().as_u16(),
+            400,
+            "The API did not fail with 400 Bad Request with {}.",
+            description
+        );
+    }
+}
+
+#[tokio::test]
+async fn removing_a_missing_suppression_returns_a_404() {
+    let app = spawn_app().await;
+
+    let response = app
+        .delete_suppression(&app.admin_token, "domain", "example.com")
+        .await;
+
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn suppressed_addresses_do_not_receive_the_confirmation_email() {
+    let app = spawn_app().await;
+    app.post_suppressions(
+        &app.admin_token,
+        serde_json::json!({"scope": "address", "value": "ursula_le_guin@gmail.com", "reason": "hard_bounce"}),
+    )
+    .await
+    .error_for_status()
+    .unwrap();
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    let response = app.post_subscriptions(body.into()).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    app.dispatch_pending_emails().await;
+    assert_eq!(
+        skipped_emails(&app).await,
+        vec![("ursula_le_guin@gmail.com".into(), "hard_bounce".into())]
+    );
+}
+
+#[tokio::test]
+async fn newsletters_are_not_delivered_to_suppressed_domains() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    app.post_suppressions(
+        &app.admin_token,
+        serde_json::json!({"scope": "domain", "value": "GMAIL.com", "reason": "manual"}),
+    )
+    .await
+    .error_for_status()
+    .unwrap();
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let newsletter_request_body = serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    });
+    let response = app.post_newsletters(newsletter_request_body).await;
+
+    assert_eq!(response.status().as_u16(), 202);
+    app.deliver_queued_issues().await;
+    assert_eq!(skipped_emails(&app).await.len(), 1);
+}
+
+#[tokio::test]
+async fn only_the_suppressed_recipients_of_a_batch_are_skipped() {
+    let app = spawn_app().await;
+    for email in [
+        "ursula@example.com",
+        "le_guin@example.com",
+        "ursula@spam.example",
+    ] {
+        create_confirmed_subscriber_with_email(&app, email).await;
+    }
+    for (scope, value) in [
+        ("address", "LE_GUIN@example.com"),
+        ("domain", "spam.example"),
+    ] {
+        app.post_suppressions(
+            &app.admin_token,
+            serde_json::json!({"scope": scope, "value": value, "reason": "manual"}),
+        )
+        .await
+        .error_for_status()
+        .unwrap();
+    }
+
+    Mock::given(path("/email/batch"))
+        .respond_with(BatchResponder::rejecting(&[]))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            }
+        }))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 202);
+    app.deliver_queued_issues().await;
+    let requests = app.email_server.received_requests().await.unwrap();
+    let batch = requests
+        .iter()
+        .find(|request| request.url.path() == "/email/batch")
+        .unwrap();
+    let batch: Vec<serde_json::Value> = serde_json::from_slice(&batch.body).unwrap();
+    assert_eq!(batch.len(), 1);
+    assert_eq!(batch[0]["To"], "ursula@example.com");
+    let mut skipped: Vec<_> = skipped_emails(&app)
+        .await
+        .into_iter()
+        .map(|(recipient, _)| recipient)
+        .collect();
+    skipped.sort();
+    assert_eq!(skipped, vec!["le_guin@example.com", "ursula@spam.example"]);
+}
+
+#[tokio::test]
+async fn expired_suppressions_are_ignored() {
+    let app = spawn_app().await;
+    app.post_suppressions(
+        &app.admin_token,
+        serde_json::json!({
+            "scope": "domain",
+            "value": "gmail.com",
+            "reason": "manual",
+            "expires_at": "2020-01-01T00:00:00Z",
+        }),
+    )
+    .await
+    .error_for_status()
+    .unwrap();
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    let response = app.post_subscriptions(body.into()).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    app.dispatch_pending_emails().await;
+    assert!(skipped_emails(&app).await.is_empty());
+}
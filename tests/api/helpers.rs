@@ -1,28 +1,149 @@
+use std::{
+    collections::HashSet,
+    io,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use async_trait::async_trait;
 use once_cell::sync::Lazy;
-use sqlx::{Connection, Executor, PgConnection, PgPool};
+#[cfg(feature = "sqlite")]
+use sqlx::sqlite::SqliteRow;
+use sqlx::{postgres::PgRow, Connection, Executor, FromRow, PgConnection, PgPool};
+use tracing::Subscriber;
 use uuid::Uuid;
-use wiremock::MockServer;
+use wiremock::{
+    matchers::{method, path},
+    Mock, MockServer, Respond, ResponseTemplate,
+};
+#[cfg(feature = "sqlite")]
+use zero2prod::repository::SqliteRepository;
 use zero2prod::{
-    configuration::{get_configuration, DatabaseSettings},
+    configuration::{
+        get_configuration, DatabaseBackend, DatabaseSettings, Settings, TelemetrySettings,
+    },
+    domain_check::{DomainResolver, DomainStatus},
     startup::{get_connection_pool, Application},
-    telemetry::{get_subscriber, init_subscriber},
+    telemetry::{get_subscriber, init_subscriber, LogFilterHandle},
 };
 
-static TRACING: Lazy<()> = Lazy::new(|| {
+static TRACING: Lazy<LogFilterHandle> = Lazy::new(|| {
     if std::env::var("TEST_LOG").is_ok() {
-        let subscriber = get_subscriber("zero2prod_test", "debug", std::io::stdout);
+        let (subscriber, log_filter) =
+            get_subscriber("zero2prod_test", &test_telemetry(), std::io::stdout);
         init_subscriber(subscriber);
+        log_filter
     } else {
-        let subscriber = get_subscriber("zero2prod_test", "debug", std::io::sink);
+        let (subscriber, log_filter) =
+            get_subscriber("zero2prod_test", &test_telemetry(), std::io::sink);
         init_subscriber(subscriber);
-    };
+        log_filter
+    }
 });
 
+fn test_telemetry() -> TelemetrySettings {
+    TelemetrySettings {
+        log_filter: "debug".into(),
+        ..TelemetrySettings::default()
+    }
+}
+
 pub struct TestApp {
     pub address: String,
     pub port: u16,
-    pub db_pool: PgPool,
+    pub db: TestDatabase,
     pub email_server: MockServer,
+    pub admin_token: String,
+    pub webhook_credentials: (String, String),
+    pub resolver: Arc<StubResolver>,
+    /// The subscriber of a log filter of its own, see `spawn_app_with_own_log_filter`.
+    _log_subscriber: Option<Box<dyn Subscriber + Send + Sync>>,
+}
+
+/// The database of the application under test.
+pub enum TestDatabase {
+    Postgres(PgPool),
+    #[cfg(feature = "sqlite")]
+    Sqlite(SqliteRepository),
+}
+
+/// A row read by the tests, it is decoded from both backends.
+#[cfg(not(feature = "sqlite"))]
+pub trait TestRow: for<'r> FromRow<'r, PgRow> + Send + Unpin {}
+#[cfg(not(feature = "sqlite"))]
+impl<T> TestRow for T where T: for<'r> FromRow<'r, PgRow> + Send + Unpin {}
+
+/// A row read by the tests, it is decoded from both backends.
+#[cfg(feature = "sqlite")]
+pub trait TestRow:
+    for<'r> FromRow<'r, PgRow> + for<'r> FromRow<'r, SqliteRow> + Send + Unpin
+{
+}
+#[cfg(feature = "sqlite")]
+impl<T> TestRow for T where
+    T: for<'r> FromRow<'r, PgRow> + for<'r> FromRow<'r, SqliteRow> + Send + Unpin
+{
+}
+
+/// Resolve the domains without the network, every domain accepts emails unless marked as
+/// undeliverable.
+#[derive(Default)]
+pub struct StubResolver {
+    undeliverable: Mutex<HashSet<String>>,
+}
+
+impl StubResolver {
+    pub fn mark_undeliverable(&self, domain: &str) {
+        self.undeliverable.lock().unwrap().insert(domain.to_owned());
+    }
+}
+
+#[async_trait]
+impl DomainResolver for StubResolver {
+    async fn resolve(&self, domain: &str) -> Result<DomainStatus, io::Error> {
+        if self.undeliverable.lock().unwrap().contains(domain) {
+            Ok(DomainStatus::NoMail)
+        } else {
+            Ok(DomainStatus::AcceptsMail)
+        }
+    }
+}
+
+/// Answer the calls to the batch API like Postmark, the emails sent to the `rejected` addresses
+/// fail and the other ones succeed.
+pub struct BatchResponder {
+    rejected: Vec<String>,
+}
+
+impl BatchResponder {
+    pub fn rejecting(rejected: &[&str]) -> Self {
+        Self {
+            rejected: rejected.iter().map(|r| r.to_string()).collect(),
+        }
+    }
+}
+
+impl Respond for BatchResponder {
+    fn respond(&self, request: &wiremock::Request) -> ResponseTemplate {
+        let emails: Vec<serde_json::Value> = serde_json::from_slice(&request.body).unwrap();
+        let results: Vec<_> = emails
+            .iter()
+            .map(|email| {
+                let to = email["To"].as_str().unwrap();
+                if self.rejected.iter().any(|r| r == to) {
+                    serde_json::json!({"ErrorCode": 406, "Message": "Inactive recipient", "To": to})
+                } else {
+                    serde_json::json!({
+                        "ErrorCode": 0,
+                        "Message": "OK",
+                        "MessageID": format!("message-{}", to),
+                        "To": to
+                    })
+                }
+            })
+            .collect();
+        ResponseTemplate::new(200).set_body_json(results)
+    }
 }
 
 pub struct ConfirmationLinks {
@@ -31,9 +152,91 @@ pub struct ConfirmationLinks {
 }
 
 impl TestApp {
+    /// Wait until the relay processed every due message of the outbox.
+    pub async fn dispatch_pending_emails(&self) {
+        for _ in 0..500 {
+            if self.pending_emails().await == 0 {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("The messages of the outbox were not relayed");
+    }
+
+    /// Wait until the worker sent every delivery queued for the subscribers receiving every issue,
+    /// the retries it scheduled are not waited for.
+    pub async fn deliver_queued_issues(&self) {
+        for _ in 0..500 {
+            let (queued,): (i64,) = self
+                .fetch_one(
+                    r#"SELECT COUNT(*) FROM issue_deliveries
+                        WHERE status = 'queued' AND NOT digest AND next_attempt_at IS NULL"#,
+                )
+                .await;
+            if queued == 0 {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("The queued deliveries were not sent");
+    }
+
+    async fn pending_emails(&self) -> i64 {
+        let (count,) = match &self.db {
+            TestDatabase::Postgres(pool) => sqlx::query_as(
+                "SELECT COUNT(*) FROM outbox WHERE dead_lettered_at IS NULL AND next_attempt_at <= now()",
+            )
+            .fetch_one(pool)
+            .await,
+            #[cfg(feature = "sqlite")]
+            TestDatabase::Sqlite(repository) => sqlx::query_as(
+                "SELECT COUNT(*) FROM outbox WHERE dead_lettered_at IS NULL AND next_attempt_at <= ?",
+            )
+            .bind(chrono::Utc::now())
+            .fetch_one(repository.pool())
+            .await,
+        }
+        .expect("Failed to count the pending messages of the outbox.");
+        count
+    }
+
+    /// Run a statement valid for both backends.
+    pub async fn execute(&self, sql: &str) {
+        match &self.db {
+            TestDatabase::Postgres(pool) => pool.execute(sql).await.map(|_| ()),
+            #[cfg(feature = "sqlite")]
+            TestDatabase::Sqlite(repository) => repository.pool().execute(sql).await.map(|_| ()),
+        }
+        .expect("Failed to execute the statement.");
+    }
+
+    /// Read the only row of a query valid for both backends.
+    pub async fn fetch_one<T: TestRow>(&self, sql: &str) -> T {
+        match &self.db {
+            TestDatabase::Postgres(pool) => sqlx::query_as(sql).fetch_one(pool).await,
+            #[cfg(feature = "sqlite")]
+            TestDatabase::Sqlite(repository) => {
+                sqlx::query_as(sql).fetch_one(repository.pool()).await
+            }
+        }
+        .expect("Failed to fetch the row.")
+    }
+
+    /// Read the rows of a query valid for both backends.
+    pub async fn fetch_all<T: TestRow>(&self, sql: &str) -> Vec<T> {
+        match &self.db {
+            TestDatabase::Postgres(pool) => sqlx::query_as(sql).fetch_all(pool).await,
+            #[cfg(feature = "sqlite")]
+            TestDatabase::Sqlite(repository) => {
+                sqlx::query_as(sql).fetch_all(repository.pool()).await
+            }
+        }
+        .expect("Failed to fetch the rows.")
+    }
+
     pub async fn post_subscriptions(&self, body: String) -> reqwest::Response {
         reqwest::Client::new()
-            .post(&format!("{}/subscriptions", self.address))
+            .post(format!("{}/subscriptions", self.address))
             .header("Content-Type", "application/x-www-form-urlencoded")
             .body(body)
             .send()
@@ -41,6 +244,42 @@ impl TestApp {
             .expect("Failed to execute request")
     }
 
+    /// A token issued by `/subscriptions/form_token`, for the human verification.
+    pub async fn get_form_token(&self) -> String {
+        let response = reqwest::Client::new()
+            .get(format!("{}/subscriptions/form_token", self.address))
+            .send()
+            .await
+            .expect("Failed to execute request");
+        assert_eq!(response.status().as_u16(), 200);
+        let body: serde_json::Value = response.json().await.unwrap();
+        body["form_token"].as_str().unwrap().to_owned()
+    }
+
+    pub async fn get_subscribe_form(&self) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!("{}/subscribe", self.address))
+            .send()
+            .await
+            .expect("Failed to execute request")
+    }
+
+    /// Submit the subscription form with the CSRF cookie set by `get_subscribe_form`.
+    pub async fn post_subscribe_form(
+        &self,
+        body: String,
+        csrf_cookie: Option<&str>,
+    ) -> reqwest::Response {
+        let mut request = reqwest::Client::new()
+            .post(format!("{}/subscribe", self.address))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(body);
+        if let Some(csrf_cookie) = csrf_cookie {
+            request = request.header("Cookie", format!("subscribe_csrf={}", csrf_cookie));
+        }
+        request.send().await.expect("Failed to execute request")
+    }
+
     pub fn get_confirmation_links(&self, email_request: &wiremock::Request) -> ConfirmationLinks {
         let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
 
@@ -66,45 +305,388 @@ impl TestApp {
         }
     }
 
+    pub async fn get_metrics(&self, token: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!("{}/admin/metrics", &self.address))
+            .bearer_auth(token)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_log_filter(&self, token: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!("{}/admin/log_filter", &self.address))
+            .bearer_auth(token)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn put_log_filter(&self, token: &str, body: serde_json::Value) -> reqwest::Response {
+        reqwest::Client::new()
+            .put(format!("{}/admin/log_filter", &self.address))
+            .bearer_auth(token)
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_email_webhook(
+        &self,
+        provider: &str,
+        payload: String,
+        credentials: Option<(&str, &str)>,
+    ) -> reqwest::Response {
+        let mut request = reqwest::Client::new()
+            .post(format!("{}/webhooks/email/{}", &self.address, provider))
+            .header("Content-Type", "application/json")
+            .body(payload);
+        if let Some((username, password)) = credentials {
+            request = request.basic_auth(username, Some(password));
+        }
+        request.send().await.expect("Failed to execute request.")
+    }
+
+    pub async fn get_suppressions(&self, token: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!("{}/admin/suppressions", &self.address))
+            .bearer_auth(token)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_suppressions(
+        &self,
+        token: &str,
+        body: serde_json::Value,
+    ) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/admin/suppressions", &self.address))
+            .bearer_auth(token)
+            .json(&body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn delete_suppression(
+        &self,
+        token: &str,
+        scope: &str,
+        value: &str,
+    ) -> reqwest::Response {
+        reqwest::Client::new()
+            .delete(format!(
+                "{}/admin/suppressions/{}/{}",
+                &self.address, scope, value
+            ))
+            .bearer_auth(token)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
     pub async fn post_newsletters(&self, body: serde_json::Value) -> reqwest::Response {
         reqwest::Client::new()
-            .post(&format!("{}/newsletters", &self.address))
+            .post(format!("{}/newsletters", &self.address))
             .json(&body)
             .send()
             .await
             .expect("Failed to execute request.")
     }
+
+    pub async fn get_issue_stats(&self, token: &str, issue_id: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!(
+                "{}/admin/newsletters/{}/stats",
+                &self.address, issue_id
+            ))
+            .bearer_auth(token)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_issue_deliveries(&self, token: &str, issue_id: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!(
+                "{}/admin/newsletters/{}/deliveries",
+                &self.address, issue_id
+            ))
+            .bearer_auth(token)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_issue_resend(&self, token: &str, issue_id: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!(
+                "{}/admin/newsletters/{}/resend",
+                &self.address, issue_id
+            ))
+            .bearer_auth(token)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_digests(&self, token: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/admin/newsletters/digests", &self.address))
+            .bearer_auth(token)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_dead_letters(&self, token: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!("{}/admin/dead_letters", &self.address))
+            .bearer_auth(token)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_dead_letters_page(
+        &self,
+        token: &str,
+        page: &[(&str, &str)],
+    ) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!("{}/admin/dead_letters", &self.address))
+            .query(page)
+            .bearer_auth(token)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn get_dead_letter(&self, token: &str, id: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!("{}/admin/dead_letters/{}", &self.address, id))
+            .bearer_auth(token)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_dead_letter_replay(&self, token: &str, id: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!(
+                "{}/admin/dead_letters/{}/replay",
+                &self.address, id
+            ))
+            .bearer_auth(token)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn post_dead_letters_replay(&self, token: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/admin/dead_letters/replay", &self.address))
+            .bearer_auth(token)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn delete_dead_letter(&self, token: &str, id: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .delete(format!("{}/admin/dead_letters/{}", &self.address, id))
+            .bearer_auth(token)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    pub async fn delete_dead_letters(&self, token: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .delete(format!("{}/admin/dead_letters", &self.address))
+            .bearer_auth(token)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
 }
 
 pub async fn spawn_app() -> TestApp {
+    spawn_app_with(|_| {}).await
+}
+
+/// Spawn the application after changing its configuration with `configure`.
+pub async fn spawn_app_with(configure: impl FnOnce(&mut Settings)) -> TestApp {
+    let log_filter = Lazy::force(&TRACING).clone();
+    spawn(configure, log_filter).await
+}
+
+/// Spawn the application with a log filter of its own rather than the filter of the global
+/// subscriber, the filters set through `/admin/log_filter` do not change the logs of the other
+/// tests.
+pub async fn spawn_app_with_own_log_filter() -> TestApp {
     Lazy::force(&TRACING);
+    let (subscriber, log_filter) =
+        get_subscriber("zero2prod_test", &test_telemetry(), std::io::sink);
+    let mut app = spawn(|_| {}, log_filter).await;
+    app._log_subscriber = Some(Box::new(subscriber));
+    app
+}
 
+async fn spawn(configure: impl FnOnce(&mut Settings), log_filter: LogFilterHandle) -> TestApp {
     let email_server = MockServer::start().await;
 
-    let configuration = {
+    let mut configuration = {
         let mut configuration = get_configuration().expect("Failed to read configuration");
         configuration.application.port = 0;
         configuration.database.name = Uuid::new_v4().to_string();
         configuration.email_client.base_url = email_server.uri();
+        configure(&mut configuration);
         configuration
     };
+    // Every test gets its own file, the suite runs against SQLite with
+    // `APP__DATABASE__BACKEND__KIND=sqlite APP__DATABASE__BACKEND__PATH=unused`
+    let db = match &mut configuration.database.backend {
+        DatabaseBackend::Postgres => {
+            TestDatabase::Postgres(configure_database(&configuration.database).await)
+        }
+        #[cfg(feature = "sqlite")]
+        DatabaseBackend::Sqlite { path } => {
+            let (repository, new_path) = spawn_sqlite_database().await;
+            *path = new_path;
+            TestDatabase::Sqlite(repository)
+        }
+        #[cfg(not(feature = "sqlite"))]
+        DatabaseBackend::Sqlite { .. } => panic!("The SQLite backend needs the `sqlite` feature"),
+    };
+    let admin_token = configuration.application.admin_token.expose().to_owned();
+    let webhook_credentials = (
+        configuration.webhooks.postmark.username.clone(),
+        configuration.webhooks.postmark.password.expose().to_owned(),
+    );
 
-    let db_pool = configure_database(&configuration.database).await;
-
-    let application = Application::build(configuration);
+    let resolver = Arc::new(StubResolver::default());
+    let application = Application::build_with_resolver(configuration, log_filter, resolver.clone());
     let address = format!("http://{}", application.address());
     let port = application.port();
 
-    let _ = tokio::spawn(async move { application.run().await.expect("Failed to run the server") });
+    tokio::spawn(async move { application.run().await.expect("Failed to run the server") });
 
     TestApp {
         address,
         port,
-        db_pool,
+        db,
         email_server,
+        admin_token,
+        webhook_credentials,
+        resolver,
+        _log_subscriber: None,
     }
 }
 
+/// Use the public API of the application under test to create
+/// an unconfirmed subscriber.
+pub async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
+    subscribe(app, "le guin", "ursula_le_guin@gmail.com").await
+}
+
+pub async fn create_confirmed_subscriber(app: &TestApp) {
+    create_confirmed_subscriber_with_email(app, "ursula_le_guin@gmail.com").await;
+}
+
+pub async fn create_confirmed_subscriber_with_email(app: &TestApp, email: &str) {
+    let confirmation_links = subscribe(app, "le guin", email).await;
+    reqwest::get(confirmation_links.html)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+}
+
+/// Subscribe through the public API, it returns the links of the confirmation email.
+async fn subscribe(app: &TestApp, name: &str, email: &str) -> ConfirmationLinks {
+    let body = form_urlencoded(&[("name", name), ("email", email)]);
+
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .named("Create unconfirmed subscriber")
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body)
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_pending_emails().await;
+
+    let email_request = &app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+
+    app.get_confirmation_links(email_request)
+}
+
+/// Subscribe `email` while the provider rejects the confirmation email, it is dead-lettered.
+pub async fn create_dead_letter(app: &TestApp, email: &str) {
+    let body = form_urlencoded(&[("name", "le guin"), ("email", email)]);
+
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(422))
+        .named("Reject the confirmation email")
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    app.post_subscriptions(body)
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_pending_emails().await;
+}
+
+pub fn form_urlencoded(pairs: &[(&str, &str)]) -> String {
+    let mut url = reqwest::Url::parse("http://localhost").unwrap();
+    url.query_pairs_mut().extend_pairs(pairs);
+    url.query().unwrap_or_default().to_owned()
+}
+
+/// A fresh database with all the migrations applied, for the tests that do not need the
+/// application.
+pub async fn spawn_database() -> PgPool {
+    let mut settings = get_configuration()
+        .expect("Failed to read configuration")
+        .database;
+    settings.name = Uuid::new_v4().to_string();
+    configure_database(&settings).await
+}
+
+/// A fresh SQLite database with all the migrations applied in the temporary directory, with its
+/// path.
+#[cfg(feature = "sqlite")]
+pub async fn spawn_sqlite_database() -> (SqliteRepository, String) {
+    let path = std::env::temp_dir()
+        .join(format!("{}.db", Uuid::new_v4()))
+        .to_string_lossy()
+        .into_owned();
+    let repository = SqliteRepository::open(&path);
+    repository
+        .migrate()
+        .await
+        .expect("Failed to migrate the SQLite database");
+    (repository, path)
+}
+
 async fn configure_database(settings: &DatabaseSettings) -> PgPool {
     let mut connection = PgConnection::connect_with(&settings.without_db())
         .await
@@ -115,7 +697,7 @@ async fn configure_database(settings: &DatabaseSettings) -> PgPool {
         .await
         .expect("Failed to create database");
 
-    let db_pool = get_connection_pool(&settings);
+    let db_pool = get_connection_pool(settings);
 
     sqlx::migrate!("./migrations")
         .run(&db_pool)
@@ -0,0 +1,201 @@
+use std::time::Duration;
+
+use wiremock::{
+    matchers::{body_string_contains, method, path},
+    Mock, MockServer, ResponseTemplate,
+};
+use zero2prod::{configuration::HumanVerificationSettings, secret::Secret};
+
+use crate::helpers::{form_urlencoded, spawn_app_with, TestApp};
+
+async fn spawn_app_with_honeypot(min_seconds: u64) -> TestApp {
+    spawn_app_with(|configuration| {
+        configuration.subscriptions.human_verification = HumanVerificationSettings::Honeypot {
+            min_seconds,
+            max_age_seconds: 3600,
+        };
+    })
+    .await
+}
+
+async fn spawn_app_with_captcha(provider: &MockServer) -> TestApp {
+    let verify_url = format!("{}/siteverify", provider.uri());
+    spawn_app_with(|configuration| {
+        configuration.subscriptions.human_verification = HumanVerificationSettings::Captcha {
+            verify_url,
+            secret: Secret::new("my-captcha-secret".into()),
+            timeout_milliseconds: 200,
+        };
+    })
+    .await
+}
+
+async fn subscriber_count(app: &TestApp) -> i64 {
+    let (count,) = app.fetch_one("SELECT COUNT(*) FROM subscriptions").await;
+    count
+}
+
+#[tokio::test]
+async fn a_subscription_with_a_form_token_passes_the_honeypot() {
+    let app = spawn_app_with_honeypot(0).await;
+    let form_token = app.get_form_token().await;
+
+    let body = form_urlencoded(&[
+        ("name", "le guin"),
+        ("email", "ursula_le_guin@gmail.com"),
+        ("website", ""),
+        ("form_token", &form_token),
+    ]);
+    let response = app.post_subscriptions(body).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(subscriber_count(&app).await, 1);
+}
+
+#[tokio::test]
+async fn the_honeypot_refuses_the_scripts_and_counts_them() {
+    let app = spawn_app_with_honeypot(0).await;
+    let form_token = app.get_form_token().await;
+
+    let test_cases = vec![
+        (
+            vec![
+                ("website", "https://spam.example"),
+                ("form_token", &*form_token),
+            ],
+            "filled honeypot",
+        ),
+        (vec![], "missing form token"),
+        (vec![("form_token", "forged")], "forged form token"),
+    ];
+    for (fields, description) in test_cases {
+        let mut pairs = vec![("name", "le guin"), ("email", "ursula_le_guin@gmail.com")];
+        pairs.extend(fields);
+        let response = app.post_subscriptions(form_urlencoded(&pairs)).await;
+
+        assert_eq!(
+            response.status().as_u16(),
+            403,
+            "a {} was accepted",
+            description
+        );
+    }
+    assert_eq!(subscriber_count(&app).await, 0);
+
+    let metrics = app
+        .get_metrics(&app.admin_token)
+        .await
+        .text()
+        .await
+        .unwrap();
+    assert!(metrics.contains("# TYPE human_verification_failures_total counter"));
+    assert!(metrics.contains(
+        r#"human_verification_failures_total{verifier="honeypot",reason="honeypot_filled"} 1"#
+    ));
+    assert!(metrics.contains(
+        r#"human_verification_failures_total{verifier="honeypot",reason="invalid_form_token"} 2"#
+    ));
+}
+
+#[tokio::test]
+async fn a_form_submitted_too_quickly_is_refused() {
+    let app = spawn_app_with_honeypot(60).await;
+    let form_token = app.get_form_token().await;
+
+    let body = form_urlencoded(&[
+        ("name", "le guin"),
+        ("email", "ursula_le_guin@gmail.com"),
+        ("form_token", &form_token),
+    ]);
+    let response = app.post_subscriptions(body).await;
+
+    assert_eq!(response.status().as_u16(), 403);
+    assert_eq!(subscriber_count(&app).await, 0);
+}
+
+#[tokio::test]
+async fn a_captcha_response_accepted_by_the_provider_passes() {
+    let provider = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/siteverify"))
+        .and(body_string_contains("secret=my-captcha-secret"))
+        .and(body_string_contains("response=a-good-response"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "success": true
+        })))
+        .expect(1)
+        .mount(&provider)
+        .await;
+    let app = spawn_app_with_captcha(&provider).await;
+
+    // The field of the hCaptcha widget
+    let body = form_urlencoded(&[
+        ("name", "le guin"),
+        ("email", "ursula_le_guin@gmail.com"),
+        ("h-captcha-response", "a-good-response"),
+    ]);
+    let response = app.post_subscriptions(body).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(subscriber_count(&app).await, 1);
+}
+
+#[tokio::test]
+async fn a_captcha_response_rejected_by_the_provider_is_refused() {
+    let provider = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/siteverify"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "success": false,
+            "error-codes": ["invalid-input-response"]
+        })))
+        .expect(1)
+        .mount(&provider)
+        .await;
+    let app = spawn_app_with_captcha(&provider).await;
+
+    // The field of the Turnstile widget
+    let body = form_urlencoded(&[
+        ("name", "le guin"),
+        ("email", "ursula_le_guin@gmail.com"),
+        ("cf-turnstile-response", "a-bad-response"),
+    ]);
+    let response = app.post_subscriptions(body).await;
+
+    assert_eq!(response.status().as_u16(), 403);
+    assert_eq!(subscriber_count(&app).await, 0);
+    let metrics = app
+        .get_metrics(&app.admin_token)
+        .await
+        .text()
+        .await
+        .unwrap();
+    assert!(metrics.contains(
+        r#"human_verification_failures_total{verifier="captcha",reason="captcha_rejected"} 1"#
+    ));
+}
+
+#[tokio::test]
+async fn subscriptions_are_refused_while_the_captcha_provider_is_down() {
+    let provider = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/siteverify"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(serde_json::json!({ "success": true }))
+                .set_delay(Duration::from_secs(2)),
+        )
+        .mount(&provider)
+        .await;
+    let app = spawn_app_with_captcha(&provider).await;
+
+    let body = form_urlencoded(&[
+        ("name", "le guin"),
+        ("email", "ursula_le_guin@gmail.com"),
+        ("captcha_response", "a-good-response"),
+    ]);
+    let response = app.post_subscriptions(body).await;
+
+    assert_eq!(response.status().as_u16(), 503);
+    assert_eq!(subscriber_count(&app).await, 0);
+}
@@ -1,5 +1,15 @@
+mod admin_dead_letters;
+mod admin_log_filter;
+mod admin_metrics;
+mod admin_suppressions;
 mod health_check;
 mod helpers;
+mod human_verification;
 mod newsletters;
+mod preferences;
+mod repositories;
+mod subscribe_form;
 mod subscriptions;
 mod subscriptions_confirm;
+mod tracking;
+mod webhooks_email;
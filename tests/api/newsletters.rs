@@ -1,9 +1,54 @@
+use std::time::Duration;
+
 use wiremock::{
-    matchers::{any, method, path},
-    Mock, ResponseTemplate,
+    matchers::{any, body_partial_json, method, path},
+    Mock, MockServer, ResponseTemplate,
+};
+use zero2prod::{
+    configuration::{ProviderSettings, TransportSettings},
+    secret::Secret,
+};
+
+use crate::helpers::{
+    create_confirmed_subscriber, create_confirmed_subscriber_with_email,
+    create_unconfirmed_subscriber, spawn_app, spawn_app_with, BatchResponder, TestApp,
 };
 
-use crate::helpers::{spawn_app, ConfirmationLinks, TestApp};
+fn newsletter_request_body() -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>"
+        }
+    })
+}
+
+/// Two confirmed subscribers, the provider rejects the address of the second one. It returns the
+/// summary of the deliveries once they were sent.
+async fn publish_with_a_rejected_address(app: &TestApp) -> serde_json::Value {
+    create_confirmed_subscriber_with_email(app, "ursula@example.com").await;
+    create_confirmed_subscriber_with_email(app, "rejected@example.com").await;
+
+    let _mock_guard = Mock::given(path("/email/batch"))
+        .respond_with(BatchResponder::rejecting(&["rejected@example.com"]))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    let response = app.post_newsletters(newsletter_request_body()).await;
+    assert_eq!(response.status().as_u16(), 202);
+    let published: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(published["queued"], 2);
+    app.deliver_queued_issues().await;
+    app.get_issue_deliveries(&app.admin_token, published["issue_id"].as_str().unwrap())
+        .await
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap()
+}
 
 #[tokio::test]
 async fn newsletters_are_not_delivered_to_unconfirmed_subscribers() {
@@ -25,7 +70,9 @@ async fn newsletters_are_not_delivered_to_unconfirmed_subscribers() {
     });
     let response = app.post_newsletters(newsletter_request_body).await;
 
-    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.status().as_u16(), 202);
+    let published: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(published["queued"], 0);
 }
 
 #[tokio::test]
@@ -33,7 +80,7 @@ async fn newsletters_are_delivered_to_confirmed_subscribers() {
     let app = spawn_app().await;
     create_confirmed_subscriber(&app).await;
 
-    Mock::given(path("/email"))
+    Mock::given(path("/email/batch"))
         .and(method("POST"))
         .respond_with(ResponseTemplate::new(200))
         .expect(1)
@@ -49,7 +96,42 @@ async fn newsletters_are_delivered_to_confirmed_subscribers() {
     });
     let response = app.post_newsletters(newsletter_request_body).await;
 
-    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.status().as_u16(), 202);
+    app.deliver_queued_issues().await;
+}
+
+#[tokio::test]
+async fn newsletters_are_sent_in_the_background() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    Mock::given(path("/email/batch"))
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(1)))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_newsletters(newsletter_request_body()).await;
+
+    // The response does not wait for the provider
+    assert_eq!(response.status().as_u16(), 202);
+    let published: serde_json::Value = response.json().await.unwrap();
+    let issue_id = published["issue_id"].as_str().unwrap();
+    let summary: serde_json::Value = app
+        .get_issue_deliveries(&app.admin_token, issue_id)
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(summary["queued"], 1);
+    app.deliver_queued_issues().await;
+    let summary: serde_json::Value = app
+        .get_issue_deliveries(&app.admin_token, issue_id)
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(summary["sent"], 1);
 }
 
 #[tokio::test]
@@ -83,40 +165,209 @@ async fn newsletters_returns_422_for_invalid_data() {
     }
 }
 
-/// Use the public API of the application under test to create
-/// an unconfirmed subscriber.
-async fn create_unconfirmed_subscriber(app: &TestApp) -> ConfirmationLinks {
-    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+#[tokio::test]
+async fn a_failed_delivery_does_not_prevent_the_other_ones() {
+    let app = spawn_app().await;
 
-    let _mock_guard = Mock::given(path("/email"))
-        .and(method("POST"))
-        .respond_with(ResponseTemplate::new(200))
-        .named("Create unconfirmed subscriber")
+    let summary = publish_with_a_rejected_address(&app).await;
+
+    assert_eq!(summary["total"], 2);
+    assert_eq!(summary["sent"], 1);
+    assert_eq!(summary["failed"], 1);
+    assert_eq!(summary["queued"], 0);
+    let failures = summary["failures"].as_array().unwrap();
+    assert_eq!(failures.len(), 1);
+    assert_eq!(failures[0]["email"], "rejected@example.com");
+    assert_eq!(failures[0]["error_class"], "permanent");
+    assert_eq!(failures[0]["attempts"], 1);
+
+    let (provider_message_id,): (Option<String>,) = app
+        .fetch_one("SELECT provider_message_id FROM issue_deliveries WHERE status = 'sent'")
+        .await;
+    assert_eq!(
+        provider_message_id.as_deref(),
+        Some("message-ursula@example.com")
+    );
+}
+
+#[tokio::test]
+async fn the_failed_deliveries_are_sent_again() {
+    let app = spawn_app().await;
+    let summary = publish_with_a_rejected_address(&app).await;
+    let issue_id = summary["issue_id"].as_str().unwrap();
+
+    Mock::given(path("/email/batch"))
+        .and(body_partial_json(
+            serde_json::json!([{"To": "rejected@example.com"}]),
+        ))
+        .respond_with(BatchResponder::rejecting(&[]))
         .expect(1)
-        .mount_as_scoped(&app.email_server)
+        .mount(&app.email_server)
         .await;
 
-    app.post_subscriptions(body.into())
-        .await
-        .error_for_status()
-        .unwrap();
+    let response = app.post_issue_resend(&app.admin_token, issue_id).await;
 
-    let email_request = &app
-        .email_server
-        .received_requests()
+    assert_eq!(response.status().as_u16(), 202);
+    let resent: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(resent["queued"], 1);
+    app.deliver_queued_issues().await;
+    let summary: serde_json::Value = app
+        .get_issue_deliveries(&app.admin_token, issue_id)
+        .await
+        .json()
         .await
-        .unwrap()
-        .pop()
         .unwrap();
+    assert_eq!(summary["sent"], 2);
+    assert_eq!(summary["failed"], 0);
+    let attempts: Vec<(String,)> = app
+        .fetch_all("SELECT status FROM issue_delivery_attempts")
+        .await;
+    assert_eq!(attempts.len(), 3);
+}
+
+#[tokio::test]
+async fn delivery_reports_require_the_admin_token() {
+    let app = spawn_app().await;
+    let issue_id = uuid::Uuid::new_v4().to_string();
+
+    let response = app.get_issue_deliveries("wrong-token", &issue_id).await;
+    assert_eq!(response.status().as_u16(), 401);
+
+    let response = app.post_issue_resend("wrong-token", &issue_id).await;
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn delivery_reports_of_unknown_issues_return_404() {
+    let app = spawn_app().await;
+    let issue_id = uuid::Uuid::new_v4().to_string();
+
+    let response = app.get_issue_deliveries(&app.admin_token, &issue_id).await;
+    assert_eq!(response.status().as_u16(), 404);
+
+    let response = app.post_issue_resend(&app.admin_token, &issue_id).await;
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn a_failed_batch_is_queued_again_as_transient_failures() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber_with_email(&app, "ursula@example.com").await;
+    create_confirmed_subscriber_with_email(&app, "octavia@example.com").await;
+
+    Mock::given(path("/email/batch"))
+        .respond_with(ResponseTemplate::new(503))
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_newsletters(newsletter_request_body()).await;
+
+    assert_eq!(response.status().as_u16(), 202);
+    app.deliver_queued_issues().await;
+    let deliveries: Vec<(String, Option<String>, i32)> = app
+        .fetch_all("SELECT status, error_class, attempts FROM issue_deliveries")
+        .await;
+    assert_eq!(deliveries.len(), 2);
+    for delivery in deliveries {
+        assert_eq!(delivery, ("queued".into(), Some("transient".into()), 1));
+    }
+    let (scheduled,): (i64,) = app
+        .fetch_one("SELECT COUNT(*) FROM issue_deliveries WHERE next_attempt_at IS NOT NULL")
+        .await;
+    assert_eq!(scheduled, 2);
+}
+
+#[tokio::test]
+async fn a_transient_failure_is_delivered_on_a_later_run() {
+    let app = spawn_app_with(|configuration| {
+        configuration.email_client.max_retries = 0;
+    })
+    .await;
+    create_confirmed_subscriber_with_email(&app, "ursula@example.com").await;
+
+    Mock::given(path("/email/batch"))
+        .respond_with(ResponseTemplate::new(503))
+        .up_to_n_times(1)
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    Mock::given(path("/email/batch"))
+        .respond_with(BatchResponder::rejecting(&[]))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
 
-    app.get_confirmation_links(&email_request)
+    let response = app.post_newsletters(newsletter_request_body()).await;
+
+    assert_eq!(response.status().as_u16(), 202);
+    let published: serde_json::Value = response.json().await.unwrap();
+    let issue_id = published["issue_id"].as_str().unwrap();
+    // The retry waits for the next poll of the worker
+    let mut summary = serde_json::Value::Null;
+    for _ in 0..150 {
+        summary = app
+            .get_issue_deliveries(&app.admin_token, issue_id)
+            .await
+            .json()
+            .await
+            .unwrap();
+        if summary["sent"] == 1 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert_eq!(summary["sent"], 1);
+    assert_eq!(summary["failed"], 0);
+    let attempts: Vec<(String,)> = app
+        .fetch_all("SELECT status FROM issue_delivery_attempts ORDER BY attempted_at")
+        .await;
+    assert_eq!(attempts, vec![("failed".into(),), ("sent".into(),)]);
 }
 
-async fn create_confirmed_subscriber(app: &TestApp) {
-    let confirmation_links = create_unconfirmed_subscriber(app).await;
-    reqwest::get(confirmation_links.html)
+#[tokio::test]
+async fn newsletters_fail_over_to_the_next_provider() {
+    let backup_server = MockServer::start().await;
+    let backup_url = backup_server.uri();
+    let app = spawn_app_with(|configuration| {
+        configuration.email_client.max_retries = 0;
+        configuration.email_client.fallback_providers = vec![ProviderSettings {
+            name: "backup".into(),
+            max_messages_per_second: None,
+            transport: TransportSettings::Postmark {
+                base_url: backup_url,
+                authorization_token: Secret::new("backup-token".into()),
+            },
+        }];
+    })
+    .await;
+    create_confirmed_subscriber_with_email(&app, "ursula@example.com").await;
+    create_confirmed_subscriber_with_email(&app, "octavia@example.com").await;
+
+    Mock::given(path("/email/batch"))
+        .respond_with(ResponseTemplate::new(503))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    Mock::given(path("/email/batch"))
+        .respond_with(BatchResponder::rejecting(&[]))
+        .expect(1)
+        .mount(&backup_server)
+        .await;
+
+    let response = app.post_newsletters(newsletter_request_body()).await;
+
+    assert_eq!(response.status().as_u16(), 202);
+    let published: serde_json::Value = response.json().await.unwrap();
+    app.deliver_queued_issues().await;
+    let summary: serde_json::Value = app
+        .get_issue_deliveries(&app.admin_token, published["issue_id"].as_str().unwrap())
+        .await
+        .json()
         .await
-        .unwrap()
-        .error_for_status()
         .unwrap();
+    assert_eq!(summary["sent"], 2);
+    assert_eq!(
+        summary["sent_by_provider"],
+        serde_json::json!({"backup": 2})
+    );
 }
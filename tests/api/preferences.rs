@@ -0,0 +1,352 @@
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+use zero2prod::{configuration::Topic, domain::SubscriptionStatus};
+
+use crate::helpers::{
+    create_confirmed_subscriber, form_urlencoded, spawn_app_with, BatchResponder, TestApp,
+};
+
+async fn spawn_app_with_topics() -> TestApp {
+    spawn_app_with(|configuration| {
+        configuration.subscriptions.topics = vec![
+            Topic {
+                id: "releases".into(),
+                name: "Release notes".into(),
+            },
+            Topic {
+                id: "events".into(),
+                name: "Events".into(),
+            },
+        ];
+    })
+    .await
+}
+
+/// Publish an issue to a new confirmed subscriber and return the link to their preference
+/// centre found in the issue.
+async fn preferences_link(app: &TestApp) -> reqwest::Url {
+    create_confirmed_subscriber(app).await;
+    let _mock_guard = Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.post_newsletters(serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+    app.deliver_queued_issues().await;
+
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    let find = |content: &str| {
+        let link = linkify::LinkFinder::new()
+            .links(content)
+            .map(|l| reqwest::Url::parse(l.as_str()).unwrap())
+            .find(|l| l.path().starts_with("/preferences/"))
+            .unwrap();
+        let mut link = link;
+        link.set_port(Some(app.port)).unwrap();
+        link
+    };
+    let html_link = find(body[0]["HtmlBody"].as_str().unwrap());
+    assert_eq!(html_link, find(body[0]["TextBody"].as_str().unwrap()));
+    html_link
+}
+
+async fn post_preferences(link: &reqwest::Url, pairs: &[(&str, &str)]) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(link.clone())
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body(form_urlencoded(pairs))
+        .send()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn the_preference_centre_shows_the_current_preferences() {
+    let app = spawn_app_with_topics().await;
+    let link = preferences_link(&app).await;
+
+    let response = reqwest::get(link).await.unwrap();
+
+    assert_eq!(response.status().as_u16(), 200);
+    let html = response.text().await.unwrap();
+    assert!(html.contains(r#"value="le guin""#));
+    assert!(html.contains(r#"<input type="checkbox" name="topic" value="releases"> Release notes"#));
+    assert!(html.contains(r#"value="immediate" checked"#));
+    assert!(html.contains(r#"value="unsubscribe""#));
+}
+
+#[tokio::test]
+async fn the_preferences_are_saved() {
+    let app = spawn_app_with_topics().await;
+    let link = preferences_link(&app).await;
+
+    let response = post_preferences(
+        &link,
+        &[
+            ("name", "ursula k. le guin"),
+            ("topic", "releases"),
+            ("topic", "unknown"),
+            ("frequency", "digest"),
+            ("action", "save"),
+        ],
+    )
+    .await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let html = response.text().await.unwrap();
+    assert!(html.contains("Your preferences are saved."));
+    assert!(html.contains(r#"value="releases" checked"#));
+    assert!(html.contains(r#"value="digest" checked"#));
+    let (name, frequency): (String, String) = app
+        .fetch_one("SELECT name, frequency FROM subscriptions")
+        .await;
+    assert_eq!(name, "ursula k. le guin");
+    assert_eq!(frequency, "digest");
+    // There is only one subscriber
+    let topics: Vec<(String,)> = app.fetch_all("SELECT topic FROM subscriber_topics").await;
+    assert_eq!(topics, vec![("releases".to_owned(),)]);
+}
+
+#[tokio::test]
+async fn an_invalid_name_is_not_saved() {
+    let app = spawn_app_with_topics().await;
+    let link = preferences_link(&app).await;
+
+    let response = post_preferences(&link, &[("name", " "), ("action", "save")]).await;
+
+    assert_eq!(response.status().as_u16(), 422);
+    let (name,): (String,) = app.fetch_one("SELECT name FROM subscriptions").await;
+    assert_eq!(name, "le guin");
+}
+
+#[tokio::test]
+async fn a_name_rejected_by_the_subscription_policy_is_not_saved() {
+    let app = spawn_app_with(|configuration| configuration.subscriptions.min_name_length = 4).await;
+    let link = preferences_link(&app).await;
+
+    let response = post_preferences(&link, &[("name", "le"), ("action", "save")]).await;
+
+    assert_eq!(response.status().as_u16(), 422);
+    assert!(response.text().await.unwrap().contains("at least 4"));
+    let (name,): (String,) = app.fetch_one("SELECT name FROM subscriptions").await;
+    assert_eq!(name, "le guin");
+}
+
+#[tokio::test]
+async fn an_issue_with_a_topic_is_only_sent_to_the_subscribers_who_chose_it() {
+    let app = spawn_app_with_topics().await;
+    let link = preferences_link(&app).await;
+    post_preferences(
+        &link,
+        &[
+            ("name", "le guin"),
+            ("topic", "releases"),
+            ("action", "save"),
+        ],
+    )
+    .await
+    .error_for_status()
+    .unwrap();
+
+    for (topic, sent) in [("events", 0), ("releases", 1)] {
+        let _mock_guard = Mock::given(path("/email/batch"))
+            .respond_with(BatchResponder::rejecting(&[]))
+            .expect(sent)
+            .mount_as_scoped(&app.email_server)
+            .await;
+        let response = app
+            .post_newsletters(serde_json::json!({
+                "title": "Newsletter title",
+                "content": {
+                    "text": "Newsletter body as plain text",
+                    "html": "<p>Newsletter body as HTML</p>",
+                },
+                "topic": topic,
+            }))
+            .await;
+
+        let published: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(published["queued"], sent);
+        app.deliver_queued_issues().await;
+    }
+}
+
+#[tokio::test]
+async fn an_issue_with_an_unknown_topic_is_rejected() {
+    let app = spawn_app_with_topics().await;
+
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            },
+            "topic": "gossip",
+        }))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 422);
+    let (issues,): (i64,) = app
+        .fetch_one("SELECT COUNT(*) FROM newsletter_issues")
+        .await;
+    assert_eq!(issues, 0);
+}
+
+#[tokio::test]
+async fn the_subscribers_who_chose_a_digest_get_the_issues_in_one_email() {
+    let app = spawn_app_with_topics().await;
+    let link = preferences_link(&app).await;
+    post_preferences(
+        &link,
+        &[
+            ("name", "le guin"),
+            ("frequency", "digest"),
+            ("action", "save"),
+        ],
+    )
+    .await
+    .error_for_status()
+    .unwrap();
+
+    let mut issue_ids = Vec::new();
+    {
+        let _mock_guard = Mock::given(path("/email/batch"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(0)
+            .mount_as_scoped(&app.email_server)
+            .await;
+        for title in ["First issue", "Second issue"] {
+            let report: serde_json::Value = app
+                .post_newsletters(serde_json::json!({
+                    "title": title,
+                    "content": {
+                        "text": format!("{} as plain text", title),
+                        "html": format!("<html><body><p>{} as HTML</p></body></html>", title),
+                    }
+                }))
+                .await
+                .json()
+                .await
+                .unwrap();
+            issue_ids.push(report["issue_id"].as_str().unwrap().to_owned());
+        }
+    }
+    Mock::given(path("/email/batch"))
+        .respond_with(BatchResponder::rejecting(&[]))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_digests(&app.admin_token).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(report["sent"], 2);
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body[0]["Subject"], "Your digest of 2 issues");
+    let html = body[0]["HtmlBody"].as_str().unwrap();
+    assert!(html.contains("<p>First issue as HTML</p>"));
+    assert!(html.contains("<p>Second issue as HTML</p>"));
+    assert!(html.contains("/preferences/"));
+    assert!(body[0]["TextBody"]
+        .as_str()
+        .unwrap()
+        .contains("Second issue as plain text"));
+    for issue_id in &issue_ids {
+        let summary: serde_json::Value = app
+            .get_issue_deliveries(&app.admin_token, issue_id)
+            .await
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(summary["sent"], 1);
+        assert_eq!(summary["queued"], 0);
+    }
+
+    // Nothing is left for the next digest
+    let report: serde_json::Value = app
+        .post_digests(&app.admin_token)
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(report["sent"], 0);
+}
+
+#[tokio::test]
+async fn unsubscribed_subscribers_do_not_receive_the_next_issues() {
+    let app = spawn_app_with_topics().await;
+    let link = preferences_link(&app).await;
+
+    let response = post_preferences(&link, &[("action", "unsubscribe")]).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("You are unsubscribed"));
+    let (status,): (SubscriptionStatus,) = app.fetch_one("SELECT status FROM subscriptions").await;
+    assert_eq!(status, SubscriptionStatus::Unsubscribed);
+
+    Mock::given(path("/email/batch"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    app.post_newsletters(serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        }
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+
+    let response = reqwest::get(link).await.unwrap();
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("You are unsubscribed"));
+}
+
+#[tokio::test]
+async fn an_invalid_link_is_rejected_with_a_404() {
+    let app = spawn_app_with_topics().await;
+
+    let response = reqwest::get(format!("{}/preferences/not-a-token", app.address))
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 404);
+}
@@ -0,0 +1,366 @@
+//! The behaviour shared by all the backends of the repositories, every case runs against Postgres,
+//! the in-memory backend and SQLite when the `sqlite` feature is enabled.
+
+use chrono::{Duration, Utc};
+use uuid::Uuid;
+use zero2prod::{
+    domain::{
+        DeliveryFrequency, EmailAddress, NewSubscriber, SubscriptionStatus, SubscriptionToken,
+    },
+    email_client::OutgoingEmail,
+    issue_delivery::NewsletterIssue,
+    repository::{
+        IssueRepository, Preferences, RepositoryError, SubscriberRepository, TokenRepository,
+    },
+    tracking::TrackingOptions,
+};
+
+/// The repositories of one backend.
+trait Repository: SubscriberRepository + TokenRepository + IssueRepository {}
+
+impl<T: SubscriberRepository + TokenRepository + IssueRepository> Repository for T {}
+
+/// Run each case against both backends.
+macro_rules! conformance_tests {
+    ($($case:ident),* $(,)?) => {
+        mod in_memory {
+            use zero2prod::repository::InMemoryRepository;
+
+            $(
+                #[tokio::test]
+                async fn $case() {
+                    super::$case(InMemoryRepository::new()).await;
+                }
+            )*
+        }
+
+        mod postgres {
+            use zero2prod::repository::PgRepository;
+
+            $(
+                #[tokio::test]
+                async fn $case() {
+                    super::$case(PgRepository::new(crate::helpers::spawn_database().await)).await;
+                }
+            )*
+        }
+
+        #[cfg(feature = "sqlite")]
+        mod sqlite {
+            $(
+                #[tokio::test]
+                async fn $case() {
+                    super::$case(crate::helpers::spawn_sqlite_database().await.0).await;
+                }
+            )*
+        }
+    };
+}
+
+conformance_tests!(
+    a_new_subscriber_is_pending_confirmation,
+    a_token_leads_to_its_subscriber,
+    another_form_of_a_subscribed_address_is_a_duplicate,
+    a_pending_subscriber_subscribing_again_gets_a_new_token,
+    only_the_allowed_transitions_are_applied,
+    the_preferences_are_replaced,
+    an_issue_is_read_back_as_published,
+);
+
+fn new_subscriber(email: &str) -> NewSubscriber {
+    NewSubscriber {
+        email: email.parse().unwrap(),
+        name: "le guin".parse().unwrap(),
+    }
+}
+
+async fn add_pending(
+    repository: &impl Repository,
+    email: &str,
+    token: &SubscriptionToken,
+) -> Result<Uuid, RepositoryError> {
+    let subscriber = new_subscriber(email);
+    let recipient: EmailAddress = email.parse().unwrap();
+    let confirmation = OutgoingEmail {
+        recipient: &recipient,
+        subject: "Welcome!",
+        html_content: "<p>Welcome!</p>",
+        text_content: "Welcome!",
+    };
+    repository
+        .add_pending(&subscriber, token, &confirmation)
+        .await
+}
+
+async fn status(repository: &impl Repository, id: Uuid) -> SubscriptionStatus {
+    SubscriberRepository::get(repository, id)
+        .await
+        .unwrap()
+        .unwrap()
+        .status
+}
+
+async fn a_new_subscriber_is_pending_confirmation(repository: impl Repository) {
+    let id = add_pending(
+        &repository,
+        "ursula@example.com",
+        &SubscriptionToken::generate(),
+    )
+    .await
+    .unwrap();
+
+    let subscriber = SubscriberRepository::get(&repository, id)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(subscriber.id, id);
+    assert_eq!(subscriber.email, "ursula@example.com");
+    assert_eq!(subscriber.name, "le guin");
+    assert_eq!(subscriber.status, SubscriptionStatus::PendingConfirmation);
+    assert!(Utc::now() - subscriber.subscribed_at < Duration::minutes(1));
+    assert!(SubscriberRepository::get(&repository, Uuid::new_v4())
+        .await
+        .unwrap()
+        .is_none());
+}
+
+async fn a_token_leads_to_its_subscriber(repository: impl Repository) {
+    let token = SubscriptionToken::generate();
+    let id = add_pending(&repository, "ursula@example.com", &token)
+        .await
+        .unwrap();
+
+    assert_eq!(repository.subscriber_id(&token).await.unwrap(), Some(id));
+    assert_eq!(
+        repository
+            .subscriber_id(&SubscriptionToken::generate())
+            .await
+            .unwrap(),
+        None
+    );
+}
+
+async fn another_form_of_a_subscribed_address_is_a_duplicate(repository: impl Repository) {
+    let id = add_pending(
+        &repository,
+        "ursula.le.guin@gmail.com",
+        &SubscriptionToken::generate(),
+    )
+    .await
+    .unwrap();
+    repository
+        .transition(id, SubscriptionStatus::Confirmed)
+        .await
+        .unwrap();
+
+    let token = SubscriptionToken::generate();
+    let result = add_pending(&repository, "UrsulaLeGuin+news@gmail.com", &token).await;
+
+    assert!(matches!(result, Err(RepositoryError::Duplicate)));
+    // Nothing is stored for the duplicate
+    assert_eq!(repository.subscriber_id(&token).await.unwrap(), None);
+}
+
+async fn a_pending_subscriber_subscribing_again_gets_a_new_token(repository: impl Repository) {
+    let id = add_pending(
+        &repository,
+        "ursula.le.guin@gmail.com",
+        &SubscriptionToken::generate(),
+    )
+    .await
+    .unwrap();
+    let first_subscribed_at = SubscriberRepository::get(&repository, id)
+        .await
+        .unwrap()
+        .unwrap()
+        .subscribed_at;
+
+    let token = SubscriptionToken::generate();
+    let result = add_pending(&repository, "UrsulaLeGuin+news@gmail.com", &token).await;
+
+    assert_eq!(result.unwrap(), id);
+    assert_eq!(repository.subscriber_id(&token).await.unwrap(), Some(id));
+    let subscriber = SubscriberRepository::get(&repository, id)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(subscriber.status, SubscriptionStatus::PendingConfirmation);
+    assert!(subscriber.subscribed_at >= first_subscribed_at);
+}
+
+async fn only_the_allowed_transitions_are_applied(repository: impl Repository) {
+    let id = add_pending(
+        &repository,
+        "ursula@example.com",
+        &SubscriptionToken::generate(),
+    )
+    .await
+    .unwrap();
+
+    assert!(repository
+        .transition(id, SubscriptionStatus::Confirmed)
+        .await
+        .unwrap());
+    assert!(!repository
+        .transition(id, SubscriptionStatus::PendingConfirmation)
+        .await
+        .unwrap());
+    assert_eq!(status(&repository, id).await, SubscriptionStatus::Confirmed);
+
+    assert!(repository
+        .transition(id, SubscriptionStatus::Suppressed)
+        .await
+        .unwrap());
+    assert!(!repository
+        .transition(id, SubscriptionStatus::Confirmed)
+        .await
+        .unwrap());
+    assert_eq!(
+        status(&repository, id).await,
+        SubscriptionStatus::Suppressed
+    );
+
+    assert!(!repository
+        .transition(id, SubscriptionStatus::Unsubscribed)
+        .await
+        .unwrap());
+
+    assert!(!repository
+        .transition(Uuid::new_v4(), SubscriptionStatus::Confirmed)
+        .await
+        .unwrap());
+}
+
+async fn the_preferences_are_replaced(repository: impl Repository) {
+    let id = add_pending(
+        &repository,
+        "ursula@example.com",
+        &SubscriptionToken::generate(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        repository.preferences(id).await.unwrap(),
+        Some(Preferences::default())
+    );
+
+    for topics in [vec!["events", "releases"], vec!["releases"]] {
+        let preferences = Preferences {
+            topics: topics.into_iter().map(String::from).collect(),
+            frequency: DeliveryFrequency::Digest,
+        };
+        assert!(repository
+            .update_preferences(id, &"ursula k. le guin".parse().unwrap(), &preferences)
+            .await
+            .unwrap());
+
+        assert_eq!(repository.preferences(id).await.unwrap(), Some(preferences));
+    }
+    let subscriber = SubscriberRepository::get(&repository, id)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(subscriber.name, "ursula k. le guin");
+
+    assert!(!repository
+        .update_preferences(
+            Uuid::new_v4(),
+            &"le guin".parse().unwrap(),
+            &Preferences::default()
+        )
+        .await
+        .unwrap());
+    assert_eq!(repository.preferences(Uuid::new_v4()).await.unwrap(), None);
+}
+
+async fn an_issue_is_read_back_as_published(repository: impl Repository) {
+    let issue = NewsletterIssue {
+        id: Uuid::new_v4(),
+        title: "Newsletter title".into(),
+        html_content: "<p>Newsletter body as HTML</p>".into(),
+        text_content: "Newsletter body as plain text".into(),
+        tracking: TrackingOptions {
+            opens: true,
+            clicks: false,
+        },
+        topic: Some("releases".into()),
+    };
+
+    IssueRepository::add(&repository, &issue).await.unwrap();
+
+    assert_eq!(
+        IssueRepository::get(&repository, issue.id).await.unwrap(),
+        Some(issue)
+    );
+    assert_eq!(
+        IssueRepository::get(&repository, Uuid::new_v4())
+            .await
+            .unwrap(),
+        None
+    );
+}
+
+#[tokio::test]
+async fn the_canonical_addresses_of_postgres_are_backfilled_from_rust() {
+    let pool = crate::helpers::spawn_database().await;
+    // Rows left by the SQL backfill of the migration, which lowercases without punycode
+    for (email, canonical) in [
+        ("Ursula@BÜCHER.de", Some("ursula@bÜcher.de")),
+        ("ursula@xn--bcher-kva.de", None),
+        ("le.guin@example.com", Some("le.guin@example.com")),
+    ] {
+        sqlx::query(
+            r#"INSERT INTO subscriptions (id, email, canonical_email, name, subscribed_at, status)
+                VALUES ($1, $2, $3, 'le guin', now(), 'confirmed')"#,
+        )
+        .bind(Uuid::new_v4())
+        .bind(email)
+        .bind(canonical)
+        .execute(&pool)
+        .await
+        .unwrap();
+    }
+    sqlx::query(
+        r#"INSERT INTO suppressions (scope, value, reason, created_at)
+            VALUES ('address', 'ursula.le.guin@gmail.com', 'bounced', now())"#,
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+    let repository = zero2prod::repository::PgRepository::new(pool.clone());
+
+    let backfill = repository.backfill_canonical_emails().await.unwrap();
+
+    assert_eq!(backfill.subscribers, 1);
+    assert_eq!(backfill.collisions, 1);
+    assert_eq!(backfill.suppressions, 1);
+    let mut canonical: Vec<(String, Option<String>)> =
+        sqlx::query_as("SELECT email, canonical_email FROM subscriptions")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+    canonical.sort();
+    assert_eq!(
+        canonical,
+        vec![
+            (
+                "Ursula@BÜCHER.de".to_owned(),
+                Some("ursula@xn--bcher-kva.de".to_owned())
+            ),
+            (
+                "le.guin@example.com".to_owned(),
+                Some("le.guin@example.com".to_owned())
+            ),
+            ("ursula@xn--bcher-kva.de".to_owned(), None),
+        ]
+    );
+    let (suppressed,): (String,) = sqlx::query_as("SELECT value FROM suppressions")
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(suppressed, "ursulaleguin@gmail.com");
+    assert_eq!(
+        repository.backfill_canonical_emails().await.unwrap(),
+        Default::default()
+    );
+}
@@ -0,0 +1,176 @@
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+use zero2prod::domain::SubscriptionStatus;
+
+use crate::helpers::{form_urlencoded, spawn_app, TestApp};
+
+/// Fetch the form, it returns the CSRF token of its cookie and of its hidden field.
+async fn fetch_form(app: &TestApp) -> (String, String) {
+    let response = app.get_subscribe_form().await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let cookie = response.headers()["Set-Cookie"]
+        .to_str()
+        .unwrap()
+        .to_owned();
+    assert!(cookie.contains("HttpOnly"));
+    assert!(cookie.contains("SameSite=Strict"));
+    let cookie = cookie
+        .split(';')
+        .next()
+        .unwrap()
+        .strip_prefix("subscribe_csrf=")
+        .unwrap()
+        .to_owned();
+
+    let html = response.text().await.unwrap();
+    let field = html
+        .split(r#"name="csrf_token" value=""#)
+        .nth(1)
+        .and_then(|rest| rest.split('"').next())
+        .unwrap()
+        .to_owned();
+    (cookie, field)
+}
+
+async fn subscriber_count(app: &TestApp) -> i64 {
+    let (count,) = app.fetch_one("SELECT COUNT(*) FROM subscriptions").await;
+    count
+}
+
+#[tokio::test]
+async fn the_form_has_a_csrf_token_and_a_honeypot() {
+    let app = spawn_app().await;
+
+    let (cookie, field) = fetch_form(&app).await;
+
+    assert_eq!(cookie, field);
+    let html = app.get_subscribe_form().await.text().await.unwrap();
+    assert!(html.contains(r#"name="website""#));
+}
+
+#[tokio::test]
+async fn the_form_subscribes_a_new_subscriber() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let (cookie, csrf_token) = fetch_form(&app).await;
+
+    let body = form_urlencoded(&[
+        ("name", "le guin"),
+        ("email", "ursula_le_guin@gmail.com"),
+        ("csrf_token", &csrf_token),
+        ("website", ""),
+    ]);
+    let response = app.post_subscribe_form(body, Some(&cookie)).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.text().await.unwrap().contains("Check your inbox"));
+    let (email, status): (String, SubscriptionStatus) = app
+        .fetch_one("SELECT email, status FROM subscriptions")
+        .await;
+    assert_eq!(email, "ursula_le_guin@gmail.com");
+    assert_eq!(status, SubscriptionStatus::PendingConfirmation);
+    app.dispatch_pending_emails().await;
+}
+
+#[tokio::test]
+async fn a_submission_without_a_matching_csrf_token_is_rejected() {
+    let app = spawn_app().await;
+    let (cookie, csrf_token) = fetch_form(&app).await;
+    let (other_cookie, _) = fetch_form(&app).await;
+
+    for (csrf_token, cookie) in [
+        (csrf_token.as_str(), None),
+        ("", Some(cookie.as_str())),
+        (csrf_token.as_str(), Some(other_cookie.as_str())),
+    ] {
+        let body = form_urlencoded(&[
+            ("name", "le guin"),
+            ("email", "ursula_le_guin@gmail.com"),
+            ("csrf_token", csrf_token),
+        ]);
+        let response = app.post_subscribe_form(body, cookie).await;
+
+        assert_eq!(response.status().as_u16(), 403);
+    }
+    assert_eq!(subscriber_count(&app).await, 0);
+}
+
+#[tokio::test]
+async fn a_filled_honeypot_looks_successful_but_stores_nothing() {
+    let app = spawn_app().await;
+    let (cookie, csrf_token) = fetch_form(&app).await;
+
+    let body = form_urlencoded(&[
+        ("name", "le guin"),
+        ("email", "ursula_le_guin@gmail.com"),
+        ("csrf_token", &csrf_token),
+        ("website", "https://spam.example.com"),
+    ]);
+    let response = app.post_subscribe_form(body, Some(&cookie)).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.text().await.unwrap().contains("Check your inbox"));
+    assert_eq!(subscriber_count(&app).await, 0);
+}
+
+#[tokio::test]
+async fn invalid_fields_show_the_form_again_with_an_error() {
+    let app = spawn_app().await;
+    let (cookie, csrf_token) = fetch_form(&app).await;
+
+    let body = form_urlencoded(&[
+        ("name", "le guin"),
+        ("email", r#""><script>alert(1)</script>"#),
+        ("csrf_token", &csrf_token),
+    ]);
+    let response = app.post_subscribe_form(body, Some(&cookie)).await;
+
+    assert_eq!(response.status().as_u16(), 422);
+    let html = response.text().await.unwrap();
+    assert!(html.contains("invalid email address"));
+    assert!(html.contains(r#"value="le guin""#));
+    assert!(html.contains("&quot;&gt;&lt;script&gt;"));
+    assert!(!html.contains("<script>"));
+    assert_eq!(subscriber_count(&app).await, 0);
+}
+
+#[tokio::test]
+async fn an_address_already_subscribed_shows_the_form_again_with_an_error() {
+    let app = spawn_app().await;
+    let (cookie, csrf_token) = fetch_form(&app).await;
+    let body = form_urlencoded(&[
+        ("name", "le guin"),
+        ("email", "ursula_le_guin@gmail.com"),
+        ("csrf_token", &csrf_token),
+    ]);
+    assert_eq!(
+        app.post_subscribe_form(body, Some(&cookie))
+            .await
+            .status()
+            .as_u16(),
+        200
+    );
+    app.execute("UPDATE subscriptions SET status = 'confirmed'")
+        .await;
+
+    let body = form_urlencoded(&[
+        ("name", "le guin"),
+        ("email", "Ursula_Le_Guin+news@gmail.com"),
+        ("csrf_token", &csrf_token),
+    ]);
+    let response = app.post_subscribe_form(body, Some(&cookie)).await;
+
+    assert_eq!(response.status().as_u16(), 409);
+    let html = response.text().await.unwrap();
+    assert!(html.contains("already subscribed"));
+    assert!(html.contains(r#"value="Ursula_Le_Guin+news@gmail.com""#));
+    assert_eq!(subscriber_count(&app).await, 1);
+}
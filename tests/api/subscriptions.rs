@@ -1,9 +1,14 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
 use wiremock::{
     matchers::{method, path},
     Mock, ResponseTemplate,
 };
 
-use crate::helpers::spawn_app;
+use zero2prod::domain::{Script, SubscriptionStatus};
+
+use crate::helpers::{form_urlencoded, spawn_app, spawn_app_with};
 
 #[tokio::test]
 async fn subscribe_returns_a_200_for_valid_form_data() {
@@ -20,6 +25,7 @@ async fn subscribe_returns_a_200_for_valid_form_data() {
     let response = app.post_subscriptions(body.into()).await;
 
     assert_eq!(response.status().as_u16(), 200);
+    app.dispatch_pending_emails().await;
 }
 
 #[tokio::test]
@@ -29,14 +35,97 @@ async fn subscribe_persists_the_new_subscriber() {
     let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
     app.post_subscriptions(body.into()).await;
 
-    let saved = sqlx::query!("SELECT email, name, status FROM subscriptions")
-        .fetch_one(&app.db_pool)
+    let (email, name, status): (String, String, SubscriptionStatus) = app
+        .fetch_one("SELECT email, name, status FROM subscriptions")
+        .await;
+
+    assert_eq!(email, "ursula_le_guin@gmail.com");
+    assert_eq!(name, "le guin");
+    assert_eq!(status, SubscriptionStatus::PendingConfirmation);
+}
+
+#[tokio::test]
+async fn subscribe_normalises_the_email_address() {
+    let app = spawn_app().await;
+
+    let body = form_urlencoded(&[("name", "le guin"), ("email", " Ursula.Le.Guin@GMAIL.com ")]);
+    app.post_subscriptions(body)
+        .await
+        .error_for_status()
+        .unwrap();
+
+    let (email, canonical_email): (String, Option<String>) = app
+        .fetch_one("SELECT email, canonical_email FROM subscriptions")
+        .await;
+    assert_eq!(email, "Ursula.Le.Guin@gmail.com");
+    assert_eq!(canonical_email.as_deref(), Some("ursulaleguin@gmail.com"));
+}
+
+#[tokio::test]
+async fn subscribe_normalises_the_name() {
+    let app = spawn_app().await;
+
+    let body = form_urlencoded(&[
+        ("name", "  Zoe\u{0308}\t Le   Guin "),
+        ("email", "ursula_le_guin@gmail.com"),
+    ]);
+    app.post_subscriptions(body)
         .await
-        .expect("Failed to fetch saved subscription.");
+        .error_for_status()
+        .unwrap();
 
-    assert_eq!(saved.email, "ursula_le_guin@gmail.com");
-    assert_eq!(saved.name, "le guin");
-    assert_eq!(saved.status, "pending_confirmation");
+    let (name,): (String,) = app.fetch_one("SELECT name FROM subscriptions").await;
+    assert_eq!(name, "Zo\u{00eb} Le Guin");
+}
+
+#[tokio::test]
+async fn subscribe_rejects_the_names_refused_by_the_policy() {
+    let app = spawn_app_with(|configuration| {
+        configuration.subscriptions.min_name_length = 3;
+        configuration.subscriptions.allowed_name_scripts = vec![Script::Latin];
+    })
+    .await;
+
+    let test_cases = vec![
+        ("Le", "at least 3 characters"),
+        ("Urs\u{0430}la", "accepted alphabets"),
+    ];
+
+    for (name, reason) in test_cases {
+        let body = form_urlencoded(&[("name", name), ("email", "ursula_le_guin@gmail.com")]);
+        let response = app.post_subscriptions(body).await;
+
+        assert_eq!(response.status().as_u16(), 422, "{} was accepted", name);
+        let message = response.text().await.unwrap();
+        assert!(message.contains(reason), "unexpected reason: {}", message);
+    }
+}
+
+#[tokio::test]
+async fn subscribe_rejects_another_form_of_a_subscribed_address() {
+    let app = spawn_app().await;
+
+    let body = form_urlencoded(&[("name", "le guin"), ("email", "ursula.le.guin@gmail.com")]);
+    app.post_subscriptions(body)
+        .await
+        .error_for_status()
+        .unwrap();
+    app.execute("UPDATE subscriptions SET status = 'confirmed'")
+        .await;
+    let body = form_urlencoded(&[
+        ("name", "le guin"),
+        ("email", "UrsulaLeGuin+news@Gmail.com"),
+    ]);
+    let response = app.post_subscriptions(body).await;
+
+    assert_eq!(response.status().as_u16(), 409);
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("already subscribed"));
+    let (subscribers,): (i64,) = app.fetch_one("SELECT COUNT(*) FROM subscriptions").await;
+    assert_eq!(subscribers, 1);
 }
 
 #[tokio::test]
@@ -83,6 +172,80 @@ async fn subscribe_returns_a_422_when_fields_are_present_but_empty() {
     }
 }
 
+#[tokio::test]
+async fn subscribe_rejects_the_addresses_refused_by_the_policy() {
+    let app = spawn_app_with(|configuration| {
+        configuration.subscriptions.denied_domains = vec!["spam.example.com".into()];
+    })
+    .await;
+
+    let test_cases = vec![
+        (
+            "ursula@yopmail.com",
+            "disposable email addresses are not accepted",
+        ),
+        ("noreply@example.com", "role addresses"),
+        (
+            "ursula@news.spam.example.com",
+            "this email domain are not accepted",
+        ),
+    ];
+
+    for (email, reason) in test_cases {
+        let body = form_urlencoded(&[("name", "le guin"), ("email", email)]);
+        let response = app.post_subscriptions(body).await;
+
+        assert_eq!(response.status().as_u16(), 422, "{} was accepted", email);
+        let message = response.text().await.unwrap();
+        assert!(message.contains(reason), "unexpected reason: {}", message);
+    }
+    let (subscribers,): (i64,) = app.fetch_one("SELECT COUNT(*) FROM subscriptions").await;
+    assert_eq!(subscribers, 0);
+}
+
+#[tokio::test]
+async fn subscribe_rejects_a_misspelt_domain_or_a_domain_without_mail_server() {
+    let app = spawn_app_with(|configuration| {
+        configuration.subscriptions.domain_check.enabled = true;
+    })
+    .await;
+    app.resolver.mark_undeliverable("gmial.com");
+    app.resolver.mark_undeliverable("nowhere.example");
+
+    let test_cases = vec![
+        ("ursula@gmial.com", "did you mean gmail.com?"),
+        ("ursula@nowhere.example", "cannot receive emails"),
+    ];
+
+    for (email, reason) in test_cases {
+        let body = form_urlencoded(&[("name", "le guin"), ("email", email)]);
+        let response = app.post_subscriptions(body).await;
+
+        assert_eq!(response.status().as_u16(), 422, "{} was accepted", email);
+        let message = response.text().await.unwrap();
+        assert!(message.contains(reason), "unexpected reason: {}", message);
+    }
+    let (subscribers,): (i64,) = app.fetch_one("SELECT COUNT(*) FROM subscriptions").await;
+    assert_eq!(subscribers, 0);
+}
+
+#[tokio::test]
+async fn subscribe_accepts_a_real_provider_close_to_a_popular_domain() {
+    let app = spawn_app_with(|configuration| {
+        configuration.subscriptions.domain_check.enabled = true;
+    })
+    .await;
+
+    for email in ["ursula@mail.com", "ursula@gmx.net", "ursula@yahoo.de"] {
+        let body = form_urlencoded(&[("name", "le guin"), ("email", email)]);
+        let response = app.post_subscriptions(body).await;
+
+        assert_eq!(response.status().as_u16(), 200, "{} was rejected", email);
+    }
+    let (subscribers,): (i64,) = app.fetch_one("SELECT COUNT(*) FROM subscriptions").await;
+    assert_eq!(subscribers, 3);
+}
+
 #[tokio::test]
 async fn subscribe_sends_a_confirmation_email_for_valid_data() {
     let app = spawn_app().await;
@@ -96,6 +259,7 @@ async fn subscribe_sends_a_confirmation_email_for_valid_data() {
 
     let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
     app.post_subscriptions(body.into()).await;
+    app.dispatch_pending_emails().await;
 
     let email_request = &app.email_server.received_requests().await.unwrap()[0];
     let confirmation_links = app.get_confirmation_links(email_request);
@@ -103,14 +267,187 @@ async fn subscribe_sends_a_confirmation_email_for_valid_data() {
     assert_eq!(confirmation_links.html, confirmation_links.text);
 }
 
+#[tokio::test]
+async fn the_provider_of_a_sent_confirmation_email_is_recorded() {
+    let app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "MessageID": "b7bc2f4a-e38e-4336-af7d-e6c392c2f817"
+        })))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    app.post_subscriptions(body.into()).await;
+    app.dispatch_pending_emails().await;
+
+    let (recipient, provider, provider_message_id): (String, String, Option<String>) = app
+        .fetch_one("SELECT recipient, provider, provider_message_id FROM sent_emails")
+        .await;
+    assert_eq!(recipient, "ursula_le_guin@gmail.com");
+    assert_eq!(provider, "postmark");
+    assert_eq!(
+        provider_message_id.as_deref(),
+        Some("b7bc2f4a-e38e-4336-af7d-e6c392c2f817")
+    );
+}
+
 #[tokio::test]
 async fn subscribe_fails_if_there_is_a_fatal_database_error() {
     let app = spawn_app().await;
     let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
-    sqlx::query!("ALTER TABLE subscription_tokens DROP COLUMN subscription_token;")
-        .execute(&app.db_pool)
-        .await
-        .unwrap();
+    app.execute("ALTER TABLE subscription_tokens RENAME COLUMN subscription_token TO token;")
+        .await;
+    let response = app.post_subscriptions(body.into()).await;
+    assert_eq!(response.status().as_u16(), 500);
+}
+
+#[tokio::test]
+async fn subscribe_does_not_store_the_subscriber_if_the_email_cannot_be_queued() {
+    let app = spawn_app().await;
+    app.execute("ALTER TABLE outbox DROP COLUMN subject;").await;
+
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
     let response = app.post_subscriptions(body.into()).await;
+
     assert_eq!(response.status().as_u16(), 500);
+    let (subscribers,): (i64,) = app.fetch_one("SELECT COUNT(*) FROM subscriptions").await;
+    assert_eq!(subscribers, 0);
+}
+
+#[tokio::test]
+async fn subscribe_succeeds_while_the_provider_is_down() {
+    let app = spawn_app_with(|configuration| {
+        configuration.email_client.max_retries = 0;
+        configuration.email_client.circuit_breaker.window_size = 2;
+    })
+    .await;
+
+    // The circuit opens after the first two failures, the third email is not sent
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(503))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    for email in [
+        "ursula@example.com",
+        "octavia@example.com",
+        "ted@example.com",
+    ] {
+        let body = form_urlencoded(&[("name", "le guin"), ("email", email)]);
+        let response = app.post_subscriptions(body).await;
+        assert_eq!(response.status().as_u16(), 200);
+        app.dispatch_pending_emails().await;
+    }
+
+    let pending: Vec<(String, i32, Option<String>)> = app
+        .fetch_all(
+            r#"SELECT recipient, attempts, last_error FROM outbox
+                WHERE dead_lettered_at IS NULL
+                ORDER BY recipient"#,
+        )
+        .await;
+    assert_eq!(pending.len(), 3);
+    // Nothing was sent to ted, the attempt is not counted
+    assert!(pending.iter().any(
+        |(recipient, attempts, last_error)| recipient == "ted@example.com"
+            && *attempts == 0
+            && last_error.as_deref() == Some("circuit breaker open")
+    ));
+}
+
+#[tokio::test]
+async fn the_confirmation_email_is_retried_until_the_provider_is_back() {
+    let app = spawn_app_with(|configuration| configuration.email_client.max_retries = 0).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(503))
+        .up_to_n_times(1)
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    let response = app.post_subscriptions(body.into()).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let mut remaining: i64 = 1;
+    for _ in 0..50 {
+        (remaining,) = app.fetch_one("SELECT COUNT(*) FROM outbox").await;
+        if remaining == 0 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert_eq!(remaining, 0);
+    let email_request = &app.email_server.received_requests().await.unwrap()[1];
+    let confirmation_links = app.get_confirmation_links(email_request);
+    assert_eq!(confirmation_links.html, confirmation_links.text);
+}
+
+#[tokio::test]
+async fn the_confirmation_email_is_leased_while_it_is_sent() {
+    let app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(1)))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    let response = app.post_subscriptions(body.into()).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    // The lease is committed before the provider answers, SQLite polls the outbox every second
+    let mut leased: i64 = 0;
+    for _ in 0..300 {
+        (leased,) = app
+            .fetch_one("SELECT COUNT(*) FROM outbox WHERE locked_until IS NOT NULL")
+            .await;
+        if leased == 1 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(leased, 1);
+    app.dispatch_pending_emails().await;
+    let (remaining,): (i64,) = app.fetch_one("SELECT COUNT(*) FROM outbox").await;
+    assert_eq!(remaining, 0);
+}
+
+#[tokio::test]
+async fn a_rejected_confirmation_email_is_dead_lettered() {
+    let app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(422))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    let response = app.post_subscriptions(body.into()).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    app.dispatch_pending_emails().await;
+    let (attempts, dead_lettered_at): (i32, Option<DateTime<Utc>>) = app
+        .fetch_one("SELECT attempts, dead_lettered_at FROM outbox")
+        .await;
+    assert_eq!(attempts, 1);
+    assert!(dead_lettered_at.is_some());
 }
@@ -1,9 +1,28 @@
+use chrono::Utc;
 use wiremock::{
     matchers::{method, path},
     Mock, ResponseTemplate,
 };
 
-use crate::helpers::spawn_app;
+use zero2prod::domain::SubscriptionStatus;
+
+use crate::helpers::{spawn_app, spawn_app_with, TestApp};
+
+/// Subscribe and return the confirmation link sent by email.
+async fn subscribe(app: &TestApp) -> reqwest::Url {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    app.post_subscriptions(body.into()).await;
+    app.dispatch_pending_emails().await;
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    app.get_confirmation_links(email_request).html
+}
 
 #[tokio::test]
 async fn confirmations_without_token_are_rejected_with_a_400() {
@@ -16,6 +35,37 @@ async fn confirmations_without_token_are_rejected_with_a_400() {
     assert_eq!(response.status().as_u16(), 422);
 }
 
+#[tokio::test]
+async fn confirmations_with_a_malformed_token_are_rejected_with_a_400() {
+    let app = spawn_app().await;
+
+    for token in ["too-short", "aaaaaaaaaaaa%27OR%271%27%3D%271aaaa"] {
+        let response = reqwest::get(&format!(
+            "{}/subscriptions/confirm?token={}",
+            app.address, token
+        ))
+        .await
+        .unwrap();
+
+        assert_eq!(response.status().as_u16(), 400, "{} was accepted", token);
+    }
+}
+
+#[tokio::test]
+async fn confirmations_with_an_unknown_token_are_rejected_with_a_401() {
+    let app = spawn_app().await;
+
+    let response = reqwest::get(&format!(
+        "{}/subscriptions/confirm?token={}",
+        app.address,
+        "a".repeat(25)
+    ))
+    .await
+    .unwrap();
+
+    assert_eq!(response.status().as_u16(), 401);
+}
+
 #[tokio::test]
 async fn the_link_returned_by_subscribe_returns_a_200_if_called() {
     let app = spawn_app().await;
@@ -28,6 +78,7 @@ async fn the_link_returned_by_subscribe_returns_a_200_if_called() {
 
     let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
     app.post_subscriptions(body.into()).await;
+    app.dispatch_pending_emails().await;
 
     let email_request = &app.email_server.received_requests().await.unwrap()[0];
     let confirmation_links = app.get_confirmation_links(email_request);
@@ -48,6 +99,7 @@ async fn clicking_on_the_confirmation_link_confirms_a_subscriber() {
 
     let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
     app.post_subscriptions(body.into()).await;
+    app.dispatch_pending_emails().await;
 
     let email_request = &app.email_server.received_requests().await.unwrap()[0];
     let confirmation_links = app.get_confirmation_links(email_request);
@@ -58,12 +110,156 @@ async fn clicking_on_the_confirmation_link_confirms_a_subscriber() {
         .error_for_status()
         .unwrap();
 
-    let saved = sqlx::query!("SELECT email, name, status FROM subscriptions")
-        .fetch_one(&app.db_pool)
+    let (email, name, status): (String, String, SubscriptionStatus) = app
+        .fetch_one("SELECT email, name, status FROM subscriptions")
+        .await;
+
+    assert_eq!(email, "ursula_le_guin@gmail.com");
+    assert_eq!(name, "le guin");
+    assert_eq!(status, SubscriptionStatus::Confirmed);
+}
+
+#[tokio::test]
+async fn browsers_get_an_html_page() {
+    let app = spawn_app().await;
+    let confirmation_link = subscribe(&app).await;
+
+    let response = reqwest::Client::new()
+        .get(confirmation_link)
+        .header("Accept", "text/html,*/*;q=0.8")
+        .send()
         .await
-        .expect("Failed to fetch saved subscription");
+        .unwrap();
 
-    assert_eq!(saved.email, "ursula_le_guin@gmail.com");
-    assert_eq!(saved.name, "le guin");
-    assert_eq!(saved.status, "confirmed");
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.headers()["Content-Type"]
+        .to_str()
+        .unwrap()
+        .starts_with("text/html"));
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("Subscription confirmed"));
+}
+
+#[tokio::test]
+async fn api_clients_get_a_json_status() {
+    let app = spawn_app().await;
+    let confirmation_link = subscribe(&app).await;
+
+    for expected in ["confirmed", "already_confirmed"] {
+        let response = reqwest::Client::new()
+            .get(confirmation_link.clone())
+            .header("Accept", "application/json")
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(response.status().as_u16(), 200);
+        let body: serde_json::Value = response.json().await.unwrap();
+        assert_eq!(body["status"], expected);
+    }
+
+    let response = reqwest::Client::new()
+        .get(format!(
+            "{}/subscriptions/confirm?token={}",
+            app.address,
+            "a".repeat(25)
+        ))
+        .header("Accept", "application/json")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 401);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "invalid");
+}
+
+#[tokio::test]
+async fn an_expired_link_does_not_confirm_the_subscriber() {
+    let app = spawn_app_with(|configuration| {
+        configuration.subscriptions.confirmation_link_ttl_hours = Some(24);
+    })
+    .await;
+    let confirmation_link = subscribe(&app).await;
+    let subscribed_at = Utc::now() - chrono::Duration::days(2);
+    app.execute(&format!(
+        "UPDATE subscriptions SET subscribed_at = '{}'",
+        subscribed_at.to_rfc3339()
+    ))
+    .await;
+
+    let response = reqwest::get(confirmation_link).await.unwrap();
+
+    assert_eq!(response.status().as_u16(), 410);
+    let html = response.text().await.unwrap();
+    assert!(html.contains("expired"));
+    assert!(html.contains(r#"<a href="../subscribe">"#));
+    let (status,): (SubscriptionStatus,) = app.fetch_one("SELECT status FROM subscriptions").await;
+    assert_eq!(status, SubscriptionStatus::PendingConfirmation);
+}
+
+#[tokio::test]
+async fn subscribing_again_after_the_link_expired_sends_a_new_link() {
+    let app = spawn_app_with(|configuration| {
+        configuration.subscriptions.confirmation_link_ttl_hours = Some(24);
+    })
+    .await;
+    let expired_link = subscribe(&app).await;
+    let subscribed_at = Utc::now() - chrono::Duration::days(2);
+    app.execute(&format!(
+        "UPDATE subscriptions SET subscribed_at = '{}'",
+        subscribed_at.to_rfc3339()
+    ))
+    .await;
+    assert_eq!(
+        reqwest::get(expired_link).await.unwrap().status().as_u16(),
+        410
+    );
+
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    let response = app.post_subscriptions(body.into()).await;
+    assert_eq!(response.status().as_u16(), 200);
+    app.dispatch_pending_emails().await;
+    let email_requests = app.email_server.received_requests().await.unwrap();
+    assert_eq!(email_requests.len(), 2);
+    let new_link = app.get_confirmation_links(&email_requests[1]).html;
+    let response = reqwest::get(new_link).await.unwrap();
+
+    assert_eq!(response.status().as_u16(), 200);
+    let (subscribers,): (i64,) = app.fetch_one("SELECT COUNT(*) FROM subscriptions").await;
+    assert_eq!(subscribers, 1);
+    let (status,): (SubscriptionStatus,) = app.fetch_one("SELECT status FROM subscriptions").await;
+    assert_eq!(status, SubscriptionStatus::Confirmed);
+}
+
+#[tokio::test]
+async fn browsers_are_redirected_to_the_configured_pages() {
+    let app = spawn_app_with(|configuration| {
+        configuration
+            .subscriptions
+            .confirmation_pages
+            .confirmed
+            .redirect_url = Some("https://example.com/welcome".into());
+    })
+    .await;
+    let confirmation_link = subscribe(&app).await;
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap();
+
+    let response = client.get(confirmation_link.clone()).send().await.unwrap();
+
+    assert_eq!(response.status().as_u16(), 303);
+    assert_eq!(
+        response.headers()["Location"],
+        "https://example.com/welcome"
+    );
+
+    // The other pages are still rendered
+    let response = client.get(confirmation_link).send().await.unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+    assert!(response.text().await.unwrap().contains("already confirmed"));
 }
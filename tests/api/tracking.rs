@@ -0,0 +1,259 @@
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use crate::helpers::{create_confirmed_subscriber, spawn_app, TestApp};
+
+/// Links of an issue sent to the subscriber.
+struct IssueLinks {
+    issue_id: String,
+    html: String,
+    tracked: Vec<reqwest::Url>,
+    opt_out: Option<reqwest::Url>,
+    pixel: Option<reqwest::Url>,
+}
+
+async fn publish_issue(app: &TestApp, tracking: serde_json::Value) -> IssueLinks {
+    let _mock_guard = Mock::given(path("/email/batch"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    let response = app
+        .post_newsletters(serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": r#"<html><body><p>Read <a href="https://example.com/article?id=1&amp;ref=news">the article</a></p></body></html>"#,
+            },
+            "tracking": tracking,
+        }))
+        .await
+        .error_for_status()
+        .unwrap();
+    let issue_id = response.json::<serde_json::Value>().await.unwrap()["issue_id"]
+        .as_str()
+        .unwrap()
+        .to_owned();
+    app.deliver_queued_issues().await;
+
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    let html = body[0]["HtmlBody"].as_str().unwrap().to_owned();
+
+    let links: Vec<reqwest::Url> = linkify::LinkFinder::new()
+        .links(&html)
+        .filter(|l| *l.kind() == linkify::LinkKind::Url)
+        .map(|l| {
+            let mut url = reqwest::Url::parse(l.as_str()).unwrap();
+            if url.host_str() == Some("127.0.0.1") {
+                url.set_port(Some(app.port)).unwrap();
+            }
+            url
+        })
+        .collect();
+    let find = |prefix: &str| links.iter().find(|l| l.path().starts_with(prefix)).cloned();
+
+    IssueLinks {
+        issue_id,
+        tracked: links
+            .iter()
+            .filter(|l| l.path().starts_with("/t/c/"))
+            .cloned()
+            .collect(),
+        opt_out: find("/t/opt_out/"),
+        pixel: find("/t/o/"),
+        html,
+    }
+}
+
+async fn issue_stats(app: &TestApp, issue_id: &str) -> serde_json::Value {
+    app.get_issue_stats(&app.admin_token, issue_id)
+        .await
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap()
+}
+
+fn client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap()
+}
+
+#[tokio::test]
+async fn issues_are_not_tracked_by_default() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+
+    let links = publish_issue(&app, serde_json::json!({})).await;
+
+    assert!(links.tracked.is_empty());
+    assert!(links.pixel.is_none());
+    assert!(links.opt_out.is_none());
+    assert!(links
+        .html
+        .contains("https://example.com/article?id=1&amp;ref=news"));
+}
+
+#[tokio::test]
+async fn tracked_links_redirect_to_their_destination() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let links = publish_issue(&app, serde_json::json!({"clicks": true})).await;
+    assert_eq!(links.tracked.len(), 1);
+
+    for _ in 0..2 {
+        let response = client().get(links.tracked[0].clone()).send().await.unwrap();
+
+        assert_eq!(response.status().as_u16(), 302);
+        assert_eq!(
+            response.headers()["Location"],
+            "https://example.com/article?id=1&ref=news"
+        );
+    }
+
+    let stats = issue_stats(&app, &links.issue_id).await;
+    assert_eq!(stats["unique_clicks"], 1);
+    assert_eq!(stats["unique_opens"], 0);
+    assert_eq!(
+        stats["links"],
+        serde_json::json!([{"url": "https://example.com/article?id=1&ref=news", "unique_clicks": 1}])
+    );
+}
+
+#[tokio::test]
+async fn the_pixel_records_the_opens() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let links = publish_issue(&app, serde_json::json!({"opens": true})).await;
+    assert!(links.tracked.is_empty());
+
+    let response = client().get(links.pixel.unwrap()).send().await.unwrap();
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(response.headers()["Content-Type"], "image/gif");
+    let stats = issue_stats(&app, &links.issue_id).await;
+    assert_eq!(stats["unique_opens"], 1);
+}
+
+#[tokio::test]
+async fn forged_tokens_are_rejected() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let links = publish_issue(&app, serde_json::json!({"clicks": true})).await;
+    let mut forged = links.tracked[0].clone();
+    let token = forged.path().trim_start_matches("/t/c/").to_owned();
+    let (_, signature) = token.split_once('.').unwrap();
+    let payload = base64::encode_config(
+        format!(
+            "c:{}:{}:https://evil.com",
+            links.issue_id,
+            uuid::Uuid::new_v4()
+        ),
+        base64::URL_SAFE_NO_PAD,
+    );
+    forged.set_path(&format!("/t/c/{}.{}", payload, signature));
+
+    let response = client().get(forged).send().await.unwrap();
+
+    assert_eq!(response.status().as_u16(), 404);
+    let stats = issue_stats(&app, &links.issue_id).await;
+    assert_eq!(stats["unique_clicks"], 0);
+}
+
+#[tokio::test]
+async fn opted_out_subscribers_are_not_tracked() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let links = publish_issue(&app, serde_json::json!({"opens": true, "clicks": true})).await;
+
+    client()
+        .post(links.opt_out.unwrap())
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    // The links already sent still work but nothing is recorded
+    let response = client().get(links.tracked[0].clone()).send().await.unwrap();
+    assert_eq!(response.status().as_u16(), 302);
+    client().get(links.pixel.unwrap()).send().await.unwrap();
+    let stats = issue_stats(&app, &links.issue_id).await;
+    assert_eq!(stats["unique_opens"], 0);
+    assert_eq!(stats["unique_clicks"], 0);
+
+    let links = publish_issue(&app, serde_json::json!({"opens": true, "clicks": true})).await;
+    assert!(links.tracked.is_empty());
+    assert!(links.pixel.is_none());
+}
+
+#[tokio::test]
+async fn following_the_opt_out_link_asks_for_a_confirmation() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let links = publish_issue(&app, serde_json::json!({"opens": true, "clicks": true})).await;
+
+    let response = client().get(links.opt_out.unwrap()).send().await.unwrap();
+
+    assert_eq!(response.status().as_u16(), 200);
+    let page = response.text().await.unwrap();
+    assert!(page.contains(r#"<form method="post">"#));
+    // Nothing changes until the subscriber confirms
+    let links = publish_issue(&app, serde_json::json!({"opens": true, "clicks": true})).await;
+    assert!(links.pixel.is_some());
+}
+
+#[tokio::test]
+async fn a_one_click_opt_out_is_accepted() {
+    let app = spawn_app().await;
+    create_confirmed_subscriber(&app).await;
+    let links = publish_issue(&app, serde_json::json!({"opens": true, "clicks": true})).await;
+
+    let response = client()
+        .post(links.opt_out.unwrap())
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .body("List-Unsubscribe=One-Click")
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status().as_u16(), 200);
+    let links = publish_issue(&app, serde_json::json!({"opens": true, "clicks": true})).await;
+    assert!(links.pixel.is_none());
+}
+
+#[tokio::test]
+async fn issue_stats_require_the_admin_token() {
+    let app = spawn_app().await;
+
+    let response = app
+        .get_issue_stats("wrong-token", &uuid::Uuid::new_v4().to_string())
+        .await;
+
+    assert_eq!(response.status().as_u16(), 401);
+}
+
+#[tokio::test]
+async fn stats_of_unknown_issues_return_404() {
+    let app = spawn_app().await;
+
+    let response = app
+        .get_issue_stats(&app.admin_token, &uuid::Uuid::new_v4().to_string())
+        .await;
+
+    assert_eq!(response.status().as_u16(), 404);
+}
@@ -0,0 +1,211 @@
+use uuid::Uuid;
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+use zero2prod::domain::SubscriptionStatus;
+
+use crate::helpers::{spawn_app, TestApp};
+
+const HARD_BOUNCE: &str = include_str!("fixtures/postmark/hard_bounce.json");
+const SOFT_BOUNCE: &str = include_str!("fixtures/postmark/soft_bounce.json");
+const SPAM_COMPLAINT: &str = include_str!("fixtures/postmark/spam_complaint.json");
+const DELIVERY: &str = include_str!("fixtures/postmark/delivery.json");
+const OPEN: &str = include_str!("fixtures/postmark/open.json");
+
+impl TestApp {
+    async fn post_postmark_webhook(&self, payload: &str) -> reqwest::Response {
+        let (username, password) = &self.webhook_credentials;
+        self.post_email_webhook("postmark", payload.into(), Some((username, password)))
+            .await
+    }
+}
+
+/// Subscribe the recipient of the recorded payloads.
+async fn create_subscriber(app: &TestApp) {
+    let _mock_guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    app.post_subscriptions("name=john&email=john%40example.com".into())
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_pending_emails().await;
+}
+
+async fn subscriber_status(app: &TestApp) -> SubscriptionStatus {
+    let (status,) = app
+        .fetch_one("SELECT status FROM subscriptions WHERE email = 'john@example.com'")
+        .await;
+    status
+}
+
+/// Give the recorded payload a new message id, as if it was about another message.
+fn with_new_message_id(payload: &str) -> String {
+    let mut payload: serde_json::Value = serde_json::from_str(payload).unwrap();
+    payload["MessageID"] = Uuid::new_v4().to_string().into();
+    payload.to_string()
+}
+
+#[tokio::test]
+async fn webhooks_without_valid_credentials_are_rejected() {
+    let app = spawn_app().await;
+    let username = app.webhook_credentials.0.clone();
+
+    let test_cases = vec![
+        (None, "missing credentials"),
+        (
+            Some((username.as_str(), "wrong-password")),
+            "wrong password",
+        ),
+        (Some(("wrong-username", "")), "wrong username"),
+    ];
+
+    for (credentials, description) in test_cases {
+        let response = app
+            .post_email_webhook("postmark", HARD_BOUNCE.into(), credentials)
+            .await;
+
+        assert_eq!(
+            response.status().as_u16(),
+            401,
+            "The API did not fail with 401 Unauthorized with {}.",
+            description
+        );
+        assert!(response.headers().contains_key("WWW-Authenticate"));
+    }
+}
+
+#[tokio::test]
+async fn webhooks_of_unknown_providers_return_404() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_email_webhook("carrier-pigeon", HARD_BOUNCE.into(), None)
+        .await;
+
+    assert_eq!(response.status().as_u16(), 404);
+}
+
+#[tokio::test]
+async fn malformed_payloads_are_rejected_with_a_400() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_postmark_webhook(r#"{"RecordType": "Bounce"}"#)
+        .await;
+
+    assert_eq!(response.status().as_u16(), 400);
+}
+
+#[tokio::test]
+async fn events_are_stored_once() {
+    let app = spawn_app().await;
+
+    for _ in 0..2 {
+        let response = app.post_postmark_webhook(DELIVERY).await;
+        assert_eq!(response.status().as_u16(), 200);
+    }
+
+    let events: Vec<(String, String, String)> = app
+        .fetch_all("SELECT provider, kind, recipient FROM email_events")
+        .await;
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].0, "postmark");
+    assert_eq!(events[0].1, "delivered");
+    assert_eq!(events[0].2, "john@example.com");
+}
+
+#[tokio::test]
+async fn irrelevant_events_are_ignored() {
+    let app = spawn_app().await;
+
+    let response = app.post_postmark_webhook(OPEN).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let events: Vec<(Uuid,)> = app.fetch_all("SELECT id FROM email_events").await;
+    assert!(events.is_empty());
+}
+
+#[tokio::test]
+async fn a_hard_bounce_suppresses_the_subscriber() {
+    let app = spawn_app().await;
+    create_subscriber(&app).await;
+
+    let response = app.post_postmark_webhook(HARD_BOUNCE).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        subscriber_status(&app).await,
+        SubscriptionStatus::Suppressed
+    );
+    let (scope, value, reason): (String, String, String) = app
+        .fetch_one("SELECT scope, value, reason FROM suppressions")
+        .await;
+    assert_eq!(scope, "address");
+    assert_eq!(value, "john@example.com");
+    assert_eq!(reason, "hard_bounce");
+}
+
+#[tokio::test]
+async fn a_spam_complaint_suppresses_the_subscriber() {
+    let app = spawn_app().await;
+    create_subscriber(&app).await;
+
+    let response = app.post_postmark_webhook(SPAM_COMPLAINT).await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    assert_eq!(
+        subscriber_status(&app).await,
+        SubscriptionStatus::Suppressed
+    );
+}
+
+#[tokio::test]
+async fn soft_bounces_suppress_the_subscriber_after_the_threshold() {
+    let app = spawn_app().await;
+    create_subscriber(&app).await;
+
+    // The threshold is 3 in the base configuration
+    for _ in 0..2 {
+        app.post_postmark_webhook(&with_new_message_id(SOFT_BOUNCE))
+            .await
+            .error_for_status()
+            .unwrap();
+    }
+    assert_eq!(
+        subscriber_status(&app).await,
+        SubscriptionStatus::PendingConfirmation
+    );
+
+    app.post_postmark_webhook(&with_new_message_id(SOFT_BOUNCE))
+        .await
+        .error_for_status()
+        .unwrap();
+    assert_eq!(
+        subscriber_status(&app).await,
+        SubscriptionStatus::Suppressed
+    );
+}
+
+#[tokio::test]
+async fn a_delivery_resets_the_soft_bounce_count() {
+    let app = spawn_app().await;
+    create_subscriber(&app).await;
+
+    for payload in [SOFT_BOUNCE, SOFT_BOUNCE, DELIVERY, SOFT_BOUNCE, SOFT_BOUNCE] {
+        app.post_postmark_webhook(&with_new_message_id(payload))
+            .await
+            .error_for_status()
+            .unwrap();
+    }
+
+    assert_eq!(
+        subscriber_status(&app).await,
+        SubscriptionStatus::PendingConfirmation
+    );
+}